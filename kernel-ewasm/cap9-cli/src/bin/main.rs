@@ -5,22 +5,29 @@ use clap::{App, AppSettings, Arg, SubCommand};
 use ethabi::token::Tokenizer;
 
 use rustc_hex::ToHex;
-use std::fs::create_dir;
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::prelude::*;
 use std::path::PathBuf;
+use std::time::Duration;
+use web3::types::BlockNumber;
 
 use env_logger;
 
 use cap9_cli::build;
+use cap9_cli::caps;
 use cap9_cli::connection;
 use cap9_cli::fetch;
+use cap9_cli::manifest;
+use cap9_cli::output;
 use cap9_cli::project;
+use cap9_cli::template;
 use futures::future::Future;
 
 use cap9_cli::utils::string_to_proc_key;
-use cap9_std::proc_table::cap::*;
-use fetch::{DeployedKernel, DeployedKernelWithACL, SerialNewCapList};
+use fetch::{DeployedKernel, DeployedKernelWithACL, LogFilter, SerialNewCapList, StorageKind};
+use output::Format;
+use serde_json::json;
+use validator::requirements::cap_requirements;
 
 fn main() {
     env_logger::init();
@@ -29,6 +36,28 @@ fn main() {
         .version("0.2.0")
         .author("Daolab <info@daolab.io>")
         .about("A command-line interface for BeakerOS on the Ethereum blockchain.")
+        .arg(
+            Arg::with_name("local")
+                .long("local")
+                .help("Use a local chain saved in chain.json rather than a node"),
+        )
+        .arg(
+            Arg::with_name("network")
+                .long("network")
+                .takes_value(true)
+                .value_name("NAME")
+                .conflicts_with("local")
+                .help("Use the network profile NAME from networks.json"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&output::Format::NAMES)
+                .default_value("text")
+                .help("The output format of the fetch, call and query commands"),
+        )
         .subcommand(
             SubCommand::with_name("new")
                 .about("Create a new Cap9 project in directory PROJECT-NAME")
@@ -50,11 +79,27 @@ fn main() {
         )
         .subcommand(
             SubCommand::with_name("compile")
-                .about("Compile a WASM contract")
+                .about("Compile a procedure crate, validate it, and record it in the project")
                 .arg(
                     Arg::with_name("CARGO-PATH")
-                        .required(true)
+                        .default_value(".")
                         .help("path to cargo project"),
+                )
+                .arg(
+                    Arg::with_name("pages")
+                        .short("p")
+                        .long("pages")
+                        .value_name("PAGES")
+                        .default_value("4")
+                        .help("Number of pages to set the memory to"),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .default_value("kernel.json")
+                        .help("The kernel manifest to record the procedure in, which is created if it does not exist"),
                 ),
         )
         // This will understand the ACL
@@ -171,22 +216,16 @@ fn main() {
                         .required(true)
                         .help("Name of the procedure"),
                 )
-                // .arg(
-                //     Arg::with_name("CODE-FILE")
-                //         .required(true)
-                //         .help("Binary code file"),
-                // )
-                // .arg(
-                //     Arg::with_name("ABI-FILE")
-                //         .required(true)
-                //         .help("JSON ABI file"),
-                // )
-                // .arg(
-                //     Arg::with_name("CAP-FILE")
-                //         .required(true)
-                //         .help("JSON cap file"),
-                // )
-                .about("Create a new procedure"),
+                .arg(
+                    Arg::with_name("template")
+                        .long("template")
+                        .takes_value(true)
+                        .value_name("TEMPLATE")
+                        .possible_values(&template::Template::NAMES)
+                        .default_value("logger")
+                        .help("The kind of procedure to create"),
+                )
+                .about("Create a new procedure crate from a template"),
         )
         .subcommand(
             SubCommand::with_name("delete-procedure")
@@ -226,6 +265,46 @@ fn main() {
                 )
                 .about("Add an new group"),
         )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .default_value("kernel.json")
+                        .help("The kernel manifest"),
+                )
+                .about("Show the changes needed to bring the kernel in line with its manifest"),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .default_value("kernel.json")
+                        .help("The kernel manifest"),
+                )
+                .about("Bring the kernel in line with its manifest"),
+        )
+        .subcommand(
+            SubCommand::with_name("check-caps")
+                .arg(
+                    Arg::with_name("CAP-FILE")
+                        .required(true)
+                        .help("The cap file, as TOML or JSON"),
+                )
+                .arg(
+                    Arg::with_name("code")
+                        .long("code")
+                        .takes_value(true)
+                        .value_name("CODE-FILE")
+                        .help("A compiled procedure, whose syscalls are checked against the caps"),
+                )
+                .about("Check that the admin procedure can grant the caps in a cap file, and print them as JSON"),
+        )
         .subcommand(
             SubCommand::with_name("fetch")
                 .setting(AppSettings::ArgRequiredElseHelp)
@@ -237,7 +316,51 @@ fn main() {
                     SubCommand::with_name("procedures").about("List all the registered procedures"),
                 )
                 .subcommand(
-                    SubCommand::with_name("all-logs").about("Print all logs of the kernel"),
+                    SubCommand::with_name("all-logs")
+                        .about("Print the logs of the kernel, decoded with the ABIs of the kernel and procedures")
+                        .arg(
+                            Arg::with_name("procedure")
+                                .long("procedure")
+                                .takes_value(true)
+                                .value_name("PROC-NAME")
+                                .help("Only print events from the ABI of this procedure"),
+                        )
+                        .arg(
+                            Arg::with_name("event")
+                                .long("event")
+                                .takes_value(true)
+                                .value_name("EVENT-NAME")
+                                .help("Only print events with this name"),
+                        )
+                        .arg(
+                            Arg::with_name("from-block")
+                                .long("from-block")
+                                .takes_value(true)
+                                .value_name("BLOCK")
+                                .help("The first block to print logs from"),
+                        )
+                        .arg(
+                            Arg::with_name("to-block")
+                                .long("to-block")
+                                .takes_value(true)
+                                .value_name("BLOCK")
+                                .conflicts_with("follow")
+                                .help("The last block to print logs from"),
+                        )
+                        .arg(
+                            Arg::with_name("follow")
+                                .long("follow")
+                                .help("Keep printing logs as new blocks are mined"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("storage")
+                        .about("List and classify every storage key of the kernel")
+                        .arg(
+                            Arg::with_name("unaccounted")
+                                .long("unaccounted")
+                                .help("Only list the keys which no capability accounts for"),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name("acl")
@@ -255,26 +378,67 @@ fn main() {
                 ),
         )
         .get_matches();
+    let local = matches.is_present("local");
+    let network = matches.value_of("network");
+    let format = Format::from_name(matches.value_of("format").unwrap()).unwrap();
 
     if let Some(_deploy_matches) = matches.subcommand_matches("deploy") {
-        // Connect to a local network over http.
-        let conn: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
         // Read the local project from out current directory.
         let local_project = project::LocalProject::read();
         // Deploy a kernel with the ACL Bootstrap procedure
         local_project
             .deploy(&conn)
             .unwrap_or_else(|err| panic!("Deployment failure: {}", err));
-    } else if let Some(_compile_matches) = matches.subcommand_matches("compile") {
-        // let cargo_path = PathBuf::from(compile_matches.value_of("CARGO-PATH").expect("No code file"));
-        // Read the local project from out current directory.
-        // let local_project = project::LocalProject::read();
+    } else if let Some(compile_matches) = matches.subcommand_matches("compile") {
+        let cargo_path = PathBuf::from(compile_matches.value_of("CARGO-PATH").unwrap());
+        let mem_pages = compile_matches
+            .value_of("pages")
+            .unwrap()
+            .parse()
+            .expect("expected number for number of pages");
+        let compiled = build::compile::compile(&cargo_path, mem_pages, compile_matches)
+            .unwrap_or_else(|err| panic!("{}", err));
+        let contract_spec = &compiled.proc_spec.contract_spec;
+        println!("Code: {}", contract_spec.code_path);
+        println!("ABI: {}", contract_spec.abi_path);
+        println!("Caps: {}", compiled.proc_spec.cap_path.display());
+        // Without the standard ACL, the procedure is the initial entry
+        // procedure of the project. With it, the initial entry procedure is
+        // the ACL bootstrap, and the procedure goes in the manifest.
+        let project_dir = PathBuf::from(".");
+        if let Some(mut deploy_file) = project::DeployFile::read_dir(&project_dir) {
+            if !deploy_file.standard_acl_abi {
+                deploy_file.deploy_spec.initial_entry = compiled.proc_spec.clone();
+                deploy_file.write_dir(&project_dir);
+                println!("Set \"{}\" as the initial entry procedure in deploy.json", compiled.name);
+            }
+        }
+        // Record the procedure in the kernel manifest, creating it if needed.
+        let manifest_path = PathBuf::from(compile_matches.value_of("manifest").unwrap());
+        let mut manifest = if manifest_path.exists() {
+            manifest::Manifest::read(&manifest_path).unwrap_or_else(|err| panic!("{}", err))
+        } else {
+            manifest::Manifest::default()
+        };
+        manifest
+            .set_procedure(compiled.name.clone(), &compiled.proc_spec)
+            .unwrap_or_else(|err| panic!("{}", err));
+        manifest
+            .write(&manifest_path)
+            .unwrap_or_else(|err| panic!("{}", err));
+        println!(
+            "Recorded procedure \"{}\" in {}",
+            compiled.name,
+            manifest_path.display()
+        );
     } else if let Some(call_matches) = matches.subcommand_matches("call") {
         let function_name = call_matches
             .value_of("FUNCTION-NAME")
             .expect("No code file");
 
-        let network: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        let network = connection::EthConn::connect(local, network);
         let local_project = project::LocalProject::read();
         let kernel = DeployedKernel::new(&network, local_project);
         let kernel_with_acl = DeployedKernelWithACL::new(kernel);
@@ -300,10 +464,20 @@ fn main() {
                 .collect(),
             None => Vec::new(),
         };
-        println!("Sending from: {:?}", kernel_with_acl.kernel.conn.sender);
-        println!("Inputs: {:?}", inputs);
+        let sender = kernel_with_acl.kernel.conn.sender;
+        if format == Format::Text {
+            println!("Sending from: {:?}", sender);
+            println!("Inputs: {:?}", inputs);
+        }
         let result: web3::types::TransactionReceipt = kernel_with_acl.call(function_name, &inputs);
-        println!("Result: {:?}", result);
+        match format {
+            Format::Text => println!("Result: {:?}", result),
+            Format::Json => output::print_json(&json!({
+                "sender": sender,
+                "inputs": inputs.iter().map(output::token_json).collect::<Vec<_>>(),
+                "receipt": output::receipt_json(&result),
+            })),
+        }
     } else if let Some(call_any_matches) = matches.subcommand_matches("call-any") {
         let proc_name = call_any_matches
             .value_of("PROC-NAME")
@@ -316,7 +490,7 @@ fn main() {
         let proc_key = cap9_std::SysCallProcedureKey(string_to_proc_key(proc_name.to_string()));
 
 
-        let network: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        let network = connection::EthConn::connect(local, network);
         let local_project = project::LocalProject::read();
         let kernel = DeployedKernel::new(&network, local_project);
         let kernel_with_acl = DeployedKernelWithACL::new(kernel);
@@ -351,7 +525,14 @@ fn main() {
         // println!("Sending from: {:?}", kernel_with_acl.kernel.conn.sender);
         // println!("Inputs: {:?}", inputs);
         let result: web3::types::TransactionReceipt = kernel_with_acl.call_any(proc_key, function_name, &inputs);
-        println!("Result: {:?}", result);
+        match format {
+            Format::Text => println!("Result: {:?}", result),
+            Format::Json => output::print_json(&json!({
+                "sender": kernel_with_acl.kernel.conn.sender,
+                "inputs": inputs.iter().map(output::token_json).collect::<Vec<_>>(),
+                "receipt": output::receipt_json(&result),
+            })),
+        }
     } else if let Some(call_any_matches) = matches.subcommand_matches("query-any") {
         let proc_name = call_any_matches
             .value_of("PROC-NAME")
@@ -364,7 +545,7 @@ fn main() {
         let proc_key = cap9_std::SysCallProcedureKey(string_to_proc_key(proc_name.to_string()));
 
 
-        let network: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        let network = connection::EthConn::connect(local, network);
         let local_project = project::LocalProject::read();
         let kernel = DeployedKernel::new(&network, local_project);
         let kernel_with_acl = DeployedKernelWithACL::new(kernel);
@@ -393,13 +574,23 @@ fn main() {
             None => Vec::new(),
         };
         let result = kernel_with_acl.query_any(proc_key, function_name, &inputs);
-        println!("Result: {:?}", result);
+        match format {
+            Format::Text => println!("Result: {:?}", result),
+            Format::Json => {
+                let result = result.unwrap_or_else(|err| panic!("query failed: {}", err));
+                output::print_json(&json!({
+                    "sender": kernel_with_acl.kernel.conn.sender,
+                    "inputs": inputs.iter().map(output::token_json).collect::<Vec<_>>(),
+                    "result": result.iter().map(output::token_json).collect::<Vec<_>>(),
+                }))
+            }
+        }
     } else if let Some(query_matches) = matches.subcommand_matches("query") {
         let function_name = query_matches
             .value_of("FUNCTION-NAME")
             .expect("No code file");
 
-        let network: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        let network = connection::EthConn::connect(local, network);
         let local_project = project::LocalProject::read();
         let kernel = DeployedKernel::new(&network, local_project);
         let kernel_with_acl = DeployedKernelWithACL::new(kernel);
@@ -425,10 +616,20 @@ fn main() {
                 .collect(),
             None => Vec::new(),
         };
-        println!("Sending from: {:?}", kernel_with_acl.kernel.conn.sender);
-        println!("Inputs: {:?}", inputs);
+        let sender = kernel_with_acl.kernel.conn.sender;
+        if format == Format::Text {
+            println!("Sending from: {:?}", sender);
+            println!("Inputs: {:?}", inputs);
+        }
         let result: Vec<ethabi::Token> = kernel_with_acl.query(function_name, &inputs).unwrap();
-        println!("Result: {:?}", result);
+        match format {
+            Format::Text => println!("Result: {:?}", result),
+            Format::Json => output::print_json(&json!({
+                "sender": sender,
+                "inputs": inputs.iter().map(output::token_json).collect::<Vec<_>>(),
+                "result": result.iter().map(output::token_json).collect::<Vec<_>>(),
+            })),
+        }
     } else if let Some(new_group_matches) = matches.subcommand_matches("new-group") {
         let group_number: u8 = new_group_matches
             .value_of("GROUP-NUMBER")
@@ -445,8 +646,8 @@ fn main() {
         );
         let abi_file = PathBuf::from(new_group_matches.value_of("ABI-FILE").expect("No ABI file"));
         let cap_file = PathBuf::from(new_group_matches.value_of("CAP-FILE").expect("No ABI file"));
-        // Connect to a local network over http.
-        let conn: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
         // Read the local project from out current directory.
         let local_project = project::LocalProject::read();
         let kernel = DeployedKernel::new(&conn, local_project);
@@ -459,167 +660,27 @@ fn main() {
         kernel_with_acl
             .new_group(group_number, proc_name.to_string(), proc_spec)
             .unwrap();
-    } else if let Some(deploy_procedure_matches) = matches.subcommand_matches("new-procedure") {
-        let proc_name = deploy_procedure_matches
+    } else if let Some(new_procedure_matches) = matches.subcommand_matches("new-procedure") {
+        let proc_name = new_procedure_matches
             .value_of("PROCEDURE-NAME")
             .expect("No code file");
+        let proc_template =
+            template::Template::from_name(new_procedure_matches.value_of("template").unwrap())
+                .expect("unknown template");
         // Read the local project from out current directory. We do this to
         // ensure we are in a project.
         let _local_project = project::LocalProject::read();
-        // Create a new directory in the local project.
-        // Create a new directory, throw an error if the directory exists.
+        // Create a new directory in the local project, throw an error if the
+        // directory exists.
         let proc_path = PathBuf::from(proc_name);
-        let creation_result = create_dir(&proc_path);
-        // Check that the directory was correctly created.
-        match creation_result {
-            Ok(_) => (),
+        match proc_template.create(&proc_path, proc_name) {
+            Ok(()) => (),
             Err(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
                 println!("The directory {:?} already exists.", proc_path);
                 std::process::exit(1);
             }
-            e => e.unwrap(),
-        }
-        // Create a source directory within that directory
-        let mut src_path = PathBuf::from(proc_name);
-        src_path.push("src");
-        let creation_result_src = create_dir(&src_path);
-        // Check that the directory was correctly created.
-        match creation_result_src {
-            Ok(_) => (),
-            Err(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
-                println!("The directory {:?} already exists.", src_path);
-                std::process::exit(1);
-            }
-            e => e.unwrap(),
-        }
-        let mut main_path = src_path.clone();
-        main_path.push("main.rs");
-        let mut main_file = std::fs::File::create(&main_path).unwrap();
-        main_file
-            .write_all(include_bytes!("example_proc.rs.example"))
-            .unwrap();
-
-        let mut toml_path = proc_path.clone();
-        toml_path.push("Cargo.toml");
-        let mut toml_file = std::fs::File::create(&toml_path).unwrap();
-        let mut toml_data: toml::Value =
-            toml::from_slice(include_bytes!("example_Cargo.toml.example")).unwrap();
-        // println!("toml-data: {:?}", toml_data);
-        let package_table = toml_data
-            .get_mut("package")
-            .unwrap()
-            .as_table_mut()
-            .unwrap();
-        let package_name: toml::Value = toml::Value::String(proc_name.to_string());
-        package_table.insert("name".to_string(), package_name);
-        toml_file
-            .write_all(toml::to_string_pretty(&toml_data).unwrap().as_bytes())
-            .unwrap();
-
-        let config_str = "[target.wasm32-unknown-unknown]\n
-rustflags = [
-  \"-C\", \"link-args=-z stack-size=65536\",
-]";
-        // Create a source directory within that directory
-        let mut config_dir_path = proc_path.clone();
-        config_dir_path.push(".cargo");
-        let creation_result_config = create_dir(&config_dir_path);
-        // Check that the directory was correctly created.
-        match creation_result_config {
-            Ok(_) => (),
-            Err(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
-                println!("The directory {:?} already exists.", config_dir_path);
-                std::process::exit(1);
-            }
-            e => e.unwrap(),
+            Err(err) => panic!("could not create procedure: {}", err),
         }
-        let mut config_file_path = config_dir_path.clone();
-        config_file_path.push("config");
-        let mut config_file = std::fs::File::create(&config_file_path).unwrap();
-        config_file.write_all(config_str.as_bytes()).unwrap();
-
-        let mut caps_path = proc_path.clone();
-        caps_path.push("caps.json");
-        let prefix = 0;
-        let cap_key = string_to_proc_key("".to_string());
-        let caps_file = std::fs::File::create(&caps_path).unwrap();
-        let caps_data: Vec<NewCapability> = vec![
-            NewCapability {
-                cap: Capability::ProcedureRegister(ProcedureRegisterCap {
-                    prefix,
-                    key: cap_key,
-                }),
-                parent_index: 0,
-            },
-            NewCapability {
-                cap: Capability::ProcedureRegister(ProcedureRegisterCap {
-                    prefix,
-                    key: cap_key,
-                }),
-                parent_index: 1,
-            },
-            NewCapability {
-                cap: Capability::ProcedureCall(ProcedureCallCap {
-                    prefix,
-                    key: cap_key,
-                }),
-                parent_index: 0,
-            },
-            NewCapability {
-                cap: Capability::ProcedureDelete(ProcedureDeleteCap {
-                    prefix,
-                    key: cap_key,
-                }),
-                parent_index: 0,
-            },
-            NewCapability {
-                cap: Capability::Log(LogCap {
-                    topics: 0_u8,
-                    t1: [0_u8; 32],
-                    t2: [0_u8; 32],
-                    t3: [0_u8; 32],
-                    t4: [0_u8; 32],
-                }),
-                parent_index: 0,
-            },
-            NewCapability {
-                cap: Capability::StoreWrite(StoreWriteCap {
-                    location: [
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                    ],
-                    size: [
-                        0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                    ],
-                }),
-                parent_index: 0,
-            },
-            NewCapability {
-                cap: Capability::StoreWrite(StoreWriteCap {
-                    location: [
-                        0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                    ],
-                    size: [
-                        0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                    ],
-                }),
-                parent_index: 1,
-            },
-            NewCapability {
-                cap: Capability::ProcedureEntry(ProcedureEntryCap),
-                parent_index: 0,
-            },
-        ];
-        let serial_cap_list = SerialNewCapList(NewCapList(caps_data));
-        serde_json::ser::to_writer_pretty(caps_file, &serial_cap_list)
-            .expect("Could not serialise caps data");
     } else if let Some(deploy_procedure_matches) = matches.subcommand_matches("deploy-procedure") {
         let proc_name = deploy_procedure_matches
             .value_of("PROCEDURE-NAME")
@@ -651,12 +712,12 @@ rustflags = [
         }
         // Check that the cap file exists
         if !cap_file.as_path().exists() {
-            println!("The capabilities JSON file does not exist. The root of the procedure directory should contain a file called \"caps.json\" which contains JSON formatted capability information.");
+            println!("The capabilities file does not exist. The root of the procedure directory should contain a file called \"caps.toml\" or \"caps.json\" which contains the capability information.");
             std::process::exit(1);
         }
 
-        // Connect to a local network over http.
-        let conn: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
         // Read the local project from out current directory. We don't need it,
         // we just want to make sure we are in one.
         let local_project = project::LocalProject::read();
@@ -682,8 +743,8 @@ rustflags = [
         let proc_name = delete_procedure_matches
             .value_of("PROCEDURE-NAME")
             .expect("No code file");
-        // Connect to a local network over http.
-        let conn: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
         // Read the local project from out current directory.
         let local_project = project::LocalProject::read();
         let kernel = DeployedKernel::new(&conn, local_project);
@@ -691,6 +752,65 @@ rustflags = [
         kernel_with_acl
             .delete_procedure(proc_name.to_string())
             .unwrap();
+    } else if let Some(plan_matches) = matches.subcommand_matches("plan") {
+        let manifest_path = PathBuf::from(plan_matches.value_of("manifest").unwrap());
+        let manifest = manifest::Manifest::read(&manifest_path).unwrap_or_else(|err| panic!("{}", err));
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
+        // Read the local project from out current directory.
+        let local_project = project::LocalProject::read();
+        let kernel = DeployedKernel::new(&conn, local_project);
+        let kernel_with_acl = DeployedKernelWithACL::new(kernel);
+        let plan = manifest
+            .plan(&kernel_with_acl)
+            .unwrap_or_else(|err| panic!("{}", err));
+        if plan.is_empty() {
+            println!("No changes, the kernel matches {}", manifest_path.display());
+        } else {
+            for operation in plan.operations.iter() {
+                println!("{}", operation);
+            }
+        }
+    } else if let Some(check_caps_matches) = matches.subcommand_matches("check-caps") {
+        let cap_path = PathBuf::from(check_caps_matches.value_of("CAP-FILE").unwrap());
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
+        // Read the local project from out current directory.
+        let local_project = project::LocalProject::read();
+        let kernel = DeployedKernel::new(&conn, local_project);
+        let kernel_with_acl = DeployedKernelWithACL::new(kernel);
+        let caps = caps::read_cap_file(&cap_path, &kernel_with_acl.admin_caps())
+            .unwrap_or_else(|err| panic!("{}", err));
+        if let Some(code_file) = check_caps_matches.value_of("code") {
+            let code = std::fs::read(code_file)
+                .unwrap_or_else(|err| panic!("could not read {}: {}", code_file, err));
+            let module = validator::Module::new(&code)
+                .unwrap_or_else(|err| panic!("could not decode {}: {:?}", code_file, err));
+            let requirements = cap_requirements(&module, &validator::PwasmPolicy);
+            caps::check_requirements(&caps, &requirements).unwrap_or_else(|err| panic!("{}", err));
+        }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&SerialNewCapList(caps)).unwrap()
+        );
+    } else if let Some(apply_matches) = matches.subcommand_matches("apply") {
+        let manifest_path = PathBuf::from(apply_matches.value_of("manifest").unwrap());
+        let manifest = manifest::Manifest::read(&manifest_path).unwrap_or_else(|err| panic!("{}", err));
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
+        // Read the local project from out current directory.
+        let local_project = project::LocalProject::read();
+        let kernel = DeployedKernel::new(&conn, local_project);
+        let mut kernel_with_acl = DeployedKernelWithACL::new(kernel);
+        let plan = manifest
+            .plan(&kernel_with_acl)
+            .unwrap_or_else(|err| panic!("{}", err));
+        if plan.is_empty() {
+            println!("No changes, the kernel matches {}", manifest_path.display());
+        } else {
+            plan.apply(&manifest, &mut kernel_with_acl)
+                .unwrap_or_else(|err| panic!("{}", err));
+        }
     } else if let Some(deploy_contract_matches) = matches.subcommand_matches("deploy-contract") {
         let code_file = PathBuf::from(
             deploy_contract_matches
@@ -702,8 +822,8 @@ rustflags = [
                 .value_of("ABI-FILE")
                 .expect("No ABI file"),
         );
-        // Connect to a local network over http.
-        let conn: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        // Connect to a node over http, or to the local chain.
+        let conn = connection::EthConn::connect(local, network);
         // Create a contract specification from the given files.
         let contract_spec = project::ContractSpec::from_files(&code_file, &abi_file);
         // Deploy the contract onto the chain.
@@ -734,51 +854,138 @@ rustflags = [
             panic!("no build command");
         }
     } else if let Some(fetch_matches) = matches.subcommand_matches("fetch") {
-        let network: connection::EthConn<web3::transports::Http> = connection::EthConn::new_http();
+        let network = connection::EthConn::connect(local, network);
         let local_project = project::LocalProject::read();
         let kernel = DeployedKernel::new(&network, local_project);
         if let Some(_procs_matches) = fetch_matches.subcommand_matches("procedures") {
             // List procedures
             let procs = kernel.procedures();
-            for procedure in procs {
-                println!("{}", procedure);
+            match format {
+                Format::Text => {
+                    for procedure in procs {
+                        println!("{}", procedure);
+                    }
+                }
+                Format::Json => output::print_json(&procs),
             }
         } else if let Some(_gas_matches) = fetch_matches.subcommand_matches("gas") {
             let gas = kernel.gas();
-            println!("Gas: {}", gas);
-        } else if let Some(_all_logs_matches) = fetch_matches.subcommand_matches("all-logs") {
-            let logs = kernel.all_logs();
-            for (i,log) in logs.iter().enumerate() {
-                let hex_s: String = log.data.0.clone().to_hex();
-                print!("log[{}]: 0x{}", i, hex_s);
-                match String::from_utf8(log.data.0.clone()) {
-                    Ok(s) => print!(" => {}", s),
-                    Err(_) => (),
+            match format {
+                Format::Text => println!("Gas: {}", gas),
+                Format::Json => output::print_json(&json!({ "gas": gas })),
+            }
+        } else if let Some(logs_matches) = fetch_matches.subcommand_matches("all-logs") {
+            let decoder = kernel.event_decoder();
+            let filter = LogFilter {
+                procedure: logs_matches.value_of("procedure").map(String::from),
+                event: logs_matches.value_of("event").map(String::from),
+            };
+            let block_arg = |name: &str| -> Option<u64> {
+                logs_matches
+                    .value_of(name)
+                    .map(|block| block.parse().expect("expected a block number"))
+            };
+            let from_block = block_arg("from-block").unwrap_or(0);
+            if logs_matches.is_present("follow") {
+                let mut i = 0;
+                kernel.follow_logs(from_block, Duration::from_secs(1), |log| {
+                    let log = decoder.decode(log);
+                    if filter.matches(&log) {
+                        match format {
+                            Format::Text => println!("log[{}]: {}", i, log),
+                            // One document per line, as the output has no end.
+                            Format::Json => println!("{}", output::decoded_log_json(&log)),
+                        }
+                        i += 1;
+                    }
+                });
+            }
+            let to_block = block_arg("to-block").map_or(BlockNumber::Latest, BlockNumber::Number);
+            let logs: Vec<_> = kernel
+                .logs(BlockNumber::Number(from_block), to_block)
+                .into_iter()
+                .map(|log| decoder.decode(log))
+                .filter(|log| filter.matches(log))
+                .collect();
+            match format {
+                Format::Text => {
+                    for (i, log) in logs.iter().enumerate() {
+                        println!("log[{}]: {}", i, log);
+                    }
                 }
-                println!("");
+                Format::Json => output::print_json(
+                    &logs
+                        .iter()
+                        .map(output::decoded_log_json)
+                        .collect::<Vec<_>>(),
+                ),
+            }
+        } else if let Some(storage_matches) = fetch_matches.subcommand_matches("storage") {
+            let entries = kernel
+                .storage()
+                .unwrap_or_else(|err| panic!("could not list the storage keys: {}", err));
+            let n_keys = entries.len();
+            let n_unaccounted = entries
+                .iter()
+                .filter(|entry| entry.kind == StorageKind::Unaccounted)
+                .count();
+            let entries: Vec<_> = entries
+                .iter()
+                .filter(|entry| {
+                    !storage_matches.is_present("unaccounted")
+                        || entry.kind == StorageKind::Unaccounted
+                })
+                .collect();
+            match format {
+                Format::Text => {
+                    for entry in entries.iter() {
+                        println!("{}", entry);
+                    }
+                    println!("# Keys: {}, unaccounted: {}", n_keys, n_unaccounted);
+                }
+                Format::Json => output::print_json(&entries),
             }
         } else if let Some(acl_matches) = fetch_matches.subcommand_matches("acl") {
             let kernel_with_acl = DeployedKernelWithACL::new(kernel);
             if let Some(_groups_matches) = acl_matches.subcommand_matches("groups") {
                 let groups = kernel_with_acl.groups();
-                println!("# Groups: {}", groups.len());
-                for (k, v) in groups.iter() {
-                    let ks = &v.procedure_key.0;
-                    let key: String = ks.to_hex();
-                    let key_utf8: &str = std::str::from_utf8(ks).unwrap().trim_end_matches('\0');
-                    print!(
-                        "  {}: procedure_key: 0x{} (\"{}\")\n    Users:\n",
-                        k, key, key_utf8
-                    );
-                    for user in v.users.iter() {
-                        print!("      {}\n", user);
+                match format {
+                    Format::Text => {
+                        println!("# Groups: {}", groups.len());
+                        for (k, v) in groups.iter() {
+                            let ks = &v.procedure_key.0;
+                            let key: String = ks.to_hex();
+                            let key_utf8: &str =
+                                std::str::from_utf8(ks).unwrap().trim_end_matches('\0');
+                            print!(
+                                "  {}: procedure_key: 0x{} (\"{}\")\n    Users:\n",
+                                k, key, key_utf8
+                            );
+                            for user in v.users.iter() {
+                                print!("      {}\n", user);
+                            }
+                        }
+                    }
+                    Format::Json => {
+                        let mut groups: Vec<_> = groups.values().collect();
+                        groups.sort_by_key(|group| group.id);
+                        output::print_json(&groups);
                     }
                 }
             } else if let Some(_users_matches) = acl_matches.subcommand_matches("users") {
                 let users = kernel_with_acl.users();
-                println!("# Users: {}", users.len());
-                for (k, v) in users.iter() {
-                    println!("  {}: {}", k, v);
+                match format {
+                    Format::Text => {
+                        println!("# Users: {}", users.len());
+                        for (k, v) in users.iter() {
+                            println!("  {}: {}", k, v);
+                        }
+                    }
+                    Format::Json => {
+                        // Map each user to their group, in a stable order.
+                        let users: BTreeMap<_, _> = users.into_iter().collect();
+                        output::print_json(&users);
+                    }
                 }
             } else if let Some(_users_matches) = acl_matches.subcommand_matches("abi") {
                 // Take the information from the ABI files.
@@ -789,6 +996,7 @@ rustflags = [
                     .status_file()
                     .as_ref()
                     .unwrap();
+                let mut procs_json = Vec::new();
                 // TODO: get proc names
                 for procedure in procs {
                     // println!("{}", procedure.address);
@@ -796,14 +1004,26 @@ rustflags = [
                     let ks = procedure.key;
                     let key: String = ks.to_hex();
                     let key_utf8: &str = std::str::from_utf8(&ks).unwrap().trim_end_matches('\0');
-                    println!("Procedure: 0x{} (\"{}\")", key, key_utf8);
                     let abi_file = File::open(path).unwrap();
                     let abi = ethabi::Contract::load(abi_file).unwrap();
-                    for function in abi.functions() {
-                        print!("  ");
-                        print_function(function);
+                    match format {
+                        Format::Text => {
+                            println!("Procedure: 0x{} (\"{}\")", key, key_utf8);
+                            for function in abi.functions() {
+                                print!("  ");
+                                print_function(function);
+                            }
+                            println!("");
+                        }
+                        Format::Json => procs_json.push(json!({
+                            "key": format!("0x{}", key),
+                            "name": key_utf8,
+                            "functions": abi.functions().map(output::function_json).collect::<Vec<_>>(),
+                        })),
                     }
-                    println!("");
+                }
+                if format == Format::Json {
+                    output::print_json(&procs_json);
                 }
             }
         }
@@ -838,12 +1058,20 @@ fn get_proc_paths(proc_name: &String) -> (PathBuf, PathBuf, PathBuf) {
         println!("The directory for {} does not exist. Are you sure you created such a procedure? Try using `cap9-cli new-procedure {}`.",proc_name, proc_name);
         std::process::exit(1);
     }
+    // Code built with `compile` is used in preference to the raw cargo
+    // output.
     let mut code_path = proc_dir.clone();
     code_path.push("target");
-    code_path.push("wasm32-unknown-unknown");
-    code_path.push("release");
     code_path.push(proc_name);
     code_path.set_extension("wasm");
+    if !code_path.exists() {
+        code_path = proc_dir.clone();
+        code_path.push("target");
+        code_path.push("wasm32-unknown-unknown");
+        code_path.push("release");
+        code_path.push(proc_name);
+        code_path.set_extension("wasm");
+    }
 
     // For now we will assume there is only one JSON abi file.
     let mut abi_path = proc_dir.clone();
@@ -869,9 +1097,12 @@ fn get_proc_paths(proc_name: &String) -> (PathBuf, PathBuf, PathBuf) {
         panic!("No ABI files");
     }
 
+    // A symbolic cap file is used in preference to a JSON one.
     let mut cap_path = proc_dir.clone();
-    cap_path.push("caps");
-    cap_path.set_extension("json");
+    cap_path.push("caps.toml");
+    if !cap_path.exists() {
+        cap_path.set_extension("json");
+    }
 
     (code_path, abi_path, cap_path)
 }
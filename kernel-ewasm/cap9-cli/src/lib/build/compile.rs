@@ -0,0 +1,331 @@
+//! Compile a procedure crate into a procedure which is ready to deploy.
+//!
+//! The crate is built for `wasm32-unknown-unknown` in release mode, the output
+//! is passed through [contract_build], [set_mem] and [wasm_build] in the same
+//! way as `build full`, and the result is checked with the validator. The code
+//! is written to `<target-dir>/<name>.wasm`, next to the `json` directory in
+//! which `pwasm-abi-derive` leaves the ABI.
+//!
+//! `pwasm-abi-derive` names the ABI after the interface trait, and every crate
+//! of a workspace writes to the same directory, so the name of the interface
+//! is taken from the crate's metadata:
+//!
+//! ```toml
+//! [package.metadata.cap9]
+//! abi = "CounterInterface"
+//! ```
+//!
+//! Without it, the interface of a crate `my-counter` is taken to be
+//! `MyCounterInterface`.
+use clap::ArgMatches;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use validator::{PwasmPolicy, Validity, ValidityError};
+
+use super::{contract_build, set_mem, wasm_build};
+use crate::project::{ContractSpec, ProcSpec};
+
+#[derive(Debug, Fail)]
+pub enum CompileError {
+    #[fail(display = "could not run cargo: {}", err)]
+    CargoFailure { err: String },
+    #[fail(display = "cargo build failed")]
+    BuildFailure,
+    #[fail(display = "could not read the cargo metadata: {}", err)]
+    MetadataFailure { err: String },
+    #[fail(display = "{} has no bin or cdylib target", path)]
+    NoTarget { path: String },
+    #[fail(display = "could not decode {}: {}", path, err)]
+    Decoding { path: String, err: String },
+    #[fail(display = "could not write {}: {}", path, err)]
+    Encoding { path: String, err: String },
+    #[fail(display = "the compiled procedure is not valid: {:?}", err)]
+    Invalid { err: ValidityError },
+    #[fail(display = "there is no ABI file {}", path)]
+    NoAbi { path: String },
+}
+
+/// A compiled procedure.
+pub struct CompiledProcedure {
+    /// The name of the crate, which is used as the procedure key.
+    pub name: String,
+    pub proc_spec: ProcSpec,
+}
+
+/// The parts of `cargo metadata` we need.
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    target_directory: PathBuf,
+    workspace_root: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+    targets: Vec<Target>,
+    #[serde(default)]
+    metadata: Option<PackageMetadata>,
+}
+
+#[derive(Deserialize)]
+struct PackageMetadata {
+    cap9: Option<Cap9Metadata>,
+}
+
+#[derive(Deserialize)]
+struct Cap9Metadata {
+    /// The name of the interface trait, which names the ABI file.
+    abi: String,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl Metadata {
+    /// The package with the manifest `manifest_path`.
+    fn package(&self, manifest_path: &Path) -> Option<&Package> {
+        match self.packages.as_slice() {
+            [package] => Some(package),
+            packages => packages
+                .iter()
+                .find(|package| package.manifest_path == manifest_path),
+        }
+    }
+}
+
+impl Package {
+    /// The file name of the WASM the package compiles to, without the
+    /// extension.
+    fn artifact(&self) -> Option<String> {
+        self.targets.iter().find_map(|target| {
+            if target.kind.iter().any(|kind| kind == "bin") {
+                Some(target.name.clone())
+            } else if target.kind.iter().any(|kind| kind == "cdylib") {
+                Some(target.name.replace("-", "_"))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The name of the ABI file of the package, without the extension.
+    fn abi_name(&self) -> String {
+        match self
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.cap9.as_ref())
+        {
+            Some(cap9) => cap9.abi.clone(),
+            None => {
+                let mut name: String = self
+                    .name
+                    .split(|c| c == '-' || c == '_')
+                    .flat_map(|word| {
+                        let mut chars = word.chars();
+                        chars
+                            .next()
+                            .into_iter()
+                            .flat_map(char::to_uppercase)
+                            .chain(chars)
+                    })
+                    .collect();
+                name.push_str("Interface");
+                name
+            }
+        }
+    }
+}
+
+/// Compile the crate in `cargo_path`. Options for [wasm_build] are taken from
+/// `opts`.
+pub fn compile(
+    cargo_path: &Path,
+    mem_pages: u32,
+    opts: &ArgMatches,
+) -> Result<CompiledProcedure, CompileError> {
+    let manifest_path = cargo_path
+        .join("Cargo.toml")
+        .canonicalize()
+        .map_err(|err| CompileError::MetadataFailure {
+            err: err.to_string(),
+        })?;
+    let metadata = metadata(cargo_path)?;
+    let package =
+        metadata
+            .package(&manifest_path)
+            .ok_or_else(|| CompileError::MetadataFailure {
+                err: format!("no package for {}", manifest_path.display()),
+            })?;
+    let artifact = package.artifact().ok_or_else(|| CompileError::NoTarget {
+        path: manifest_path.display().to_string(),
+    })?;
+
+    // Cargo is run from within the crate so that its .cargo/config, which
+    // sets the stack size, is used.
+    let status = Command::new("cargo")
+        .args(&["build", "--release", "--target", "wasm32-unknown-unknown"])
+        .current_dir(cargo_path)
+        .status()
+        .map_err(|err| CompileError::CargoFailure {
+            err: err.to_string(),
+        })?;
+    if !status.success() {
+        return Err(CompileError::BuildFailure);
+    }
+
+    let mut raw_path = metadata.target_directory.clone();
+    raw_path.push("wasm32-unknown-unknown");
+    raw_path.push("release");
+    raw_path.push(&artifact);
+    raw_path.set_extension("wasm");
+    let module =
+        parity_wasm::deserialize_file(&raw_path).map_err(|err| CompileError::Decoding {
+            path: raw_path.display().to_string(),
+            err: err.to_string(),
+        })?;
+    let module = wasm_build(opts, set_mem(contract_build(module), mem_pages));
+
+    let mut code_path = metadata.target_directory.clone();
+    code_path.push(&artifact);
+    code_path.set_extension("wasm");
+    let code = parity_wasm::serialize(module).map_err(|err| CompileError::Encoding {
+        path: code_path.display().to_string(),
+        err: err.to_string(),
+    })?;
+    validator::Module::new(&code)
+        .map_err(|err| CompileError::Decoding {
+            path: code_path.display().to_string(),
+            err: format!("{:?}", err),
+        })?
+        .validate_with(&PwasmPolicy)
+        .map_err(|err| CompileError::Invalid { err })?;
+    std::fs::write(&code_path, code).map_err(|err| CompileError::Encoding {
+        path: code_path.display().to_string(),
+        err: err.to_string(),
+    })?;
+
+    let mut abi_path = metadata.workspace_root.join("target").join("json");
+    abi_path.push(package.abi_name());
+    abi_path.set_extension("json");
+    if !abi_path.is_file() {
+        return Err(CompileError::NoAbi {
+            path: abi_path.display().to_string(),
+        });
+    }
+    // A symbolic cap file is used in preference to a JSON one.
+    let mut cap_path = cargo_path.join("caps.toml");
+    if !cap_path.exists() {
+        cap_path.set_extension("json");
+    }
+    Ok(CompiledProcedure {
+        name: package.name.clone(),
+        proc_spec: ProcSpec {
+            contract_spec: ContractSpec::from_files(&relative(&code_path), &relative(&abi_path)),
+            cap_path: relative(&cap_path),
+        },
+    })
+}
+
+fn metadata(cargo_path: &Path) -> Result<Metadata, CompileError> {
+    let output = Command::new("cargo")
+        .args(&["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(cargo_path)
+        .output()
+        .map_err(|err| CompileError::CargoFailure {
+            err: err.to_string(),
+        })?;
+    if !output.status.success() {
+        return Err(CompileError::MetadataFailure {
+            err: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    serde_json::from_slice(&output.stdout).map_err(|err| CompileError::MetadataFailure {
+        err: err.to_string(),
+    })
+}
+
+/// Make a path relative to the current directory, which is the project
+/// directory, where possible.
+fn relative(path: &Path) -> PathBuf {
+    std::env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(PathBuf::from))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifact_names() {
+        let metadata: Metadata = serde_json::from_str(
+            r#"{
+                "packages": [
+                    {
+                        "name": "my-proc",
+                        "manifest_path": "/project/my-proc/Cargo.toml",
+                        "targets": [{ "name": "my-proc", "kind": ["bin"] }]
+                    },
+                    {
+                        "name": "my-lib",
+                        "manifest_path": "/project/my-lib/Cargo.toml",
+                        "targets": [{ "name": "my-lib", "kind": ["lib", "cdylib"] }]
+                    },
+                    {
+                        "name": "plain",
+                        "manifest_path": "/project/plain/Cargo.toml",
+                        "targets": [{ "name": "plain", "kind": ["lib"] }]
+                    }
+                ],
+                "target_directory": "/project/target",
+                "workspace_root": "/project"
+            }"#,
+        )
+        .unwrap();
+        let artifact = |path: &str| {
+            metadata
+                .package(Path::new(path))
+                .and_then(|package| package.artifact())
+        };
+        // Binaries keep their names, but library names use underscores.
+        assert_eq!(
+            artifact("/project/my-proc/Cargo.toml"),
+            Some(String::from("my-proc"))
+        );
+        assert_eq!(
+            artifact("/project/my-lib/Cargo.toml"),
+            Some(String::from("my_lib"))
+        );
+        assert_eq!(artifact("/project/plain/Cargo.toml"), None);
+        assert_eq!(artifact("/project/other/Cargo.toml"), None);
+    }
+    #[test]
+    fn abi_names() {
+        let packages: Vec<Package> = serde_json::from_str(
+            r#"[
+                {
+                    "name": "my-counter",
+                    "manifest_path": "/project/my-counter/Cargo.toml",
+                    "targets": [],
+                    "metadata": null
+                },
+                {
+                    "name": "logger",
+                    "manifest_path": "/project/logger/Cargo.toml",
+                    "targets": [],
+                    "metadata": { "cap9": { "abi": "TestLoggerInterface" } }
+                }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(packages[0].abi_name(), "MyCounterInterface");
+        assert_eq!(packages[1].abi_name(), "TestLoggerInterface");
+    }
+}
@@ -0,0 +1,637 @@
+//! Capability files.
+//!
+//! A procedure's caps can be given as JSON, in the encoding of
+//! [SerialNewCapList](crate::fetch::SerialNewCapList), or in a symbolic TOML
+//! format which is meant to be written by hand. A file is taken to be
+//! symbolic if its name ends in `.toml`.
+//!
+//! ```toml
+//! # Named storage regions, for store_write caps.
+//! [regions]
+//! counter = { location = "0x8000", size = 256 }
+//!
+//! # Labels for caps of the parent procedure, the procedure which registers
+//! # this one. Caps are numbered from 0 within each type.
+//! [parents]
+//! any_write = { type = "store_write", index = 0 }
+//!
+//! [[caps]]
+//! type = "procedure_call"
+//! key = "counter"
+//!
+//! [[caps]]
+//! type = "store_write"
+//! region = "counter"
+//! parent = "any_write"
+//!
+//! [[caps]]
+//! type = "log"
+//! topics = ["0x01"]
+//! ```
+//!
+//! The cap types are `procedure_call`, `procedure_register`,
+//! `procedure_delete`, `procedure_entry`, `store_write`, `log` and
+//! `account_call`. Procedure keys are written as names, or as 24 bytes of hex.
+//! Without a key, a procedure cap covers every procedure, and with one the
+//! `prefix` (the number of leading bits of the key which must match) defaults
+//! to the whole key. Numbers are integers or hex strings. A cap's `parent` is
+//! a label, an index, or left out, in which case the first cap of the parent
+//! which covers it is used.
+//!
+//! Either way, the caps are checked against the caps of the parent procedure
+//! before they are used. They can also be checked against the syscalls of the
+//! compiled procedure, as far as the caps those need can be inferred from its
+//! code.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use web3::types::Address;
+
+use crate::fetch::SerialNewCapList;
+use crate::utils::{string_to_proc_key, to_common_address};
+use cap9_std::proc_table::cap::*;
+use validator::requirements::CapRequirement;
+
+#[derive(Debug, Fail)]
+pub enum CapFileError {
+    #[fail(display = "could not read cap file {}: {}", path, err)]
+    ReadFailure { path: String, err: String },
+    #[fail(display = "could not parse cap file {}: {}", path, err)]
+    ParseFailure { path: String, err: String },
+    #[fail(display = "cap {}: no region named \"{}\"", index, region)]
+    UnknownRegion { index: usize, region: String },
+    #[fail(display = "cap {}: no parent labelled \"{}\"", index, label)]
+    UnknownLabel { index: usize, label: String },
+    #[fail(
+        display = "cap {}: the parent \"{}\" is a different type of cap",
+        index, label
+    )]
+    LabelType { index: usize, label: String },
+    #[fail(display = "cap {}: invalid procedure key \"{}\"", index, key)]
+    InvalidKey { index: usize, key: String },
+    #[fail(display = "cap {}: invalid number \"{}\"", index, value)]
+    InvalidNumber { index: usize, value: String },
+    #[fail(display = "cap {}: {}", index, err)]
+    InvalidCap { index: usize, err: String },
+    #[fail(
+        display = "cap {} ({}) is not covered by any cap of the parent",
+        index, cap
+    )]
+    NoParent { index: usize, cap: String },
+    #[fail(display = "caps are not a subset of the parent's caps: {}", caps)]
+    NotSubset { caps: String },
+    #[fail(
+        display = "the caps do not cover the syscalls of the procedure: {}",
+        requirements
+    )]
+    Unsatisfied { requirements: String },
+}
+
+/// Read a cap file and check it against the caps of the parent procedure.
+pub fn read_cap_file(path: &Path, parent: &Capabilities) -> Result<NewCapList, CapFileError> {
+    let read_failure = |err: String| CapFileError::ReadFailure {
+        path: path.display().to_string(),
+        err,
+    };
+    let parse_failure = |err: String| CapFileError::ParseFailure {
+        path: path.display().to_string(),
+        err,
+    };
+    let caps = if path.extension() == Some(std::ffi::OsStr::new("toml")) {
+        let contents = std::fs::read(path).map_err(|err| read_failure(err.to_string()))?;
+        let cap_file: SymbolicCapFile =
+            toml::from_slice(&contents).map_err(|err| parse_failure(err.to_string()))?;
+        cap_file.compile(parent)?
+    } else {
+        let file = File::open(path).map_err(|err| read_failure(err.to_string()))?;
+        let SerialNewCapList(caps) =
+            serde_json::from_reader(file).map_err(|err| parse_failure(err.to_string()))?;
+        caps
+    };
+    check_caps(&caps, parent)?;
+    Ok(caps)
+}
+
+/// Check that each cap is a subset of its parent.
+pub fn check_caps(caps: &NewCapList, parent: &Capabilities) -> Result<(), CapFileError> {
+    let errors = caps.check_subset_of(parent.clone());
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        let described: Vec<String> = errors
+            .iter()
+            .map(|error| format!("{} ({})", error.index, caps.0[error.index].cap))
+            .collect();
+        Err(CapFileError::NotSubset {
+            caps: described.join(", "),
+        })
+    }
+}
+
+/// Check that the caps could satisfy each of the cap requirements inferred
+/// from the code of the procedure.
+pub fn check_requirements(
+    caps: &NewCapList,
+    requirements: &[CapRequirement],
+) -> Result<(), CapFileError> {
+    let cap_types: Vec<u8> = caps
+        .0
+        .iter()
+        .map(|new_cap| new_cap.cap.cap_type())
+        .collect();
+    let unsatisfied: Vec<String> = requirements
+        .iter()
+        .filter(|requirement| !requirement.is_satisfied_by(&cap_types))
+        .map(|requirement| requirement.to_string())
+        .collect();
+    if unsatisfied.is_empty() {
+        Ok(())
+    } else {
+        Err(CapFileError::Unsatisfied {
+            requirements: unsatisfied.join(", "),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolicCapFile {
+    #[serde(default)]
+    regions: BTreeMap<String, Region>,
+    #[serde(default)]
+    parents: BTreeMap<String, ParentLabel>,
+    #[serde(default)]
+    caps: Vec<SymbolicCap>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Region {
+    location: Number,
+    size: Number,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParentLabel {
+    #[serde(rename = "type")]
+    cap_type: CapType,
+    index: u8,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum CapType {
+    ProcedureCall,
+    ProcedureRegister,
+    ProcedureDelete,
+    ProcedureEntry,
+    StoreWrite,
+    Log,
+    AccountCall,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SymbolicCap {
+    #[serde(rename = "type")]
+    cap_type: CapType,
+    key: Option<String>,
+    prefix: Option<u8>,
+    region: Option<String>,
+    location: Option<Number>,
+    size: Option<Number>,
+    #[serde(default)]
+    topics: Vec<Number>,
+    address: Option<Address>,
+    #[serde(default)]
+    can_call_any: bool,
+    #[serde(default)]
+    can_send: bool,
+    parent: Option<Parent>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Parent {
+    Index(u8),
+    Label(String),
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Integer(u64),
+    Hex(String),
+}
+
+impl Number {
+    /// The number as 32 big endian bytes.
+    fn to_b32(&self, index: usize) -> Result<[u8; 32], CapFileError> {
+        let mut bytes = [0; 32];
+        match self {
+            Number::Integer(n) => bytes[24..].copy_from_slice(&n.to_be_bytes()),
+            Number::Hex(s) => {
+                let invalid = || CapFileError::InvalidNumber {
+                    index,
+                    value: s.clone(),
+                };
+                let digits = s.trim_start_matches("0x");
+                if !s.starts_with("0x") || digits.is_empty() || digits.len() > 64 {
+                    return Err(invalid());
+                }
+                // Pad to a whole number of bytes.
+                let digits = if digits.len() % 2 == 1 {
+                    format!("0{}", digits)
+                } else {
+                    digits.to_string()
+                };
+                let value: Vec<u8> =
+                    rustc_hex::FromHex::from_hex(digits.as_str()).map_err(|_| invalid())?;
+                bytes[32 - value.len()..].copy_from_slice(&value);
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+impl SymbolicCapFile {
+    /// Compile the caps into a [NewCapList], choosing parents from the caps
+    /// of the parent procedure.
+    pub fn compile(&self, parent: &Capabilities) -> Result<NewCapList, CapFileError> {
+        let mut new_caps = Vec::new();
+        for (index, symbolic) in self.caps.iter().enumerate() {
+            let cap = self.capability(index, symbolic)?;
+            let parent_index = match &symbolic.parent {
+                Some(Parent::Index(parent_index)) => *parent_index,
+                Some(Parent::Label(label)) => {
+                    let parent_label =
+                        self.parents
+                            .get(label)
+                            .ok_or_else(|| CapFileError::UnknownLabel {
+                                index,
+                                label: label.clone(),
+                            })?;
+                    if parent_label.cap_type != symbolic.cap_type {
+                        return Err(CapFileError::LabelType {
+                            index,
+                            label: label.clone(),
+                        });
+                    }
+                    parent_label.index
+                }
+                None => caps_of_type(parent, symbolic.cap_type)
+                    .iter()
+                    .position(|parent_cap| cap.is_subset_of(parent_cap))
+                    .ok_or_else(|| CapFileError::NoParent {
+                        index,
+                        cap: cap.to_string(),
+                    })? as u8,
+            };
+            new_caps.push(NewCapability { cap, parent_index });
+        }
+        Ok(NewCapList(new_caps))
+    }
+
+    fn capability(&self, index: usize, symbolic: &SymbolicCap) -> Result<Capability, CapFileError> {
+        let missing = |field: &str| CapFileError::InvalidCap {
+            index,
+            err: format!("missing {}", field),
+        };
+        Ok(match symbolic.cap_type {
+            CapType::ProcedureCall => {
+                let (prefix, key) = procedure_key(index, symbolic)?;
+                Capability::ProcedureCall(ProcedureCallCap { prefix, key })
+            }
+            CapType::ProcedureRegister => {
+                let (prefix, key) = procedure_key(index, symbolic)?;
+                Capability::ProcedureRegister(ProcedureRegisterCap { prefix, key })
+            }
+            CapType::ProcedureDelete => {
+                let (prefix, key) = procedure_key(index, symbolic)?;
+                Capability::ProcedureDelete(ProcedureDeleteCap { prefix, key })
+            }
+            CapType::ProcedureEntry => Capability::ProcedureEntry(ProcedureEntryCap),
+            CapType::StoreWrite => {
+                let (location, size) = match (&symbolic.region, &symbolic.location, &symbolic.size)
+                {
+                    (Some(region), None, None) => {
+                        let region = self.regions.get(region).ok_or_else(|| {
+                            CapFileError::UnknownRegion {
+                                index,
+                                region: region.clone(),
+                            }
+                        })?;
+                        (region.location.clone(), region.size.clone())
+                    }
+                    (None, Some(location), Some(size)) => (location.clone(), size.clone()),
+                    (Some(_), _, _) => {
+                        return Err(CapFileError::InvalidCap {
+                            index,
+                            err: String::from("give either a region, or a location and size"),
+                        })
+                    }
+                    (None, None, _) => return Err(missing("location")),
+                    (None, _, None) => return Err(missing("size")),
+                };
+                Capability::StoreWrite(StoreWriteCap {
+                    location: location.to_b32(index)?,
+                    size: size.to_b32(index)?,
+                })
+            }
+            CapType::Log => {
+                if symbolic.topics.len() > 4 {
+                    return Err(CapFileError::InvalidCap {
+                        index,
+                        err: String::from("a log cap has at most 4 topics"),
+                    });
+                }
+                let mut topics = [[0; 32]; 4];
+                for (topic, number) in topics.iter_mut().zip(symbolic.topics.iter()) {
+                    *topic = number.to_b32(index)?;
+                }
+                Capability::Log(LogCap {
+                    topics: symbolic.topics.len() as u8,
+                    t1: topics[0],
+                    t2: topics[1],
+                    t3: topics[2],
+                    t4: topics[3],
+                })
+            }
+            CapType::AccountCall => {
+                let address = match (symbolic.address, symbolic.can_call_any) {
+                    (Some(address), _) => address,
+                    (None, true) => Address::zero(),
+                    (None, false) => return Err(missing("address")),
+                };
+                Capability::AccountCall(AccountCallCap {
+                    can_call_any: symbolic.can_call_any,
+                    can_send: symbolic.can_send,
+                    address: to_common_address(address),
+                })
+            }
+        })
+    }
+}
+
+/// The prefix and key of a procedure cap.
+fn procedure_key(index: usize, symbolic: &SymbolicCap) -> Result<(u8, [u8; 24]), CapFileError> {
+    let key = match &symbolic.key {
+        None => return Ok((symbolic.prefix.unwrap_or(0), [0; 24])),
+        Some(key) => key,
+    };
+    let invalid = || CapFileError::InvalidKey {
+        index,
+        key: key.clone(),
+    };
+    let hex = key.trim_start_matches("0x");
+    let key_bytes = if hex.len() < key.len() {
+        let bytes: Vec<u8> = rustc_hex::FromHex::from_hex(hex).map_err(|_| invalid())?;
+        if bytes.len() != 24 {
+            return Err(invalid());
+        }
+        let mut key_bytes = [0; 24];
+        key_bytes.copy_from_slice(&bytes);
+        key_bytes
+    } else if key.is_ascii() && key.len() <= 24 {
+        string_to_proc_key(key.clone())
+    } else {
+        return Err(invalid());
+    };
+    Ok((symbolic.prefix.unwrap_or(192), key_bytes))
+}
+
+fn caps_of_type(caps: &Capabilities, cap_type: CapType) -> Vec<Capability> {
+    match cap_type {
+        CapType::ProcedureCall => caps
+            .proc_call_caps
+            .iter()
+            .cloned()
+            .map(Capability::ProcedureCall)
+            .collect(),
+        CapType::ProcedureRegister => caps
+            .proc_register_caps
+            .iter()
+            .cloned()
+            .map(Capability::ProcedureRegister)
+            .collect(),
+        CapType::ProcedureDelete => caps
+            .proc_delete_caps
+            .iter()
+            .cloned()
+            .map(Capability::ProcedureDelete)
+            .collect(),
+        CapType::ProcedureEntry => caps
+            .proc_entry_caps
+            .iter()
+            .cloned()
+            .map(Capability::ProcedureEntry)
+            .collect(),
+        CapType::StoreWrite => caps
+            .store_write_caps
+            .iter()
+            .cloned()
+            .map(Capability::StoreWrite)
+            .collect(),
+        CapType::Log => caps.log_caps.iter().cloned().map(Capability::Log).collect(),
+        CapType::AccountCall => caps
+            .account_call_caps
+            .iter()
+            .cloned()
+            .map(Capability::AccountCall)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Caps like those of the ACL admin procedure: any procedure, and two
+    /// storage ranges.
+    fn parent() -> Capabilities {
+        Capabilities::from(vec![
+            Capability::ProcedureCall(ProcedureCallCap {
+                prefix: 0,
+                key: [0; 24],
+            }),
+            Capability::StoreWrite(StoreWriteCap {
+                location: [0; 32],
+                size: {
+                    let mut size = [0; 32];
+                    size[0] = 0x10;
+                    size
+                },
+            }),
+            Capability::StoreWrite(StoreWriteCap {
+                location: {
+                    let mut location = [0; 32];
+                    location[0] = 0x30;
+                    location
+                },
+                size: {
+                    let mut size = [0; 32];
+                    size[0] = 0x10;
+                    size
+                },
+            }),
+        ])
+    }
+
+    fn compile(source: &str) -> Result<NewCapList, CapFileError> {
+        let cap_file: SymbolicCapFile = toml::from_str(source).unwrap();
+        let caps = cap_file.compile(&parent())?;
+        check_caps(&caps, &parent())?;
+        Ok(caps)
+    }
+
+    #[test]
+    fn symbolic_caps() {
+        let caps = compile(
+            r#"
+            [regions]
+            counter = { location = "0x3000000000000000000000000000000000000000000000000000000000000000", size = 256 }
+
+            [parents]
+            high = { type = "store_write", index = 1 }
+
+            [[caps]]
+            type = "procedure_call"
+            key = "counter"
+
+            [[caps]]
+            type = "store_write"
+            region = "counter"
+            parent = "high"
+
+            [[caps]]
+            type = "store_write"
+            location = 0
+            size = "0x100"
+            "#,
+        )
+        .unwrap();
+        let mut location = [0; 32];
+        location[0] = 0x30;
+        let mut size = [0; 32];
+        size[30] = 0x01;
+        assert_eq!(
+            caps,
+            NewCapList(vec![
+                NewCapability {
+                    cap: Capability::ProcedureCall(ProcedureCallCap {
+                        prefix: 192,
+                        key: string_to_proc_key(String::from("counter")),
+                    }),
+                    parent_index: 0,
+                },
+                NewCapability {
+                    cap: Capability::StoreWrite(StoreWriteCap { location, size }),
+                    parent_index: 1,
+                },
+                // The parent is found by searching the parent's caps.
+                NewCapability {
+                    cap: Capability::StoreWrite(StoreWriteCap {
+                        location: [0; 32],
+                        size,
+                    }),
+                    parent_index: 0,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn caps_outside_parent() {
+        // No parent covers this location.
+        match compile(
+            r#"
+            [[caps]]
+            type = "store_write"
+            location = "0x2000000000000000000000000000000000000000000000000000000000000000"
+            size = 1
+            "#,
+        ) {
+            Err(CapFileError::NoParent { index: 0, .. }) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+        // The labelled parent does not cover this location.
+        match compile(
+            r#"
+            [parents]
+            low = { type = "store_write", index = 0 }
+
+            [[caps]]
+            type = "store_write"
+            location = "0x3000000000000000000000000000000000000000000000000000000000000000"
+            size = 1
+            parent = "low"
+            "#,
+        ) {
+            Err(CapFileError::NotSubset { .. }) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_names() {
+        match compile("[[caps]]\ntype = \"store_write\"\nregion = \"nowhere\"\n") {
+            Err(CapFileError::UnknownRegion { .. }) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+        match compile("[[caps]]\ntype = \"procedure_call\"\nparent = \"nobody\"\n") {
+            Err(CapFileError::UnknownLabel { .. }) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn syscall_requirements() {
+        let caps = compile(
+            r#"
+            [[caps]]
+            type = "procedure_call"
+
+            [[caps]]
+            type = "store_write"
+            location = "0x00"
+            size = 256
+
+            [[caps]]
+            type = "store_write"
+            location = "0x100"
+            size = 256
+            "#,
+        )
+        .unwrap();
+        let requirement = |cap_type, cap_index| CapRequirement {
+            function_index: 6,
+            cap_type,
+            cap_index,
+        };
+        check_requirements(
+            &caps,
+            &[
+                requirement(Some(CAP_PROC_CALL), Some(0)),
+                requirement(Some(CAP_STORE_WRITE), Some(1)),
+                requirement(None, None),
+            ],
+        )
+        .unwrap();
+        // There is no log cap, and only two store write caps.
+        match check_requirements(
+            &caps,
+            &[
+                requirement(Some(CAP_LOG), Some(0)),
+                requirement(Some(CAP_STORE_WRITE), Some(2)),
+            ],
+        ) {
+            Err(CapFileError::Unsatisfied { requirements }) => assert_eq!(
+                requirements,
+                "function 6: cap type 8, cap index 0, function 6: cap type 7, cap index 2"
+            ),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
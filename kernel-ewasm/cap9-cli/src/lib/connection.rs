@@ -1,35 +1,161 @@
+use crate::local::{LocalTransport, DEV_ACCOUNT};
+use crate::network::{Account, NetworkError, NetworkProfile, NetworksFile};
+use crate::signer::SigningTransport;
 use pwasm_abi::types::*;
-use web3::types::Address;
+use std::path::{Path, PathBuf};
+use web3::futures::Future;
+use web3::rpc;
+use web3::types::{Address, H256};
 /// Module for handling the connection to a local Ethereum node.
-use web3::Transport;
+use web3::{RequestId, Transport};
 
 pub struct EthConn<T: Transport> {
     pub web3: web3::api::Web3<T>,
     pub sender: Address,
-    pub eloop: web3::transports::EventLoopHandle,
+    /// The event loop of the HTTP transport. The local chain has none.
+    pub eloop: Option<web3::transports::EventLoopHandle>,
+    /// The password the node needs to unlock the sender's account. There is
+    /// none when the account is already unlocked, or when transactions are
+    /// signed locally.
+    pub password: Option<String>,
 }
 
-impl<T: Transport> EthConn<T> {}
+impl<T: Transport> EthConn<T> {
+    /// Unlock the sender's account on the node, if it needs a password.
+    pub fn unlock_sender(&self) {
+        if let Some(password) = &self.password {
+            self.web3
+                .personal()
+                .unlock_account(self.sender, password, None)
+                .wait()
+                .unwrap();
+        }
+    }
+}
 
 impl EthConn<web3::transports::Http> {
     pub fn new_http() -> Self {
-        let (eloop, transport) =
-            web3::transports::Http::new("http://localhost:8545").expect("No network");
+        let url = NetworkProfile::dev().url;
+        let (eloop, transport) = http_transport(&url);
         // eloop.intos_remote();
         let web3 = web3::Web3::new(transport);
-        let sender = create_account(String::from("user"), String::from("user"));
+        let sender = create_account(&url, String::from("user"), String::from("user"));
         EthConn {
             web3,
             sender,
-            eloop,
+            eloop: Some(eloop),
+            password: Some(String::from("user")),
+        }
+    }
+}
+
+impl EthConn<LocalTransport> {
+    /// Connect to the local chain saved at `path`, creating it if it does not
+    /// exist.
+    pub fn new_local(path: PathBuf) -> Self {
+        let transport = LocalTransport::open(path).unwrap_or_else(|err| panic!("{}", err));
+        EthConn {
+            web3: web3::Web3::new(transport),
+            sender: Address::from(DEV_ACCOUNT),
+            eloop: None,
+            password: None,
         }
     }
 }
 
-fn create_account(name: String, password: String) -> Address {
+impl EthConn<Network> {
+    /// Connect to the local chain in `chain.json` in the current directory if
+    /// `local` is set, and otherwise to the node of the network profile
+    /// `network`, or of the default profile.
+    pub fn connect(local: bool, network: Option<&str>) -> Self {
+        if local {
+            let conn = EthConn::new_local(PathBuf::from("chain.json"));
+            EthConn {
+                web3: web3::Web3::new(Network::Local(conn.web3.transport().clone())),
+                sender: conn.sender,
+                eloop: None,
+                password: None,
+            }
+        } else {
+            let dir = PathBuf::from(".");
+            NetworksFile::read_dir(&dir)
+                .and_then(|networks| EthConn::with_profile(networks.profile(network)?, &dir))
+                .unwrap_or_else(|err| panic!("{}", err))
+        }
+    }
+
+    /// Connect to the node of a network profile. Paths in the profile are
+    /// relative to `dir`.
+    pub fn with_profile(profile: &NetworkProfile, dir: &Path) -> Result<Self, NetworkError> {
+        let account = profile.account(dir)?;
+        let (eloop, transport) = http_transport(&profile.url);
+        let (web3, sender, password) = match account {
+            Account::Dev => (
+                web3::Web3::new(Network::Http(transport)),
+                create_account(&profile.url, String::from("user"), String::from("user")),
+                Some(String::from("user")),
+            ),
+            Account::Node { address, password } => {
+                (web3::Web3::new(Network::Http(transport)), address, password)
+            }
+            Account::Local(signer) => (
+                web3::Web3::new(Network::Signed(SigningTransport::new(
+                    transport,
+                    signer.clone(),
+                ))),
+                signer.address(),
+                None,
+            ),
+        };
+        Ok(EthConn {
+            web3,
+            sender,
+            eloop: Some(eloop),
+            password,
+        })
+    }
+}
+
+/// A transport chosen at run time, either to a node over HTTP or to the local
+/// chain.
+#[derive(Clone, Debug)]
+pub enum Network {
+    Http(web3::transports::Http),
+    /// A node over HTTP, with transactions signed locally.
+    Signed(SigningTransport<web3::transports::Http>),
+    Local(LocalTransport),
+}
+
+impl Transport for Network {
+    type Out = Box<dyn Future<Item = rpc::Value, Error = web3::Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        match self {
+            Network::Http(transport) => transport.prepare(method, params),
+            Network::Signed(transport) => transport.prepare(method, params),
+            Network::Local(transport) => transport.prepare(method, params),
+        }
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        match self {
+            Network::Http(transport) => Box::new(transport.send(id, request)),
+            Network::Signed(transport) => transport.send(id, request),
+            Network::Local(transport) => Box::new(transport.send(id, request)),
+        }
+    }
+}
+
+fn http_transport(url: &str) -> (web3::transports::EventLoopHandle, web3::transports::Http) {
+    web3::transports::Http::new(url).expect("No network")
+}
+
+/// Create an account from a phrase, using the parity specific
+/// `parity_newAccountFromPhrase` method.
+fn create_account(url: &str, name: String, password: String) -> Address {
     let name_json = serde_json::to_value(name).unwrap();
     let password_json = serde_json::to_value(password).unwrap();
-    let client = jsonrpc::client::Client::new(String::from("http://localhost:8545"), None, None);
+    let client = jsonrpc::client::Client::new(url.to_string(), None, None);
     let params = &[name_json, password_json];
     let request = client.build_request("parity_newAccountFromPhrase", params);
     match client
@@ -41,19 +167,37 @@ fn create_account(name: String, password: String) -> Address {
     }
 }
 
-pub fn list_storage_keys(address: Address) -> jsonrpc::Response {
-    let address_json = serde_json::to_value(address).unwrap();
-    println!("address_json: {:?}", address_json);
-    let client = jsonrpc::client::Client::new(String::from("http://localhost:8545"), None, None);
-    let params = &[
-        address_json,
-        serde_json::to_value(1000).unwrap(),
-        serde_json::Value::Null,
-    ];
-    let request = client.build_request("parity_listStorageKeys", params);
-    println!("request: {:?}", request);
-    match client.send_request(&request) {
-        Ok(x) => x,
-        Err(e) => panic!("{:?}", e),
+/// List the storage keys of a contract which have been set, using the parity
+/// specific `parity_listStorageKeys` method. The keys are requested in pages,
+/// each starting after the last key of the previous page.
+pub fn list_storage_keys<T: Transport>(
+    conn: &EthConn<T>,
+    address: Address,
+) -> Result<Vec<H256>, web3::Error> {
+    const PAGE_SIZE: usize = 1000;
+    let mut keys: Vec<H256> = Vec::new();
+    loop {
+        let params = vec![
+            serde_json::to_value(address).unwrap(),
+            serde_json::to_value(PAGE_SIZE).unwrap(),
+            match keys.last() {
+                Some(key) => serde_json::to_value(key).unwrap(),
+                None => serde_json::Value::Null,
+            },
+        ];
+        let page = conn
+            .web3
+            .transport()
+            .execute("parity_listStorageKeys", params)
+            .wait()?;
+        // Parity returns null rather than an empty list.
+        let page: Option<Vec<H256>> = serde_json::from_value(page)
+            .map_err(|err| web3::Error::InvalidResponse(err.to_string()))?;
+        let page = page.unwrap_or_default();
+        let page_len = page.len();
+        keys.extend(page);
+        if page_len < PAGE_SIZE {
+            return Ok(keys);
+        }
     }
 }
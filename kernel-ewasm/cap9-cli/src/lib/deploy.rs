@@ -36,11 +36,7 @@ pub fn deploy_contract<T: Transport, P: Tokenize>(
     let mem_module = build::set_mem(module, mem_pages);
     let new_module = build::wasm_build(&ArgMatches::default(), mem_module);
     let code = parity_wasm::serialize(new_module).unwrap();
-    conn.web3
-        .personal()
-        .unlock_account(conn.sender, "user", None)
-        .wait()
-        .unwrap();
+    conn.unlock_sender();
     let code_hex: String = code.to_hex();
     Contract::deploy(conn.web3.eth(), interface)
         .map_err(|err| ContractDeploymentError::ConstructionFailure {
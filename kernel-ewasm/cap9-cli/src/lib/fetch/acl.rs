@@ -1,15 +1,19 @@
+use crate::caps::read_cap_file;
 use crate::utils::{from_common_address, from_common_u256};
 use cap9_std::proc_table::cap::*;
 use pwasm_abi;
+use rustc_hex::ToHex;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use web3::types::{Address, H256, U256};
 use std::fs::File;
+use tiny_keccak::keccak256;
 use web3::contract::tokens::Tokenize;
 use web3::contract::Options;
 use web3::futures::Future;
 use web3::Transport;
 
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use super::kernel::*;
 use super::map::*;
@@ -80,23 +84,10 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
     ) -> Result<(), ProjectDeploymentError> {
         let proc_key = crate::utils::string_to_proc_key(proc_name);
         let cap_index = 0;
+        let caps = self.read_caps(&group_proc.cap_path)?;
         // Deploy the contract to the blockchain.
         let contract = group_proc.deploy(&self.kernel.conn, ()).unwrap();
 
-        let cap_file = File::open(group_proc.cap_path).expect("could not open file");
-        let crate::fetch::procedure::SerialNewCapList(caps) =
-            serde_json::from_reader(cap_file).unwrap();
-
-        let existing_caps: Capabilities = self
-            .kernel
-            .procedure(self.admin_proc_key().expect("no admin key"))
-            .expect("no admin proc")
-            .caps
-            .into();
-        let cap_test = caps.check_subset_of(existing_caps);
-        if cap_test.len() != 0 {
-            panic!("invalid caps: {:?}", cap_test);
-        }
         let cap_list: Vec<U256> = caps
             .to_u256_list()
             .into_iter()
@@ -229,6 +220,22 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
         self.group_key(1_u8)
     }
 
+    /// The caps of the admin procedure, which registers procedures for the
+    /// ACL.
+    pub fn admin_caps(&self) -> Capabilities {
+        self.kernel
+            .procedure(self.admin_proc_key().expect("no admin key"))
+            .expect("no admin proc")
+            .caps
+            .into()
+    }
+
+    /// Read a cap file, checking that the admin procedure can grant its caps.
+    fn read_caps(&self, cap_path: &Path) -> Result<NewCapList, ProjectDeploymentError> {
+        read_cap_file(cap_path, &self.admin_caps())
+            .map_err(|err| ProjectDeploymentError::InvalidCaps { err })
+    }
+
     // pub fn group(&self, index: u8) -> Option<Group> {
     //     // Currently we assume the group map is at cap index 1
     //     let groups: LocalEnumerableMap<_, u8, cap9_std::SysCallProcedureKey> = LocalEnumerableMap::from(&self.kernel, 1).expect("could not create group map");
@@ -243,22 +250,11 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
     ) -> Result<(), ProjectDeploymentError> {
         let proc_key = crate::utils::string_to_proc_key(proc_name);
 
-        let cap_file = File::open(&proc_spec.cap_path).expect("could not open file");
-        let crate::fetch::procedure::SerialNewCapList(caps) =
-            serde_json::from_reader(cap_file).unwrap();
+        // Check the caps before anything is sent to the chain.
+        let caps = self.read_caps(&proc_spec.cap_path)?;
 
         let cap_index = 0;
         let contract = proc_spec.deploy(&self.kernel.conn, ()).unwrap();
-        let existing_caps: Capabilities = self
-            .kernel
-            .procedure(self.admin_proc_key().expect("no admin key"))
-            .expect("no admin proc")
-            .caps
-            .into();
-        let cap_test = caps.check_subset_of(existing_caps);
-        if cap_test.len() != 0 {
-            panic!("invalid caps: {:?}", cap_test);
-        }
         let cap_list: Vec<U256> = caps
             .to_u256_list()
             .into_iter()
@@ -320,6 +316,10 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
             .status_file_mut()
             .as_mut()
             .unwrap();
+        status_file.add_code_hash(
+            contract.address(),
+            H256::from(keccak256(&proc_spec.contract_spec.code())),
+        );
         status_file.add_abi(
             contract.address(),
             PathBuf::from(proc_spec.contract_spec.abi_path),
@@ -346,13 +346,7 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
         let encoded_proc_key: U256 = crate::utils::proc_key_to_32_bytes(&proc_key).into();
 
         let params = (cap_index, encoded_proc_key);
-        self.kernel
-            .conn
-            .web3
-            .personal()
-            .unlock_account(self.kernel.conn.sender, "user", None)
-            .wait()
-            .unwrap();
+        self.kernel.conn.unlock_sender();
         // Register the procedure
         let file: &[u8] = default_procedures::ACL_ADMIN.abi();
         let admin_abi = ethabi::Contract::load(file).expect("no ABI");
@@ -426,13 +420,7 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
             .function(function_name)
             .and_then(|function| function.encode_input(params))
             .expect("message encoding failed");
-        self.kernel
-            .conn
-            .web3
-            .personal()
-            .unlock_account(self.kernel.conn.sender, "user", None)
-            .wait()
-            .unwrap();
+        self.kernel.conn.unlock_sender();
         let res = proxied_entry_contract
             .call(
                 "proxy",
@@ -465,7 +453,7 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
         function_name: &str,
         params: &[ethabi::Token],
     ) -> web3::types::TransactionReceipt {
-        println!("Using Call-Any");
+        log::debug!("Using Call-Any");
         let file: &[u8] = default_procedures::ACL_ADMIN.abi();
         let admin_abi = ethabi::Contract::load(file).expect("no ABI");
         let proxied_entry_contract = web3::contract::Contract::from_json(
@@ -506,13 +494,7 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
             .function("call_any")
             .and_then(|function| function.encode_input(admin_params.as_slice()))
             .expect("message encoding failed");
-        self.kernel
-            .conn
-            .web3
-            .personal()
-            .unlock_account(self.kernel.conn.sender, "user", None)
-            .wait()
-            .unwrap();
+        self.kernel.conn.unlock_sender();
         let res = proxied_entry_contract
             .call(
                 "proxy",
@@ -545,7 +527,7 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
         function_name: &str,
         params: &[ethabi::Token],
     ) -> ethabi::Result<Vec<ethabi::Token>> {
-        println!("Using Query-Any");
+        log::debug!("Using Query-Any");
         let file: &[u8] = default_procedures::ACL_ADMIN.abi();
         let admin_abi = ethabi::Contract::load(file).expect("no ABI");
         let proxied_entry_contract = web3::contract::Contract::from_json(
@@ -584,13 +566,7 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
             .function("call_any")
             .and_then(|function| function.encode_input(admin_params.as_slice()))
             .expect("message encoding failed");
-        self.kernel
-            .conn
-            .web3
-            .personal()
-            .unlock_account(self.kernel.conn.sender, "user", None)
-            .wait()
-            .unwrap();
+        self.kernel.conn.unlock_sender();
         let res = proxied_entry_contract
             .query_tokens(
                 "proxy",
@@ -627,13 +603,7 @@ impl<'a, T: Transport> DeployedKernelWithACL<'a, T> {
             .function(function_name)
             .and_then(|function| function.encode_input(params))
             .expect("message encoding failed");
-        self.kernel
-            .conn
-            .web3
-            .personal()
-            .unlock_account(self.kernel.conn.sender, "user", None)
-            .wait()
-            .unwrap();
+        self.kernel.conn.unlock_sender();
         let res: ethabi::Result<Vec<ethabi::Token>> = proxied_entry_contract
             .query_tokens(
                 "proxy",
@@ -657,3 +627,24 @@ pub struct Group {
     pub procedure_key: cap9_std::SysCallProcedureKey,
     pub users: HashSet<Address>,
 }
+
+impl Serialize for Group {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let key = &self.procedure_key.0;
+        let key_hex: String = key.to_hex();
+        let mut users: Vec<&Address> = self.users.iter().collect();
+        users.sort();
+        let mut state = serializer.serialize_struct("Group", 4)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("procedure_key", &format!("0x{}", key_hex))?;
+        state.serialize_field(
+            "procedure_name",
+            String::from_utf8_lossy(key).trim_end_matches('\0'),
+        )?;
+        state.serialize_field("users", &users)?;
+        state.end()
+    }
+}
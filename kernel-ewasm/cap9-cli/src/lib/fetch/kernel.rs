@@ -1,11 +1,14 @@
 use web3::futures::Future;
-use web3::types::{Address, H256, U256, Log};
+use web3::types::{Address, BlockNumber, H256, U256, Log};
 // use web3::types::TransactionReceipt;
 use rustc_hex::ToHex;
 use web3::Transport;
 // use ethabi::Token::Uint;
+use super::{EventDecoder, SerialCapability, StorageEntry};
+use crate::connection;
 use crate::connection::EthConn;
 use crate::constants;
+use crate::default_procedures;
 use crate::project::LocalProject;
 use crate::utils::to_common_h256;
 use cap9_core::Error;
@@ -15,7 +18,10 @@ use cap9_std::proc_table;
 use cap9_std::proc_table::cap::*;
 use cap9_std::proc_table::ProcPointer;
 use pwasm_abi;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::fmt;
+use std::fs::File;
+use std::time::Duration;
 /// A representation and connection to a deployed kernel. This has both a
 /// connection to the node and a filesystem representation.
 pub struct DeployedKernel<'a, T: Transport> {
@@ -172,15 +178,86 @@ impl<'a, T: Transport> DeployedKernel<'a, T> {
         procs
     }
 
+    /// List every storage key of the kernel with its value, classified by the
+    /// layout of the kernel and the caps of the registered procedures.
+    pub fn storage(&self) -> Result<Vec<StorageEntry>, web3::Error> {
+        let procs = self.procedures();
+        let mut keys = connection::list_storage_keys(self.conn, self.address)?;
+        keys.sort();
+        Ok(keys
+            .into_iter()
+            .map(|key| {
+                let value = self.get_storage(U256::from_big_endian(key.as_bytes()));
+                StorageEntry::new(key, value, &procs)
+            })
+            .collect())
+    }
+
     pub fn all_logs(&self) -> Vec<Log> {
+        self.logs(BlockNumber::Number(0_u64), BlockNumber::Latest)
+    }
+
+    /// The logs of the kernel between two blocks, inclusive.
+    pub fn logs(&self, from_block: BlockNumber, to_block: BlockNumber) -> Vec<Log> {
         let filter = web3::types::FilterBuilder::default()
-            .from_block(web3::types::BlockNumber::Number(0_u64))
-            .to_block(web3::types::BlockNumber::Latest)
+            .from_block(from_block)
+            .to_block(to_block)
             .address(vec!{self.address()})
             .build();
         self.conn.web3.eth().logs(filter).wait().unwrap()
     }
 
+    /// Call `f` on each log of the kernel from `from_block` onwards, as the
+    /// blocks are mined. The node is polled every `interval`. This does not
+    /// return.
+    pub fn follow_logs<F: FnMut(Log)>(&self, from_block: u64, interval: Duration, mut f: F) -> ! {
+        let mut next_block = from_block;
+        loop {
+            let latest = self
+                .conn
+                .web3
+                .eth()
+                .block_number()
+                .wait()
+                .expect("could not retrieve block number")
+                .as_u64();
+            if latest >= next_block {
+                for log in self.logs(BlockNumber::Number(next_block), BlockNumber::Number(latest)) {
+                    f(log);
+                }
+                next_block = latest + 1;
+            }
+            std::thread::sleep(interval);
+        }
+    }
+
+    /// A decoder for the events of the kernel and of the procedures whose
+    /// ABIs are recorded in the status file. Procedures are named by their
+    /// keys.
+    pub fn event_decoder(&self) -> EventDecoder {
+        let mut decoder = EventDecoder::new();
+        let kernel_abi = ethabi::Contract::load(default_procedures::KERNEL.abi())
+            .expect("invalid kernel ABI");
+        decoder.add_abi("kernel", &kernel_abi);
+        if let Some(status_file) = self.local_project.status_file() {
+            for procedure in self.procedures() {
+                let path = match status_file.abis.get(&procedure.address) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let name = String::from_utf8_lossy(&procedure.key);
+                match File::open(path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|file| ethabi::Contract::load(file).map_err(|err| err.to_string()))
+                {
+                    Ok(abi) => decoder.add_abi(name.trim_end_matches('\0'), &abi),
+                    Err(err) => log::warn!("could not read ABI {}: {}", path.display(), err),
+                }
+            }
+        }
+        decoder
+    }
+
     /// Retrieve a specific procedure.
     /// TODO: this is currently inefficient as it retrieves all procs first.
     pub fn procedure(&self, proc_key: cap9_std::SysCallProcedureKey) -> Option<Procedure> {
@@ -569,6 +646,64 @@ impl fmt::Display for Procedure {
     }
 }
 
+impl Serialize for Procedure {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let key_hex: String = self.key.to_hex();
+        let caps = &self.caps;
+        let cap_list: Vec<SerialCapability> = caps
+            .proc_call_caps
+            .iter()
+            .cloned()
+            .map(Capability::ProcedureCall)
+            .chain(
+                caps.proc_register_caps
+                    .iter()
+                    .cloned()
+                    .map(Capability::ProcedureRegister),
+            )
+            .chain(
+                caps.proc_delete_caps
+                    .iter()
+                    .cloned()
+                    .map(Capability::ProcedureDelete),
+            )
+            .chain(
+                caps.proc_entry_caps
+                    .iter()
+                    .cloned()
+                    .map(Capability::ProcedureEntry),
+            )
+            .chain(
+                caps.store_write_caps
+                    .iter()
+                    .cloned()
+                    .map(Capability::StoreWrite),
+            )
+            .chain(caps.log_caps.iter().cloned().map(Capability::Log))
+            .chain(
+                caps.account_call_caps
+                    .iter()
+                    .cloned()
+                    .map(Capability::AccountCall),
+            )
+            .map(SerialCapability)
+            .collect();
+        let mut state = serializer.serialize_struct("Procedure", 5)?;
+        state.serialize_field("index", &self.index.as_u64())?;
+        state.serialize_field("key", &format!("0x{}", key_hex))?;
+        state.serialize_field(
+            "name",
+            String::from_utf8_lossy(&self.key).trim_end_matches('\0'),
+        )?;
+        state.serialize_field("address", &self.address)?;
+        state.serialize_field("caps", &cap_list)?;
+        state.end()
+    }
+}
+
 fn get_idx_proc_address(i: u64) -> U256 {
     let idx: u8 = i as u8;
     U256::from_big_endian(&[
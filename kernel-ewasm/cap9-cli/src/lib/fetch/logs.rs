@@ -0,0 +1,265 @@
+//! Decode the logs of a kernel with the ABIs of the kernel and its
+//! procedures.
+//!
+//! A log is matched to an event by its first topic, which is the hash of the
+//! event's signature. The kernel does not record which procedure made a log,
+//! so a log is attributed to every procedure whose ABI has a matching event.
+use crate::output::token_json;
+use rustc_hex::ToHex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use web3::types::Log;
+
+/// The events of a set of ABIs, by their signature hashes.
+#[derive(Clone, Debug, Default)]
+pub struct EventDecoder {
+    events: HashMap<[u8; 32], Vec<(String, ethabi::Event)>>,
+}
+
+impl EventDecoder {
+    pub fn new() -> Self {
+        EventDecoder::default()
+    }
+
+    /// Add the events of `abi`, which are attributed to `source`. Anonymous
+    /// events have no signature topic and cannot be matched, so they are
+    /// ignored.
+    pub fn add_abi(&mut self, source: &str, abi: &ethabi::Contract) {
+        for event in abi.events().filter(|event| !event.anonymous) {
+            self.events
+                .entry(event.signature().0)
+                .or_default()
+                .push((source.to_string(), event.clone()));
+        }
+    }
+
+    /// Decode a log. If no event matches, the log is left undecoded.
+    pub fn decode(&self, log: Log) -> DecodedLog {
+        let event = log
+            .topics
+            .first()
+            .and_then(|topic| self.events.get(&topic.to_fixed_bytes()))
+            .and_then(|candidates| {
+                let mut decoded: Option<DecodedEvent> = None;
+                for (source, event) in candidates {
+                    let raw_log = ethabi::RawLog {
+                        topics: log
+                            .topics
+                            .iter()
+                            .map(|topic| ethabi::Hash::from_slice(&topic.to_fixed_bytes()))
+                            .collect(),
+                        data: log.data.0.clone(),
+                    };
+                    if let Ok(parsed) = event.parse_log(raw_log) {
+                        match decoded {
+                            // The same event in another ABI.
+                            Some(ref mut decoded) => decoded.sources.push(source.clone()),
+                            None => {
+                                decoded = Some(DecodedEvent {
+                                    sources: vec![source.clone()],
+                                    name: event.name.clone(),
+                                    params: parsed
+                                        .params
+                                        .into_iter()
+                                        .map(|param| (param.name, param.value))
+                                        .collect(),
+                                })
+                            }
+                        }
+                    }
+                }
+                decoded
+            });
+        DecodedLog { log, event }
+    }
+}
+
+/// A log of the kernel, and the event it was decoded as, if any.
+#[derive(Clone, Debug)]
+pub struct DecodedLog {
+    pub log: Log,
+    pub event: Option<DecodedEvent>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedEvent {
+    /// The names of the ABIs which have the event.
+    pub sources: Vec<String>,
+    pub name: String,
+    pub params: Vec<(String, ethabi::Token)>,
+}
+
+impl fmt::Display for DecodedLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(block_number) = self.log.block_number {
+            write!(f, "block {}: ", block_number)?;
+        }
+        match &self.event {
+            Some(event) => write!(f, "{}", event),
+            None => {
+                let hex_s: String = self.log.data.0.to_hex();
+                write!(f, "0x{}", hex_s)?;
+                if let Ok(s) = std::str::from_utf8(&self.log.data.0) {
+                    write!(f, " => {}", s)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for DecodedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, (name, value)) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match token_json(value) {
+                Value::String(s) => write!(f, "{}: {}", name, s)?,
+                value => write!(f, "{}: {}", name, value)?,
+            }
+        }
+        write!(f, ") [{}]", self.sources.join(", "))
+    }
+}
+
+/// Which logs to show. Logs which were not decoded only pass a filter with
+/// no procedure or event.
+#[derive(Clone, Debug, Default)]
+pub struct LogFilter {
+    /// Only logs with an event in the ABI of this procedure.
+    pub procedure: Option<String>,
+    /// Only logs of events with this name.
+    pub event: Option<String>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, log: &DecodedLog) -> bool {
+        match &log.event {
+            Some(event) => {
+                let procedure_matches = match &self.procedure {
+                    Some(procedure) => event.sources.contains(procedure),
+                    None => true,
+                };
+                let event_matches = match &self.event {
+                    Some(name) => &event.name == name,
+                    None => true,
+                };
+                procedure_matches && event_matches
+            }
+            None => self.procedure.is_none() && self.event.is_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ABI: &str = r#"[
+        {
+            "type": "event",
+            "name": "Transfer",
+            "inputs": [
+                { "name": "to", "type": "address", "indexed": true },
+                { "name": "value", "type": "uint256", "indexed": false }
+            ],
+            "anonymous": false
+        },
+        {
+            "type": "event",
+            "name": "Ping",
+            "inputs": [],
+            "anonymous": false
+        }
+    ]"#;
+
+    fn decoder() -> EventDecoder {
+        let abi = ethabi::Contract::load(ABI.as_bytes()).unwrap();
+        let mut decoder = EventDecoder::new();
+        decoder.add_abi("token", &abi);
+        decoder
+    }
+
+    fn log(topics: Vec<[u8; 32]>, data: &[u8]) -> Log {
+        let topics: Vec<String> = topics
+            .iter()
+            .map(|topic| {
+                let topic: String = topic.to_hex();
+                format!("0x{}", topic)
+            })
+            .collect();
+        let data: String = data.to_hex();
+        serde_json::from_value(json!({
+            "address": "0x0000000000000000000000000000000000000000",
+            "topics": topics,
+            "data": format!("0x{}", data),
+        }))
+        .unwrap()
+    }
+
+    fn transfer_log() -> Log {
+        let abi = ethabi::Contract::load(ABI.as_bytes()).unwrap();
+        let mut to = [0; 32];
+        to[31] = 0xaa;
+        log(
+            vec![abi.event("Transfer").unwrap().signature().0, to],
+            &ethabi::encode(&[ethabi::Token::Uint(42.into())]),
+        )
+    }
+
+    #[test]
+    fn decode_events() {
+        let mut decoder = decoder();
+        let decoded = decoder.decode(transfer_log());
+        let event = decoded.event.expect("not decoded");
+        assert_eq!(event.name, "Transfer");
+        assert_eq!(event.sources, vec![String::from("token")]);
+        assert_eq!(event.params[0].0, "to");
+        assert_eq!(
+            event.params[1],
+            (String::from("value"), ethabi::Token::Uint(42.into()))
+        );
+        assert_eq!(
+            event.to_string(),
+            "Transfer(to: 0x00000000000000000000000000000000000000aa, value: 42) [token]"
+        );
+
+        // The same event in another ABI.
+        let abi = ethabi::Contract::load(ABI.as_bytes()).unwrap();
+        decoder.add_abi("other", &abi);
+        let event = decoder.decode(transfer_log()).event.unwrap();
+        assert_eq!(
+            event.sources,
+            vec![String::from("token"), String::from("other")]
+        );
+
+        // A log with no topics is left as it is.
+        let decoded = decoder.decode(log(Vec::new(), b"Hello World!"));
+        assert!(decoded.event.is_none());
+        assert_eq!(
+            decoded.to_string(),
+            "0x48656c6c6f20576f726c6421 => Hello World!"
+        );
+    }
+
+    #[test]
+    fn filter_logs() {
+        let decoder = decoder();
+        let transfer = decoder.decode(transfer_log());
+        let raw = decoder.decode(log(Vec::new(), &[]));
+        let filter = |procedure: Option<&str>, event: Option<&str>| LogFilter {
+            procedure: procedure.map(String::from),
+            event: event.map(String::from),
+        };
+        assert!(filter(None, None).matches(&transfer));
+        assert!(filter(None, None).matches(&raw));
+        assert!(filter(Some("token"), Some("Transfer")).matches(&transfer));
+        assert!(!filter(Some("token"), None).matches(&raw));
+        assert!(!filter(Some("other"), None).matches(&transfer));
+        assert!(!filter(None, Some("Ping")).matches(&transfer));
+    }
+}
@@ -4,6 +4,7 @@ use crate::utils::{to_common_h256, to_common_u256};
 use cap9_core::*;
 use cap9_std::data::{DataStructureError, Keyable, Storable};
 use cap9_std::proc_table;
+use cap9_std::StorageEnumerableMap;
 use core::marker::PhantomData;
 use web3::types::{H256, U256};
 use web3::Transport;
@@ -27,10 +28,13 @@ impl<'a, T: Transport, K: Keyable, V: Storable> LocalEnumerableMap<'a, T, K, V>
         kernel: &'a DeployedKernel<'a, T>,
         cap_index: u8,
     ) -> Result<Self, DataStructureError> {
-        // The size of the cap needs to be key_width+1 in bytes
-        let address_bytes = K::key_width() + 1;
-        let address_bits = address_bytes * 8;
-        let address_size = U256::from(2).pow(U256::from(address_bits));
+        // The map covers every storage key with the key, presence,
+        // enumeration, and data bits.
+        let map_bits = StorageEnumerableMap::<K, V>::map_bits();
+        if map_bits >= 256 {
+            return Err(DataStructureError::TooSmall);
+        }
+        let map_size = U256::from(1) << map_bits as usize;
         // The address also need to be aligned.
 
         // The cap_index is an index into the caplist of the entry procedure
@@ -42,11 +46,12 @@ impl<'a, T: Transport, K: Keyable, V: Storable> LocalEnumerableMap<'a, T, K, V>
             size,
         })) = kernel.get_proc_cap(this_proc_key, proc_table::cap::CAP_STORE_WRITE, cap_index)
         {
-            // Check that the size of the cap is correct.
-            if U256::from(size) < address_size {
+            // Check that the size of the cap is correct. The cap covers
+            // size+1 keys.
+            if U256::from(size) < map_size - U256::from(1) {
                 Err(DataStructureError::TooSmall)
-            } else if U256::from(location).trailing_zeros() < (address_bits as u32 + 1 + 1 + 6) {
-                // the trailing number of 0 bits should be equal to or greater than the address_bits
+            } else if U256::from(location).trailing_zeros() < map_bits {
+                // the trailing number of 0 bits should be equal to or greater than the map_bits
                 Err(DataStructureError::MisAligned)
             } else {
                 Ok(LocalEnumerableMap {
@@ -68,38 +73,33 @@ impl<'a, T: Transport, K: Keyable, V: Storable> LocalEnumerableMap<'a, T, K, V>
         self.location
     }
 
+    /// Return the storage key at the given key bits and flags. The flags are
+    /// the presence and enumeration bits. This is the same layout as
+    /// [`StorageEnumerableMap`].
+    fn flagged_key(&self, key_bits: U256, flags: u8) -> U256 {
+        let data_bits = StorageEnumerableMap::<K, V>::data_bits() as usize;
+        h256_to_u256(self.location)
+            | (key_bits << (data_bits + 2))
+            | (U256::from(flags) << data_bits)
+    }
+
     /// Return the base storage key of a given map key.
     fn base_key(&self, key: &K) -> [u8; 32] {
-        let mut base: [u8; 32] = [0; 32];
-        // The key start 32 - width - 1, the - 1 is for data and presence. This
-        // is in bytes.
-        let key_start = 32 - K::key_width() as usize - 1;
-        // First we copy in the relevant parts of the location.
-        base[0..key_start].copy_from_slice(&self.location().as_bytes()[0..key_start]);
-        // Then we copy in the key
-        // TODO: overflow
-        base[key_start..(key_start + K::key_width() as usize)]
-            .clone_from_slice(key.key_slice().as_slice());
-        base
+        let key_bits = U256::from_big_endian(key.key_slice().as_slice());
+        u256_to_h256(self.flagged_key(key_bits, 0b00)).to_fixed_bytes()
     }
 
     fn presence_key(&self, key: &K) -> H256 {
         // The presence_key is the storage key which indicates whether there is
         // a value associated with this key.
-        let mut presence_key = self.base_key(&key);
-        // The first bit of the data byte indicates presence
-        presence_key[31] = presence_key[31] | 0b10000000;
-        presence_key.into()
+        let key_bits = U256::from_big_endian(key.key_slice().as_slice());
+        u256_to_h256(self.flagged_key(key_bits, 0b10))
     }
 
     fn length_key(&self) -> H256 {
-        // The presence_key is the storage key which indicates whether there is
-        // a value associated with this key.
-        let mut location = self.location.clone();
-        let length_key = location.as_fixed_bytes_mut();
-        let index = 31;
-        length_key[index as usize] = length_key[index as usize] | 0b01000000;
-        length_key.into()
+        // The length of the map is stored with both the presence and
+        // enumeration bits set.
+        u256_to_h256(self.flagged_key(U256::zero(), 0b11))
     }
 
     /// Return the number of elements in the map.
@@ -179,8 +179,9 @@ impl<'a, T: Transport, K: Keyable, V: Storable> LocalEnumerableMap<'a, T, K, V>
         if index >= self.length() {
             return None;
         }
-        let storage_key_h = self.length_key().clone();
-        let storage_key: U256 = h256_to_u256(storage_key_h) + index + U256::from(1);
+        // The element at index i of the enumeration vector is stored at key bits
+        // i with the enumeration bit set.
+        let storage_key: U256 = self.flagged_key(index, 0b01);
         let mut store_buf: [u8; 32] = [0; 32];
         storage_key.to_big_endian(&mut store_buf);
         let storage_value: StorageValue =
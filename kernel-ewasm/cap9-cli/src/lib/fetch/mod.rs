@@ -5,11 +5,17 @@ pub use acl::*;
 mod kernel;
 pub use kernel::*;
 
+mod logs;
+pub use logs::*;
+
 mod map;
 pub use map::*;
 
 mod procedure;
 pub use procedure::*;
 
+mod storage;
+pub use storage::*;
+
 mod utils;
 pub use utils::*;
@@ -166,7 +166,7 @@ pub struct SerialNewCapList(pub NewCapList);
 #[derive(Clone, Debug)]
 pub struct SerialNewCap(NewCapability);
 #[derive(Clone, Debug)]
-pub struct SerialCapability(Capability);
+pub struct SerialCapability(pub Capability);
 #[derive(Clone, Debug)]
 pub struct SerialAddress(Address);
 
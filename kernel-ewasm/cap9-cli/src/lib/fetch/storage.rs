@@ -0,0 +1,518 @@
+//! Classify the storage of a kernel.
+//!
+//! Storage keys beginning with `0xffffffff` are reserved by the kernel for the
+//! procedure heap (`0xffffffff00`), the procedure list (`0xffffffff01`), the
+//! kernel address (`0xffffffff02`) and the current (`0xffffffff03`) and entry
+//! (`0xffffffff04`) procedures. Within the heap, each procedure has an address
+//! slot, an index slot, and a length and value slots for each cap type; see
+//! [cap9_std::proc_table::ProcPointer]. Any other key should be covered by the
+//! StoreWrite caps of some procedure, otherwise nothing can have written it.
+use super::kernel::Procedure;
+use super::SerialCapability;
+use cap9_std::proc_table::cap::*;
+use rustc_hex::ToHex;
+use serde::ser::{Serialize, Serializer};
+use serde_json::json;
+use std::fmt;
+use web3::types::{H256, U256};
+
+/// What a storage key of the kernel is used for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageKind {
+    /// The number of procedures in the procedure list.
+    ProcListLength,
+    /// The key of the procedure at an index of the procedure list.
+    ProcListEntry { index: u64 },
+    /// The address of the kernel.
+    KernelAddress,
+    /// The key of the currently executing procedure.
+    CurrentProc,
+    /// The key of the entry procedure.
+    EntryProc,
+    /// The address of a procedure.
+    ProcAddress { key: [u8; 24] },
+    /// The index of a procedure in the procedure list.
+    ProcIndex { key: [u8; 24] },
+    /// The number of caps of a type a procedure has.
+    CapListLength { key: [u8; 24], cap_type: u8 },
+    /// A value of a cap of a procedure. The cap is only given for the first
+    /// value.
+    CapValue {
+        key: [u8; 24],
+        cap_type: u8,
+        cap_index: u8,
+        val_index: u8,
+        cap: Option<Capability>,
+    },
+    /// Storage which procedures can write to. The procedures and the indices
+    /// of their StoreWrite caps which cover the key are listed.
+    ProcStorage { writers: Vec<([u8; 24], u8)> },
+    /// A key which no capability accounts for. This includes kernel storage
+    /// of procedures which are not registered and of caps they do not have.
+    Unaccounted,
+}
+
+/// A storage key of a kernel, with its value.
+#[derive(Clone, Debug)]
+pub struct StorageEntry {
+    pub key: H256,
+    pub value: H256,
+    pub kind: StorageKind,
+}
+
+impl StorageEntry {
+    pub fn new(key: H256, value: H256, procs: &[Procedure]) -> Self {
+        StorageEntry {
+            key,
+            value,
+            kind: classify(key.as_fixed_bytes(), procs),
+        }
+    }
+}
+
+impl fmt::Display for StorageEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {:?}\n  ", self.key, self.value)?;
+        match &self.kind {
+            StorageKind::ProcListLength => write!(f, "Procedure list length"),
+            StorageKind::ProcListEntry { index } => {
+                write!(
+                    f,
+                    "Procedure list entry {}: {}",
+                    index,
+                    proc_name(&self.value.as_bytes()[8..])
+                )
+            }
+            StorageKind::KernelAddress => write!(f, "Kernel address"),
+            StorageKind::CurrentProc => {
+                write!(
+                    f,
+                    "Current procedure: {}",
+                    proc_name(&self.value.as_bytes()[8..])
+                )
+            }
+            StorageKind::EntryProc => write!(
+                f,
+                "Entry procedure: {}",
+                proc_name(&self.value.as_bytes()[8..])
+            ),
+            StorageKind::ProcAddress { key } => {
+                write!(f, "Address of procedure {}", proc_name(key))
+            }
+            StorageKind::ProcIndex { key } => write!(f, "Index of procedure {}", proc_name(key)),
+            StorageKind::CapListLength { key, cap_type } => write!(
+                f,
+                "Number of {} caps of procedure {}",
+                cap_type_name(*cap_type),
+                proc_name(key)
+            ),
+            StorageKind::CapValue {
+                key,
+                cap_type,
+                cap_index,
+                val_index,
+                cap,
+            } => {
+                write!(
+                    f,
+                    "Value {} of {} cap {} of procedure {}",
+                    val_index,
+                    cap_type_name(*cap_type),
+                    cap_index,
+                    proc_name(key)
+                )?;
+                if let Some(cap) = cap {
+                    write!(f, "\n  {}", cap)?;
+                }
+                Ok(())
+            }
+            StorageKind::ProcStorage { writers } => {
+                write!(f, "Writable by")?;
+                for (key, cap_index) in writers {
+                    write!(f, " {} (CAP_STORE_WRITE cap {})", proc_name(key), cap_index)?;
+                }
+                Ok(())
+            }
+            StorageKind::Unaccounted => write!(f, "UNACCOUNTED: no capability covers this key"),
+        }
+    }
+}
+
+impl Serialize for StorageEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let key_hex = |key: &[u8]| -> String {
+            let key_hex: String = key.to_hex();
+            format!("0x{}", key_hex)
+        };
+        let mut entry = json!({
+            "key": self.key,
+            "value": self.value,
+        });
+        let kind = match &self.kind {
+            StorageKind::ProcListLength => json!({ "kind": "proc_list_length" }),
+            StorageKind::ProcListEntry { index } => {
+                json!({ "kind": "proc_list_entry", "index": index })
+            }
+            StorageKind::KernelAddress => json!({ "kind": "kernel_address" }),
+            StorageKind::CurrentProc => json!({ "kind": "current_proc" }),
+            StorageKind::EntryProc => json!({ "kind": "entry_proc" }),
+            StorageKind::ProcAddress { key } => {
+                json!({ "kind": "proc_address", "procedure": key_hex(key) })
+            }
+            StorageKind::ProcIndex { key } => {
+                json!({ "kind": "proc_index", "procedure": key_hex(key) })
+            }
+            StorageKind::CapListLength { key, cap_type } => json!({
+                "kind": "cap_list_length",
+                "procedure": key_hex(key),
+                "cap_type": cap_type_name(*cap_type),
+            }),
+            StorageKind::CapValue {
+                key,
+                cap_type,
+                cap_index,
+                val_index,
+                cap,
+            } => json!({
+                "kind": "cap_value",
+                "procedure": key_hex(key),
+                "cap_type": cap_type_name(*cap_type),
+                "cap_index": cap_index,
+                "val_index": val_index,
+                "cap": cap.clone().map(SerialCapability),
+            }),
+            StorageKind::ProcStorage { writers } => json!({
+                "kind": "proc_storage",
+                "writers": writers
+                    .iter()
+                    .map(|(key, cap_index)| {
+                        json!({ "procedure": key_hex(key), "cap_index": cap_index })
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+            StorageKind::Unaccounted => json!({ "kind": "unaccounted" }),
+        };
+        if let (Some(entry), Some(kind)) = (entry.as_object_mut(), kind.as_object()) {
+            entry.extend(kind.clone());
+        }
+        entry.serialize(serializer)
+    }
+}
+
+/// Classify a storage key of a kernel with the procedures `procs`.
+pub fn classify(key: &[u8; 32], procs: &[Procedure]) -> StorageKind {
+    if key[0..4] == [0xff; 4] {
+        classify_kernel(key, procs)
+    } else {
+        classify_proc_storage(key, procs)
+    }
+}
+
+/// Classify a key in the kernel's reserved storage.
+fn classify_kernel(key: &[u8; 32], procs: &[Procedure]) -> StorageKind {
+    match key[4] {
+        0 => {
+            let mut proc_key = [0; 24];
+            proc_key.copy_from_slice(&key[5..29]);
+            match procs.iter().find(|procedure| procedure.key == proc_key) {
+                Some(procedure) => classify_proc_heap(procedure, key[29], key[30], key[31]),
+                None => StorageKind::Unaccounted,
+            }
+        }
+        1 if key[29..] == [0; 3] => {
+            let index = U256::from_big_endian(&key[5..29]);
+            if index.is_zero() {
+                StorageKind::ProcListLength
+            } else if index <= U256::from(procs.len()) {
+                StorageKind::ProcListEntry {
+                    index: index.as_u64(),
+                }
+            } else {
+                StorageKind::Unaccounted
+            }
+        }
+        2 if key[5..] == [0; 27] => StorageKind::KernelAddress,
+        3 if key[5..] == [0; 27] => StorageKind::CurrentProc,
+        4 if key[5..] == [0; 27] => StorageKind::EntryProc,
+        _ => StorageKind::Unaccounted,
+    }
+}
+
+/// Classify a key in the heap of a registered procedure. The last three
+/// bytes of the key are the cap type, the cap index plus one, and the value
+/// index.
+fn classify_proc_heap(
+    procedure: &Procedure,
+    cap_type: u8,
+    cap_slot: u8,
+    val_index: u8,
+) -> StorageKind {
+    let key = procedure.key;
+    match (cap_type, cap_slot, val_index) {
+        (0, 0, 0) => StorageKind::ProcAddress { key },
+        (0, 0, 1) => StorageKind::ProcIndex { key },
+        (cap_type, 0, 0) if CAP_TYPES.contains(&cap_type) => {
+            StorageKind::CapListLength { key, cap_type }
+        }
+        (cap_type, cap_slot, val_index) if cap_slot > 0 => {
+            let cap_index = cap_slot - 1;
+            match get_cap(&procedure.caps, cap_type, cap_index) {
+                Some(ref cap) if val_index < cap.get_cap_size() => StorageKind::CapValue {
+                    key,
+                    cap_type,
+                    cap_index,
+                    val_index,
+                    cap: if val_index == 0 {
+                        Some(cap.clone())
+                    } else {
+                        None
+                    },
+                },
+                _ => StorageKind::Unaccounted,
+            }
+        }
+        _ => StorageKind::Unaccounted,
+    }
+}
+
+/// Classify a key outside the kernel's reserved storage by the StoreWrite
+/// caps which cover it.
+fn classify_proc_storage(key: &[u8; 32], procs: &[Procedure]) -> StorageKind {
+    let key = U256::from_big_endian(key);
+    let mut writers = Vec::new();
+    for procedure in procs {
+        for (i, cap) in procedure.caps.store_write_caps.iter().enumerate() {
+            let location = U256::from_big_endian(&cap.location);
+            let size = U256::from_big_endian(&cap.size);
+            // The region includes location + size, as in the kernel's check.
+            // Subtract rather than add, as the end of the region may overflow.
+            if key >= location && key - location <= size {
+                writers.push((procedure.key, i as u8));
+            }
+        }
+    }
+    if writers.is_empty() {
+        StorageKind::Unaccounted
+    } else {
+        StorageKind::ProcStorage { writers }
+    }
+}
+
+/// Get a cap of a procedure by its type and index.
+fn get_cap(caps: &Capabilities, cap_type: u8, cap_index: u8) -> Option<Capability> {
+    let i = cap_index as usize;
+    match cap_type {
+        CAP_PROC_CALL => caps
+            .proc_call_caps
+            .get(i)
+            .cloned()
+            .map(Capability::ProcedureCall),
+        CAP_PROC_REGISTER => caps
+            .proc_register_caps
+            .get(i)
+            .cloned()
+            .map(Capability::ProcedureRegister),
+        CAP_PROC_DELETE => caps
+            .proc_delete_caps
+            .get(i)
+            .cloned()
+            .map(Capability::ProcedureDelete),
+        CAP_PROC_ENTRY => caps
+            .proc_entry_caps
+            .get(i)
+            .cloned()
+            .map(Capability::ProcedureEntry),
+        CAP_STORE_WRITE => caps
+            .store_write_caps
+            .get(i)
+            .cloned()
+            .map(Capability::StoreWrite),
+        CAP_LOG => caps.log_caps.get(i).cloned().map(Capability::Log),
+        CAP_ACC_CALL => caps
+            .account_call_caps
+            .get(i)
+            .cloned()
+            .map(Capability::AccountCall),
+        _ => None,
+    }
+}
+
+fn cap_type_name(cap_type: u8) -> String {
+    match cap_type {
+        CAP_PROC_CALL => String::from("CAP_PROC_CALL"),
+        CAP_PROC_REGISTER => String::from("CAP_PROC_REGISTER"),
+        CAP_PROC_DELETE => String::from("CAP_PROC_DELETE"),
+        CAP_PROC_ENTRY => String::from("CAP_PROC_ENTRY"),
+        CAP_STORE_WRITE => String::from("CAP_STORE_WRITE"),
+        CAP_LOG => String::from("CAP_LOG"),
+        CAP_ACC_CALL => String::from("CAP_ACC_CALL"),
+        cap_type => format!("cap type {}", cap_type),
+    }
+}
+
+/// A procedure key in hex, with its UTF-8 reading.
+fn proc_name(key: &[u8]) -> String {
+    let key_hex: String = key.to_hex();
+    format!(
+        "0x{} (\"{}\")",
+        key_hex,
+        String::from_utf8_lossy(key).trim_end_matches('\0')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cap9_std::proc_table::ProcPointer;
+    use web3::types::Address;
+
+    fn procedure(name: &str, index: u64, caps: Vec<Capability>) -> Procedure {
+        let mut key = [0; 24];
+        key[..name.len()].copy_from_slice(name.as_bytes());
+        Procedure {
+            key,
+            index: index.into(),
+            address: Address::zero(),
+            caps: Capabilities::from(caps),
+        }
+    }
+
+    fn procs() -> Vec<Procedure> {
+        let mut location = [0; 32];
+        location[0] = 0x30;
+        let mut size = [0; 32];
+        size[31] = 0x10;
+        vec![
+            procedure(
+                "init",
+                1,
+                vec![
+                    Capability::StoreWrite(StoreWriteCap { location, size }),
+                    Capability::Log(LogCap {
+                        topics: 0,
+                        t1: [0; 32],
+                        t2: [0; 32],
+                        t3: [0; 32],
+                        t4: [0; 32],
+                    }),
+                ],
+            ),
+            procedure(
+                "other",
+                2,
+                vec![Capability::StoreWrite(StoreWriteCap {
+                    location,
+                    size: [0xff; 32],
+                })],
+            ),
+        ]
+    }
+
+    #[test]
+    fn classify_kernel_storage() {
+        let procs = procs();
+        let init = procs[0].key;
+        let pointer = ProcPointer::from_key(init);
+        let mut list_length = [0; 32];
+        list_length[..5].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 1]);
+        let mut entry = [0; 32];
+        entry[..5].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 4]);
+        assert_eq!(classify(&list_length, &procs), StorageKind::ProcListLength);
+        assert_eq!(
+            classify(&ProcPointer::get_list_ptr(2.into()), &procs),
+            StorageKind::ProcListEntry { index: 2 }
+        );
+        assert_eq!(
+            classify(&ProcPointer::get_list_ptr(3.into()), &procs),
+            StorageKind::Unaccounted
+        );
+        assert_eq!(classify(&entry, &procs), StorageKind::EntryProc);
+        assert_eq!(
+            classify(&pointer.get_addr_ptr(), &procs),
+            StorageKind::ProcAddress { key: init }
+        );
+        assert_eq!(
+            classify(&pointer.get_index_ptr(), &procs),
+            StorageKind::ProcIndex { key: init }
+        );
+        assert_eq!(
+            classify(&pointer.get_cap_type_len_ptr(CAP_LOG), &procs),
+            StorageKind::CapListLength {
+                key: init,
+                cap_type: CAP_LOG
+            }
+        );
+        // The first value of a cap carries the decoded cap.
+        assert_eq!(
+            classify(&pointer.get_cap_val_ptr(CAP_STORE_WRITE, 0, 0), &procs),
+            StorageKind::CapValue {
+                key: init,
+                cap_type: CAP_STORE_WRITE,
+                cap_index: 0,
+                val_index: 0,
+                cap: Some(Capability::StoreWrite(
+                    procs[0].caps.store_write_caps[0].clone()
+                )),
+            }
+        );
+        assert_eq!(
+            classify(&pointer.get_cap_val_ptr(CAP_STORE_WRITE, 0, 1), &procs),
+            StorageKind::CapValue {
+                key: init,
+                cap_type: CAP_STORE_WRITE,
+                cap_index: 0,
+                val_index: 1,
+                cap: None,
+            }
+        );
+        // A StoreWrite cap has only two values, and there is only one.
+        assert_eq!(
+            classify(&pointer.get_cap_val_ptr(CAP_STORE_WRITE, 0, 2), &procs),
+            StorageKind::Unaccounted
+        );
+        assert_eq!(
+            classify(&pointer.get_cap_val_ptr(CAP_STORE_WRITE, 1, 0), &procs),
+            StorageKind::Unaccounted
+        );
+        // The heap of a procedure which isn't registered.
+        assert_eq!(
+            classify(&ProcPointer::from_key([1; 24]).get_addr_ptr(), &procs),
+            StorageKind::Unaccounted
+        );
+    }
+
+    #[test]
+    fn classify_proc_storage() {
+        let procs = procs();
+        let mut key = [0; 32];
+        key[0] = 0x30;
+        key[31] = 0x0f;
+        assert_eq!(
+            classify(&key, &procs),
+            StorageKind::ProcStorage {
+                writers: vec![(procs[0].key, 0), (procs[1].key, 0)]
+            }
+        );
+        // The last key of the first procedure's region.
+        key[31] = 0x10;
+        assert_eq!(
+            classify(&key, &procs),
+            StorageKind::ProcStorage {
+                writers: vec![(procs[0].key, 0), (procs[1].key, 0)]
+            }
+        );
+        // Only the second procedure's region reaches this far, and its end
+        // overflows.
+        key[31] = 0x11;
+        assert_eq!(
+            classify(&key, &procs),
+            StorageKind::ProcStorage {
+                writers: vec![(procs[1].key, 0)]
+            }
+        );
+        assert_eq!(classify(&[0; 32], &procs), StorageKind::Unaccounted);
+    }
+}
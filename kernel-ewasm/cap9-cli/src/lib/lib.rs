@@ -4,10 +4,17 @@ extern crate lazy_static;
 extern crate failure_derive;
 
 pub mod build;
+pub mod caps;
 pub mod connection;
 pub mod constants;
 pub mod default_procedures;
 pub mod deploy;
 pub mod fetch;
+pub mod local;
+pub mod manifest;
+pub mod network;
+pub mod output;
 pub mod project;
+pub mod signer;
+pub mod template;
 pub mod utils;
@@ -0,0 +1,485 @@
+//! The state of a local chain, and the execution of transactions against it.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_keccak::keccak256;
+use web3::types::{Address, Bytes, H256, U256};
+
+use super::runtime::{self, Frame};
+
+/// The account which sends transactions to the local chain. This is the
+/// development account of parity's dev chain.
+pub const DEV_ACCOUNT: [u8; 20] = [
+    0x00, 0xa3, 0x29, 0xc0, 0x64, 0x87, 0x69, 0xa7, 0x3a, 0xfa, 0xc7, 0xf9, 0x38, 0x1e, 0x08, 0xfb,
+    0x43, 0xdb, 0xea, 0x72,
+];
+
+/// The gas available to transactions which do not specify a limit.
+pub const DEFAULT_GAS: u64 = 200_800_000;
+
+/// The maximum depth of nested calls.
+const MAX_DEPTH: usize = 64;
+
+/// A transaction, or a call which is not committed to the chain.
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    pub from: Address,
+    /// The contract to call, or `None` to create a contract.
+    pub to: Option<Address>,
+    pub value: U256,
+    pub data: Vec<u8>,
+    pub gas: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LocalLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+}
+
+/// The outcome of a transaction. Every transaction is mined in its own block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Receipt {
+    pub transaction_hash: H256,
+    pub block_number: u64,
+    /// The time the block was created, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp: u64,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub contract_address: Option<Address>,
+    pub status: bool,
+    /// Why the transaction failed, if it did.
+    pub error: Option<String>,
+    pub logs: Vec<LocalLog>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Chain {
+    code: BTreeMap<Address, Bytes>,
+    storage: BTreeMap<Address, BTreeMap<H256, H256>>,
+    balances: BTreeMap<Address, U256>,
+    nonces: BTreeMap<Address, u64>,
+    receipts: Vec<Receipt>,
+    /// Parsed code, so that contracts which are called often (such as the
+    /// kernel, on every syscall) are only parsed once.
+    #[serde(skip)]
+    modules: HashMap<Address, Rc<wasmi::Module>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Chain::default()
+    }
+
+    /// Execute a transaction and mine it, returning the transaction hash. A
+    /// transaction which fails is still mined, with a failed receipt.
+    pub fn transact(&mut self, tx: Transaction) -> H256 {
+        let nonce = self.nonce(tx.from);
+        self.nonces.insert(tx.from, nonce + 1);
+        let transaction_hash = transaction_hash(&tx, nonce);
+        let block_number = self.block_number() + 1;
+        // Block timestamps never go backwards, even if the clock does.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let timestamp = now.max(self.block_timestamp());
+
+        let mut execution = Execution::new(self, tx.from, tx.gas, block_number, timestamp);
+        let checkpoint = execution.checkpoint();
+        // Ether sent with a transaction is created, rather than being taken
+        // from the sender.
+        execution.credit(
+            tx.to.unwrap_or_else(|| contract_address(tx.from, nonce)),
+            tx.value,
+        );
+        let result = match tx.to {
+            Some(to) => execution
+                .call(
+                    Frame {
+                        address: to,
+                        code_address: to,
+                        sender: tx.from,
+                        value: tx.value,
+                        input: tx.data.clone(),
+                        is_static: false,
+                    },
+                    false,
+                )
+                .map(|_| None),
+            None => execution
+                .create(contract_address(tx.from, nonce), &tx)
+                .map(Some),
+        };
+        let (contract_address, status, error, logs) = match result {
+            Ok(contract_address) => (contract_address, true, None, execution.logs),
+            Err(err) => {
+                execution.revert(checkpoint);
+                (None, false, Some(err), Vec::new())
+            }
+        };
+        self.receipts.push(Receipt {
+            transaction_hash,
+            block_number,
+            timestamp,
+            from: tx.from,
+            to: tx.to,
+            contract_address,
+            status,
+            error,
+            logs,
+        });
+        transaction_hash
+    }
+
+    /// Execute a call without committing any of its changes, returning the
+    /// output.
+    pub fn call(&mut self, tx: Transaction) -> Result<Vec<u8>, String> {
+        let to = tx
+            .to
+            .ok_or_else(|| String::from("calls must have a recipient"))?;
+        let block_number = self.block_number();
+        let timestamp = self.block_timestamp();
+        let mut execution = Execution::new(self, tx.from, tx.gas, block_number, timestamp);
+        let checkpoint = execution.checkpoint();
+        let result = execution.call(
+            Frame {
+                address: to,
+                code_address: to,
+                sender: tx.from,
+                value: tx.value,
+                input: tx.data,
+                is_static: false,
+            },
+            false,
+        );
+        execution.revert(checkpoint);
+        result
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.receipts.len() as u64
+    }
+
+    /// The timestamp of the latest block, which is zero before the first.
+    pub fn block_timestamp(&self) -> u64 {
+        self.receipts
+            .last()
+            .map(|receipt| receipt.timestamp)
+            .unwrap_or(0)
+    }
+
+    pub fn nonce(&self, address: Address) -> u64 {
+        self.nonces.get(&address).cloned().unwrap_or(0)
+    }
+
+    pub fn balance(&self, address: Address) -> U256 {
+        self.balances
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(U256::zero)
+    }
+
+    pub fn code(&self, address: Address) -> &[u8] {
+        self.code
+            .get(&address)
+            .map(|code| code.0.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn storage_at(&self, address: Address, key: H256) -> H256 {
+        self.storage
+            .get(&address)
+            .and_then(|storage| storage.get(&key))
+            .cloned()
+            .unwrap_or_else(H256::zero)
+    }
+
+    /// The non-zero storage keys of a contract, in order.
+    pub fn storage_keys(&self, address: Address) -> Vec<H256> {
+        self.storage
+            .get(&address)
+            .map(|storage| storage.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn receipt(&self, transaction_hash: H256) -> Option<&Receipt> {
+        self.receipts
+            .iter()
+            .find(|receipt| receipt.transaction_hash == transaction_hash)
+    }
+
+    pub fn receipts(&self) -> &[Receipt] {
+        &self.receipts
+    }
+}
+
+/// A change to the chain made by an execution, with the value it replaced,
+/// so that it can be undone.
+enum Change {
+    Storage(Address, H256, Option<H256>),
+    Balance(Address, Option<U256>),
+}
+
+/// The point in an execution which its changes can be reverted to when a
+/// call fails.
+pub struct Checkpoint {
+    journal: usize,
+    logs: usize,
+}
+
+/// A transaction in progress.
+pub struct Execution<'a> {
+    chain: &'a mut Chain,
+    pub origin: Address,
+    pub gas: u64,
+    pub block_number: u64,
+    /// The timestamp of the block, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    logs: Vec<LocalLog>,
+    /// The changes made so far, oldest first.
+    journal: Vec<Change>,
+    depth: usize,
+}
+
+impl<'a> Execution<'a> {
+    fn new(
+        chain: &'a mut Chain,
+        origin: Address,
+        gas: u64,
+        block_number: u64,
+        timestamp: u64,
+    ) -> Self {
+        Execution {
+            chain,
+            origin,
+            gas,
+            block_number,
+            timestamp,
+            logs: Vec::new(),
+            journal: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Execute a call frame. If the call fails all of its changes are
+    /// reverted. If `transfer` is set the value of the frame is transferred
+    /// from the sender to the address of the frame.
+    pub fn call(&mut self, frame: Frame, transfer: bool) -> Result<Vec<u8>, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(String::from("maximum call depth exceeded"));
+        }
+        let checkpoint = self.checkpoint();
+        if transfer {
+            self.transfer(frame.sender, frame.address, frame.value)?;
+        }
+        let result = match self.module(frame.code_address)? {
+            Some(module) => {
+                self.depth += 1;
+                let result = runtime::execute(self, &module, frame);
+                self.depth -= 1;
+                result
+            }
+            // Calling an account with no code succeeds, as on Ethereum.
+            None => Ok(Vec::new()),
+        };
+        if result.is_err() {
+            self.revert(checkpoint);
+        }
+        result
+    }
+
+    /// Create a contract by running its constructor. The constructor
+    /// arguments follow the code in the transaction data, and the constructor
+    /// returns the code of the contract.
+    fn create(&mut self, address: Address, tx: &Transaction) -> Result<Address, String> {
+        if !self.chain.code(address).is_empty() {
+            return Err(format!("a contract already exists at {:?}", address));
+        }
+        let code_len = peek_size(&tx.data);
+        let module = wasmi::Module::from_buffer(&tx.data[..code_len])
+            .map_err(|err| format!("invalid constructor: {}", err))?;
+        let frame = Frame {
+            address,
+            code_address: address,
+            sender: tx.from,
+            value: tx.value,
+            input: tx.data[code_len..].to_vec(),
+            is_static: false,
+        };
+        let code = runtime::execute(self, &module, frame)?;
+        let module = wasmi::Module::from_buffer(&code)
+            .map_err(|err| format!("constructor returned invalid code: {}", err))?;
+        self.chain.modules.insert(address, Rc::new(module));
+        self.chain.code.insert(address, Bytes(code));
+        Ok(address)
+    }
+
+    /// The parsed code of an account, or `None` if it has no code.
+    fn module(&mut self, address: Address) -> Result<Option<Rc<wasmi::Module>>, String> {
+        if let Some(module) = self.chain.modules.get(&address) {
+            return Ok(Some(module.clone()));
+        }
+        let code = self.chain.code(address);
+        if code.is_empty() {
+            return Ok(None);
+        }
+        let module = Rc::new(
+            wasmi::Module::from_buffer(code)
+                .map_err(|err| format!("invalid code at {:?}: {}", address, err))?,
+        );
+        self.chain.modules.insert(address, module.clone());
+        Ok(Some(module))
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            journal: self.journal.len(),
+            logs: self.logs.len(),
+        }
+    }
+
+    /// Undo the changes made since the checkpoint, latest first.
+    pub fn revert(&mut self, checkpoint: Checkpoint) {
+        for change in self.journal.drain(checkpoint.journal..).rev() {
+            match change {
+                Change::Storage(address, key, previous) => {
+                    let storage = self.chain.storage.entry(address).or_default();
+                    match previous {
+                        Some(value) => storage.insert(key, value),
+                        None => storage.remove(&key),
+                    };
+                }
+                Change::Balance(address, previous) => {
+                    match previous {
+                        Some(balance) => self.chain.balances.insert(address, balance),
+                        None => self.chain.balances.remove(&address),
+                    };
+                }
+            }
+        }
+        self.logs.truncate(checkpoint.logs);
+    }
+
+    pub fn code(&self, address: Address) -> &[u8] {
+        self.chain.code(address)
+    }
+
+    pub fn balance(&self, address: Address) -> U256 {
+        self.chain.balance(address)
+    }
+
+    pub fn storage(&self, address: Address, key: H256) -> H256 {
+        self.chain.storage_at(address, key)
+    }
+
+    pub fn set_storage(&mut self, address: Address, key: H256, value: H256) {
+        let storage = self.chain.storage.entry(address).or_default();
+        // Zero values are removed, so that only set keys are listed.
+        let previous = if value.is_zero() {
+            storage.remove(&key)
+        } else {
+            storage.insert(key, value)
+        };
+        self.journal.push(Change::Storage(address, key, previous));
+    }
+
+    pub fn log(&mut self, address: Address, topics: Vec<H256>, data: Vec<u8>) {
+        self.logs.push(LocalLog {
+            address,
+            topics,
+            data: Bytes(data),
+        });
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        let previous = self.chain.balances.insert(address, balance);
+        self.journal.push(Change::Balance(address, previous));
+    }
+
+    fn credit(&mut self, address: Address, value: U256) {
+        if !value.is_zero() {
+            let balance = self.chain.balance(address);
+            self.set_balance(address, balance.saturating_add(value));
+        }
+    }
+
+    fn transfer(&mut self, from: Address, to: Address, value: U256) -> Result<(), String> {
+        if value.is_zero() || from == to {
+            return Ok(());
+        }
+        let balance = self.chain.balance(from);
+        if balance < value {
+            return Err(format!("insufficient balance in {:?}", from));
+        }
+        self.set_balance(from, balance - value);
+        self.credit(to, value);
+        Ok(())
+    }
+}
+
+/// The length of the WASM module at the start of `data`. Anything after the
+/// module is input to the constructor. This follows parity, which takes the
+/// module to end at the first section which is not valid.
+fn peek_size(data: &[u8]) -> usize {
+    if data.len() < 8 {
+        return data.len();
+    }
+    let mut cursor = 8;
+    while cursor < data.len() {
+        let id = data[cursor];
+        let (section_len, len_bytes) = match read_varuint32(&data[cursor + 1..]) {
+            Some(result) => result,
+            None => break,
+        };
+        let next = cursor + 1 + len_bytes + section_len as usize;
+        if id > 11 || section_len == 0 || next > data.len() {
+            break;
+        }
+        cursor = next;
+    }
+    cursor
+}
+
+/// Read a LEB128 encoded u32, returning the value and the number of bytes it
+/// took.
+fn read_varuint32(data: &[u8]) -> Option<(u32, usize)> {
+    let mut value: u32 = 0;
+    for (i, byte) in data.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// The address of a contract created by `sender`, as on Ethereum: the last 20
+/// bytes of the hash of the RLP encoding of the sender and its nonce.
+fn contract_address(sender: Address, nonce: u64) -> Address {
+    let nonce_bytes = nonce.to_be_bytes();
+    let nonce_bytes = &nonce_bytes[nonce_bytes.iter().take_while(|b| **b == 0).count()..];
+    let mut rlp = vec![0xd6, 0x94];
+    rlp.extend_from_slice(sender.as_bytes());
+    match nonce_bytes {
+        [] => rlp.push(0x80),
+        [byte] if *byte < 0x80 => rlp.push(*byte),
+        bytes => {
+            rlp.push(0x80 + bytes.len() as u8);
+            rlp.extend_from_slice(bytes);
+        }
+    }
+    rlp[0] = 0xc0 + (rlp.len() - 1) as u8;
+    Address::from_slice(&keccak256(&rlp)[12..])
+}
+
+fn transaction_hash(tx: &Transaction, nonce: u64) -> H256 {
+    let mut preimage = Vec::with_capacity(28 + tx.data.len());
+    preimage.extend_from_slice(tx.from.as_bytes());
+    preimage.extend_from_slice(&nonce.to_be_bytes());
+    preimage.extend_from_slice(&tx.data);
+    H256::from(keccak256(&preimage))
+}
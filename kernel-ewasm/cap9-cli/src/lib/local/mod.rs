@@ -0,0 +1,346 @@
+//! A local chain, which runs the kernel and procedures in an embedded WASM
+//! interpreter rather than on a node.
+//!
+//! [LocalTransport] is a web3 transport which answers the JSON-RPC methods
+//! used by the CLI from an in-memory [Chain]. An `EthConn<LocalTransport>` can
+//! therefore be used wherever a connection to a node can, including with
+//! [DeployedKernel](crate::fetch::DeployedKernel) and
+//! [DeployedKernelWithACL](crate::fetch::DeployedKernelWithACL). The chain can
+//! be saved to a file so that it persists between runs of the CLI.
+//!
+//! Transactions are executed as soon as they are sent, each in its own block.
+//! Gas is not metered, and ether sent with a transaction is created rather
+//! than taken from the sender.
+mod chain;
+mod runtime;
+
+pub use chain::{Chain, LocalLog, Receipt, Transaction, DEFAULT_GAS, DEV_ACCOUNT};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
+use std::rc::Rc;
+use web3::futures::future::{self, FutureResult};
+use web3::rpc::{self, Value};
+use web3::types::{Address, Bytes, H256, U256};
+use web3::{RequestId, Transport};
+
+#[derive(Debug, Fail)]
+pub enum LocalChainError {
+    #[fail(display = "could not read chain file: {}", err)]
+    Io { err: String },
+    #[fail(display = "could not parse chain file: {}", err)]
+    Parse { err: String },
+}
+
+/// A web3 transport to a [Chain] in this process.
+#[derive(Clone)]
+pub struct LocalTransport {
+    chain: Rc<RefCell<Chain>>,
+    /// The file the chain is saved to after each transaction, if any.
+    path: Option<PathBuf>,
+    next_id: Rc<Cell<RequestId>>,
+}
+
+impl fmt::Debug for LocalTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalTransport")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl LocalTransport {
+    /// A transport to a new chain which is only kept in memory.
+    pub fn new() -> Self {
+        LocalTransport {
+            chain: Rc::new(RefCell::new(Chain::new())),
+            path: None,
+            next_id: Rc::new(Cell::new(1)),
+        }
+    }
+
+    /// A transport to the chain saved at `path`, or to a new chain if there
+    /// is no file. The chain is saved to `path` after every transaction.
+    pub fn open(path: PathBuf) -> Result<Self, LocalChainError> {
+        let chain = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(file).map_err(|err| LocalChainError::Parse {
+                err: err.to_string(),
+            })?,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Chain::new(),
+            Err(err) => {
+                return Err(LocalChainError::Io {
+                    err: err.to_string(),
+                })
+            }
+        };
+        Ok(LocalTransport {
+            chain: Rc::new(RefCell::new(chain)),
+            path: Some(path),
+            next_id: Rc::new(Cell::new(1)),
+        })
+    }
+
+    fn save(&self) -> Result<(), rpc::Error> {
+        if let Some(path) = &self.path {
+            let file = File::create(path)
+                .map_err(|err| internal_error(format!("could not save chain: {}", err)))?;
+            serde_json::to_writer(file, &*self.chain.borrow())
+                .map_err(|err| internal_error(format!("could not save chain: {}", err)))?;
+        }
+        Ok(())
+    }
+
+    /// Answer a JSON-RPC request.
+    fn handle(&self, method: &str, params: &[Value]) -> Result<Value, rpc::Error> {
+        let chain = &self.chain;
+        Ok(match method {
+            "eth_accounts" => json!([Address::from(DEV_ACCOUNT)]),
+            "personal_unlockAccount" => json!(true),
+            "net_version" => json!("17"),
+            "eth_blockNumber" => json!(U256::from(chain.borrow().block_number())),
+            "eth_gasPrice" => json!(U256::zero()),
+            "eth_estimateGas" => json!(U256::from(DEFAULT_GAS)),
+            "eth_getTransactionCount" => {
+                json!(U256::from(chain.borrow().nonce(param(params, 0)?)))
+            }
+            "eth_getBalance" => json!(chain.borrow().balance(param(params, 0)?)),
+            "eth_getCode" => json!(Bytes(chain.borrow().code(param(params, 0)?).to_vec())),
+            "eth_getStorageAt" => {
+                let position: U256 = param(params, 1)?;
+                let mut key = [0; 32];
+                position.to_big_endian(&mut key);
+                json!(chain
+                    .borrow()
+                    .storage_at(param(params, 0)?, H256::from(key)))
+            }
+            "parity_listStorageKeys" => {
+                let count: u64 = param(params, 1)?;
+                let after: Option<H256> = param(params, 2)?;
+                let keys: Vec<H256> = chain
+                    .borrow()
+                    .storage_keys(param(params, 0)?)
+                    .into_iter()
+                    .filter(|key| after.map_or(true, |after| *key > after))
+                    .take(count as usize)
+                    .collect();
+                json!(keys)
+            }
+            "eth_sendTransaction" => {
+                let request: TransactionRequest = param(params, 0)?;
+                let hash = chain.borrow_mut().transact(request.into_transaction());
+                self.save()?;
+                json!(hash)
+            }
+            "eth_call" => {
+                let request: TransactionRequest = param(params, 0)?;
+                let output = chain
+                    .borrow_mut()
+                    .call(request.into_transaction())
+                    .map_err(|err| rpc::Error {
+                        code: rpc::ErrorCode::ServerError(-32015),
+                        message: format!("VM execution error: {}", err),
+                        data: None,
+                    })?;
+                json!(Bytes(output))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: H256 = param(params, 0)?;
+                match chain.borrow().receipt(hash) {
+                    Some(receipt) => receipt_json(receipt),
+                    None => Value::Null,
+                }
+            }
+            "eth_getLogs" => {
+                let filter: Value = param(params, 0)?;
+                logs_json(&chain.borrow(), &filter)?
+            }
+            _ => return Err(rpc::Error::method_not_found()),
+        })
+    }
+}
+
+impl Default for LocalTransport {
+    fn default() -> Self {
+        LocalTransport::new()
+    }
+}
+
+impl Transport for LocalTransport {
+    type Out = FutureResult<Value, web3::Error>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, rpc::Call) {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        let request = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            method: method.into(),
+            params: rpc::Params::Array(params),
+            id: rpc::Id::Num(id as u64),
+        });
+        (id, request)
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let result = match request {
+            rpc::Call::MethodCall(call) => {
+                let params = match call.params {
+                    rpc::Params::Array(params) => params,
+                    rpc::Params::None => Vec::new(),
+                    rpc::Params::Map(_) => {
+                        return future::err(web3::Error::Transport(String::from(
+                            "named parameters are not supported",
+                        )))
+                    }
+                };
+                log::debug!("local request: {} {:?}", call.method, params);
+                self.handle(&call.method, &params).map_err(web3::Error::Rpc)
+            }
+            _ => Err(web3::Error::Transport(String::from(
+                "only method calls are supported",
+            ))),
+        };
+        future::result(result)
+    }
+}
+
+/// The fields of a transaction or call request which the local chain uses.
+#[derive(Deserialize)]
+struct TransactionRequest {
+    from: Option<Address>,
+    to: Option<Address>,
+    gas: Option<U256>,
+    value: Option<U256>,
+    data: Option<Bytes>,
+}
+
+impl TransactionRequest {
+    fn into_transaction(self) -> Transaction {
+        Transaction {
+            from: self.from.unwrap_or_else(|| Address::from(DEV_ACCOUNT)),
+            to: self.to,
+            value: self.value.unwrap_or_else(U256::zero),
+            data: self.data.map(|data| data.0).unwrap_or_default(),
+            gas: self.gas.map(|gas| gas.low_u64()).unwrap_or(DEFAULT_GAS),
+        }
+    }
+}
+
+fn param<T: DeserializeOwned>(params: &[Value], index: usize) -> Result<T, rpc::Error> {
+    let value = params.get(index).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value)
+        .map_err(|err| rpc::Error::invalid_params(format!("parameter {}: {}", index, err)))
+}
+
+fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, rpc::Error> {
+    serde_json::from_value(value.clone()).map_err(|err| rpc::Error::invalid_params(err.to_string()))
+}
+
+fn internal_error(message: String) -> rpc::Error {
+    rpc::Error {
+        code: rpc::ErrorCode::InternalError,
+        message,
+        data: None,
+    }
+}
+
+/// Each block holds a single transaction, so the block hash is taken to be
+/// the transaction hash.
+fn receipt_json(receipt: &Receipt) -> Value {
+    let logs: Vec<Value> = receipt
+        .logs
+        .iter()
+        .enumerate()
+        .map(|(i, log)| log_json(receipt, i, log))
+        .collect();
+    json!({
+        "transactionHash": receipt.transaction_hash,
+        "transactionIndex": "0x0",
+        "blockHash": receipt.transaction_hash,
+        "blockNumber": U256::from(receipt.block_number),
+        "from": receipt.from,
+        "to": receipt.to,
+        "cumulativeGasUsed": "0x0",
+        "gasUsed": "0x0",
+        "contractAddress": receipt.contract_address,
+        "logs": logs,
+        "logsBloom": format!("0x{}", "0".repeat(512)),
+        "status": if receipt.status { "0x1" } else { "0x0" },
+    })
+}
+
+fn log_json(receipt: &Receipt, index: usize, log: &LocalLog) -> Value {
+    json!({
+        "address": log.address,
+        "topics": log.topics,
+        "data": log.data,
+        "blockHash": receipt.transaction_hash,
+        "blockNumber": U256::from(receipt.block_number),
+        "transactionHash": receipt.transaction_hash,
+        "transactionIndex": "0x0",
+        "logIndex": U256::from(index),
+        "transactionLogIndex": U256::from(index),
+        "type": "mined",
+        "removed": false,
+    })
+}
+
+/// The logs matching a filter, as given to `eth_getLogs`.
+fn logs_json(chain: &Chain, filter: &Value) -> Result<Value, rpc::Error> {
+    let from_block = block_number(chain, &filter["fromBlock"], 0)?;
+    let to_block = block_number(chain, &filter["toBlock"], chain.block_number())?;
+    let addresses: Option<Vec<Address>> = match &filter["address"] {
+        Value::Null => None,
+        Value::Array(_) => Some(from_value(&filter["address"])?),
+        address => Some(vec![from_value(address)?]),
+    };
+    // Each position is either null, which matches anything, or a list of
+    // alternatives.
+    let topics: Vec<Option<Vec<H256>>> = match &filter["topics"] {
+        Value::Array(topics) => topics
+            .iter()
+            .map(|topic| match topic {
+                Value::Null => Ok(None),
+                Value::Array(_) => from_value(topic).map(Some),
+                topic => from_value(topic).map(|topic| Some(vec![topic])),
+            })
+            .collect::<Result<_, _>>()?,
+        _ => Vec::new(),
+    };
+
+    let mut logs = Vec::new();
+    for receipt in chain.receipts() {
+        if receipt.block_number < from_block || receipt.block_number > to_block {
+            continue;
+        }
+        for (i, log) in receipt.logs.iter().enumerate() {
+            let address_matches = addresses
+                .as_ref()
+                .map_or(true, |addresses| addresses.contains(&log.address));
+            let topics_match = topics.iter().enumerate().all(|(j, alternatives)| {
+                match (alternatives, log.topics.get(j)) {
+                    (None, _) => true,
+                    (Some(alternatives), Some(topic)) => alternatives.contains(topic),
+                    (Some(_), None) => false,
+                }
+            });
+            if address_matches && topics_match {
+                logs.push(log_json(receipt, i, log));
+            }
+        }
+    }
+    Ok(Value::Array(logs))
+}
+
+fn block_number(chain: &Chain, value: &Value, default: u64) -> Result<u64, rpc::Error> {
+    Ok(match value {
+        Value::Null => default,
+        Value::String(tag) if tag == "latest" || tag == "pending" => chain.block_number(),
+        Value::String(tag) if tag == "earliest" => 0,
+        number => from_value::<U256>(number)?.low_u64(),
+    })
+}
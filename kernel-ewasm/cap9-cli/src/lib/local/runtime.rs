@@ -0,0 +1,425 @@
+//! Execution of pwasm contracts with wasmi, and the `env` host functions which
+//! parity provides to them.
+use std::cell::RefCell;
+use std::fmt;
+use wasmi::memory_units::Pages;
+use wasmi::ValueType::{I32, I64};
+use wasmi::{
+    Error as InterpreterError, Externals, FuncInstance, FuncRef, HostError, ImportsBuilder,
+    MemoryDescriptor, MemoryInstance, MemoryRef, ModuleImportResolver, ModuleInstance, RuntimeArgs,
+    RuntimeValue, Signature, Trap, TrapKind, ValueType,
+};
+use web3::types::{Address, H256, U256};
+
+use super::chain::Execution;
+
+/// A call frame: the code being executed and the account it executes as.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The account whose storage is used, and which is reported by `address`.
+    pub address: Address,
+    /// The account whose code is executed.
+    pub code_address: Address,
+    pub sender: Address,
+    pub value: U256,
+    pub input: Vec<u8>,
+    /// Static calls may not modify state.
+    pub is_static: bool,
+}
+
+/// The host functions, by index, with their parameters and return type.
+const FUNCTIONS: &[(&str, &[ValueType], Option<ValueType>)] = &[
+    ("storage_read", &[I32, I32], None),
+    ("storage_write", &[I32, I32], None),
+    ("ret", &[I32, I32], None),
+    ("gas", &[I32], None),
+    ("input_length", &[], Some(I32)),
+    ("fetch_input", &[I32], None),
+    ("panic", &[I32, I32], None),
+    ("debug", &[I32, I32], None),
+    ("ccall", &[I64, I32, I32, I32, I32, I32, I32], Some(I32)),
+    ("dcall", &[I64, I32, I32, I32, I32, I32], Some(I32)),
+    ("scall", &[I64, I32, I32, I32, I32, I32], Some(I32)),
+    ("call_code", &[I64, I32, I32, I32, I32, I32, I32], Some(I32)),
+    ("result_length", &[], Some(I32)),
+    ("fetch_result", &[I32], None),
+    ("extcodesize", &[I32], Some(I32)),
+    ("extcodecopy", &[I32, I32], None),
+    ("value", &[I32], None),
+    ("sender", &[I32], None),
+    ("origin", &[I32], None),
+    ("address", &[I32], None),
+    ("balance", &[I32, I32], None),
+    ("gasleft", &[], Some(I64)),
+    ("gaslimit", &[I32], None),
+    ("blocknumber", &[], Some(I64)),
+    ("timestamp", &[], Some(I64)),
+    ("coinbase", &[I32], None),
+    ("difficulty", &[I32], None),
+    ("blockhash", &[I64, I32], None),
+    ("elog", &[I32, I32, I32, I32], None),
+    ("create", &[I32, I32, I32, I32], Some(I32)),
+    ("create2", &[I32, I32, I32, I32, I32], Some(I32)),
+    ("suicide", &[I32], None),
+];
+
+/// Reasons for execution to stop before the end of `call`.
+#[derive(Debug)]
+enum Halt {
+    /// `ret` was called, which finishes execution successfully.
+    Return,
+    /// The contract panicked with a message.
+    Panic(String),
+    /// A memory access was out of bounds, or the contract has no memory.
+    Memory,
+    /// State was modified during a static call.
+    Static,
+    /// The contract used a host function which is not supported locally.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::Return => write!(f, "returned"),
+            Halt::Panic(message) => write!(f, "panicked: {}", message),
+            Halt::Memory => write!(f, "memory access out of bounds"),
+            Halt::Static => write!(f, "state modified in a static call"),
+            Halt::Unsupported(name) => write!(f, "{} is not supported on the local chain", name),
+        }
+    }
+}
+
+impl HostError for Halt {}
+
+/// Provides the host functions and, for contracts which import it, the memory.
+#[derive(Default)]
+struct Resolver {
+    memory: RefCell<Option<MemoryRef>>,
+}
+
+impl ModuleImportResolver for Resolver {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        let index = FUNCTIONS
+            .iter()
+            .position(|(name, _, _)| *name == field_name)
+            .ok_or_else(|| {
+                InterpreterError::Instantiation(format!("unknown host function env.{}", field_name))
+            })?;
+        let (_, params, return_type) = FUNCTIONS[index];
+        if signature.params() != params || signature.return_type() != return_type {
+            return Err(InterpreterError::Instantiation(format!(
+                "env.{} is imported with the wrong signature",
+                field_name
+            )));
+        }
+        Ok(FuncInstance::alloc_host(
+            Signature::new(params, return_type),
+            index,
+        ))
+    }
+
+    fn resolve_memory(
+        &self,
+        field_name: &str,
+        descriptor: &MemoryDescriptor,
+    ) -> Result<MemoryRef, InterpreterError> {
+        if field_name != "memory" {
+            return Err(InterpreterError::Instantiation(format!(
+                "unknown memory env.{}",
+                field_name
+            )));
+        }
+        let memory = MemoryInstance::alloc(
+            Pages(descriptor.initial() as usize),
+            descriptor.maximum().map(|maximum| Pages(maximum as usize)),
+        )?;
+        *self.memory.borrow_mut() = Some(memory.clone());
+        Ok(memory)
+    }
+}
+
+/// Execute the `call` export of `module` in `frame`, returning its output.
+pub fn execute(
+    ext: &mut Execution,
+    module: &wasmi::Module,
+    frame: Frame,
+) -> Result<Vec<u8>, String> {
+    let resolver = Resolver::default();
+    let imports = ImportsBuilder::new().with_resolver("env", &resolver);
+    let instance = ModuleInstance::new(module, &imports).map_err(|err| err.to_string())?;
+    // Contracts built by the CLI import their memory, but other contracts
+    // may export it instead.
+    let memory = resolver.memory.into_inner().or_else(|| {
+        instance
+            .not_started_instance()
+            .export_by_name("memory")
+            .and_then(|export| export.as_memory().cloned())
+    });
+    let mut runtime = Runtime {
+        ext,
+        frame,
+        memory,
+        result: Vec::new(),
+        output: Vec::new(),
+    };
+    let result = instance
+        .run_start(&mut runtime)
+        .map_err(InterpreterError::Trap)
+        .and_then(|instance| instance.invoke_export("call", &[], &mut runtime));
+    match result {
+        Ok(_) => Ok(runtime.output),
+        Err(InterpreterError::Trap(trap)) => match trap.kind() {
+            TrapKind::Host(err) => match err.downcast_ref::<Halt>() {
+                Some(Halt::Return) => Ok(runtime.output),
+                Some(halt) => Err(halt.to_string()),
+                None => Err(format!("{:?}", err)),
+            },
+            kind => Err(format!("trap: {:?}", kind)),
+        },
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CallKind {
+    Call,
+    CallCode,
+    Delegate,
+    Static,
+}
+
+struct Runtime<'a, 'b> {
+    ext: &'a mut Execution<'b>,
+    frame: Frame,
+    memory: Option<MemoryRef>,
+    /// The return data of the last call.
+    result: Vec<u8>,
+    /// The output set by `ret`.
+    output: Vec<u8>,
+}
+
+impl<'a, 'b> Runtime<'a, 'b> {
+    fn memory(&self) -> Result<&MemoryRef, Trap> {
+        self.memory.as_ref().ok_or_else(|| Halt::Memory.into())
+    }
+
+    fn read(&self, ptr: i32, len: usize) -> Result<Vec<u8>, Trap> {
+        self.memory()?
+            .get(ptr as u32, len)
+            .map_err(|_| Halt::Memory.into())
+    }
+
+    fn write(&self, ptr: i32, data: &[u8]) -> Result<(), Trap> {
+        self.memory()?
+            .set(ptr as u32, data)
+            .map_err(|_| Halt::Memory.into())
+    }
+
+    fn read_address(&self, ptr: i32) -> Result<Address, Trap> {
+        Ok(Address::from_slice(&self.read(ptr, 20)?))
+    }
+
+    fn read_h256(&self, ptr: i32) -> Result<H256, Trap> {
+        Ok(H256::from_slice(&self.read(ptr, 32)?))
+    }
+
+    fn read_u256(&self, ptr: i32) -> Result<U256, Trap> {
+        Ok(U256::from_big_endian(&self.read(ptr, 32)?))
+    }
+
+    fn write_u256(&self, ptr: i32, value: U256) -> Result<(), Trap> {
+        let mut bytes = [0; 32];
+        value.to_big_endian(&mut bytes);
+        self.write(ptr, &bytes)
+    }
+
+    fn check_static(&self) -> Result<(), Trap> {
+        if self.frame.is_static {
+            Err(Halt::Static.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Perform a call of any kind. The arguments are the same for each, except
+    /// that `ccall` and `call_code` also take a value.
+    fn call(&mut self, kind: CallKind, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        let address = self.read_address(args.nth_checked(1)?)?;
+        let (value, rest) = match kind {
+            CallKind::Call | CallKind::CallCode => (self.read_u256(args.nth_checked(2)?)?, 3),
+            CallKind::Delegate => (self.frame.value, 2),
+            CallKind::Static => (U256::zero(), 2),
+        };
+        let input_len: i32 = args.nth_checked(rest + 1)?;
+        let input = self.read(args.nth_checked(rest)?, input_len as u32 as usize)?;
+        let result_ptr: i32 = args.nth_checked(rest + 2)?;
+        let result_len: i32 = args.nth_checked(rest + 3)?;
+        if kind == CallKind::Call && !value.is_zero() {
+            self.check_static()?;
+        }
+
+        let frame = match kind {
+            CallKind::Call | CallKind::Static => Frame {
+                address,
+                code_address: address,
+                sender: self.frame.address,
+                value,
+                input,
+                is_static: self.frame.is_static || kind == CallKind::Static,
+            },
+            CallKind::CallCode => Frame {
+                address: self.frame.address,
+                code_address: address,
+                sender: self.frame.address,
+                value,
+                input,
+                is_static: self.frame.is_static,
+            },
+            CallKind::Delegate => Frame {
+                address: self.frame.address,
+                code_address: address,
+                sender: self.frame.sender,
+                value,
+                input,
+                is_static: self.frame.is_static,
+            },
+        };
+        let (status, data) = match self.ext.call(frame, kind == CallKind::Call) {
+            Ok(data) => (0, data),
+            Err(err) => {
+                log::debug!("call to {:?} failed: {}", address, err);
+                (1, Vec::new())
+            }
+        };
+        let len = data.len().min(result_len as u32 as usize);
+        self.write(result_ptr, &data[..len])?;
+        self.result = data;
+        Ok(Some(RuntimeValue::I32(status)))
+    }
+}
+
+impl<'a, 'b> Externals for Runtime<'a, 'b> {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let name = FUNCTIONS[index].0;
+        match name {
+            "storage_read" => {
+                let key = self.read_h256(args.nth_checked(0)?)?;
+                let value = self.ext.storage(self.frame.address, key);
+                self.write(args.nth_checked(1)?, value.as_bytes())?;
+            }
+            "storage_write" => {
+                self.check_static()?;
+                let key = self.read_h256(args.nth_checked(0)?)?;
+                let value = self.read_h256(args.nth_checked(1)?)?;
+                self.ext.set_storage(self.frame.address, key, value);
+            }
+            "ret" => {
+                let len: i32 = args.nth_checked(1)?;
+                self.output = self.read(args.nth_checked(0)?, len as u32 as usize)?;
+                return Err(Halt::Return.into());
+            }
+            // Gas is not metered locally.
+            "gas" => {}
+            "input_length" => return Ok(Some(RuntimeValue::I32(self.frame.input.len() as i32))),
+            "fetch_input" => self.write(args.nth_checked(0)?, &self.frame.input)?,
+            "panic" => {
+                let len: i32 = args.nth_checked(1)?;
+                let payload = self.read(args.nth_checked(0)?, len as u32 as usize)?;
+                return Err(Halt::Panic(panic_message(&payload)).into());
+            }
+            "debug" => {
+                let len: i32 = args.nth_checked(1)?;
+                let message = self.read(args.nth_checked(0)?, len as u32 as usize)?;
+                log::debug!(
+                    "{:?}: {}",
+                    self.frame.address,
+                    String::from_utf8_lossy(&message)
+                );
+            }
+            "ccall" => return self.call(CallKind::Call, args),
+            "dcall" => return self.call(CallKind::Delegate, args),
+            "scall" => return self.call(CallKind::Static, args),
+            "call_code" => return self.call(CallKind::CallCode, args),
+            "result_length" => return Ok(Some(RuntimeValue::I32(self.result.len() as i32))),
+            "fetch_result" => self.write(args.nth_checked(0)?, &self.result)?,
+            "extcodesize" => {
+                let address = self.read_address(args.nth_checked(0)?)?;
+                return Ok(Some(RuntimeValue::I32(self.ext.code(address).len() as i32)));
+            }
+            "extcodecopy" => {
+                let address = self.read_address(args.nth_checked(1)?)?;
+                self.write(args.nth_checked(0)?, self.ext.code(address))?;
+            }
+            "value" => self.write_u256(args.nth_checked(0)?, self.frame.value)?,
+            "sender" => self.write(args.nth_checked(0)?, self.frame.sender.as_bytes())?,
+            "origin" => self.write(args.nth_checked(0)?, self.ext.origin.as_bytes())?,
+            "address" => self.write(args.nth_checked(0)?, self.frame.address.as_bytes())?,
+            "balance" => {
+                let address = self.read_address(args.nth_checked(0)?)?;
+                self.write_u256(args.nth_checked(1)?, self.ext.balance(address))?;
+            }
+            "gasleft" => return Ok(Some(RuntimeValue::I64(self.ext.gas as i64))),
+            "gaslimit" => self.write_u256(args.nth_checked(0)?, self.ext.gas.into())?,
+            "blocknumber" => return Ok(Some(RuntimeValue::I64(self.ext.block_number as i64))),
+            "timestamp" => return Ok(Some(RuntimeValue::I64(self.ext.timestamp as i64))),
+            "coinbase" => self.write(args.nth_checked(0)?, Address::zero().as_bytes())?,
+            "difficulty" => self.write_u256(args.nth_checked(0)?, U256::zero())?,
+            "blockhash" => self.write(args.nth_checked(1)?, H256::zero().as_bytes())?,
+            "elog" => {
+                self.check_static()?;
+                let topic_count: i32 = args.nth_checked(1)?;
+                let topics = self
+                    .read(args.nth_checked(0)?, 32 * topic_count as u32 as usize)?
+                    .chunks(32)
+                    .map(H256::from_slice)
+                    .collect();
+                let data_len: i32 = args.nth_checked(3)?;
+                let data = self.read(args.nth_checked(2)?, data_len as u32 as usize)?;
+                self.ext.log(self.frame.address, topics, data);
+            }
+            name => return Err(Halt::Unsupported(name).into()),
+        }
+        Ok(None)
+    }
+}
+
+/// Decode the payload which pwasm-std passes to `panic`: the message and the
+/// file, each preceded by its length, then the line and column, all as little
+/// endian u32s. Payloads in any other form are shown as they are.
+fn panic_message(payload: &[u8]) -> String {
+    fn take_u32(payload: &mut &[u8]) -> Option<u32> {
+        if payload.len() < 4 {
+            return None;
+        }
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&payload[..4]);
+        *payload = &payload[4..];
+        Some(u32::from_le_bytes(bytes))
+    }
+    fn take_str(payload: &mut &[u8]) -> Option<String> {
+        let len = take_u32(payload)? as usize;
+        if payload.len() < len {
+            return None;
+        }
+        let string = String::from_utf8_lossy(&payload[..len]).into_owned();
+        *payload = &payload[len..];
+        Some(string)
+    }
+    let mut rest = payload;
+    let decoded = take_str(&mut rest).and_then(|message| {
+        let file = take_str(&mut rest)?;
+        let line = take_u32(&mut rest)?;
+        let column = take_u32(&mut rest)?;
+        Some(format!("{} at {}:{}:{}", message, file, line, column))
+    });
+    decoded.unwrap_or_else(|| String::from_utf8_lossy(payload).into_owned())
+}
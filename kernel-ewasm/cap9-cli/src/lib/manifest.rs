@@ -0,0 +1,606 @@
+//! A declarative description of a kernel with a standard ACL, kept in
+//! `kernel.json` in the project directory.
+//!
+//! ```json
+//! {
+//!     "procedures": {
+//!         "counter": {
+//!             "code": "counter/target/wasm32-unknown-unknown/release/counter.wasm",
+//!             "abi": "counter/target/json/CounterInterface.json",
+//!             "caps": "counter/caps.json"
+//!         }
+//!     },
+//!     "groups": { "5": "counter" },
+//!     "users": {
+//!         "0x00a329c0648769a73afac7f9381e08fb43dbea72": 1,
+//!         "0x1111111111111111111111111111111111111111": 5
+//!     }
+//! }
+//! ```
+//!
+//! Procedures are named by their keys. [Manifest::plan] compares the manifest
+//! with the deployed kernel and gives the operations which bring the kernel in
+//! line with it, and [Plan::apply] carries them out through the ACL admin
+//! procedure.
+//!
+//! The entry and admin procedures belong to the ACL. They are not listed in
+//! the manifest, and are never deleted or replaced. Groups can be repointed
+//! but not removed, as the ACL has no way to remove them. If `users` is left
+//! out, the users of the kernel are not changed.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tiny_keccak::keccak256;
+use web3::types::{Address, H256, U256};
+use web3::Transport;
+
+use crate::caps::{read_cap_file, CapFileError};
+use crate::fetch::DeployedKernelWithACL;
+use crate::project::{ContractSpec, ProcSpec, ProjectDeploymentError};
+use crate::utils::{proc_key_to_32_bytes, proc_key_to_string, string_to_proc_key};
+use cap9_std::proc_table::cap::*;
+
+#[derive(Debug, Fail)]
+pub enum ManifestError {
+    #[fail(display = "could not read {}: {}", path, err)]
+    ReadFailure { path: String, err: String },
+    #[fail(display = "could not write {}: {}", path, err)]
+    WriteFailure { path: String, err: String },
+    #[fail(display = "\"{}\" is not a valid procedure key", key)]
+    InvalidKey { key: String },
+    #[fail(display = "\"{}\" is not a procedure in the manifest", key)]
+    UnknownProcedure { key: String },
+    #[fail(
+        display = "\"{}\" is a procedure of the ACL, which the manifest cannot change",
+        key
+    )]
+    AclProcedure { key: String },
+    #[fail(
+        display = "group {} uses the procedure \"{}\", which the manifest deletes",
+        group, key
+    )]
+    GroupProcedureDeleted { group: u8, key: String },
+    #[fail(
+        display = "the manifest removes the sender {:?}, which would lock it out",
+        user
+    )]
+    SenderRemoved { user: Address },
+    #[fail(display = "the standard ACL cannot carry out \"{}\"", operation)]
+    Unsupported { operation: String },
+    #[fail(display = "{}", err)]
+    InvalidCaps { err: CapFileError },
+    #[fail(display = "{}", err)]
+    DeploymentFailure { err: ProjectDeploymentError },
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The procedures registered in the kernel, other than those of the ACL,
+    /// by key.
+    pub procedures: BTreeMap<String, ProcedureManifest>,
+    /// The entry procedure, if it is to be changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    /// The procedure of each group.
+    #[serde(default)]
+    pub groups: BTreeMap<u8, String>,
+    /// The group of each user. If this is left out, users are not changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub users: Option<BTreeMap<Address, u8>>,
+}
+
+/// The files of a procedure. The paths are relative to the project directory.
+#[derive(Serialize, Deserialize)]
+pub struct ProcedureManifest {
+    pub code: PathBuf,
+    pub abi: PathBuf,
+    pub caps: PathBuf,
+}
+
+impl ProcedureManifest {
+    pub fn proc_spec(&self) -> ProcSpec {
+        ProcSpec {
+            contract_spec: ContractSpec::from_files(&self.code, &self.abi),
+            cap_path: self.caps.clone(),
+        }
+    }
+
+    /// The state the procedure should have in the kernel.
+    /// `parent` is the caps of the procedure which registers it.
+    fn state(&self, parent: &Capabilities) -> Result<ProcedureState, ManifestError> {
+        let caps =
+            read_cap_file(&self.caps, parent).map_err(|err| ManifestError::InvalidCaps { err })?;
+        let code = std::fs::read(&self.code).map_err(|err| ManifestError::ReadFailure {
+            path: self.code.display().to_string(),
+            err: err.to_string(),
+        })?;
+        Ok(ProcedureState {
+            caps: Capabilities::from(
+                caps.0
+                    .into_iter()
+                    .map(|new_cap| new_cap.cap)
+                    .collect::<Vec<_>>(),
+            ),
+            code_hash: Some(H256::from(keccak256(&code))),
+        })
+    }
+}
+
+impl Manifest {
+    /// Read the manifest at `path`.
+    pub fn read(path: &Path) -> Result<Self, ManifestError> {
+        let file = File::open(path).map_err(|err| ManifestError::ReadFailure {
+            path: path.display().to_string(),
+            err: err.to_string(),
+        })?;
+        serde_json::from_reader(file).map_err(|err| ManifestError::ReadFailure {
+            path: path.display().to_string(),
+            err: err.to_string(),
+        })
+    }
+
+    /// Write the manifest to `path`.
+    pub fn write(&self, path: &Path) -> Result<(), ManifestError> {
+        let write_failure = |err: String| ManifestError::WriteFailure {
+            path: path.display().to_string(),
+            err,
+        };
+        let file = File::create(path).map_err(|err| write_failure(err.to_string()))?;
+        serde_json::to_writer_pretty(file, self).map_err(|err| write_failure(err.to_string()))
+    }
+
+    /// Add a procedure to the manifest, or replace the files of an existing
+    /// one.
+    pub fn set_procedure(
+        &mut self,
+        key: String,
+        proc_spec: &ProcSpec,
+    ) -> Result<(), ManifestError> {
+        if !valid_key(&key) {
+            return Err(ManifestError::InvalidKey { key });
+        }
+        self.procedures.insert(
+            key,
+            ProcedureManifest {
+                code: PathBuf::from(&proc_spec.contract_spec.code_path),
+                abi: PathBuf::from(&proc_spec.contract_spec.abi_path),
+                caps: proc_spec.cap_path.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// The state the manifest describes.
+    fn state(&self, parent: &Capabilities) -> Result<KernelState, ManifestError> {
+        let mut procedures = BTreeMap::new();
+        for (key, procedure) in self.procedures.iter() {
+            if !valid_key(key) {
+                return Err(ManifestError::InvalidKey { key: key.clone() });
+            }
+            procedures.insert(key.clone(), procedure.state(parent)?);
+        }
+        Ok(KernelState {
+            procedures,
+            entry: self.entry.clone(),
+            groups: self.groups.clone(),
+            users: self.users.clone().unwrap_or_default(),
+        })
+    }
+
+    /// The operations which bring a deployed kernel in line with the
+    /// manifest.
+    pub fn plan<T: Transport>(
+        &self,
+        kernel: &DeployedKernelWithACL<T>,
+    ) -> Result<Plan, ManifestError> {
+        let current = KernelState::fetch(kernel);
+        // Procedures are registered by the admin procedure of group 1.
+        let admin_caps = current
+            .groups
+            .get(&1)
+            .and_then(|key| current.procedures.get(key))
+            .map(|procedure| procedure.caps.clone())
+            .unwrap_or_else(Capabilities::new);
+        let desired = self.state(&admin_caps)?;
+        diff(
+            &current,
+            &desired,
+            self.users.is_some(),
+            kernel.kernel.conn.sender,
+        )
+        .map(|operations| Plan { operations })
+    }
+}
+
+/// Whether a string can be used as a procedure key.
+fn valid_key(key: &str) -> bool {
+    key.is_ascii() && !key.is_empty() && key.len() <= 24
+}
+
+/// The state of a kernel which a manifest describes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KernelState {
+    pub procedures: BTreeMap<String, ProcedureState>,
+    pub entry: Option<String>,
+    pub groups: BTreeMap<u8, String>,
+    pub users: BTreeMap<Address, u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcedureState {
+    pub caps: Capabilities,
+    /// The hash of the code the procedure was built from. This is only known
+    /// for procedures registered by the CLI.
+    pub code_hash: Option<H256>,
+}
+
+impl KernelState {
+    /// Read the state of a deployed kernel.
+    pub fn fetch<T: Transport>(kernel: &DeployedKernelWithACL<T>) -> Self {
+        let status_file = kernel
+            .kernel
+            .local_project
+            .status_file()
+            .as_ref()
+            .expect("no status file");
+        let procedures = kernel
+            .kernel
+            .procedures()
+            .into_iter()
+            .map(|procedure| {
+                let state = ProcedureState {
+                    caps: procedure.caps,
+                    code_hash: status_file.code_hashes.get(&procedure.address).cloned(),
+                };
+                (proc_key_to_string(&procedure.key).to_string(), state)
+            })
+            .collect();
+        let groups = kernel
+            .groups()
+            .into_iter()
+            .map(|(id, group)| (id, proc_key_to_string(&group.procedure_key.0).to_string()))
+            .collect();
+        KernelState {
+            procedures,
+            entry: Some(proc_key_to_string(&kernel.kernel.entry_proc().0).to_string()),
+            groups,
+            users: kernel.users().into_iter().collect(),
+        }
+    }
+
+    /// The procedures of the ACL: the entry procedure, and the procedure of
+    /// the admin group.
+    fn acl_procedures(&self) -> Vec<&String> {
+        self.entry.iter().chain(self.groups.get(&1)).collect()
+    }
+}
+
+/// Compare two states of a kernel. Procedures are registered before groups
+/// and users are changed, and deleted after, so that groups never point at
+/// procedures which do not exist.
+fn diff(
+    current: &KernelState,
+    desired: &KernelState,
+    manage_users: bool,
+    sender: Address,
+) -> Result<Vec<Operation>, ManifestError> {
+    let acl_procedures = current.acl_procedures();
+    let known =
+        |key: &String| desired.procedures.contains_key(key) || acl_procedures.contains(&key);
+    let mut operations = Vec::new();
+
+    for (key, procedure) in desired.procedures.iter() {
+        if acl_procedures.contains(&key) {
+            return Err(ManifestError::AclProcedure { key: key.clone() });
+        }
+        match current.procedures.get(key) {
+            None => operations.push(Operation::Register { key: key.clone() }),
+            Some(existing) => {
+                let code_changed = match (existing.code_hash, procedure.code_hash) {
+                    (Some(existing), Some(desired)) => existing != desired,
+                    _ => false,
+                };
+                if code_changed || existing.caps != procedure.caps {
+                    operations.push(Operation::Delete { key: key.clone() });
+                    operations.push(Operation::Register { key: key.clone() });
+                }
+            }
+        }
+    }
+
+    if let Some(entry) = &desired.entry {
+        if !known(entry) {
+            return Err(ManifestError::UnknownProcedure { key: entry.clone() });
+        }
+        if current.entry.as_ref() != Some(entry) {
+            operations.push(Operation::SetEntry { key: entry.clone() });
+        }
+    }
+
+    for (group, key) in desired.groups.iter() {
+        if !known(key) {
+            return Err(ManifestError::UnknownProcedure { key: key.clone() });
+        }
+        if *group == 1 && current.groups.get(group) != Some(key) {
+            return Err(ManifestError::AclProcedure { key: key.clone() });
+        }
+        if current.groups.get(group) != Some(key) {
+            operations.push(Operation::SetGroupProcedure {
+                group: *group,
+                key: key.clone(),
+            });
+        }
+    }
+    for (group, key) in current.groups.iter() {
+        if !desired.groups.contains_key(group) && !known(key) {
+            return Err(ManifestError::GroupProcedureDeleted {
+                group: *group,
+                key: key.clone(),
+            });
+        }
+    }
+
+    if manage_users {
+        for (user, group) in desired.users.iter() {
+            if current.users.get(user) != Some(group) {
+                operations.push(Operation::SetUserGroup {
+                    user: *user,
+                    group: *group,
+                });
+            }
+        }
+        for user in current.users.keys() {
+            if !desired.users.contains_key(user) {
+                if *user == sender {
+                    return Err(ManifestError::SenderRemoved { user: *user });
+                }
+                operations.push(Operation::RemoveUser { user: *user });
+            }
+        }
+    }
+
+    for key in current.procedures.keys() {
+        if !known(key) {
+            operations.push(Operation::Delete { key: key.clone() });
+        }
+    }
+    Ok(operations)
+}
+
+/// A single change to a kernel.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    /// Deploy a procedure's contract and register it.
+    Register {
+        key: String,
+    },
+    Delete {
+        key: String,
+    },
+    SetEntry {
+        key: String,
+    },
+    SetGroupProcedure {
+        group: u8,
+        key: String,
+    },
+    SetUserGroup {
+        user: Address,
+        group: u8,
+    },
+    RemoveUser {
+        user: Address,
+    },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Register { key } => write!(f, "+ register procedure \"{}\"", key),
+            Operation::Delete { key } => write!(f, "- delete procedure \"{}\"", key),
+            Operation::SetEntry { key } => write!(f, "~ set entry procedure to \"{}\"", key),
+            Operation::SetGroupProcedure { group, key } => {
+                write!(f, "~ set procedure of group {} to \"{}\"", group, key)
+            }
+            Operation::SetUserGroup { user, group } => {
+                write!(f, "~ set group of user {:?} to {}", user, group)
+            }
+            Operation::RemoveUser { user } => write!(f, "- remove user {:?}", user),
+        }
+    }
+}
+
+/// The operations which bring a kernel in line with a manifest, in the order
+/// they are to be carried out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    pub operations: Vec<Operation>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Carry out the plan through the ACL admin procedure, printing each
+    /// operation as it is done. A plan with an operation the ACL cannot carry
+    /// out is rejected before anything is sent.
+    pub fn apply<T: Transport>(
+        &self,
+        manifest: &Manifest,
+        kernel: &mut DeployedKernelWithACL<T>,
+    ) -> Result<(), ManifestError> {
+        for operation in self.operations.iter() {
+            if let Operation::SetEntry { .. } = operation {
+                return Err(ManifestError::Unsupported {
+                    operation: operation.to_string(),
+                });
+            }
+        }
+        for operation in self.operations.iter() {
+            println!("{}", operation);
+            match operation {
+                Operation::Register { key } => kernel
+                    .deploy_procedure(key.clone(), manifest.procedures[key].proc_spec())
+                    .map_err(|err| ManifestError::DeploymentFailure { err })?,
+                Operation::Delete { key } => kernel
+                    .delete_procedure(key.clone())
+                    .map_err(|err| ManifestError::DeploymentFailure { err })?,
+                Operation::SetEntry { .. } => unreachable!(),
+                Operation::SetGroupProcedure { group, key } => {
+                    kernel.call(
+                        "set_group_procedure",
+                        &[
+                            ethabi::Token::Uint(U256::from(*group)),
+                            ethabi::Token::Uint(encode_key(key)),
+                        ],
+                    );
+                }
+                Operation::SetUserGroup { user, group } => {
+                    kernel.call(
+                        "set_account_group",
+                        &[
+                            ethabi::Token::Address(*user),
+                            ethabi::Token::Uint(U256::from(*group)),
+                        ],
+                    );
+                }
+                Operation::RemoveUser { user } => {
+                    kernel.call("remove_account_group", &[ethabi::Token::Address(*user)]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode_key(key: &str) -> U256 {
+    proc_key_to_32_bytes(&string_to_proc_key(key.to_string())).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn procedure(code: u8) -> ProcedureState {
+        ProcedureState {
+            caps: Capabilities::new(),
+            code_hash: Some(H256::from([code; 32])),
+        }
+    }
+
+    /// A kernel with only the ACL, and the sender in the admin group.
+    fn acl_kernel(sender: Address) -> KernelState {
+        let mut state = KernelState::default();
+        state.procedures.insert("entry".to_string(), procedure(0));
+        state.procedures.insert("admin".to_string(), procedure(0));
+        state.entry = Some("entry".to_string());
+        state.groups.insert(1, "admin".to_string());
+        state.users.insert(sender, 1);
+        state
+    }
+
+    #[test]
+    fn register_then_repoint_group() {
+        let sender = Address::from_low_u64_be(1);
+        let current = acl_kernel(sender);
+        let mut desired = KernelState::default();
+        desired
+            .procedures
+            .insert("counter".to_string(), procedure(1));
+        desired.groups.insert(5, "counter".to_string());
+        let operations = diff(&current, &desired, false, sender).unwrap();
+        assert_eq!(
+            operations,
+            vec![
+                Operation::Register {
+                    key: "counter".to_string()
+                },
+                Operation::SetGroupProcedure {
+                    group: 5,
+                    key: "counter".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replace_changed_and_delete_unlisted() {
+        let sender = Address::from_low_u64_be(1);
+        let mut current = acl_kernel(sender);
+        current
+            .procedures
+            .insert("counter".to_string(), procedure(1));
+        current.procedures.insert("old".to_string(), procedure(1));
+        let mut desired = KernelState::default();
+        desired
+            .procedures
+            .insert("counter".to_string(), procedure(2));
+        let operations = diff(&current, &desired, false, sender).unwrap();
+        assert_eq!(
+            operations,
+            vec![
+                Operation::Delete {
+                    key: "counter".to_string()
+                },
+                Operation::Register {
+                    key: "counter".to_string()
+                },
+                Operation::Delete {
+                    key: "old".to_string()
+                },
+            ]
+        );
+        // Once applied, there is nothing left to do.
+        let mut applied = acl_kernel(sender);
+        applied
+            .procedures
+            .insert("counter".to_string(), procedure(2));
+        assert_eq!(diff(&applied, &desired, false, sender).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn acl_and_sender_are_protected() {
+        let sender = Address::from_low_u64_be(1);
+        let current = acl_kernel(sender);
+        let mut desired = KernelState::default();
+        desired.procedures.insert("admin".to_string(), procedure(1));
+        match diff(&current, &desired, false, sender) {
+            Err(ManifestError::AclProcedure { .. }) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+        match diff(&current, &KernelState::default(), true, sender) {
+            Err(ManifestError::SenderRemoved { .. }) => (),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+    #[test]
+    fn new_manifest_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kernel.json");
+        let mut manifest = Manifest::default();
+        let proc_spec = ProcSpec {
+            contract_spec: ContractSpec::from_files(
+                &PathBuf::from("counter/target/counter.wasm"),
+                &PathBuf::from("counter/target/json/CounterInterface.json"),
+            ),
+            cap_path: PathBuf::from("counter/caps.toml"),
+        };
+        manifest
+            .set_procedure("counter".to_string(), &proc_spec)
+            .unwrap();
+        manifest.write(&path).unwrap();
+
+        let manifest = Manifest::read(&path).unwrap();
+        let procedure = &manifest.procedures["counter"];
+        assert_eq!(
+            procedure.abi,
+            PathBuf::from("counter/target/json/CounterInterface.json")
+        );
+        assert_eq!(procedure.caps, PathBuf::from("counter/caps.toml"));
+        assert!(manifest.groups.is_empty());
+        assert!(manifest.users.is_none());
+    }
+}
@@ -0,0 +1,199 @@
+//! Network profiles, kept in `networks.json` in the project directory. A
+//! profile says which node the CLI talks to, and which account it sends
+//! transactions from.
+//!
+//! ```json
+//! {
+//!     "default": "dev",
+//!     "networks": {
+//!         "dev": { "url": "http://localhost:8545" },
+//!         "testnet": {
+//!             "url": "https://node.example.com",
+//!             "chain_id": 3,
+//!             "keystore": "keys/deployer.json",
+//!             "password_file": "keys/deployer.password"
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The account is taken from the first of these which the profile gives:
+//!
+//!   * `keystore`: an encrypted JSON keystore. The password is read from
+//!     `password_file`, or from the `CAP9_PASSWORD` environment variable.
+//!   * `key_file`: a file containing a hex encoded private key.
+//!   * `sender`: an account held by the node, unlocked with the password in
+//!     `password_file` if one is given.
+//!
+//! Transactions from a keystore or key file are signed by the CLI, so any node
+//! will do. A profile with none of these uses the development account of our
+//! parity fork, as the CLI always has.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use web3::types::Address;
+
+use crate::signer::{Signer, SignerError};
+
+/// The environment variable holding the keystore password, for profiles
+/// without a password file.
+pub const PASSWORD_VAR: &str = "CAP9_PASSWORD";
+
+#[derive(Debug, Fail)]
+pub enum NetworkError {
+    #[fail(display = "could not read networks file: {}", err)]
+    ReadFailure { err: String },
+    #[fail(display = "no network profile named \"{}\"", name)]
+    NoSuchProfile { name: String },
+    #[fail(
+        display = "no password for keystore {}, set password_file or {}",
+        path, var
+    )]
+    NoPassword { path: String, var: &'static str },
+    #[fail(display = "{}", err)]
+    SignerFailure { err: SignerError },
+    #[fail(
+        display = "the key controls {:?}, but the profile's sender is {:?}",
+        key_address, sender
+    )]
+    SenderMismatch {
+        key_address: Address,
+        sender: Address,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NetworksFile {
+    /// The profile used when none is named on the command line.
+    pub default: Option<String>,
+    pub networks: BTreeMap<String, NetworkProfile>,
+}
+
+impl NetworksFile {
+    /// A single profile, "dev", for a node on this machine.
+    pub fn dev() -> Self {
+        let mut networks = BTreeMap::new();
+        networks.insert(String::from("dev"), NetworkProfile::dev());
+        NetworksFile {
+            default: Some(String::from("dev")),
+            networks,
+        }
+    }
+
+    /// Read the networks file in `dir`. A project without one has only the
+    /// "dev" profile.
+    pub fn read_dir(dir: &Path) -> Result<Self, NetworkError> {
+        match File::open(dir.join("networks.json")) {
+            Ok(file) => serde_json::from_reader(file).map_err(|err| NetworkError::ReadFailure {
+                err: err.to_string(),
+            }),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(NetworksFile::dev()),
+            Err(err) => Err(NetworkError::ReadFailure {
+                err: err.to_string(),
+            }),
+        }
+    }
+
+    /// Write the networks file to `dir`.
+    pub fn write_dir(&self, dir: &Path) {
+        let file = File::create(dir.join("networks.json")).expect("could not create networks file");
+        serde_json::to_writer_pretty(file, self).expect("could not serialise to file");
+    }
+
+    /// The profile called `name`, or the default profile.
+    pub fn profile(&self, name: Option<&str>) -> Result<&NetworkProfile, NetworkError> {
+        let name = name
+            .or_else(|| self.default.as_ref().map(String::as_str))
+            .unwrap_or("dev");
+        self.networks
+            .get(name)
+            .ok_or_else(|| NetworkError::NoSuchProfile {
+                name: name.to_string(),
+            })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NetworkProfile {
+    /// The URL of the node's JSON-RPC endpoint.
+    pub url: String,
+    /// The chain id used to sign transactions (EIP-155).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<Address>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keystore: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+}
+
+/// The account transactions are sent from.
+pub enum Account {
+    /// The development account created on our parity fork.
+    Dev,
+    /// An account held by the node, and the password to unlock it, if it
+    /// needs unlocking.
+    Node {
+        address: Address,
+        password: Option<String>,
+    },
+    /// A key held by the CLI.
+    Local(Signer),
+}
+
+impl NetworkProfile {
+    pub fn dev() -> Self {
+        NetworkProfile {
+            url: String::from("http://localhost:8545"),
+            chain_id: None,
+            sender: None,
+            keystore: None,
+            key_file: None,
+            password_file: None,
+        }
+    }
+
+    /// The account this profile sends from. Paths in the profile are relative
+    /// to `dir`.
+    pub fn account(&self, dir: &Path) -> Result<Account, NetworkError> {
+        let password = match &self.password_file {
+            Some(path) => Some(
+                std::fs::read_to_string(dir.join(path))
+                    .map_err(|err| NetworkError::ReadFailure {
+                        err: err.to_string(),
+                    })?
+                    .trim_end_matches(&['\n', '\r'][..])
+                    .to_string(),
+            ),
+            None => None,
+        };
+        let signer = if let Some(keystore) = &self.keystore {
+            let password = password
+                .or_else(|| std::env::var(PASSWORD_VAR).ok())
+                .ok_or_else(|| NetworkError::NoPassword {
+                    path: keystore.display().to_string(),
+                    var: PASSWORD_VAR,
+                })?;
+            Signer::from_keystore(&dir.join(keystore), &password, self.chain_id)
+        } else if let Some(key_file) = &self.key_file {
+            Signer::from_key_file(&dir.join(key_file), self.chain_id)
+        } else {
+            return Ok(match self.sender {
+                Some(address) => Account::Node { address, password },
+                None => Account::Dev,
+            });
+        };
+        let signer = signer.map_err(|err| NetworkError::SignerFailure { err })?;
+        match self.sender {
+            Some(sender) if sender != signer.address() => Err(NetworkError::SenderMismatch {
+                key_address: signer.address(),
+                sender,
+            }),
+            _ => Ok(Account::Local(signer)),
+        }
+    }
+}
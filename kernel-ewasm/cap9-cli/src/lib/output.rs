@@ -0,0 +1,138 @@
+//! Output formats for the results of the fetch, call and query commands.
+//!
+//! The text format is meant to be read and may change. The JSON format is
+//! meant for scripts: each command prints a single JSON document, except for
+//! `fetch all-logs --follow`, which prints a document per log line. Hashes,
+//! addresses and byte strings are hex strings with a `0x` prefix, and numbers
+//! taken from the chain are hex strings as in the JSON-RPC API, except for ABI
+//! integers, which are decimal strings.
+use crate::fetch::DecodedLog;
+use rustc_hex::ToHex;
+use serde::Serialize;
+use serde_json::{json, Value};
+use web3::types::{Log, TransactionReceipt};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    /// The names of the formats, as given to `--format`.
+    pub const NAMES: [&'static str; 2] = ["text", "json"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Print a value as pretty-printed JSON.
+pub fn print_json<S: Serialize>(value: &S) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("could not encode JSON")
+    );
+}
+
+/// A log of the kernel, with its data as UTF-8 where it is valid UTF-8.
+pub fn log_json(log: &Log) -> Value {
+    let data: String = log.data.0.to_hex();
+    json!({
+        "address": log.address,
+        "topics": log.topics,
+        "data": format!("0x{}", data),
+        "text": String::from_utf8(log.data.0.clone()).ok(),
+        "block_number": log.block_number,
+        "transaction_hash": log.transaction_hash,
+        "log_index": log.log_index,
+    })
+}
+
+/// A log, with the event it was decoded as, or null.
+pub fn decoded_log_json(log: &DecodedLog) -> Value {
+    let mut value = log_json(&log.log);
+    value["event"] = match &log.event {
+        Some(event) => json!({
+            "name": event.name,
+            "sources": event.sources,
+            "params": event
+                .params
+                .iter()
+                .map(|(name, value)| json!({ "name": name, "value": token_json(value) }))
+                .collect::<Vec<_>>(),
+        }),
+        None => Value::Null,
+    };
+    value
+}
+
+pub fn receipt_json(receipt: &TransactionReceipt) -> Value {
+    json!({
+        "transaction_hash": receipt.transaction_hash,
+        "block_number": receipt.block_number,
+        "gas_used": receipt.gas_used,
+        "status": receipt.status,
+        "logs": receipt.logs.iter().map(log_json).collect::<Vec<_>>(),
+    })
+}
+
+/// An ABI value. Integers are given as decimal strings, as they may not fit
+/// in a JSON number.
+pub fn token_json(token: &ethabi::Token) -> Value {
+    match token {
+        ethabi::Token::Address(address) => {
+            let address: String = address.0.to_hex();
+            Value::String(format!("0x{}", address))
+        }
+        ethabi::Token::FixedBytes(bytes) | ethabi::Token::Bytes(bytes) => {
+            let bytes: String = bytes.to_hex();
+            Value::String(format!("0x{}", bytes))
+        }
+        ethabi::Token::Int(n) | ethabi::Token::Uint(n) => Value::String(n.to_string()),
+        ethabi::Token::Bool(b) => Value::Bool(*b),
+        ethabi::Token::String(s) => Value::String(s.clone()),
+        ethabi::Token::FixedArray(tokens) | ethabi::Token::Array(tokens) => {
+            Value::Array(tokens.iter().map(token_json).collect())
+        }
+    }
+}
+
+/// A function of an ABI, with its parameters and their types.
+pub fn function_json(function: &ethabi::Function) -> Value {
+    let params_json = |params: &[ethabi::Param]| -> Vec<Value> {
+        params
+            .iter()
+            .map(|param| json!({ "name": param.name, "type": param.kind.to_string() }))
+            .collect()
+    };
+    json!({
+        "name": function.name,
+        "inputs": params_json(&function.inputs),
+        "outputs": params_json(&function.outputs),
+        "constant": function.constant,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let token = ethabi::Token::Array(vec![
+            ethabi::Token::Uint(1_000_000.into()),
+            ethabi::Token::Bytes(vec![0xca, 0xfe]),
+            ethabi::Token::Bool(true),
+            ethabi::Token::String(String::from("hello")),
+        ]);
+        assert_eq!(
+            token_json(&token),
+            json!(["1000000", "0xcafe", true, "hello"])
+        );
+    }
+}
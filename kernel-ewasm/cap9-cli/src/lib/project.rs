@@ -9,13 +9,15 @@ use web3::futures::Future;
 use std::fs::create_dir;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::caps::CapFileError;
 use crate::connection;
 use crate::connection::*;
 use crate::default_procedures::*;
 use crate::deploy::*;
 use crate::fetch::*;
+use crate::network::NetworksFile;
 use crate::utils::*;
 use cap9_std::proc_table::cap::*;
 
@@ -45,6 +47,21 @@ impl DeployFile {
             standard_acl_abi: true,
         }
     }
+
+    /// Read the deploy file of the project in `dir`, if there is one.
+    pub fn read_dir(dir: &Path) -> Option<Self> {
+        match File::open(dir.join("deploy.json")) {
+            Ok(f) => Some(serde_json::from_reader(f).expect("Could not parse deploy file")),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => panic!("{:?}", err),
+        }
+    }
+
+    /// Write out the deploy file of the project in `dir`.
+    pub fn write_dir(&self, dir: &Path) {
+        let f = File::create(dir.join("deploy.json")).expect("Could not create file");
+        serde_json::ser::to_writer_pretty(f, self).expect("Could not serialise deploy data");
+    }
 }
 
 /// The information defining the structure of a deployed kernel. For example,
@@ -63,7 +80,7 @@ pub struct DeploySpec {
 //     }
 // }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ProcSpec {
     pub contract_spec: ContractSpec,
     pub cap_path: PathBuf,
@@ -87,7 +104,7 @@ impl ProcSpec {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ContractSpec {
     pub code_path: String,
     pub abi_path: String,
@@ -170,6 +187,10 @@ pub struct StatusFile {
     pub kernel_address: Address,
     /// A map from Contract addresses to ABI files.
     pub abis: HashMap<Address, PathBuf>,
+    /// A map from Contract addresses to the hash of the code they were built
+    /// from.
+    #[serde(default)]
+    pub code_hashes: HashMap<Address, H256>,
 }
 
 impl StatusFile {
@@ -177,12 +198,17 @@ impl StatusFile {
         StatusFile {
             kernel_address: address,
             abis: HashMap::new(),
+            code_hashes: HashMap::new(),
         }
     }
 
     pub fn add_abi(&mut self, contract_address: Address, abi_path: PathBuf) {
         self.abis.insert(contract_address, abi_path);
     }
+
+    pub fn add_code_hash(&mut self, contract_address: Address, code_hash: H256) {
+        self.code_hashes.insert(contract_address, code_hash);
+    }
 }
 
 #[derive(Debug, Fail)]
@@ -199,6 +225,8 @@ pub enum ProjectDeploymentError {
     BadParameters { err: String },
     #[fail(display = "Could not form a proxied contract: {}", err)]
     ProxiedProcedureError { err: String },
+    #[fail(display = "invalid caps: {}", err)]
+    InvalidCaps { err: CapFileError },
 }
 
 /// A representation of the local project information. Methods on this struct
@@ -250,6 +278,7 @@ impl LocalProject {
         let f = File::create(&path).expect("Could not create file");
         serde_json::ser::to_writer_pretty(f, &deploy_file)
             .expect("Could not serialise deploy data");
+        NetworksFile::dev().write_dir(&dir);
         let abs_path = PathBuf::from(".").canonicalize().unwrap();
         LocalProject {
             abs_path,
@@ -292,6 +321,7 @@ impl LocalProject {
         let f = File::create(&path).expect("Could not create file");
         serde_json::ser::to_writer_pretty(f, &deploy_file)
             .expect("Could not serialise deploy data");
+        NetworksFile::dev().write_dir(&dir);
         let abs_path = PathBuf::from(".").canonicalize().unwrap();
         LocalProject {
             abs_path,
@@ -638,12 +668,8 @@ impl LocalProject {
             panic!("ACL init failed!");
         }
 
-        let keys: Vec<H256> = serde_json::value::from_value(
-            connection::list_storage_keys(deployed_kernel.address())
-                .result
-                .unwrap(),
-        )
-        .unwrap();
+        let keys: Vec<H256> =
+            connection::list_storage_keys(conn, deployed_kernel.address()).unwrap();
         for key in keys {
             let val = conn
                 .web3
@@ -0,0 +1,339 @@
+//! Client side signing of transactions.
+//!
+//! [SigningTransport] wraps the transport to a node. Requests to
+//! `eth_sendTransaction` are signed with a local key and sent to the node with
+//! `eth_sendRawTransaction`, so the node need not hold or unlock the sender's
+//! account. All other requests are passed through unchanged, which means
+//! `web3::contract::Contract` and everything built on it work as before.
+use ethsign::{KeyFile, Protected, SecretKey};
+use serde::Deserialize;
+use serde_json::json;
+use std::fs::File;
+use std::path::Path;
+use std::rc::Rc;
+use tiny_keccak::keccak256;
+use web3::futures::future::{self, Either};
+use web3::futures::Future;
+use web3::rpc::{self, Value};
+use web3::types::{Address, Bytes, U256};
+use web3::{RequestId, Transport};
+
+#[derive(Debug, Fail)]
+pub enum SignerError {
+    #[fail(display = "could not read key file {}: {}", path, err)]
+    ReadFailure { path: String, err: String },
+    #[fail(display = "could not decrypt keystore {}: {}", path, err)]
+    DecryptionFailure { path: String, err: String },
+    #[fail(display = "invalid private key in {}", path)]
+    InvalidKey { path: String },
+}
+
+/// A private key, with the address it controls.
+#[derive(Clone)]
+pub struct Signer {
+    key: Rc<SecretKey>,
+    address: Address,
+    /// The chain id for EIP-155 replay protection. Without one, transactions
+    /// are signed as before EIP-155.
+    chain_id: Option<u64>,
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Signer")
+            .field("address", &self.address)
+            .field("chain_id", &self.chain_id)
+            .finish()
+    }
+}
+
+impl Signer {
+    pub fn new(key: SecretKey, chain_id: Option<u64>) -> Self {
+        let address = Address::from_slice(key.public().address());
+        Signer {
+            key: Rc::new(key),
+            address,
+            chain_id,
+        }
+    }
+
+    /// Read a key from an encrypted JSON keystore file, as written by geth,
+    /// parity, and most wallets.
+    pub fn from_keystore(
+        path: &Path,
+        password: &str,
+        chain_id: Option<u64>,
+    ) -> Result<Self, SignerError> {
+        let file = File::open(path).map_err(|err| SignerError::ReadFailure {
+            path: path.display().to_string(),
+            err: err.to_string(),
+        })?;
+        let key_file: KeyFile =
+            serde_json::from_reader(file).map_err(|err| SignerError::ReadFailure {
+                path: path.display().to_string(),
+                err: err.to_string(),
+            })?;
+        let password: Protected = password.into();
+        let key =
+            key_file
+                .to_secret_key(&password)
+                .map_err(|err| SignerError::DecryptionFailure {
+                    path: path.display().to_string(),
+                    err: format!("{:?}", err),
+                })?;
+        Ok(Signer::new(key, chain_id))
+    }
+
+    /// Read a key from a file containing the hex encoded private key.
+    pub fn from_key_file(path: &Path, chain_id: Option<u64>) -> Result<Self, SignerError> {
+        let contents = std::fs::read_to_string(path).map_err(|err| SignerError::ReadFailure {
+            path: path.display().to_string(),
+            err: err.to_string(),
+        })?;
+        let invalid = || SignerError::InvalidKey {
+            path: path.display().to_string(),
+        };
+        let bytes: Vec<u8> = rustc_hex::FromHex::from_hex(contents.trim().trim_start_matches("0x"))
+            .map_err(|_| invalid())?;
+        let key = SecretKey::from_raw(&bytes).map_err(|_| invalid())?;
+        Ok(Signer::new(key, chain_id))
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Sign a transaction, returning the RLP encoding of the signed
+    /// transaction.
+    pub fn sign(&self, tx: &RawTransaction) -> Result<Vec<u8>, String> {
+        let mut fields = tx.fields();
+        if let Some(chain_id) = self.chain_id {
+            fields.push(uint(chain_id.into()));
+            fields.push(Vec::new());
+            fields.push(Vec::new());
+        }
+        let hash = keccak256(&rlp_list(&fields));
+        let signature = self.key.sign(&hash).map_err(|err| format!("{:?}", err))?;
+        let v = match self.chain_id {
+            Some(chain_id) => U256::from(signature.v) + U256::from(35 + chain_id * 2),
+            None => U256::from(signature.v) + U256::from(27),
+        };
+        let mut fields = tx.fields();
+        fields.push(uint(v));
+        fields.push(uint(U256::from_big_endian(&signature.r)));
+        fields.push(uint(U256::from_big_endian(&signature.s)));
+        Ok(rlp_list(&fields))
+    }
+}
+
+/// A transaction with every field filled in, ready to be signed.
+#[derive(Clone, Debug)]
+pub struct RawTransaction {
+    pub nonce: U256,
+    pub gas_price: U256,
+    pub gas: U256,
+    /// The recipient, or `None` to create a contract.
+    pub to: Option<Address>,
+    pub value: U256,
+    pub data: Vec<u8>,
+}
+
+impl RawTransaction {
+    fn fields(&self) -> Vec<Vec<u8>> {
+        vec![
+            uint(self.nonce),
+            uint(self.gas_price),
+            uint(self.gas),
+            self.to.map(|to| to.as_bytes().to_vec()).unwrap_or_default(),
+            uint(self.value),
+            self.data.clone(),
+        ]
+    }
+}
+
+/// An integer as RLP expects it: big endian, without leading zeros.
+fn uint(value: U256) -> Vec<u8> {
+    let mut bytes = [0; 32];
+    value.to_big_endian(&mut bytes);
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    bytes[zeros..].to_vec()
+}
+
+fn rlp_length_prefix(len: usize, short: u8, long: u8) -> Vec<u8> {
+    if len < 56 {
+        vec![short + len as u8]
+    } else {
+        let len_bytes = uint(len.into());
+        let mut prefix = vec![long + len_bytes.len() as u8];
+        prefix.extend(len_bytes);
+        prefix
+    }
+}
+
+/// The RLP encoding of a list of byte strings.
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for item in items {
+        if item.len() == 1 && item[0] < 0x80 {
+            payload.push(item[0]);
+        } else {
+            payload.extend(rlp_length_prefix(item.len(), 0x80, 0xb7));
+            payload.extend_from_slice(item);
+        }
+    }
+    let mut encoded = rlp_length_prefix(payload.len(), 0xc0, 0xf7);
+    encoded.extend(payload);
+    encoded
+}
+
+/// The fields of `eth_sendTransaction` requests which are signed.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionRequest {
+    to: Option<Address>,
+    gas: Option<U256>,
+    gas_price: Option<U256>,
+    value: Option<U256>,
+    data: Option<Bytes>,
+    nonce: Option<U256>,
+}
+
+/// A transport which signs transactions locally before sending them to the
+/// node through `T`.
+#[derive(Clone, Debug)]
+pub struct SigningTransport<T> {
+    transport: T,
+    signer: Signer,
+}
+
+impl<T: Transport + 'static> SigningTransport<T>
+where
+    T::Out: 'static,
+{
+    pub fn new(transport: T, signer: Signer) -> Self {
+        SigningTransport { transport, signer }
+    }
+
+    pub fn signer(&self) -> &Signer {
+        &self.signer
+    }
+
+    /// Fill in the fields the request leaves out, then sign and send it.
+    fn send_transaction(
+        &self,
+        request: Value,
+    ) -> Box<dyn Future<Item = Value, Error = web3::Error>> {
+        let tx: TransactionRequest = match serde_json::from_value(request.clone()) {
+            Ok(tx) => tx,
+            Err(err) => return Box::new(future::err(web3::Error::Transport(err.to_string()))),
+        };
+        let from = json!(self.signer.address());
+        let nonce = match tx.nonce {
+            Some(nonce) => Either::A(future::ok(json!(nonce))),
+            None => Either::B(self.transport.execute(
+                "eth_getTransactionCount",
+                vec![from.clone(), json!("pending")],
+            )),
+        };
+        let gas_price = match tx.gas_price {
+            Some(gas_price) => Either::A(future::ok(json!(gas_price))),
+            None => Either::B(self.transport.execute("eth_gasPrice", vec![])),
+        };
+        let gas = match tx.gas {
+            Some(gas) => Either::A(future::ok(json!(gas))),
+            None => {
+                let mut estimate = request;
+                estimate["from"] = from;
+                Either::B(self.transport.execute("eth_estimateGas", vec![estimate]))
+            }
+        };
+        let transport = self.transport.clone();
+        let signer = self.signer.clone();
+        Box::new(
+            nonce
+                .join3(gas_price, gas)
+                .and_then(move |(nonce, gas_price, gas)| {
+                    let quantity = |value: Value| {
+                        serde_json::from_value::<U256>(value)
+                            .map_err(|err| web3::Error::InvalidResponse(err.to_string()))
+                    };
+                    let raw = RawTransaction {
+                        nonce: quantity(nonce)?,
+                        gas_price: quantity(gas_price)?,
+                        gas: quantity(gas)?,
+                        to: tx.to,
+                        value: tx.value.unwrap_or_else(U256::zero),
+                        data: tx.data.map(|data| data.0).unwrap_or_default(),
+                    };
+                    let signed = signer.sign(&raw).map_err(web3::Error::Transport)?;
+                    Ok(transport.execute("eth_sendRawTransaction", vec![json!(Bytes(signed))]))
+                })
+                .flatten(),
+        )
+    }
+}
+
+impl<T: Transport + 'static> Transport for SigningTransport<T>
+where
+    T::Out: 'static,
+{
+    type Out = Box<dyn Future<Item = Value, Error = web3::Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, rpc::Call) {
+        self.transport.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        match request {
+            rpc::Call::MethodCall(ref call) if call.method == "eth_sendTransaction" => {
+                let tx = match &call.params {
+                    rpc::Params::Array(params) => params.first().cloned().unwrap_or(Value::Null),
+                    _ => Value::Null,
+                };
+                self.send_transaction(tx)
+            }
+            // The node does not hold the key, so report the signer's account
+            // instead of the node's.
+            rpc::Call::MethodCall(ref call) if call.method == "eth_accounts" => {
+                Box::new(future::ok(json!([self.signer.address()])))
+            }
+            request => Box::new(self.transport.send(id, request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::{FromHex, ToHex};
+
+    /// The example transaction from EIP-155.
+    #[test]
+    fn eip155_example() {
+        let key = SecretKey::from_raw(&[0x46; 32]).unwrap();
+        let signer = Signer::new(key, Some(1));
+        let tx = RawTransaction {
+            nonce: 9.into(),
+            gas_price: 20_000_000_000u64.into(),
+            gas: 21000.into(),
+            to: Some(Address::from_slice(&[0x35; 20])),
+            value: 1_000_000_000_000_000_000u64.into(),
+            data: Vec::new(),
+        };
+        let signed: String = signer.sign(&tx).unwrap().to_hex();
+        assert_eq!(
+            signed,
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+    }
+
+    #[test]
+    fn rlp_long_string() {
+        let data: Vec<u8> = "aa".repeat(56).from_hex().unwrap();
+        let encoded = rlp_list(&[data.clone()]);
+        // A list of one 56 byte string: the string needs a length byte, so
+        // the payload is 58 bytes and needs one too.
+        assert_eq!(&encoded[..4], &[0xf8, 58, 0xb8, 56]);
+        assert_eq!(&encoded[4..], &data[..]);
+    }
+}
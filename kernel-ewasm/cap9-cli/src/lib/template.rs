@@ -0,0 +1,170 @@
+//! Templates for new procedure crates, as created by `new-procedure`.
+//!
+//! Each template is a Cargo crate with an `eth_abi` interface, a symbolic cap
+//! file (`caps.toml`) granting the caps the procedure uses, and a test which
+//! runs the procedure on [cap9_std::TestKernel] with those caps.
+use std::fs::{create_dir, File};
+use std::io::prelude::*;
+use std::path::Path;
+
+/// The flags for the stack size we need when compiling to WASM.
+const CARGO_CONFIG: &str = "[target.wasm32-unknown-unknown]\n
+rustflags = [
+  \"-C\", \"link-args=-z stack-size=65536\",
+]";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    /// A procedure with no caps.
+    Empty,
+    /// A procedure keeping a map from accounts to values in its storage.
+    StorageMap,
+    /// A group procedure for the standard ACL, which calls other procedures
+    /// for the members of its group.
+    AclGroup,
+    /// A procedure which logs.
+    Logger,
+}
+
+impl Template {
+    /// The names of the templates, as given to `--template`.
+    pub const NAMES: [&'static str; 4] = ["empty", "storage-map", "acl-group", "logger"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "empty" => Some(Template::Empty),
+            "storage-map" => Some(Template::StorageMap),
+            "acl-group" => Some(Template::AclGroup),
+            "logger" => Some(Template::Logger),
+            _ => None,
+        }
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Template::Empty => include_str!("templates/empty.rs.example"),
+            Template::StorageMap => include_str!("templates/storage_map.rs.example"),
+            Template::AclGroup => include_str!("templates/acl_group.rs.example"),
+            Template::Logger => include_str!("templates/logger.rs.example"),
+        }
+    }
+
+    /// The name of the `eth_abi` interface trait, which names the ABI file.
+    fn interface(self) -> &'static str {
+        match self {
+            Template::Empty => "ProcedureInterface",
+            Template::StorageMap => "StorageMapInterface",
+            Template::AclGroup => "GroupInterface",
+            Template::Logger => "TestLoggerInterface",
+        }
+    }
+
+    fn caps(self) -> &'static str {
+        match self {
+            Template::Empty => include_str!("templates/empty.toml.example"),
+            Template::StorageMap => include_str!("templates/storage_map.toml.example"),
+            Template::AclGroup => include_str!("templates/acl_group.toml.example"),
+            Template::Logger => include_str!("templates/logger.toml.example"),
+        }
+    }
+
+    /// Create a procedure crate called `name` in the directory `path`, which
+    /// must not already exist.
+    pub fn create(self, path: &Path, name: &str) -> std::io::Result<()> {
+        create_dir(path)?;
+        create_dir(path.join("src"))?;
+        File::create(path.join("src").join("main.rs"))?.write_all(self.source().as_bytes())?;
+
+        let mut cargo_toml: toml::Value =
+            toml::from_str(include_str!("templates/Cargo.toml.example")).unwrap();
+        let package = cargo_toml
+            .get_mut("package")
+            .and_then(|package| package.as_table_mut())
+            .unwrap();
+        package.insert("name".to_string(), toml::Value::String(name.to_string()));
+        // `compile` finds the ABI by the name of the interface.
+        let mut cap9 = toml::value::Table::new();
+        cap9.insert(
+            "abi".to_string(),
+            toml::Value::String(self.interface().to_string()),
+        );
+        let mut metadata = toml::value::Table::new();
+        metadata.insert("cap9".to_string(), toml::Value::Table(cap9));
+        package.insert("metadata".to_string(), toml::Value::Table(metadata));
+        File::create(path.join("Cargo.toml"))?
+            .write_all(toml::to_string_pretty(&cargo_toml).unwrap().as_bytes())?;
+
+        create_dir(path.join(".cargo"))?;
+        File::create(path.join(".cargo").join("config"))?.write_all(CARGO_CONFIG.as_bytes())?;
+
+        File::create(path.join("caps.toml"))?.write_all(self.caps().as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caps::read_cap_file;
+    use cap9_std::proc_table::cap::*;
+
+    /// The caps of the ACL admin procedure, which registers new procedures.
+    fn admin_caps() -> Capabilities {
+        let mut high = [0; 32];
+        high[0] = 0x30;
+        let mut size = [0; 32];
+        size[0] = 0x10;
+        Capabilities::from(vec![
+            Capability::ProcedureCall(ProcedureCallCap {
+                prefix: 0,
+                key: [0; 24],
+            }),
+            Capability::Log(LogCap {
+                topics: 0,
+                t1: [0; 32],
+                t2: [0; 32],
+                t3: [0; 32],
+                t4: [0; 32],
+            }),
+            Capability::StoreWrite(StoreWriteCap {
+                location: [0; 32],
+                size,
+            }),
+            Capability::StoreWrite(StoreWriteCap {
+                location: high,
+                size,
+            }),
+        ])
+    }
+
+    #[test]
+    fn create_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in Template::NAMES.iter() {
+            let template = Template::from_name(name).unwrap();
+            let path = dir.path().join(name);
+            template.create(&path, "my_proc").unwrap();
+
+            let cargo_toml: toml::Value =
+                toml::from_str(&std::fs::read_to_string(path.join("Cargo.toml")).unwrap()).unwrap();
+            assert_eq!(
+                cargo_toml["package"]["name"].as_str(),
+                Some("my_proc"),
+                "{}",
+                name
+            );
+            assert_eq!(
+                cargo_toml["package"]["metadata"]["cap9"]["abi"].as_str(),
+                Some(template.interface()),
+                "{}",
+                name
+            );
+            assert!(path.join("src").join("main.rs").exists(), "{}", name);
+            // The ACL admin procedure can grant the caps of each template.
+            read_cap_file(&path.join("caps.toml"), &admin_caps())
+                .unwrap_or_else(|err| panic!("{}: {}", name, err));
+            // The directory exists now.
+            assert!(template.create(&path, "my_proc").is_err(), "{}", name);
+        }
+    }
+}
@@ -187,6 +187,198 @@ mod integration {
         }
     }
 
+    /// The number of groups and procedures in the kernel of a project
+    /// deployed to the local chain. The chain is read afresh each time, as
+    /// each run of the CLI saves its changes to the chain file.
+    fn local_counts(project_dir: &PathBuf) -> (usize, usize) {
+        let chain_path: PathBuf = [project_dir, &PathBuf::from("chain.json")].iter().collect();
+        let conn = connection::EthConn::new_local(chain_path);
+        let local_project = project::LocalProject::read_dir(project_dir);
+        let kernel = DeployedKernel::new(&conn, local_project);
+        let kernel_with_acl = DeployedKernelWithACL::new(kernel);
+        (
+            kernel_with_acl.groups().len(),
+            kernel_with_acl.kernel.procedures().len(),
+        )
+    }
+
+    #[test]
+    fn create_and_deploy_local() {
+        let project_name = "example";
+
+        // Create a directory inside the temporary directory of the system.
+        let dir = tempdir().unwrap();
+
+        // Create a new project
+        Command::cargo_bin("cap9-cli")
+            .unwrap()
+            .arg("new")
+            .arg("--acl")
+            .arg(project_name)
+            .current_dir(dir.path())
+            .assert()
+            .success();
+
+        let mut project_dir = std::path::PathBuf::new();
+        project_dir.push(dir.path());
+        project_dir.push(project_name);
+
+        // Deploy the kernel to the local chain, no node is required.
+        Command::cargo_bin("cap9-cli")
+            .unwrap()
+            .arg("--local")
+            .arg("deploy")
+            .current_dir(&project_dir)
+            .assert()
+            .success();
+
+        // There should be one group, and the entry and admin procedures.
+        assert_eq!(local_counts(&project_dir), (1, 2));
+
+        let wasm_path: PathBuf = [&project_dir, &PathBuf::from("acl_group_5.wasm")]
+            .iter()
+            .collect();
+        let json_path: PathBuf = [&project_dir, &PathBuf::from("ACLGroup5Interface.json")]
+            .iter()
+            .collect();
+        let caps_path: PathBuf = [&project_dir, &PathBuf::from("example_caps.json")]
+            .iter()
+            .collect();
+        std::fs::copy(PathBuf::from("src/lib/acl_group_5.wasm"), wasm_path).unwrap();
+        std::fs::copy(PathBuf::from("src/lib/ACLGroup5Interface.json"), json_path).unwrap();
+        std::fs::copy(PathBuf::from("src/lib/example_caps.json"), caps_path).unwrap();
+
+        // Add a new group to the kernel
+        Command::cargo_bin("cap9-cli")
+            .unwrap()
+            .arg("--local")
+            .arg("new-group")
+            .arg("5")
+            .arg("randomProcName")
+            .arg("acl_group_5.wasm")
+            .arg("ACLGroup5Interface.json")
+            .arg("example_caps.json")
+            .current_dir(&project_dir)
+            .assert()
+            .success();
+
+        // There should now be two groups, and the group 5 procedure.
+        assert_eq!(local_counts(&project_dir), (2, 3));
+
+        Command::cargo_bin("cap9-cli")
+            .unwrap()
+            .arg("--local")
+            .arg("fetch")
+            .arg("procedures")
+            .current_dir(&project_dir)
+            .assert()
+            .success();
+
+        // The same, as JSON.
+        let fetch_json = |args: &[&str]| -> serde_json::Value {
+            let res = Command::cargo_bin("cap9-cli")
+                .unwrap()
+                .args(&["--local", "--format", "json", "fetch"])
+                .args(args)
+                .current_dir(&project_dir)
+                .assert()
+                .success();
+            serde_json::from_slice(&res.get_output().stdout).expect("invalid JSON")
+        };
+        let procs = fetch_json(&["procedures"]);
+        assert_eq!(procs.as_array().map(|procs| procs.len()), Some(3));
+        assert!(procs[0]["caps"].is_array());
+        let groups = fetch_json(&["acl", "groups"]);
+        assert_eq!(groups[0]["id"], 1);
+        assert_eq!(groups[1]["id"], 5);
+        assert!(fetch_json(&["acl", "users"]).is_object());
+        assert!(fetch_json(&["storage"]).is_array());
+    }
+
+    #[test]
+    fn plan_and_apply_manifest_local() {
+        let project_name = "example";
+
+        // Create a directory inside the temporary directory of the system.
+        let dir = tempdir().unwrap();
+
+        // Create a new project and deploy it to the local chain.
+        Command::cargo_bin("cap9-cli")
+            .unwrap()
+            .arg("new")
+            .arg("--acl")
+            .arg(project_name)
+            .current_dir(dir.path())
+            .assert()
+            .success();
+
+        let mut project_dir = std::path::PathBuf::new();
+        project_dir.push(dir.path());
+        project_dir.push(project_name);
+
+        Command::cargo_bin("cap9-cli")
+            .unwrap()
+            .arg("--local")
+            .arg("deploy")
+            .current_dir(&project_dir)
+            .assert()
+            .success();
+
+        for (from, to) in &[
+            ("src/lib/acl_group_5.wasm", "acl_group_5.wasm"),
+            ("src/lib/ACLGroup5Interface.json", "ACLGroup5Interface.json"),
+            ("src/lib/example_caps.json", "example_caps.json"),
+        ] {
+            let to_path: PathBuf = [&project_dir, &PathBuf::from(to)].iter().collect();
+            std::fs::copy(PathBuf::from(from), to_path).unwrap();
+        }
+        let manifest = r#"{
+            "procedures": {
+                "group5": {
+                    "code": "acl_group_5.wasm",
+                    "abi": "ACLGroup5Interface.json",
+                    "caps": "example_caps.json"
+                }
+            },
+            "groups": { "5": "group5" }
+        }"#;
+        let manifest_path: PathBuf = [&project_dir, &PathBuf::from("kernel.json")]
+            .iter()
+            .collect();
+        std::fs::write(manifest_path, manifest).unwrap();
+
+        let plan = |project_dir: &PathBuf| {
+            let output = Command::cargo_bin("cap9-cli")
+                .unwrap()
+                .arg("--local")
+                .arg("plan")
+                .current_dir(project_dir)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        // The plan registers the procedure and adds the group, but changes
+        // nothing itself.
+        let before = plan(&project_dir);
+        assert!(before.contains("+ register procedure \"group5\""));
+        assert!(before.contains("~ set procedure of group 5 to \"group5\""));
+        assert_eq!(local_counts(&project_dir), (1, 2));
+
+        Command::cargo_bin("cap9-cli")
+            .unwrap()
+            .arg("--local")
+            .arg("apply")
+            .current_dir(&project_dir)
+            .assert()
+            .success();
+        assert_eq!(local_counts(&project_dir), (2, 3));
+
+        // Once applied, the kernel matches the manifest.
+        assert!(plan(&project_dir).contains("No changes"));
+    }
+
     #[test]
     fn create_and_deploy_new_procedure() {
         let project_name = "example";
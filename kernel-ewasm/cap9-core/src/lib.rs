@@ -22,6 +22,15 @@ pub enum Error {
     InvalidData,
 }
 
+/// Set in the syscall type of a variant of the syscall for a cap type, such as
+/// a batch of writes. The other bits are the type of the cap it requires, so
+/// that the cap a syscall requires can be found without decoding it.
+pub const SYSCALL_VARIANT: u8 = 0x80;
+
+/// Return the type of the cap required by a syscall of the given type.
+pub fn syscall_cap_type(syscall_type: u8) -> u8 {
+    syscall_type & !SYSCALL_VARIANT
+}
 
 pub trait Write<T> {
     /// Write a buffer of data into this write.
@@ -185,6 +194,9 @@ impl Deserialize<U256> for u8 {
     fn deserialize<R: Read<U256>>(reader: &mut R) -> Result<Self, Self::Error> {
         let mut buf = [U256::zero(); 1];
         reader.read(&mut buf)?;
+        if buf[0] > U256::from(u8::max_value()) {
+            return Err(Error::InvalidData);
+        }
         Ok(buf[0].as_u32() as u8)
     }
 }
@@ -213,6 +225,65 @@ impl Serialize<u8> for U256 {
 }
 
 
+impl Deserialize<U256> for U256 {
+    type Error = Error;
+
+    fn deserialize<R: Read<U256>>(reader: &mut R) -> Result<Self, Self::Error> {
+        let mut buf = [U256::zero(); 1];
+        reader.read(&mut buf)?;
+        Ok(buf[0])
+    }
+}
+
+impl Serialize<U256> for U256 {
+    type Error = Error;
+
+    fn serialize<W: Write<U256>>(&self, writer: &mut W) -> Result<(), Self::Error> {
+        writer.write(&[*self])?;
+        Ok(())
+    }
+}
+
+
+impl Deserialize<u8> for [u8; 32] {
+    type Error = Error;
+
+    fn deserialize<R: Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
+        let mut u8buf = [0u8; 32];
+        reader.read(&mut u8buf)?;
+        Ok(u8buf)
+    }
+}
+
+impl Serialize<u8> for [u8; 32] {
+    type Error = Error;
+
+    fn serialize<W: Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
+        writer.write(self)?;
+        Ok(())
+    }
+}
+
+impl Deserialize<U256> for [u8; 32] {
+    type Error = Error;
+
+    fn deserialize<R: Read<U256>>(reader: &mut R) -> Result<Self, Self::Error> {
+        let mut buf = [U256::zero(); 1];
+        reader.read(&mut buf)?;
+        Ok(buf[0].into())
+    }
+}
+
+impl Serialize<U256> for [u8; 32] {
+    type Error = Error;
+
+    fn serialize<W: Write<U256>>(&self, writer: &mut W) -> Result<(), Self::Error> {
+        writer.write(&[U256::from(*self)])?;
+        Ok(())
+    }
+}
+
+
 impl Deserialize<u8> for H256 {
     type Error = Error;
 
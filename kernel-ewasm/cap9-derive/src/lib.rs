@@ -0,0 +1,792 @@
+//! Derive macros for the `Storable` and `Keyable` traits of `cap9_std::data`.
+//!
+//! `#[derive(Storable)]` works for structs and enums whose fields are all
+//! `Storable`. The fields of a struct are stored one after the other, in the
+//! order they are declared, so a struct takes the sum of the storage keys of
+//! its fields. An enum takes one storage key for the variant, which is the
+//! index of the variant plus one, followed by the fields of the variant, and
+//! takes as many storage keys as its largest variant. A variant of zero is
+//! never stored, so `read` returns `None` for cleared storage.
+//!
+//! ```ignore
+//! #[derive(Storable)]
+//! struct Account {
+//!     balance: U256,
+//!     group: u8,
+//! }
+//! ```
+//!
+//! `#[derive(Keyable)]` works for newtypes of a `Keyable` type, which must
+//! also derive `Clone`. The newtype has the same key width as the type it
+//! wraps, and also gets the conversions to and from `StorageValue` that
+//! `Keyable` requires.
+//!
+//! ```ignore
+//! #[derive(Clone, Keyable)]
+//! struct GroupId(u8);
+//! ```
+//!
+//! The generated code refers to `cap9_std`, which must be a dependency of the
+//! crate using the derives.
+//!
+//! `#[derive(Serialize, Deserialize)]` implement the `Serialize` and
+//! `Deserialize` traits of `cap9_core` for structs and enums. The fields are
+//! written one after the other, in the order they are declared. An enum first
+//! writes the index of the variant as a `u8`, and returns
+//! `Error::InvalidData` when it reads an unknown variant. The impls are
+//! generic over the word type, so a type can be written as `u8` or `U256`
+//! words as long as all of its fields can, and it takes
+//! `cap9_core::Error` as its error. A field which has no codec of its own can
+//! be written as another type with `#[serialize(via = "Type")]`, which the
+//! field type must convert to and from with `Into`. The same attribute on the
+//! struct or enum itself writes the whole value as that type, which is how
+//! values packed into a single word are written.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct DeleteProc {
+//!     #[serialize(via = "SysCallProcedureKey")]
+//!     proc_id: ProcedureKey,
+//! }
+//! ```
+//!
+//! A collection field, such as a `Vec`, is written as its items. With
+//! `#[serialize(len = "Type")]` the number of items is written first, as
+//! `Type`, and with `#[serialize(rest)]` the items take up the rest of the
+//! input, so it must be the last field. A field with
+//! `#[serialize(max = N)]` returns `Error::InvalidData` when the value read is
+//! greater than `N`.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct LogCall {
+//!     #[serialize(len = "u8")]
+//!     topics: Vec<H256>,
+//!     #[serialize(rest)]
+//!     value: Vec<u8>,
+//! }
+//! ```
+//!
+//! These refer to `cap9_core` rather than `cap9_std`, so that they can be used
+//! within `cap9_std` itself.
+// The larger `quote!` invocations need more than the default limit of 64.
+#![recursion_limit = "128"]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, ToTokens};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Fields, GenericParam, Ident, Lit, Meta,
+    NestedMeta, Type,
+};
+
+#[proc_macro_derive(Storable)]
+pub fn derive_storable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let result = match &input.data {
+        Data::Struct(data) => Ok(storable_struct(&input, &data.fields)),
+        Data::Enum(data) => Ok(storable_enum(&input, &data.variants)),
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &input.ident,
+            "Storable cannot be derived for unions",
+        )),
+    };
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(Keyable)]
+pub fn derive_keyable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match keyable(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(Serialize, attributes(serialize))]
+pub fn derive_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match serialize(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(Deserialize, attributes(serialize))]
+pub fn derive_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match deserialize(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The names of the local variables which hold the fields.
+fn field_vars(fields: &Fields) -> Vec<Ident> {
+    (0..fields.iter().count())
+        .map(|i| Ident::new(&format!("__field{}", i), Span::call_site()))
+        .collect()
+}
+
+/// An expression or pattern which builds the fields from the local variables
+/// in `vars`, after `path`, which is the struct or enum variant.
+fn construct(path: TokenStream2, fields: &Fields, vars: &[Ident]) -> TokenStream2 {
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote! { #path { #(#names: #vars),* } }
+        }
+        Fields::Unnamed(_) => quote! { #path ( #(#vars),* ) },
+        Fields::Unit => path,
+    }
+}
+
+/// The number of storage keys used by each of some fields.
+fn field_n_keys(fields: &Fields) -> Vec<TokenStream2> {
+    fields
+        .iter()
+        .map(|field| {
+            let ty = &field.ty;
+            quote! { <#ty as ::cap9_std::data::Storable>::n_keys() }
+        })
+        .collect()
+}
+
+/// The number of storage keys used by some fields.
+fn fields_n_keys(fields: &Fields) -> TokenStream2 {
+    let types = fields.iter().map(|field| &field.ty);
+    quote! {
+        ::cap9_std::data::derive::U256::zero()
+            #(+ <#types as ::cap9_std::data::Storable>::n_keys())*
+    }
+}
+
+/// Statements which store the fields, which are references in `vars`,
+/// starting at the storage key `__offset`. Afterwards `__offset` is the
+/// storage key after the fields.
+fn store_fields<T: ToTokens>(fields: &Fields, vars: &[T]) -> TokenStream2 {
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let n_keys = field_n_keys(fields);
+    quote! {
+        #(
+            <#types as ::cap9_std::data::Storable>::store(#vars, cap_index, __offset);
+            let __offset = __offset + #n_keys;
+        )*
+    }
+}
+
+/// Statements which read the fields into `vars`, starting at the storage key
+/// `__offset`, returning `None` if any field can't be read.
+fn read_fields(fields: &Fields, vars: &[Ident]) -> TokenStream2 {
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let n_keys = field_n_keys(fields);
+    quote! {
+        #(
+            let #vars = <#types as ::cap9_std::data::Storable>::read(__offset)?;
+            let __offset = __offset + #n_keys;
+        )*
+    }
+}
+
+/// Statements which read the fields into `vars` from the values in `__vals`,
+/// starting at the index `__offset`, returning `None` if there are not enough
+/// values or any field can't be read.
+fn read_vec_fields(fields: &Fields, vars: &[Ident]) -> TokenStream2 {
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let n_keys = field_n_keys(fields);
+    quote! {
+        #(
+            let __n = #n_keys.as_u64() as usize;
+            if __vals.len() < __offset + __n {
+                return None;
+            }
+            let #vars = <#types as ::cap9_std::data::Storable>::read_vec_u256(
+                __vals[__offset..(__offset + __n)].to_vec(),
+            )?;
+            let __offset = __offset + __n;
+        )*
+    }
+}
+
+fn storable_struct(input: &DeriveInput, fields: &Fields) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let vars = field_vars(fields);
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let accessors: Vec<TokenStream2> = match fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .map(|field| {
+                let ident = &field.ident;
+                quote! { &self.#ident }
+            })
+            .collect(),
+        _ => (0..vars.len())
+            .map(|i| {
+                let index = syn::Index::from(i);
+                quote! { &self.#index }
+            })
+            .collect(),
+    };
+    let n_keys = fields_n_keys(fields);
+    let field_keys = field_n_keys(fields);
+    let store = store_fields(fields, &accessors);
+    let read = read_fields(fields, &vars);
+    let read_vec = read_vec_fields(fields, &vars);
+    let value = construct(quote! { #name }, fields, &vars);
+    quote! {
+        #[allow(unused_variables)]
+        impl #impl_generics ::cap9_std::data::Storable for #name #ty_generics #where_clause {
+            fn n_keys() -> ::cap9_std::data::derive::U256 {
+                #n_keys
+            }
+
+            fn store(&self, cap_index: u8, location: ::cap9_std::data::derive::U256) {
+                let __offset = location;
+                #store
+            }
+
+            fn clear(cap_index: u8, location: ::cap9_std::data::derive::U256) {
+                let __offset = location;
+                #(
+                    <#types as ::cap9_std::data::Storable>::clear(cap_index, __offset);
+                    let __offset = __offset + #field_keys;
+                )*
+            }
+
+            fn read(location: ::cap9_std::data::derive::U256) -> Option<Self> {
+                let __offset = location;
+                #read
+                Some(#value)
+            }
+
+            fn read_vec_u256(
+                __vals: ::cap9_std::data::derive::Vec<::cap9_std::data::derive::U256>,
+            ) -> Option<Self> {
+                let __offset: usize = 0;
+                #read_vec
+                Some(#value)
+            }
+        }
+    }
+}
+
+fn storable_enum(
+    input: &DeriveInput,
+    variants: &syn::punctuated::Punctuated<syn::Variant, syn::token::Comma>,
+) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let variant_n_keys: Vec<TokenStream2> = variants
+        .iter()
+        .map(|variant| fields_n_keys(&variant.fields))
+        .collect();
+    let tags: Vec<u64> = (1..=variants.len() as u64).collect();
+    let mut store_arms = Vec::new();
+    let mut read_arms = Vec::new();
+    let mut read_vec_arms = Vec::new();
+    for (variant, tag) in variants.iter().zip(tags.iter()) {
+        let ident = &variant.ident;
+        let vars = field_vars(&variant.fields);
+        let value = construct(quote! { #name::#ident }, &variant.fields, &vars);
+        let store = store_fields(&variant.fields, &vars);
+        let read = read_fields(&variant.fields, &vars);
+        let read_vec = read_vec_fields(&variant.fields, &vars);
+        store_arms.push(quote! {
+            #value => {
+                <::cap9_std::data::derive::U256 as ::cap9_std::data::Storable>::store(
+                    &::cap9_std::data::derive::U256::from(#tag),
+                    cap_index,
+                    location,
+                );
+                let __offset = location + ::cap9_std::data::derive::U256::from(1);
+                #store
+                __offset
+            }
+        });
+        read_arms.push(quote! {
+            if __tag == ::cap9_std::data::derive::U256::from(#tag) {
+                let __offset = location + ::cap9_std::data::derive::U256::from(1);
+                #read
+                return Some(#value);
+            }
+        });
+        read_vec_arms.push(quote! {
+            if __tag == ::cap9_std::data::derive::U256::from(#tag) {
+                let __offset: usize = 1;
+                #read_vec
+                return Some(#value);
+            }
+        });
+    }
+    quote! {
+        #[allow(unused_variables)]
+        impl #impl_generics ::cap9_std::data::Storable for #name #ty_generics #where_clause {
+            fn n_keys() -> ::cap9_std::data::derive::U256 {
+                // The variant, followed by the fields of the largest variant.
+                let __max = ::cap9_std::data::derive::U256::zero();
+                #(
+                    let __n = #variant_n_keys;
+                    let __max = if __n > __max { __n } else { __max };
+                )*
+                __max + ::cap9_std::data::derive::U256::from(1)
+            }
+
+            fn store(&self, cap_index: u8, location: ::cap9_std::data::derive::U256) {
+                let __end = match self {
+                    #(#store_arms)*
+                };
+                // Clear anything left by a larger variant.
+                let mut __offset = __end;
+                while __offset < location + <Self as ::cap9_std::data::Storable>::n_keys() {
+                    <::cap9_std::data::derive::U256 as ::cap9_std::data::Storable>::clear(cap_index, __offset);
+                    __offset = __offset + ::cap9_std::data::derive::U256::from(1);
+                }
+            }
+
+            fn clear(cap_index: u8, location: ::cap9_std::data::derive::U256) {
+                let mut __offset = location;
+                while __offset < location + <Self as ::cap9_std::data::Storable>::n_keys() {
+                    <::cap9_std::data::derive::U256 as ::cap9_std::data::Storable>::clear(cap_index, __offset);
+                    __offset = __offset + ::cap9_std::data::derive::U256::from(1);
+                }
+            }
+
+            fn read(location: ::cap9_std::data::derive::U256) -> Option<Self> {
+                let __tag = <::cap9_std::data::derive::U256 as ::cap9_std::data::Storable>::read(location)?;
+                #(#read_arms)*
+                None
+            }
+
+            fn read_vec_u256(
+                __vals: ::cap9_std::data::derive::Vec<::cap9_std::data::derive::U256>,
+            ) -> Option<Self> {
+                let __tag = *__vals.first()?;
+                #(#read_vec_arms)*
+                None
+            }
+        }
+    }
+}
+
+fn keyable(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = match &input.data {
+        Data::Struct(data) if data.fields.iter().count() == 1 => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "Keyable can only be derived for structs with a single field",
+            ))
+        }
+    };
+    let field = fields.iter().next().unwrap();
+    let ty = &field.ty;
+    let var = Ident::new("__field0", Span::call_site());
+    let accessor = match &field.ident {
+        Some(ident) => quote! { #ident },
+        None => quote! { 0 },
+    };
+    let value = construct(quote! { #name }, fields, std::slice::from_ref(&var));
+    Ok(quote! {
+        impl #impl_generics ::cap9_std::data::Keyable for #name #ty_generics #where_clause {
+            fn key_width() -> u8 {
+                <#ty as ::cap9_std::data::Keyable>::key_width()
+            }
+
+            fn key_slice(&self) -> ::cap9_std::data::derive::Vec<u8> {
+                <#ty as ::cap9_std::data::Keyable>::key_slice(&self.#accessor)
+            }
+        }
+
+        impl #impl_generics From<::cap9_std::data::derive::StorageValue> for #name #ty_generics #where_clause {
+            fn from(value: ::cap9_std::data::derive::StorageValue) -> Self {
+                let #var = <#ty as From<::cap9_std::data::derive::StorageValue>>::from(value);
+                #value
+            }
+        }
+
+        impl #impl_generics From<#name #ty_generics> for ::cap9_std::data::derive::StorageValue #where_clause {
+            fn from(key: #name #ty_generics) -> Self {
+                key.#accessor.into()
+            }
+        }
+    })
+}
+
+/// How a field is written, as given by its `#[serialize(...)]` attribute.
+#[derive(Default)]
+struct FieldCodec {
+    /// `via = "Type"`: the field is written as another type.
+    via: Option<Type>,
+    /// `len = "Type"`: the field is a collection, written as its number of
+    /// items, as `Type`, followed by the items.
+    len: Option<Type>,
+    /// `rest`: the field is a collection, which takes up the rest of the
+    /// input.
+    rest: bool,
+    /// `max = N`: reading a value greater than `N` is an error.
+    max: Option<Lit>,
+}
+
+/// The items in the `#[serialize(...)]` attributes.
+fn serialize_attrs(attrs: &[syn::Attribute]) -> Result<Vec<NestedMeta>, syn::Error> {
+    let mut items = Vec::new();
+    for attr in attrs {
+        if !attr.path.is_ident("serialize") {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[serialize(...)]")),
+        }
+    }
+    Ok(items)
+}
+
+/// The type in a `name = "Type"` attribute.
+fn attr_type(lit: &Lit) -> Result<Type, syn::Error> {
+    match lit {
+        Lit::Str(lit) => lit.parse(),
+        lit => Err(syn::Error::new_spanned(lit, "expected a type in a string")),
+    }
+}
+
+/// The type a struct or enum is written as, which is given by
+/// `#[serialize(via = "Type")]`, if there is one.
+fn type_via(input: &DeriveInput) -> Result<Option<Type>, syn::Error> {
+    let mut via = None;
+    for item in serialize_attrs(&input.attrs)? {
+        match &item {
+            NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.ident == "via" => {
+                via = Some(attr_type(&name_value.lit)?)
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    item,
+                    "expected #[serialize(via = \"Type\")]",
+                ))
+            }
+        }
+    }
+    Ok(via)
+}
+
+/// How each of some fields is written. Only the last field may be `rest`.
+fn field_codecs(fields: &Fields) -> Result<Vec<FieldCodec>, syn::Error> {
+    let mut codecs = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let mut codec = FieldCodec::default();
+        for item in serialize_attrs(&field.attrs)? {
+            match &item {
+                NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.ident == "via" => {
+                    codec.via = Some(attr_type(&name_value.lit)?)
+                }
+                NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.ident == "len" => {
+                    codec.len = Some(attr_type(&name_value.lit)?)
+                }
+                NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.ident == "max" => {
+                    match &name_value.lit {
+                        Lit::Int(_) => codec.max = Some(name_value.lit.clone()),
+                        lit => return Err(syn::Error::new_spanned(lit, "expected an integer")),
+                    }
+                }
+                NestedMeta::Meta(Meta::Word(ident)) if ident == "rest" => {
+                    if i + 1 != fields.iter().count() {
+                        return Err(syn::Error::new_spanned(
+                            item,
+                            "only the last field can take up the rest of the input",
+                        ));
+                    }
+                    codec.rest = true
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        item,
+                        "expected via = \"Type\", len = \"Type\", rest or max = N",
+                    ))
+                }
+            }
+        }
+        let n_kinds = codec.via.is_some() as u8 + codec.len.is_some() as u8 + codec.rest as u8;
+        if n_kinds > 1 || (n_kinds > 0 && codec.max.is_some()) {
+            return Err(syn::Error::new_spanned(
+                field,
+                "a field can only have one of via, len, rest and max",
+            ));
+        }
+        codecs.push(codec);
+    }
+    Ok(codecs)
+}
+
+/// The generics of a `Serialize` or `Deserialize` impl, which add the word
+/// type `__W`, and require each of `types` to have a codec for it.
+fn codec_generics(input: &DeriveInput, trait_name: &str, types: &[TokenStream2]) -> syn::Generics {
+    let trait_ident = Ident::new(trait_name, Span::call_site());
+    let mut generics = input.generics.clone();
+    generics.params.push(GenericParam::Type(parse_quote!(__W)));
+    let where_clause = generics.make_where_clause();
+    let mut bounded = Vec::new();
+    for ty in types {
+        // Only bound each type once.
+        let ty_string = ty.to_string();
+        if bounded.contains(&ty_string) {
+            continue;
+        }
+        bounded.push(ty_string);
+        where_clause.predicates.push(parse_quote! {
+            #ty: ::cap9_core::#trait_ident<__W, Error = ::cap9_core::Error>
+        });
+    }
+    generics
+}
+
+/// The types which need a codec to write the fields: the type of each field,
+/// or the type it is written as, or the type of its length and items.
+fn codec_types(fields: &Fields) -> Result<Vec<TokenStream2>, syn::Error> {
+    let mut types = Vec::new();
+    for (field, codec) in fields.iter().zip(field_codecs(fields)?) {
+        let ty = &field.ty;
+        let item = quote! { <#ty as ::core::iter::IntoIterator>::Item };
+        if let Some(via) = codec.via {
+            types.push(quote! { #via });
+        } else if let Some(len) = codec.len {
+            types.push(quote! { #len });
+            types.push(item);
+        } else if codec.rest {
+            types.push(item);
+        } else {
+            types.push(quote! { #ty });
+        }
+    }
+    Ok(types)
+}
+
+/// Statements which write the fields, which are references in `vars`.
+fn serialize_fields<T: ToTokens>(fields: &Fields, vars: &[T]) -> Result<TokenStream2, syn::Error> {
+    let mut statements = Vec::new();
+    for ((field, codec), var) in fields.iter().zip(field_codecs(fields)?).zip(vars) {
+        let ty = &field.ty;
+        let items = quote! {
+            for __item in #var.iter() {
+                ::cap9_core::Serialize::<__W>::serialize(__item, writer)?;
+            }
+        };
+        statements.push(if let Some(via) = codec.via {
+            quote! {
+                ::cap9_core::Serialize::<__W>::serialize(
+                    &<#ty as Into<#via>>::into(Clone::clone(#var)),
+                    writer,
+                )?;
+            }
+        } else if let Some(len) = codec.len {
+            quote! {
+                let __len = <#len as ::core::convert::TryFrom<usize>>::try_from(#var.len())
+                    .map_err(|_| ::cap9_core::Error::InvalidData)?;
+                ::cap9_core::Serialize::<__W>::serialize(&__len, writer)?;
+                #items
+            }
+        } else if codec.rest {
+            items
+        } else {
+            quote! {
+                ::cap9_core::Serialize::<__W>::serialize(#var, writer)?;
+            }
+        });
+    }
+    Ok(quote! { #(#statements)* })
+}
+
+/// Statements which read the fields into `vars`.
+fn deserialize_fields(fields: &Fields, vars: &[Ident]) -> Result<TokenStream2, syn::Error> {
+    let mut statements = Vec::new();
+    for ((field, codec), var) in fields.iter().zip(field_codecs(fields)?).zip(vars) {
+        let ty = &field.ty;
+        let item = quote! {
+            <<#ty as ::core::iter::IntoIterator>::Item as ::cap9_core::Deserialize<__W>>::deserialize(
+                reader,
+            )?
+        };
+        statements.push(if let Some(via) = codec.via {
+            quote! {
+                let #var: #ty = <#via as Into<#ty>>::into(
+                    <#via as ::cap9_core::Deserialize<__W>>::deserialize(reader)?,
+                );
+            }
+        } else if let Some(len) = codec.len {
+            quote! {
+                let __len = <#len as ::cap9_core::Deserialize<__W>>::deserialize(reader)?;
+                let mut #var: #ty = Default::default();
+                for _ in 0..<usize as From<#len>>::from(__len) {
+                    #var.extend(Some(#item));
+                }
+            }
+        } else if codec.rest {
+            quote! {
+                let mut #var: #ty = Default::default();
+                while ::cap9_core::Read::remaining(reader) > 0 {
+                    #var.extend(Some(#item));
+                }
+            }
+        } else if let Some(max) = codec.max {
+            quote! {
+                let #var = <#ty as ::cap9_core::Deserialize<__W>>::deserialize(reader)?;
+                if #var > #max {
+                    return Err(::cap9_core::Error::InvalidData);
+                }
+            }
+        } else {
+            quote! {
+                let #var = <#ty as ::cap9_core::Deserialize<__W>>::deserialize(reader)?;
+            }
+        });
+    }
+    Ok(quote! { #(#statements)* })
+}
+
+fn serialize(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
+    let name = &input.ident;
+    let (body, types) = match (type_via(input)?, &input.data) {
+        (Some(via), _) => {
+            let body = quote! {
+                ::cap9_core::Serialize::<__W>::serialize(&<Self as Into<#via>>::into(Clone::clone(self)), writer)?;
+            };
+            (body, vec![quote! { #via }])
+        }
+        (None, Data::Struct(data)) => {
+            let fields = &data.fields;
+            let vars = field_vars(fields);
+            let pattern = construct(quote! { #name }, fields, &vars);
+            let write = serialize_fields(fields, &vars)?;
+            let body = quote! {
+                let #pattern = self;
+                #write
+            };
+            (body, codec_types(fields)?)
+        }
+        (None, Data::Enum(data)) => {
+            let mut arms = Vec::new();
+            let mut types = vec![quote! { u8 }];
+            for (tag, variant) in data.variants.iter().enumerate() {
+                let tag = tag as u8;
+                let ident = &variant.ident;
+                let vars = field_vars(&variant.fields);
+                let pattern = construct(quote! { #name::#ident }, &variant.fields, &vars);
+                let write = serialize_fields(&variant.fields, &vars)?;
+                arms.push(quote! {
+                    #pattern => {
+                        ::cap9_core::Serialize::<__W>::serialize(&#tag, writer)?;
+                        #write
+                    }
+                });
+                types.extend(codec_types(&variant.fields)?);
+            }
+            let body = quote! {
+                match self {
+                    #(#arms)*
+                }
+            };
+            (body, types)
+        }
+        (None, Data::Union(_)) => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "Serialize cannot be derived for unions",
+            ))
+        }
+    };
+    let generics = codec_generics(input, "Serialize", &types);
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    Ok(quote! {
+        #[allow(unused_variables)]
+        impl #impl_generics ::cap9_core::Serialize<__W> for #name #ty_generics #where_clause {
+            type Error = ::cap9_core::Error;
+
+            fn serialize<__Writer: ::cap9_core::Write<__W>>(
+                &self,
+                writer: &mut __Writer,
+            ) -> Result<(), Self::Error> {
+                #body
+                Ok(())
+            }
+        }
+    })
+}
+
+fn deserialize(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
+    let name = &input.ident;
+    let (body, types) = match (type_via(input)?, &input.data) {
+        (Some(via), _) => {
+            let body = quote! {
+                Ok(<#via as Into<Self>>::into(<#via as ::cap9_core::Deserialize<__W>>::deserialize(reader)?))
+            };
+            (body, vec![quote! { #via }])
+        }
+        (None, Data::Struct(data)) => {
+            let fields = &data.fields;
+            let vars = field_vars(fields);
+            let value = construct(quote! { #name }, fields, &vars);
+            let read = deserialize_fields(fields, &vars)?;
+            let body = quote! {
+                #read
+                Ok(#value)
+            };
+            (body, codec_types(fields)?)
+        }
+        (None, Data::Enum(data)) => {
+            let mut arms = Vec::new();
+            let mut types = vec![quote! { u8 }];
+            for (tag, variant) in data.variants.iter().enumerate() {
+                let tag = tag as u8;
+                let ident = &variant.ident;
+                let vars = field_vars(&variant.fields);
+                let value = construct(quote! { #name::#ident }, &variant.fields, &vars);
+                let read = deserialize_fields(&variant.fields, &vars)?;
+                arms.push(quote! {
+                    #tag => {
+                        #read
+                        Ok(#value)
+                    }
+                });
+                types.extend(codec_types(&variant.fields)?);
+            }
+            let body = quote! {
+                match <u8 as ::cap9_core::Deserialize<__W>>::deserialize(reader)? {
+                    #(#arms)*
+                    _ => Err(::cap9_core::Error::InvalidData),
+                }
+            };
+            (body, types)
+        }
+        (None, Data::Union(_)) => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "Deserialize cannot be derived for unions",
+            ))
+        }
+    };
+    let generics = codec_generics(input, "Deserialize", &types);
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    Ok(quote! {
+        #[allow(unused_variables)]
+        impl #impl_generics ::cap9_core::Deserialize<__W> for #name #ty_generics #where_clause {
+            type Error = ::cap9_core::Error;
+
+            fn deserialize<__Reader: ::cap9_core::Read<__W>>(
+                reader: &mut __Reader,
+            ) -> Result<Self, Self::Error> {
+                #body
+            }
+        }
+    })
+}
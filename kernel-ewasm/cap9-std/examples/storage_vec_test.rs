@@ -53,12 +53,12 @@ pub mod StorageVecTest {
         fn push_this_proc(&mut self) {
             let mut vector: cap9_std::StorageVec<cap9_std::SysCallProcedureKey> = cap9_std::StorageVec::from(0).unwrap();
             let current_proc = cap9_std::proc_table::get_current_proc_id();
-            vector.push(current_proc.into());
+            vector.push(current_proc.into()).unwrap();
         }
 
         fn push_num(&mut self, num: U256) {
             let mut vector: cap9_std::StorageVec<U256> = cap9_std::StorageVec::from(0).unwrap();
-            vector.push(num);
+            vector.push(num).unwrap();
         }
 
         fn pop_num(&mut self) -> U256 {
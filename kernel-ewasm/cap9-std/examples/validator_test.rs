@@ -71,7 +71,11 @@ pub mod entry {
                 // Next we get the code of the contract, using EXTCODECOPY under
                 // the hood.
                 let code: pwasm_std::Vec<u8> = self.code_copy(target);
-                Module::new(code.as_slice()).is_valid()
+                match Module::new(code.as_slice()) {
+                    Ok(module) => module.is_valid(),
+                    // Code which cannot be parsed is not valid.
+                    Err(_) => false,
+                }
             }
         }
 
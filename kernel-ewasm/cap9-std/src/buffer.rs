@@ -0,0 +1,178 @@
+//! A write-back buffer for the storage writes of a procedure.
+//!
+//! Each [`crate::write`] is a syscall of its own, so a bulk update of a data
+//! structure spends most of its gas going in and out of the kernel. While
+//! buffering, [`crate::write`] instead keeps the value in a buffer, replacing
+//! any earlier value for the same key, and [`crate::read`] returns buffered
+//! values before stored ones. [`flush`] then writes all of the buffered
+//! values with one write batch syscall per cap, which the kernel checks
+//! against the range of the cap only once.
+//!
+//! ```ignore
+//! cap9_std::buffer::buffered(|| {
+//!     let mut vec: StorageVec<U256> = StorageVec::from(0).map_err(|_| Error)?;
+//!     for i in 0..10 {
+//!         vec.push(i.into()).map_err(|_| Error)?;
+//!     }
+//!     Ok(())
+//! })?;
+//! ```
+//!
+//! Writes are only checked against the caps when they are flushed.
+//! [`flush`] checks every buffered write before making any syscalls, so if a
+//! write is outside of its cap, none of the writes are made. If a write batch
+//! is refused by the kernel after the check, the batches before it have
+//! already been written, and storage is left partly written unless the caller
+//! reverts. The buffer is local to the procedure, so it should be flushed
+//! before calling another procedure which reads the same storage.
+extern crate pwasm_abi;
+use pwasm_abi::types::*;
+
+use cap9_core::Serialize;
+
+use crate::syscalls::{Error, SysCall, SysCallAction, WriteBatchCall, WriteCall};
+
+/// A buffered write.
+struct Entry {
+    cap_index: u8,
+    key: H256,
+    value: [u8; 32],
+}
+
+#[cfg(feature="std")]
+std::thread_local! {
+    static BUFFER: core::cell::RefCell<Option<Vec<Entry>>> = core::cell::RefCell::new(None);
+}
+
+#[cfg(feature="std")]
+fn with_buffer<R, F: FnOnce(&mut Option<Vec<Entry>>) -> R>(f: F) -> R {
+    BUFFER.with(|buffer| f(&mut buffer.borrow_mut()))
+}
+
+// A procedure is single-threaded, so there is nothing else that could access
+// the buffer.
+#[cfg(not(feature="std"))]
+static mut BUFFER: Option<Vec<Entry>> = None;
+
+#[cfg(not(feature="std"))]
+fn with_buffer<R, F: FnOnce(&mut Option<Vec<Entry>>) -> R>(f: F) -> R {
+    unsafe { f(&mut BUFFER) }
+}
+
+/// Start buffering writes. If writes are already being buffered, this does
+/// nothing.
+pub fn begin() {
+    with_buffer(|buffer| {
+        if buffer.is_none() {
+            *buffer = Some(Vec::new());
+        }
+    })
+}
+
+/// Return true if writes are being buffered.
+pub fn is_buffering() -> bool {
+    with_buffer(|buffer| buffer.is_some())
+}
+
+/// Buffer a write, returning false if writes are not being buffered.
+pub(crate) fn buffer_write(cap_index: u8, key: &[u8; 32], value: &[u8; 32]) -> bool {
+    with_buffer(|buffer| match buffer {
+        None => false,
+        Some(entries) => {
+            let key = H256::from(key);
+            match entries.iter_mut().find(|entry| entry.key == key) {
+                Some(entry) => {
+                    entry.cap_index = cap_index;
+                    entry.value = *value;
+                }
+                None => entries.push(Entry {
+                    cap_index,
+                    key,
+                    value: *value,
+                }),
+            }
+            true
+        }
+    })
+}
+
+/// Return the buffered value of a key, if there is one.
+pub(crate) fn buffered_read(key: &H256) -> Option<[u8; 32]> {
+    with_buffer(|buffer| match buffer {
+        None => None,
+        Some(entries) => entries
+            .iter()
+            .find(|entry| &entry.key == key)
+            .map(|entry| entry.value),
+    })
+}
+
+/// Write out the buffered writes and stop buffering. There is one write batch
+/// syscall for each cap which was written to.
+///
+/// Each batch is checked against its cap first, and if any of them would be
+/// refused, nothing is written and the buffered writes are discarded. A batch
+/// which is still refused by the kernel leaves the batches before it written
+/// and the rest discarded, so storage is partly written unless the caller
+/// reverts.
+pub fn flush() -> Result<(), Error> {
+    let mut entries = match with_buffer(|buffer| buffer.take()) {
+        None => return Ok(()),
+        Some(entries) => entries,
+    };
+    let mut syscalls = Vec::new();
+    while let Some(cap_index) = entries.first().map(|entry| entry.cap_index) {
+        let mut writes = Vec::new();
+        entries.retain(|entry| {
+            if entry.cap_index == cap_index {
+                writes.push(WriteCall {
+                    key: entry.key.into(),
+                    value: entry.value.into(),
+                });
+                false
+            } else {
+                true
+            }
+        });
+        let syscall = SysCall {
+            cap_index,
+            action: SysCallAction::WriteBatch(WriteBatchCall { writes }),
+        };
+        // This is the same check the kernel makes.
+        if !syscall.check_cap() {
+            return Err(Error);
+        }
+        syscalls.push(syscall);
+    }
+    for syscall in syscalls {
+        let mut input = Vec::new();
+        syscall.serialize(&mut input).unwrap();
+        crate::cap9_syscall(&input, &mut Vec::new())?;
+    }
+    Ok(())
+}
+
+/// Run `f` with writes buffered, and flush them afterwards. If `f` returns an
+/// error, the buffered writes are discarded. If writes were already being
+/// buffered, they are left for the outer call to flush.
+pub fn buffered<R, F: FnOnce() -> Result<R, Error>>(f: F) -> Result<R, Error> {
+    if is_buffering() {
+        return f();
+    }
+    begin();
+    match f() {
+        Ok(result) => {
+            flush()?;
+            Ok(result)
+        }
+        Err(err) => {
+            discard();
+            Err(err)
+        }
+    }
+}
+
+/// Stop buffering writes, discarding the buffered writes.
+pub fn discard() {
+    with_buffer(|buffer| *buffer = None)
+}
@@ -0,0 +1,597 @@
+extern crate pwasm_abi;
+use pwasm_abi::types::*;
+use cap9_core::Serialize;
+use cap9_core::StorageValue;
+
+use crate::syscalls::*;
+use crate::*;
+use crate::data::*;
+
+use pwasm_std::String;
+
+/// A byte string of variable length in storage.
+///
+/// The first storage key holds the length of the string in bytes, and is
+/// followed by the bytes themselves, packed into 32-byte chunks. The unused
+/// bytes of the last chunk are always zero. As with [`StorageVec`], the
+/// capacity is fixed by the region the string is stored in, which is the whole
+/// cap when it is derived with [`StorageBytes::from`].
+///
+/// The values of a [`StorageVec`] or a map have a fixed size, so a byte string
+/// can't be stored in them directly. Instead, the byte string is stored in a
+/// slot of a [`StorageBytesHeap`], and a [`BytesRef`] to that slot is stored in
+/// the vector or map.
+pub struct StorageBytes {
+    cap_index: u8,
+    /// The storage key of the length, which is followed by the chunks.
+    location: U256,
+    /// The maximum length in bytes.
+    capacity: U256,
+}
+
+impl StorageBytes {
+
+    /// Derive a [`StorageBytes`] from the cap at the given index.
+    pub fn from(cap_index: u8) -> Result<Self, DataStructureError> {
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size)
+    }
+
+    /// Derive a [`StorageBytes`] from a region of the cap at the given index,
+    /// which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        // The region covers size+1 keys, the first of which is the length.
+        if size.is_zero() {
+            Err(DataStructureError::TooSmall)
+        } else {
+            Ok(StorageBytes::at(cap_index, location, size))
+        }
+    }
+
+    /// A [`StorageBytes`] at the given location, with room for `n_chunks`
+    /// chunks after the length.
+    fn at(cap_index: u8, location: U256, n_chunks: U256) -> Self {
+        StorageBytes {
+            cap_index,
+            location,
+            capacity: n_chunks.saturating_mul(32.into()),
+        }
+    }
+
+    /// Return the storage key of the length.
+    pub fn location(&self) -> H256 {
+        H256::from(self.location)
+    }
+
+    /// Return the maximum length in bytes.
+    pub fn capacity(&self) -> U256 {
+        self.capacity
+    }
+
+    /// Return the length in bytes.
+    pub fn length(&self) -> U256 {
+        U256::from(read(&H256::from(self.location)))
+    }
+
+    fn set_length(&self, length: U256) {
+        write(self.cap_index, &H256::from(self.location).to_fixed_bytes(), &length.into()).unwrap();
+    }
+
+    /// Return the storage key of the chunk at the given index.
+    fn chunk_key(&self, index: U256) -> H256 {
+        H256::from(self.location + U256::from(1) + index)
+    }
+
+    /// The number of chunks needed to hold `length` bytes.
+    fn n_chunks(length: U256) -> U256 {
+        (length + U256::from(31)) / U256::from(32)
+    }
+
+    /// Return the bytes. A stored length greater than the capacity, which can
+    /// only have been written by something other than a [`StorageBytes`], is
+    /// taken to be the capacity.
+    pub fn get(&self) -> Vec<u8> {
+        let mut remaining = core::cmp::min(self.length(), self.capacity);
+        let mut bytes = Vec::new();
+        let mut index = U256::zero();
+        while !remaining.is_zero() {
+            let chunk = read(&self.chunk_key(index));
+            let n = core::cmp::min(U256::from(32), remaining);
+            bytes.extend_from_slice(&chunk[0..n.as_u32() as usize]);
+            remaining -= n;
+            index += U256::from(1);
+        }
+        bytes
+    }
+
+    /// Replace the bytes. Returns `DataStructureError::Full` if there is not
+    /// enough room for them, in which case nothing is changed.
+    pub fn set(&mut self, bytes: &[u8]) -> Result<(), DataStructureError> {
+        let length = U256::from(bytes.len());
+        if length > self.capacity {
+            return Err(DataStructureError::Full);
+        }
+        let old_length = self.length();
+        self.write_bytes(U256::zero(), bytes);
+        // Clear any chunks which are no longer used.
+        let mut index = StorageBytes::n_chunks(length);
+        while index < StorageBytes::n_chunks(old_length) {
+            write(self.cap_index, &self.chunk_key(index).to_fixed_bytes(), &[0; 32]).unwrap();
+            index += U256::from(1);
+        }
+        self.set_length(length);
+        Ok(())
+    }
+
+    /// Add bytes to the end. Returns `DataStructureError::Full` if there is not
+    /// enough room for them, in which case nothing is changed.
+    pub fn append(&mut self, bytes: &[u8]) -> Result<(), DataStructureError> {
+        let old_length = self.length();
+        let length = match old_length.checked_add(U256::from(bytes.len())) {
+            Some(length) if length <= self.capacity => length,
+            _ => return Err(DataStructureError::Full),
+        };
+        self.write_bytes(old_length, bytes);
+        self.set_length(length);
+        Ok(())
+    }
+
+    /// Shorten to `length` bytes. This has no effect if the bytes are already
+    /// no longer than `length`.
+    pub fn truncate(&mut self, length: U256) {
+        let old_length = self.length();
+        if length >= old_length {
+            return;
+        }
+        // Clear the bytes after the end in the last chunk.
+        let offset = (length % U256::from(32)).as_u32() as usize;
+        if offset != 0 {
+            let key = self.chunk_key(length / U256::from(32));
+            let mut chunk = read(&key);
+            for byte in chunk[offset..].iter_mut() {
+                *byte = 0;
+            }
+            write(self.cap_index, &key.to_fixed_bytes(), &chunk).unwrap();
+        }
+        // Clear the chunks after the last chunk.
+        let mut index = StorageBytes::n_chunks(length);
+        while index < StorageBytes::n_chunks(old_length) {
+            write(self.cap_index, &self.chunk_key(index).to_fixed_bytes(), &[0; 32]).unwrap();
+            index += U256::from(1);
+        }
+        self.set_length(length);
+    }
+
+    /// Write bytes starting at the given byte offset, which must be the
+    /// current length or zero. The unused bytes of the last chunk written are
+    /// set to zero.
+    fn write_bytes(&self, start: U256, bytes: &[u8]) {
+        let mut index = start / U256::from(32);
+        let mut offset = (start % U256::from(32)).as_u32() as usize;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let key = self.chunk_key(index);
+            // The start of a chunk which is already partly used must be kept.
+            let mut chunk = if offset == 0 {
+                [0; 32]
+            } else {
+                read(&key)
+            };
+            let n = core::cmp::min(32 - offset, bytes.len());
+            chunk[offset..(offset + n)].copy_from_slice(&bytes[0..n]);
+            write(self.cap_index, &key.to_fixed_bytes(), &chunk).unwrap();
+            bytes = &bytes[n..];
+            offset = 0;
+            index += U256::from(1);
+        }
+    }
+}
+
+/// A UTF-8 string of variable length in storage.
+///
+/// This is a [`StorageBytes`] which only holds valid UTF-8, and has the same
+/// layout.
+pub struct StorageString {
+    bytes: StorageBytes,
+}
+
+impl StorageString {
+
+    /// Derive a [`StorageString`] from the cap at the given index.
+    pub fn from(cap_index: u8) -> Result<Self, DataStructureError> {
+        Ok(StorageString::from_bytes(StorageBytes::from(cap_index)?))
+    }
+
+    /// Treat a [`StorageBytes`] as a [`StorageString`]. If the bytes are not
+    /// valid UTF-8, [`StorageString::get`] will return `None`.
+    pub fn from_bytes(bytes: StorageBytes) -> Self {
+        StorageString { bytes }
+    }
+
+    /// Return the storage key of the length.
+    pub fn location(&self) -> H256 {
+        self.bytes.location()
+    }
+
+    /// Return the maximum length in bytes.
+    pub fn capacity(&self) -> U256 {
+        self.bytes.capacity()
+    }
+
+    /// Return the length in bytes.
+    pub fn length(&self) -> U256 {
+        self.bytes.length()
+    }
+
+    /// Return the string. This is `None` if the storage does not hold valid
+    /// UTF-8, which can only happen if it was written by something other than
+    /// a [`StorageString`].
+    pub fn get(&self) -> Option<String> {
+        String::from_utf8(self.bytes.get()).ok()
+    }
+
+    /// Replace the string. Returns `DataStructureError::Full` if there is not
+    /// enough room for it.
+    pub fn set(&mut self, s: &str) -> Result<(), DataStructureError> {
+        self.bytes.set(s.as_bytes())
+    }
+
+    /// Add a string to the end. Returns `DataStructureError::Full` if there is
+    /// not enough room for it.
+    pub fn append(&mut self, s: &str) -> Result<(), DataStructureError> {
+        self.bytes.append(s.as_bytes())
+    }
+
+    /// Shorten to `length` bytes. Returns `DataStructureError::Other` if
+    /// `length` is not on a character boundary, in which case nothing is
+    /// changed.
+    pub fn truncate(&mut self, length: U256) -> Result<(), DataStructureError> {
+        if length < self.length() {
+            let key = self.bytes.chunk_key(length / U256::from(32));
+            let byte = read(&key)[(length % U256::from(32)).as_u32() as usize];
+            // Continuation bytes of a character are 0b10xxxxxx.
+            if byte & 0b1100_0000 == 0b1000_0000 {
+                return Err(DataStructureError::Other);
+            }
+        }
+        self.bytes.truncate(length);
+        Ok(())
+    }
+}
+
+/// A reference to a slot of a [`StorageBytesHeap`], which can be stored in a
+/// [`StorageVec`] or a map in place of a byte string.
+///
+/// The reference is the storage key of the slot, or zero for no slot. Storing
+/// or clearing a reference does not change the slot it refers to, which must be
+/// freed with [`StorageBytesHeap::free`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BytesRef(U256);
+
+impl BytesRef {
+    /// A reference to no slot.
+    pub fn null() -> Self {
+        BytesRef(U256::zero())
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl Storable for BytesRef {
+
+    fn n_keys() -> U256 {
+        1.into()
+    }
+
+    fn store(&self, cap_index: u8, location: U256) {
+        self.0.store(cap_index, location)
+    }
+
+    fn clear(cap_index: u8, location: U256) {
+        U256::clear(cap_index, location)
+    }
+
+    fn read(location: U256) -> Option<Self> {
+        U256::read(location).map(BytesRef)
+    }
+
+    fn read_vec_u256(vals: Vec<U256>) -> Option<Self> {
+        U256::read_vec_u256(vals).map(BytesRef)
+    }
+}
+
+/// Storage for byte strings which are referred to from other data structures.
+///
+/// The cap is divided into slots of a fixed number of storage keys, each of
+/// which holds a [`StorageBytes`]. Slots are allocated with
+/// [`StorageBytesHeap::alloc`], which returns a [`BytesRef`] to be stored
+/// elsewhere, and returned with [`StorageBytesHeap::free`].
+///
+/// ## Layout
+///
+/// The first storage key of the cap holds the number of slots that have ever
+/// been allocated, and the second holds the index plus one of the first free
+/// slot, or zero if there are no free slots. They are followed by the slots.
+/// The length of a free slot is all ones, which no allocated slot can have,
+/// and its first chunk holds the index plus one of the next free slot, which
+/// is why slots must have room for at least one chunk.
+pub struct StorageBytesHeap {
+    cap_index: u8,
+    /// The start location of the heap.
+    location: U256,
+    /// The number of storage keys in a slot, including the length.
+    slot_keys: U256,
+    /// The number of slots the heap can hold.
+    n_slots: U256,
+}
+
+impl StorageBytesHeap {
+
+    /// Derive a [`StorageBytesHeap`] from the cap at the given index, with
+    /// slots of `slot_keys` storage keys. Each slot can hold
+    /// `(slot_keys - 1) * 32` bytes.
+    pub fn from(cap_index: u8, slot_keys: U256) -> Result<Self, DataStructureError> {
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size, slot_keys)
+    }
+
+    /// Derive a [`StorageBytesHeap`] from a region of the cap at the given
+    /// index, which covers size+1 keys from location, with slots of
+    /// `slot_keys` storage keys.
+    pub fn from_region(cap_index: u8, location: U256, size: U256, slot_keys: U256) -> Result<Self, DataStructureError> {
+        if slot_keys < U256::from(2) {
+            return Err(DataStructureError::Other);
+        }
+        // The region covers size+1 keys, two of which are used by the heap
+        // itself.
+        let n_slots = size.saturating_sub(1.into()) / slot_keys;
+        if n_slots.is_zero() {
+            Err(DataStructureError::TooSmall)
+        } else {
+            Ok(StorageBytesHeap {
+                cap_index,
+                location,
+                slot_keys,
+                n_slots,
+            })
+        }
+    }
+
+    /// Return the number of slots the heap can hold.
+    pub fn capacity(&self) -> U256 {
+        self.n_slots
+    }
+
+    fn read_key(&self, offset: U256) -> U256 {
+        U256::from(read(&H256::from(self.location + offset)))
+    }
+
+    fn write_key(&self, offset: U256, value: U256) {
+        write(self.cap_index, &H256::from(self.location + offset).to_fixed_bytes(), &value.into()).unwrap();
+    }
+
+    /// Return the storage key of the slot at the given index.
+    fn slot_key(&self, index: U256) -> U256 {
+        self.location + U256::from(2) + index * self.slot_keys
+    }
+
+    /// Return the index of the slot a reference refers to, if it is an
+    /// allocated slot of this heap.
+    fn slot_index(&self, bytes_ref: &BytesRef) -> Option<U256> {
+        let offset = bytes_ref.0.checked_sub(self.location + U256::from(2))?;
+        let index = offset / self.slot_keys;
+        if offset % self.slot_keys != U256::zero() || index >= self.read_key(U256::zero()) {
+            return None;
+        }
+        if self.read_key(offset + U256::from(2)) == U256::max_value() {
+            // The slot is free.
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Allocate an empty slot. Returns `DataStructureError::Full` if there
+    /// are no slots left.
+    pub fn alloc(&mut self) -> Result<BytesRef, DataStructureError> {
+        let free = self.read_key(U256::from(1));
+        let index = if free.is_zero() {
+            let allocated = self.read_key(U256::zero());
+            if allocated >= self.n_slots {
+                return Err(DataStructureError::Full);
+            }
+            self.write_key(U256::zero(), allocated + U256::from(1));
+            allocated
+        } else {
+            // Take the first free slot, and clear the link to the next one
+            // and the mark.
+            let index = free - U256::from(1);
+            let slot_offset = self.slot_key(index) - self.location;
+            let link_offset = slot_offset + U256::from(1);
+            self.write_key(U256::from(1), self.read_key(link_offset));
+            self.write_key(link_offset, U256::zero());
+            self.write_key(slot_offset, U256::zero());
+            index
+        };
+        Ok(BytesRef(self.slot_key(index)))
+    }
+
+    /// Clear a slot and return it to the heap. Freeing a null reference does
+    /// nothing. Returns `DataStructureError::Other` if the reference is not to
+    /// an allocated slot of this heap, which includes a slot which has already
+    /// been freed, in which case nothing is changed.
+    pub fn free(&mut self, bytes_ref: BytesRef) -> Result<(), DataStructureError> {
+        if bytes_ref.is_null() {
+            return Ok(());
+        }
+        let index = self.slot_index(&bytes_ref).ok_or(DataStructureError::Other)?;
+        let mut bytes = self.bytes_at(index);
+        bytes.truncate(U256::zero());
+        let slot_offset = self.slot_key(index) - self.location;
+        self.write_key(slot_offset + U256::from(1), self.read_key(U256::from(1)));
+        self.write_key(slot_offset, U256::max_value());
+        self.write_key(U256::from(1), index + U256::from(1));
+        Ok(())
+    }
+
+    fn bytes_at(&self, index: U256) -> StorageBytes {
+        StorageBytes::at(self.cap_index, self.slot_key(index), self.slot_keys - U256::from(1))
+    }
+
+    /// Return the [`StorageBytes`] of a slot, or `None` if the reference is
+    /// not to an allocated slot of this heap.
+    pub fn bytes(&self, bytes_ref: &BytesRef) -> Option<StorageBytes> {
+        self.slot_index(bytes_ref).map(|index| self.bytes_at(index))
+    }
+
+    /// Return the [`StorageString`] of a slot, or `None` if the reference is
+    /// not to an allocated slot of this heap.
+    pub fn string(&self, bytes_ref: &BytesRef) -> Option<StorageString> {
+        self.bytes(bytes_ref).map(StorageString::from_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn storage_bytes() {
+        let mut kernel = TestKernel::new();
+        // Room for the length and 2 chunks.
+        kernel.register(key("bytes"), write_cap(0.into(), 2.into()), |input| {
+            let mut bytes = StorageBytes::from(0).map_err(|_| Error)?;
+            let result = match input[0] {
+                0 => bytes.set(&input[1..]),
+                1 => bytes.append(&input[1..]),
+                2 => {
+                    bytes.truncate(input[1].into());
+                    Ok(())
+                }
+                // Write a length which does not fit.
+                _ => write(0, &[0; 32], &[0xff; 32]).map_err(|_| DataStructureError::Other),
+            };
+            match result {
+                Ok(()) => Ok(bytes.get()),
+                Err(_) => Err(Error),
+            }
+        });
+        kernel.set_entry(key("bytes"));
+
+        let long: Vec<u8> = (1..41).collect();
+        let mut input = [0].to_vec();
+        input.extend_from_slice(&long);
+        assert_eq!(kernel.call(&input).unwrap(), long);
+        assert_eq!(read_u8(0), 40);
+        assert_eq!(kernel.call(&[1, 41, 42]).unwrap()[38..], [39, 40, 41, 42]);
+        // There is only room for 64 bytes.
+        input[0] = 1;
+        assert!(kernel.call(&input).is_err());
+        assert_eq!(read_u8(0), 42);
+        assert_eq!(kernel.call(&[2, 33]).unwrap(), long[0..33].to_vec());
+        assert_eq!(kernel.call(&[0, 7]).unwrap(), [7].to_vec());
+        // The unused bytes of the chunks are cleared.
+        assert_eq!(read_u8(1), 0);
+        assert_eq!(read_u8(2), 0);
+        // A length beyond the capacity is taken to be the capacity.
+        let mut bytes = [0; 64].to_vec();
+        bytes[0] = 7;
+        assert_eq!(kernel.call(&[3]).unwrap(), bytes);
+    }
+
+    #[test]
+    fn storage_bytes_heap() {
+        let mut kernel = TestKernel::new();
+        let mut cap_list = write_cap(0.into(), 0x0f.into());
+        cap_list.0.extend(write_cap(0x10.into(), 0x10.into()).0);
+        // A vector of strings, each of which is in a slot of 2 chunks.
+        kernel.register(key("names"), cap_list, |input| {
+            let mut names: StorageVec<BytesRef> = StorageVec::from(0).map_err(|_| Error)?;
+            let mut heap = StorageBytesHeap::from(1, 3.into()).map_err(|_| Error)?;
+            if input.is_empty() {
+                let name = names.pop().ok_or(Error)?;
+                heap.free(name).map_err(|_| Error)?;
+            } else {
+                let name = heap.alloc().map_err(|_| Error)?;
+                let mut string = heap.string(&name).ok_or(Error)?;
+                string.set(core::str::from_utf8(input).unwrap()).map_err(|_| Error)?;
+                names.push(name).map_err(|_| Error)?;
+            }
+            let mut result = Vec::new();
+            for name in names.iter() {
+                result.extend_from_slice(heap.string(&name).ok_or(Error)?.get().ok_or(Error)?.as_bytes());
+                result.push(b',');
+            }
+            Ok(result)
+        });
+        kernel.set_entry(key("names"));
+
+        assert_eq!(kernel.call(b"alice").unwrap(), b"alice,".to_vec());
+        assert_eq!(kernel.call(b"bob").unwrap(), b"alice,bob,".to_vec());
+        assert_eq!(kernel.call(&[]).unwrap(), b"alice,".to_vec());
+        // The slot of "bob" is reused.
+        assert_eq!(kernel.call(b"carol").unwrap(), b"alice,carol,".to_vec());
+        assert_eq!(read_u8(0x10), 2);
+        // 5 slots fit after the two keys of the heap.
+        for _ in 0..3 {
+            assert!(kernel.call(b"dave").is_ok());
+        }
+        assert!(kernel.call(b"eve").is_err());
+
+        // The heap can share a cap with other data structures.
+        let layout = || {
+            StorageLayout::new()
+                .vec::<BytesRef>("names", 2.into())
+                .bytes_heap("heap", 2.into(), 3.into())
+        };
+        kernel.register(key("layout"), write_cap(0x40.into(), 10.into()), move |input| {
+            // There is only one cap.
+            match StorageBytesHeap::from(1, 3.into()) {
+                Err(data::DataStructureError::BadCap) => (),
+                _ => return Err(Error),
+            }
+            let regions = layout().build(0).map_err(|_| Error)?;
+            let mut names: StorageVec<BytesRef> = regions.vec("names").map_err(|_| Error)?;
+            let mut heap = regions.bytes_heap("heap", 3.into()).map_err(|_| Error)?;
+            let name = heap.alloc().map_err(|_| Error)?;
+            heap.bytes(&name).ok_or(Error)?.set(input).map_err(|_| Error)?;
+            names.push(name).map_err(|_| Error)?;
+            Ok([heap.capacity().as_u32() as u8].to_vec())
+        });
+        kernel.set_entry(key("layout"));
+        assert_eq!(kernel.call(b"alice").unwrap(), [2].to_vec());
+        // The heap follows the length and 2 values of the vector, and its
+        // first slot follows its own two keys.
+        assert_eq!(read_u8(0x43), 1);
+        assert_eq!(read_u8(0x45), 5);
+        assert_eq!(read_u8(0x41), 0x45);
+    }
+    #[test]
+    fn storage_bytes_heap_free() {
+        let mut kernel = TestKernel::new();
+        kernel.register(key("heap"), write_cap(0.into(), 9.into()), |_input| {
+            let mut heap = StorageBytesHeap::from(0, 2.into()).map_err(|_| Error)?;
+            let a = heap.alloc().map_err(|_| Error)?;
+            let b = heap.alloc().map_err(|_| Error)?;
+            heap.free(BytesRef::null()).map_err(|_| Error)?;
+            heap.free(a).map_err(|_| Error)?;
+            // A slot which has already been freed, and one which has never
+            // been allocated.
+            let never = BytesRef(b.0 + U256::from(2));
+            let rejected = [heap.free(a).is_err(), heap.free(never).is_err(), heap.bytes(&a).is_none()];
+            // The freed slot is only reused once.
+            let c = heap.alloc().map_err(|_| Error)?;
+            let d = heap.alloc().map_err(|_| Error)?;
+            let mut result: Vec<u8> = rejected.iter().map(|&r| r as u8).collect();
+            result.extend_from_slice(&[(c == a) as u8, (d != a && d != b) as u8]);
+            Ok(result)
+        });
+        kernel.set_entry(key("heap"));
+
+        assert_eq!(kernel.call(&[]).unwrap(), [1, 1, 1, 1, 1].to_vec());
+    }
+}
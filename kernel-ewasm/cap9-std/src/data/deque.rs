@@ -0,0 +1,279 @@
+extern crate pwasm_abi;
+use pwasm_abi::types::*;
+
+use crate::*;
+use crate::data::*;
+
+use core::marker::PhantomData;
+
+/// A double-ended queue of values in storage, implemented as a ring buffer.
+///
+/// The first key of the capability holds the head index, which is the slot
+/// of the first value, and the second key holds the tail index, which is the
+/// slot after the last value. The slots follow, each of which is
+/// `V::n_keys()` keys long. Values can be added and removed at either end
+/// with a constant number of storage operations.
+///
+/// One slot is always left empty, so that a full deque can be told apart
+/// from an empty one, and the capacity is one less than the number of slots
+/// which fit in the capability. As with a [`StorageVec`], the capacity is a
+/// property of the capability and cannot be changed.
+pub struct StorageDeque<V> {
+    cap_index: u8,
+    /// The start location of the deque.
+    location: H256,
+    /// The data type of the deque.
+    data_type: PhantomData<V>,
+    /// The number of slots in the ring buffer.
+    n_slots: U256,
+    head: U256,
+    tail: U256,
+}
+
+impl<V: Storable> StorageDeque<V> {
+
+    /// Derive a [`StorageDeque`] from the cap at the given index.
+    pub fn from(cap_index: u8) -> Result<Self, DataStructureError> {
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size)
+    }
+
+    /// Derive a [`StorageDeque`] from a region of the cap at the given index,
+    /// which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        // The region covers size+1 keys, the first two of which are the head
+        // and tail indices.
+        if size.is_zero() {
+            return Err(DataStructureError::TooSmall);
+        }
+        let n_slots = match (size - U256::from(1)).checked_div(V::n_keys()) {
+            // Return an error on divide-by-zero
+            None => return Err(DataStructureError::Other),
+            Some(x) => x,
+        };
+        if n_slots.is_zero() {
+            return Err(DataStructureError::TooSmall);
+        }
+        let head = U256::from(read(&H256::from(location)));
+        let tail = U256::from(read(&H256::from(location + U256::from(1))));
+        // Indices outside of the ring can only have been written by something
+        // other than a deque of this size.
+        if head >= n_slots || tail >= n_slots {
+            return Err(DataStructureError::Other);
+        }
+        Ok(StorageDeque {
+            cap_index,
+            location: location.into(),
+            data_type: PhantomData,
+            n_slots,
+            head,
+            tail,
+        })
+    }
+
+    /// The number of values the deque can hold.
+    pub fn capacity(&self) -> U256 {
+        self.n_slots - U256::from(1)
+    }
+
+    pub fn length(&self) -> U256 {
+        if self.tail >= self.head {
+            self.tail - self.head
+        } else {
+            self.n_slots - self.head + self.tail
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    pub fn location(&self) -> H256 {
+        self.location
+    }
+
+    /// The key of the value in the given slot.
+    fn slot_key(&self, slot: U256) -> U256 {
+        U256::from(self.location) + U256::from(2) + slot * V::n_keys()
+    }
+
+    /// The slot after the given slot.
+    fn next_slot(&self, slot: U256) -> U256 {
+        (slot + U256::from(1)) % self.n_slots
+    }
+
+    /// The slot before the given slot.
+    fn prev_slot(&self, slot: U256) -> U256 {
+        (slot + self.n_slots - U256::from(1)) % self.n_slots
+    }
+
+    fn set_head(&mut self, head: U256) {
+        self.head = head;
+        write(self.cap_index, &U256::from(self.location).into(), &head.into()).unwrap();
+    }
+
+    fn set_tail(&mut self, tail: U256) {
+        self.tail = tail;
+        write(self.cap_index, &(U256::from(self.location) + U256::from(1)).into(), &tail.into()).unwrap();
+    }
+
+    /// Get the value at the given index, counting from the front.
+    pub fn get(&self, index: U256) -> Option<V> {
+        if index >= self.length() {
+            return None;
+        }
+        V::read(self.slot_key((self.head + index) % self.n_slots))
+    }
+
+    /// Get the value at the front of the deque.
+    pub fn front(&self) -> Option<V> {
+        self.get(U256::zero())
+    }
+
+    /// Get the value at the back of the deque.
+    pub fn back(&self) -> Option<V> {
+        if self.is_empty() {
+            return None;
+        }
+        V::read(self.slot_key(self.prev_slot(self.tail)))
+    }
+
+    /// Add a value to the back of the deque. Returns
+    /// `DataStructureError::Full` if the deque is already at capacity.
+    pub fn push_back(&mut self, value: V) -> Result<(), DataStructureError> {
+        let tail = self.next_slot(self.tail);
+        if tail == self.head {
+            return Err(DataStructureError::Full);
+        }
+        value.store(self.cap_index, self.slot_key(self.tail));
+        self.set_tail(tail);
+        Ok(())
+    }
+
+    /// Add a value to the front of the deque. Returns
+    /// `DataStructureError::Full` if the deque is already at capacity.
+    pub fn push_front(&mut self, value: V) -> Result<(), DataStructureError> {
+        let head = self.prev_slot(self.head);
+        if head == self.tail {
+            return Err(DataStructureError::Full);
+        }
+        value.store(self.cap_index, self.slot_key(head));
+        self.set_head(head);
+        Ok(())
+    }
+
+    /// Remove and return the value at the front of the deque.
+    pub fn pop_front(&mut self) -> Option<V> {
+        if self.is_empty() {
+            return None;
+        }
+        let key = self.slot_key(self.head);
+        let value = V::read(key);
+        V::clear(self.cap_index, key);
+        let head = self.next_slot(self.head);
+        self.set_head(head);
+        value
+    }
+
+    /// Remove and return the value at the back of the deque.
+    pub fn pop_back(&mut self) -> Option<V> {
+        if self.is_empty() {
+            return None;
+        }
+        let tail = self.prev_slot(self.tail);
+        let key = self.slot_key(tail);
+        let value = V::read(key);
+        V::clear(self.cap_index, key);
+        self.set_tail(tail);
+        value
+    }
+
+    /// Produce an iterator over the values, from front to back.
+    pub fn iter(&self) -> StorageDequeIter<V> {
+        StorageDequeIter {
+            storage_deque: self,
+            offset: U256::zero(),
+        }
+    }
+}
+
+/// An iterator over the values of a [`StorageDeque`].
+pub struct StorageDequeIter<'a, V> {
+    /// The [`StorageDeque`] we are iterating over.
+    storage_deque: &'a StorageDeque<V>,
+    /// The current offset from the front of the [`StorageDeque`].
+    offset: U256,
+}
+
+impl<'a, V: Storable> Iterator for StorageDequeIter<'a, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = self.storage_deque.get(self.offset)?;
+        self.offset += U256::from(1);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn storage_deque() {
+        let mut kernel = TestKernel::new();
+        // The head and tail indices, and 3 slots.
+        kernel.register(key("deque"), write_cap(0.into(), 4.into()), |input| {
+            let mut deque: StorageDeque<u8> = StorageDeque::from(0).map_err(|_| Error)?;
+            let popped = match input[0] {
+                0 => deque.push_back(input[1]).map(|_| 0),
+                1 => deque.push_front(input[1]).map(|_| 0),
+                2 => Ok(deque.pop_front().unwrap_or(0)),
+                _ => Ok(deque.pop_back().unwrap_or(0)),
+            }.map_err(|_| Error)?;
+            let mut values: Vec<u8> = deque.iter().collect();
+            values.push(popped);
+            Ok(values)
+        });
+        kernel.set_entry(key("deque"));
+
+        assert_eq!(kernel.call(&[0, 4]).unwrap(), [4, 0].to_vec());
+        assert_eq!(kernel.call(&[1, 3]).unwrap(), [3, 4, 0].to_vec());
+        // There are 3 slots, so there is room for 2 values.
+        assert!(kernel.call(&[0, 5]).is_err());
+        assert!(kernel.call(&[1, 5]).is_err());
+        // The front value was pushed into the last slot.
+        assert_eq!(read_u8(0), 2);
+        assert_eq!(read_u8(1), 1);
+        assert_eq!(read_u8(2), 4);
+        assert_eq!(read_u8(4), 3);
+        assert_eq!(kernel.call(&[2]).unwrap(), [4, 3].to_vec());
+        assert_eq!(read_u8(4), 0);
+        assert_eq!(kernel.call(&[0, 6]).unwrap(), [4, 6, 0].to_vec());
+        assert_eq!(kernel.call(&[2]).unwrap(), [6, 4].to_vec());
+        // The tail wraps around to the first slot.
+        assert_eq!(kernel.call(&[0, 7]).unwrap(), [6, 7, 0].to_vec());
+        assert_eq!(read_u8(0), 1);
+        assert_eq!(read_u8(1), 0);
+        assert_eq!(read_u8(4), 7);
+        assert_eq!(kernel.call(&[3]).unwrap(), [6, 7].to_vec());
+        assert_eq!(kernel.call(&[3]).unwrap(), [6].to_vec());
+        assert_eq!(kernel.call(&[3]).unwrap(), [0].to_vec());
+
+        // A tail index outside of the ring is rejected.
+        kernel.register(key("corrupt"), write_cap(0.into(), 4.into()), |_input| {
+            let mut tail = [0; 32];
+            tail[31] = 3;
+            write(0, &U256::from(1).into(), &tail)?;
+            match StorageDeque::<u8>::from(0) {
+                Err(DataStructureError::Other) => Ok(Vec::new()),
+                _ => Err(Error),
+            }
+        });
+        kernel.set_entry(key("corrupt"));
+        assert!(kernel.call(&[]).is_ok());
+    }
+}
@@ -0,0 +1,415 @@
+extern crate pwasm_abi;
+use pwasm_abi::types::*;
+
+use crate::proc_table;
+use crate::data::*;
+use crate::data::bytes::*;
+use crate::data::deque::*;
+use crate::data::map::*;
+use crate::data::map_enumerable::*;
+use crate::data::map_hash::*;
+use crate::data::set::*;
+use crate::data::vec::*;
+
+/// The kinds of data structure a [`StorageLayout`] can place.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionKind {
+    Map,
+    EnumerableMap,
+    HashMap,
+    Set,
+    Vec,
+    Deque,
+    Bytes,
+    BytesHeap,
+}
+
+/// What a data structure requires of the region it is placed in.
+#[derive(Clone, Debug)]
+struct Requirement {
+    name: &'static str,
+    kind: RegionKind,
+    /// The number of trailing zero bits the location must have.
+    align_bits: u32,
+    /// The size of the region, which covers size+1 keys. This is `None` if
+    /// the size overflows.
+    size: Option<U256>,
+}
+
+/// A description of several named data structures which share a single
+/// StoreWrite cap.
+///
+/// Each of [`StorageMap`], [`StorageEnumerableMap`], [`StorageHashMap`],
+/// [`StorageSet`], [`StorageVec`], [`StorageDeque`], [`StorageBytes`] and
+/// [`StorageBytesHeap`] can be derived from a whole cap, but that needs a cap
+/// per data structure, and the maps and sets need their caps to be aligned. A
+/// [`StorageLayout`] instead places each data structure in its own region of
+/// one cap, in the order they are added. Each region starts at the first key
+/// after the previous region which meets the alignment of its data structure,
+/// so adding the data structures with the largest alignment first wastes the
+/// least space.
+///
+/// ```ignore
+/// let regions = StorageLayout::new()
+///     .enumerable_map::<u8, U256>("balances")
+///     .vec::<U256>("history", 16.into())
+///     .build(0)?;
+/// let balances: StorageEnumerableMap<u8, U256> = regions.enumerable_map("balances")?;
+/// ```
+///
+/// The same layout always places the data structures in the same regions of
+/// a cap at the same location, so [`StorageLayout::plan`] can compute the cap
+/// to request for it beforehand.
+#[derive(Clone, Debug, Default)]
+pub struct StorageLayout {
+    requirements: Vec<Requirement>,
+}
+
+impl StorageLayout {
+
+    /// Create an empty layout.
+    pub fn new() -> Self {
+        StorageLayout {
+            requirements: Vec::new(),
+        }
+    }
+
+    fn add(mut self, name: &'static str, kind: RegionKind, align_bits: u32, size: Option<U256>) -> Self {
+        self.requirements.push(Requirement {
+            name,
+            kind,
+            align_bits,
+            size,
+        });
+        self
+    }
+
+    /// Add a [`StorageMap`].
+    pub fn map<K: Keyable, V: Storable>(self, name: &'static str) -> Self {
+        let map_bits = StorageMap::<K,V>::map_bits();
+        let size = if map_bits >= 256 {
+            None
+        } else {
+            Some(U256::from(1) << map_bits as usize)
+        };
+        self.add(name, RegionKind::Map, StorageMap::<K,V>::align_bits(), size)
+    }
+
+    /// Add a [`StorageEnumerableMap`].
+    pub fn enumerable_map<K: Keyable, V: Storable>(self, name: &'static str) -> Self {
+        let map_bits = StorageEnumerableMap::<K,V>::map_bits();
+        let size = if map_bits >= 256 {
+            None
+        } else {
+            Some((U256::from(1) << map_bits as usize) - U256::from(1))
+        };
+        self.add(name, RegionKind::EnumerableMap, map_bits, size)
+    }
+
+    /// Add a [`StorageHashMap`] with room for `capacity` keys.
+    pub fn hash_map<K: Keyable, V: Storable>(self, name: &'static str, capacity: U256) -> Self {
+        let size = capacity.checked_mul(StorageHashMap::<K,V>::bucket_size());
+        self.add(name, RegionKind::HashMap, 0, size)
+    }
+
+    /// Add a [`StorageSet`].
+    pub fn set<K: Keyable>(self, name: &'static str) -> Self {
+        let set_bits = StorageSet::<K>::set_bits();
+        let size = if set_bits >= 256 {
+            None
+        } else {
+            Some((U256::from(1) << set_bits as usize) - U256::from(1))
+        };
+        self.add(name, RegionKind::Set, set_bits, size)
+    }
+
+    /// Add a [`StorageVec`] with room for `capacity` values.
+    pub fn vec<V: Storable>(self, name: &'static str, capacity: U256) -> Self {
+        let size = capacity.checked_mul(V::n_keys());
+        self.add(name, RegionKind::Vec, 0, size)
+    }
+
+    /// Add a [`StorageDeque`] with room for `capacity` values.
+    pub fn deque<V: Storable>(self, name: &'static str, capacity: U256) -> Self {
+        // The head and tail indices, followed by one more slot than the
+        // capacity.
+        let size = capacity
+            .checked_add(1.into())
+            .and_then(|slots| slots.checked_mul(V::n_keys()))
+            .and_then(|x| x.checked_add(1.into()));
+        self.add(name, RegionKind::Deque, 0, size)
+    }
+
+    /// Add a [`StorageBytes`] or [`StorageString`] with room for `capacity`
+    /// bytes.
+    pub fn bytes(self, name: &'static str, capacity: U256) -> Self {
+        // One key for each 32-byte chunk, rounded up.
+        let size = capacity.checked_add(31.into()).map(|x| x / U256::from(32));
+        self.add(name, RegionKind::Bytes, 0, size)
+    }
+
+    /// Add a [`StorageBytesHeap`] with room for `capacity` slots of
+    /// `slot_keys` storage keys.
+    pub fn bytes_heap(self, name: &'static str, capacity: U256, slot_keys: U256) -> Self {
+        // The two keys of the heap itself, followed by the slots.
+        let size = capacity
+            .checked_mul(slot_keys)
+            .and_then(|x| x.checked_add(1.into()));
+        self.add(name, RegionKind::BytesHeap, 0, size)
+    }
+
+    /// Place the data structures in the region which covers size+1 keys from
+    /// location. Returns `DataStructureError::TooSmall` if they do not fit,
+    /// and `DataStructureError::DuplicateName` if a name is used more than
+    /// once.
+    pub fn regions(&self, location: U256, size: U256) -> Result<Vec<StorageRegion>, DataStructureError> {
+        let end = match location.checked_add(size) {
+            None => return Err(DataStructureError::Other),
+            Some(x) => x,
+        };
+        let mut regions: Vec<StorageRegion> = Vec::new();
+        // The first key which is not yet used, or `None` once the last key
+        // of storage is used.
+        let mut next = Some(location);
+        for requirement in &self.requirements {
+            if regions.iter().any(|region| region.name == requirement.name) {
+                return Err(DataStructureError::DuplicateName);
+            }
+            let region_size = requirement.size.ok_or(DataStructureError::TooSmall)?;
+            let region_location = next
+                .and_then(|next| align_up(next, requirement.align_bits))
+                .ok_or(DataStructureError::TooSmall)?;
+            let region_end = region_location
+                .checked_add(region_size)
+                .ok_or(DataStructureError::TooSmall)?;
+            if region_end > end {
+                return Err(DataStructureError::TooSmall);
+            }
+            regions.push(StorageRegion {
+                name: requirement.name,
+                kind: requirement.kind,
+                location: region_location,
+                size: region_size,
+            });
+            next = region_end.checked_add(1.into());
+        }
+        Ok(regions)
+    }
+
+    /// Place the data structures in the cap at the given index.
+    pub fn build(&self, cap_index: u8) -> Result<StorageRegions, DataStructureError> {
+        let (location, size) = store_write_region(cap_index)?;
+        Ok(StorageRegions {
+            cap_index,
+            regions: self.regions(location, size)?,
+        })
+    }
+
+    /// Compute the smallest cap starting at `location` which holds the
+    /// layout, along with the regions it will be divided into. The regions
+    /// can also be requested as caps of their own with
+    /// [`StorageRegion::cap`].
+    #[cfg(feature="std")]
+    pub fn plan(&self, location: U256) -> Result<LayoutPlan, DataStructureError> {
+        let regions = self.regions(location, U256::max_value() - location)?;
+        let size = match regions.last() {
+            Some(region) => region.location + region.size - location,
+            None => U256::zero(),
+        };
+        Ok(LayoutPlan {
+            cap: proc_table::cap::StoreWriteCap {
+                location: location.into(),
+                size: size.into(),
+            },
+            regions,
+        })
+    }
+}
+
+/// Return the first key at or after `key` with `bits` trailing zero bits.
+fn align_up(key: U256, bits: u32) -> Option<U256> {
+    if bits == 0 {
+        Some(key)
+    } else if bits >= 256 {
+        if key.is_zero() { Some(key) } else { None }
+    } else {
+        let mask = (U256::from(1) << bits as usize) - U256::from(1);
+        key.checked_add(mask).map(|x| x & !mask)
+    }
+}
+
+/// The region of storage a [`StorageLayout`] places a data structure in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageRegion {
+    pub name: &'static str,
+    pub kind: RegionKind,
+    /// The first key of the region.
+    pub location: U256,
+    /// The size of the region, which covers size+1 keys, as with a cap.
+    pub size: U256,
+}
+
+impl StorageRegion {
+    /// A StoreWrite cap which covers exactly this region.
+    pub fn cap(&self) -> proc_table::cap::StoreWriteCap {
+        proc_table::cap::StoreWriteCap {
+            location: self.location.into(),
+            size: self.size.into(),
+        }
+    }
+}
+
+/// The cap to request for a [`StorageLayout`], and how it will be divided.
+#[cfg(feature="std")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutPlan {
+    pub cap: proc_table::cap::StoreWriteCap,
+    pub regions: Vec<StorageRegion>,
+}
+
+/// The data structures of a [`StorageLayout`], placed in a cap.
+pub struct StorageRegions {
+    cap_index: u8,
+    regions: Vec<StorageRegion>,
+}
+
+impl StorageRegions {
+
+    /// Return the regions the data structures are placed in.
+    pub fn regions(&self) -> &[StorageRegion] {
+        &self.regions
+    }
+
+    /// Return the region of the data structure with the given name and kind.
+    fn region(&self, name: &str, kind: RegionKind) -> Result<&StorageRegion, DataStructureError> {
+        self.regions
+            .iter()
+            .find(|region| region.name == name && region.kind == kind)
+            .ok_or(DataStructureError::UnknownName)
+    }
+
+    /// Return the [`StorageMap`] with the given name.
+    pub fn map<K: Keyable, V: Storable>(&self, name: &str) -> Result<StorageMap<K,V>, DataStructureError> {
+        let region = self.region(name, RegionKind::Map)?;
+        StorageMap::from_region(self.cap_index, region.location, region.size)
+    }
+
+    /// Return the [`StorageEnumerableMap`] with the given name.
+    pub fn enumerable_map<K: Keyable, V: Storable>(&self, name: &str) -> Result<StorageEnumerableMap<K,V>, DataStructureError> {
+        let region = self.region(name, RegionKind::EnumerableMap)?;
+        StorageEnumerableMap::from_region(self.cap_index, region.location, region.size)
+    }
+
+    /// Return the [`StorageHashMap`] with the given name.
+    pub fn hash_map<K: Keyable, V: Storable>(&self, name: &str) -> Result<StorageHashMap<K,V>, DataStructureError> {
+        let region = self.region(name, RegionKind::HashMap)?;
+        StorageHashMap::from_region(self.cap_index, region.location, region.size)
+    }
+
+    /// Return the [`StorageSet`] with the given name.
+    pub fn set<K: Keyable>(&self, name: &str) -> Result<StorageSet<K>, DataStructureError> {
+        let region = self.region(name, RegionKind::Set)?;
+        StorageSet::from_region(self.cap_index, region.location, region.size)
+    }
+
+    /// Return the [`StorageVec`] with the given name.
+    pub fn vec<V: Storable>(&self, name: &str) -> Result<StorageVec<V>, DataStructureError> {
+        let region = self.region(name, RegionKind::Vec)?;
+        StorageVec::from_region(self.cap_index, region.location, region.size)
+    }
+
+    /// Return the [`StorageDeque`] with the given name.
+    pub fn deque<V: Storable>(&self, name: &str) -> Result<StorageDeque<V>, DataStructureError> {
+        let region = self.region(name, RegionKind::Deque)?;
+        StorageDeque::from_region(self.cap_index, region.location, region.size)
+    }
+
+    /// Return the [`StorageBytes`] with the given name.
+    pub fn bytes(&self, name: &str) -> Result<StorageBytes, DataStructureError> {
+        let region = self.region(name, RegionKind::Bytes)?;
+        StorageBytes::from_region(self.cap_index, region.location, region.size)
+    }
+
+    /// Return the [`StorageString`] with the given name, which was added with
+    /// [`StorageLayout::bytes`].
+    pub fn string(&self, name: &str) -> Result<StorageString, DataStructureError> {
+        Ok(StorageString::from_bytes(self.bytes(name)?))
+    }
+
+    /// Return the [`StorageBytesHeap`] with the given name, which has slots
+    /// of `slot_keys` storage keys.
+    pub fn bytes_heap(&self, name: &str, slot_keys: U256) -> Result<StorageBytesHeap, DataStructureError> {
+        let region = self.region(name, RegionKind::BytesHeap)?;
+        StorageBytesHeap::from_region(self.cap_index, region.location, region.size, slot_keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::proc_table::cap::*;
+    use crate::syscalls::Error;
+
+    fn layout() -> StorageLayout {
+        StorageLayout::new()
+            .enumerable_map::<u8, u8>("members")
+            .vec::<U256>("history", 2.into())
+            .bytes("name", 40.into())
+    }
+
+    #[test]
+    fn storage_layout() {
+        // The map covers the first 1024 keys, the vector the length and 2
+        // values, and the bytes the length and 2 chunks.
+        let plan = layout().plan(0.into()).unwrap();
+        assert_eq!(U256::from(plan.cap.size), U256::from(1029));
+        let locations: Vec<U256> = plan.regions.iter().map(|region| region.location).collect();
+        assert_eq!(locations, [0.into(), 1024.into(), 1027.into()].to_vec());
+        assert!(layout().regions(0.into(), 1028.into()).is_err());
+        // The map is aligned, so a cap which starts after 0 needs to be
+        // larger.
+        let plan_1 = layout().plan(1.into()).unwrap();
+        assert_eq!(plan_1.regions[0].location, 1024.into());
+        assert_eq!(U256::from(plan_1.cap.size), U256::from(2052));
+        match StorageLayout::new()
+            .vec::<U256>("history", 2.into())
+            .vec::<U256>("history", 2.into())
+            .regions(0.into(), 10.into()) {
+            Err(DataStructureError::DuplicateName) => (),
+            _ => panic!("expected DuplicateName"),
+        }
+
+        let mut kernel = TestKernel::new();
+        let cap_list = NewCapList(
+            [NewCapability {
+                cap: Capability::StoreWrite(plan.cap),
+                parent_index: 0,
+            }]
+            .to_vec(),
+        );
+        kernel.register(key("layout"), cap_list, |input| {
+            let regions = layout().build(0).map_err(|_| Error)?;
+            // There is no vector with this name.
+            if regions.vec::<U256>("name").is_ok() {
+                return Err(Error);
+            }
+            let mut members: StorageEnumerableMap<u8, u8> = regions.enumerable_map("members").map_err(|_| Error)?;
+            let mut history: StorageVec<U256> = regions.vec("history").map_err(|_| Error)?;
+            let mut name = regions.string("name").map_err(|_| Error)?;
+            members.insert(input[0], input[1]);
+            history.push(input[1].into()).map_err(|_| Error)?;
+            name.set("layout").map_err(|_| Error)?;
+            Ok([members.length().as_u32() as u8, history.capacity().as_u32() as u8].to_vec())
+        });
+        kernel.set_entry(key("layout"));
+
+        assert_eq!(kernel.call(&[3, 9]).unwrap(), [1, 2].to_vec());
+        // The length of the map.
+        assert_eq!(read_key(3), 1.into());
+        assert_eq!(read_key(1024), 1.into());
+        assert_eq!(read_key(1025), 9.into());
+        assert_eq!(read_key(1027), 6.into());
+    }
+}
@@ -57,32 +57,46 @@ impl<K: Keyable, V: Storable> StorageMap<K,V> {
 
     /// Derive a [`StorageMap`] from the cap at the given index.
     pub fn from(cap_index: u8) -> Result<Self,DataStructureError> {
-        // The size of the cap needs to be key_width+1 in bytes
-        let address_bytes = K::key_width()+1;
-        let address_bits = address_bytes*8;
-        let address_size = U256::from(2).pow(U256::from(address_bits));
-        // The address also need to be aligned.
-        let this_proc_key = proc_table::get_current_proc_id();
-        if let Some(proc_table::cap::Capability::StoreWrite(proc_table::cap::StoreWriteCap {location, size})) =
-                proc_table::get_proc_cap(this_proc_key, proc_table::cap::CAP_STORE_WRITE, cap_index) {
-                    // Check that the size of the cap is correct.
-                    if U256::from(size) < address_size {
-                        Err(DataStructureError::TooSmall)
-                    } else if U256::from(location).trailing_zeros() < (address_bits as u32 + 6) {
-                        Err(DataStructureError::MisAligned)
-                    } else {
-                        Ok(StorageMap {
-                            cap_index,
-                            location: location.into(),
-                            key_type: PhantomData,
-                            data_type: PhantomData,
-                        })
-                    }
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size)
+    }
+
+    /// Derive a [`StorageMap`] from a region of the cap at the given index,
+    /// which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self,DataStructureError> {
+        // The size of the region needs to be key_width+1 in bytes
+        let address_bits = Self::map_bits();
+        if address_bits >= 256 {
+            return Err(DataStructureError::TooSmall);
+        }
+        let address_size = U256::from(1) << address_bits as usize;
+        // Check that the size of the region is correct.
+        if size < address_size {
+            Err(DataStructureError::TooSmall)
+        } else if location.trailing_zeros() < Self::align_bits() {
+            // The address also need to be aligned.
+            Err(DataStructureError::MisAligned)
         } else {
-            Err(DataStructureError::BadCap)
+            Ok(StorageMap {
+                cap_index,
+                location: location.into(),
+                key_type: PhantomData,
+                data_type: PhantomData,
+            })
         }
     }
 
+    /// The number of bits of storage key used by the map, which is the key
+    /// and a byte for the data and presence.
+    pub fn map_bits() -> u32 {
+        (K::key_width() as u32 + 1) * 8
+    }
+
+    /// The number of trailing zero bits required of the location of the map.
+    pub fn align_bits() -> u32 {
+        Self::map_bits() + 6
+    }
+
     /// Return the start/base location of the map.
     pub fn location(&self) -> H256 {
         self.location
@@ -114,7 +128,7 @@ impl<K: Keyable, V: Storable> StorageMap<K,V> {
     pub fn present(&self, key: &K) -> bool {
         // If the value at the presence key is non-zero, then a value is
         // present.
-        let present = pwasm_ethereum::read(&self.presence_key(key));
+        let present = read(&self.presence_key(key));
         present != [0; 32]
     }
 
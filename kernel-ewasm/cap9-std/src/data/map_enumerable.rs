@@ -26,40 +26,47 @@ use core::marker::PhantomData;
 /// ## Alignment
 ///
 /// [`StorageEnumerableMap`] is aligned to a certain boundary that is determined
-/// by it's key type (`K`). The last `key_with_in_bits+2+6` bits of the location
-/// of the storage capability must be zeroes. If a [`StorageEnumerableMap`]
-/// tries to be derived from a capability that does not align on this boundary,
-/// a `DataStructureError::MisAligned` error will be returned.
+/// by it's key type (`K`) and value type (`V`). The last
+/// `key_width_in_bits+2+data_width_in_bits` bits of the location of the storage
+/// capability must be zeroes, and the capability must cover all of the keys
+/// after the location up to that boundary. If a [`StorageEnumerableMap`] tries
+/// to be derived from a capability that does not align on this boundary, a
+/// `DataStructureError::MisAligned` error will be returned.
 ///
-/// The 2 bits in the sum above are for presence and enumeration, and the 6 bits
-/// are for data. Let's take an example of a map that maps [`Address`] to
-/// [`u8`]. That is `StorageEnumerableMap<Address,u8>`. It takes 160 bits or 20
-/// bytes to store an address, therefore our key width is 160 bits. Storage keys
-/// that form part of this map will have the following format:
+/// The data width is the number of bits needed to index the 32-byte values of
+/// a single `V`, that is the base 2 logarithm of `V::n_keys()`, rounded up. A
+/// map of values which fit in a single 32-byte value has a data width of zero,
+/// while a value of 40 32-byte values needs 6 bits. The 2 bits in the sum above
+/// are for presence and enumeration. Let's take an example of a map that maps
+/// [`Address`] to [`u8`]. That is `StorageEnumerableMap<Address,u8>`. It takes
+/// 160 bits or 20 bytes to store an address, therefore our key width is 160
+/// bits, and a [`u8`] needs a single 32-byte value, therefore our data width is
+/// 0 bits. Storage keys that form part of this map will have the following
+/// format, from the most significant bit:
 ///
-/// * `0xaa`: Adress
-/// * `0x--`: Arbitrary bytes dictating the location of the map in storage.
-/// * `p` in the final byte indicates it is a "presence" value.
-/// * `e` in the final byte indicates it is part of the enumeration vector.
-/// * `d` in the final byte are the 6 bits that allow for data of up to 64
-///   32-byte values.
-///
-/// TODO: Presence only requires a single key, and could be stored in one of the
-/// data values.
-///
-/// TODO: Allow variable data size.
+/// * `location`: Arbitrary bits dictating the location of the map in storage.
+/// * `key`: The 160 bits of the address.
+/// * `p`: Set if it is a "presence" value.
+/// * `e`: Set if it is part of the enumeration vector.
+/// * `data`: The 0 bits that index the 32-byte values of the data.
 ///
 /// ```compile_fail
-///     *----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----------*
-///     |0x--|0x--|0x--|0x--|0x--|0x--|0x--|0x--|0x--|0x--|0x--|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0xaa|0bpedddddd|
-///     *----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----*----------*
+///     *-------------*--------------------*---*---*------*
+///     | location    | key                | p | e | data |
+///     *-------------*--------------------*---*---*------*
+///     | 256-160-2-0 | 160                | 1 | 1 | 0    |
+///     *-------------*--------------------*---*---*------*
 /// ```
 ///
-/// Note that key widths must be byte-aligned, and are therefore always
-/// multiples of bits. Currently the data width is fixed to 6 bits. If the data
-/// width is allowed to be variable it will be aligned to `2^x - 2` bits. I.e. it
-/// will also be byte-aligned, but minus 2 bits which are used for key presence
-/// and the enumeration vector.
+/// With `p` and `e` both clear, the storage key holds the data of the key. With
+/// `p` set, it holds the 1-based index of the key in the enumeration vector, or
+/// zero if the key is not in the map. With `e` set, the `key` bits instead hold
+/// an index into the enumeration vector, minus one, and the storage key holds
+/// the map key at that index. The length of the map is stored with both `p` and
+/// `e` set and the other bits clear.
+///
+/// TODO: Presence only requires a single key, and could be stored in one of the
+/// data values.
 pub struct StorageEnumerableMap<K,V> {
     cap_index: u8,
     /// The start location of the map.
@@ -85,31 +92,35 @@ impl<K: Keyable, V: Storable> StorageEnumerableMap<K,V> {
 
     /// Derive a [`StorageEnumerableMap`] from the cap at the given index.
     pub fn from(cap_index: u8) -> Result<Self, DataStructureError> {
-        // The size of the cap needs to be key_width+1 in bytes
-        let address_bytes = K::key_width()+1;
-        let address_bits = address_bytes*8;
-        let address_size = U256::from(2).pow(U256::from(address_bits));
-        // The address also need to be aligned.
-        let this_proc_key = proc_table::get_current_proc_id();
-        if let Some(proc_table::cap::Capability::StoreWrite(proc_table::cap::StoreWriteCap {location, size})) =
-                proc_table::get_proc_cap(this_proc_key, proc_table::cap::CAP_STORE_WRITE, cap_index) {
-                    // Check that the size of the cap is correct.
-                    if U256::from(size) < address_size {
-                        Err(DataStructureError::TooSmall)
-                    } else if U256::from(location).trailing_zeros() < (address_bits as u32 + 1 + 1 + 6) {
-                        // the trailing number of 0 bits should be equal to or greater than the address_bits
-                        Err(DataStructureError::MisAligned)
-                    } else {
-                        Ok(StorageEnumerableMap {
-                            cap_index,
-                            location: location.into(),
-                            key_type: PhantomData,
-                            data_type: PhantomData,
-                            length: None,
-                        })
-                    }
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size)
+    }
+
+    /// Derive a [`StorageEnumerableMap`] from a region of the cap at the given
+    /// index, which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        // The map covers every storage key with the key, presence,
+        // enumeration, and data bits.
+        let map_bits = Self::map_bits();
+        if map_bits >= 256 {
+            return Err(DataStructureError::TooSmall);
+        }
+        let map_size = U256::from(1) << map_bits as usize;
+        // Check that the size of the region is correct. The region covers
+        // size+1 keys.
+        if size < map_size - U256::from(1) {
+            Err(DataStructureError::TooSmall)
+        } else if location.trailing_zeros() < map_bits {
+            // the trailing number of 0 bits should be equal to or greater than the map_bits
+            Err(DataStructureError::MisAligned)
         } else {
-            Err(DataStructureError::BadCap)
+            Ok(StorageEnumerableMap {
+                cap_index,
+                location: location.into(),
+                key_type: PhantomData,
+                data_type: PhantomData,
+                length: None,
+            })
         }
     }
 
@@ -118,37 +129,55 @@ impl<K: Keyable, V: Storable> StorageEnumerableMap<K,V> {
         self.location
     }
 
+    /// The number of bits used to index the 32-byte values of the data. This
+    /// is the smallest number of bits that can hold `V::n_keys()` values.
+    pub fn data_bits() -> u32 {
+        let n_keys = V::n_keys();
+        if n_keys <= U256::from(1) {
+            0
+        } else {
+            256 - (n_keys - U256::from(1)).leading_zeros()
+        }
+    }
+
+    /// The number of bits of storage key used by the map, which is also the
+    /// alignment of its location.
+    pub fn map_bits() -> u32 {
+        K::key_width() as u32 * 8 + 2 + Self::data_bits()
+    }
+
+    /// Return the storage key at the given key bits and flags. The flags are
+    /// the presence and enumeration bits.
+    fn flagged_key(&self, key_bits: U256, flags: u8) -> U256 {
+        let data_bits = Self::data_bits() as usize;
+        U256::from(self.location)
+            | (key_bits << (data_bits + 2))
+            | (U256::from(flags) << data_bits)
+    }
+
     /// Return the base storage key of a given map key.
     fn base_key(&self, key: &K) -> [u8; 32] {
-        let mut base: [u8; 32] = [0; 32];
-        // The key start 32 - width - 1, the - 1 is for data and presence. This
-        // is in bytes.
-        let key_start = 32 - K::key_width() as usize - 1;
-        // First we copy in the relevant parts of the location.
-        base[0..key_start].copy_from_slice(&self.location().as_bytes()[0..key_start]);
-        // Then we copy in the key
-        // TODO: overflow
-        base[key_start..(key_start+K::key_width() as usize)].clone_from_slice(key.key_slice().as_slice());
-        base
+        let key_bits = U256::from_big_endian(key.key_slice().as_slice());
+        self.flagged_key(key_bits, 0b00).into()
     }
 
     fn presence_key(&self, key: &K) -> H256 {
         // The presence_key is the storage key which indicates whether there is
         // a value associated with this key.
-        let mut presence_key = self.base_key(&key);
-        // The first bit of the data byte indicates presence
-        presence_key[31] = presence_key[31] | 0b10000000;
-        presence_key.into()
+        let key_bits = U256::from_big_endian(key.key_slice().as_slice());
+        H256::from(self.flagged_key(key_bits, 0b10))
+    }
+
+    /// Return the storage key of the map key at a given 1-based index of the
+    /// enumeration vector.
+    fn element_key(&self, index: U256) -> H256 {
+        H256::from(self.flagged_key(index - U256::from(1), 0b01))
     }
 
     fn length_key(&self) -> H256 {
-        // The presence_key is the storage key which indicates whether there is
-        // a value associated with this key.
-        let mut location = self.location.clone();
-        let length_key = location.as_fixed_bytes_mut();
-        let index = 31;
-        length_key[index as usize] = length_key[index as usize] | 0b01000000;
-        length_key.into()
+        // The length of the map is stored with both the presence and
+        // enumeration bits set.
+        H256::from(self.flagged_key(U256::zero(), 0b11))
     }
 
     /// Return the number of elements in the map.
@@ -158,7 +187,7 @@ impl<K: Keyable, V: Storable> StorageEnumerableMap<K,V> {
             Some(l) => l,
             // No cached value exists, read from storage.
             None => {
-                let length = U256::from(pwasm_ethereum::read(&self.length_key()));
+                let length = U256::from(read(&self.length_key()));
                 length
             }
         }
@@ -180,13 +209,17 @@ impl<K: Keyable, V: Storable> StorageEnumerableMap<K,V> {
     pub fn present(&self, key: &K) -> bool {
         // If the value at the presence key is non-zero, then a value is
         // present.
-        let present = pwasm_ethereum::read(&self.presence_key(key));
+        let present = read(&self.presence_key(key));
         present != [0; 32]
     }
 
     fn index(&self, key: &K) -> Option<U256> {
-        let present = pwasm_ethereum::read(&self.presence_key(key));
-        Some(present.into())
+        let present = U256::from(read(&self.presence_key(key)));
+        if present.is_zero() {
+            None
+        } else {
+            Some(present)
+        }
     }
 
     fn set_present(&self, key: &K, index: U256) {
@@ -216,9 +249,8 @@ impl<K: Keyable, V: Storable> StorageEnumerableMap<K,V> {
         if index >= self.length() {
             return None;
         }
-        let mut storage_key = self.length_key().clone();
-        storage_key = H256::from(U256::from(storage_key) + index + U256::from(1));
-        let storage_value: StorageValue = pwasm_ethereum::read(&storage_key).into();
+        let storage_key = self.element_key(index + U256::from(1));
+        let storage_value: StorageValue = read(&storage_key).into();
         Some(storage_value.into())
     }
 
@@ -230,10 +262,9 @@ impl<K: Keyable, V: Storable> StorageEnumerableMap<K,V> {
         self.set_present(&key, self.length());
         value.store(self.cap_index, U256::from_big_endian(&base));
         // Insert the key into the enumeration 'vector'
-        let mut length_key = self.length_key().clone();
-        length_key = H256::from(U256::from(length_key) + self.length());
+        let element_key = self.element_key(self.length());
         let k_val: StorageValue = key.into();
-        write(self.cap_index, &length_key.to_fixed_bytes(), &k_val.into()).unwrap();
+        write(self.cap_index, &element_key.to_fixed_bytes(), &k_val.into()).unwrap();
     }
 
     /// Remove a value at a given key.
@@ -250,13 +281,13 @@ impl<K: Keyable, V: Storable> StorageEnumerableMap<K,V> {
             Some(element_index) => {
                 // element key is the the storage key of the map key in the
                 // enumeration vector.
-                let element_key = H256::from(U256::from(self.length_key()) + element_index);
+                let element_key = self.element_key(element_index);
                 // We want to overwite this enumeration vector position with the
                 // last value of the enumeration vector.
-                let last_element_key = H256::from(U256::from(self.length_key()) + self.length());
+                let last_element_key = self.element_key(self.length());
                 // Read the map key stored in the final position of the
                 // enumeration vector.
-                let last_element_value: StorageValue = pwasm_ethereum::read(&last_element_key).into();
+                let last_element_value: StorageValue = read(&last_element_key).into();
                 // Write this value over the key we are removing.
                 write(self.cap_index, &element_key.to_fixed_bytes(), &last_element_value.clone().into()).unwrap();
                 // Clear the last value in the enumeration vector.
@@ -387,3 +418,58 @@ impl<'a, K: Keyable, V: Storable> Iterator for StorageEnumerableMapIter<'a, K, V
         Some((key.clone(), self.storage_map.get(key)?))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn storage_enumerable_map() {
+        // A u8 value needs no data bits, so the map of u8 keys covers 8 key
+        // bits and the presence and enumeration bits.
+        assert_eq!(StorageEnumerableMap::<u8, u8>::map_bits(), 10);
+        assert_eq!(StorageEnumerableMap::<Address, u8>::map_bits(), 162);
+        let mut kernel = TestKernel::new();
+        kernel.register(key("map"), write_cap(0.into(), 0x3ff.into()), |input| {
+            let mut map: StorageEnumerableMap<u8, u8> = StorageEnumerableMap::from(0).map_err(|_| Error)?;
+            if input.len() > 1 {
+                map.insert(input[0], input[1]);
+            } else {
+                map.remove(input[0]);
+            }
+            let mut keys: Vec<u8> = map.keys().collect();
+            keys.sort();
+            Ok(keys)
+        });
+        kernel.set_entry(key("map"));
+
+        assert_eq!(kernel.call(&[5, 7]).unwrap(), [5].to_vec());
+        assert_eq!(kernel.call(&[9, 8]).unwrap(), [5, 9].to_vec());
+        // The value, then the presence value, the first element of the
+        // enumeration vector, and the length.
+        assert_eq!(read_u8(5 << 2), 7);
+        assert_eq!(read_u8(5 << 2 | 0b10), 1);
+        assert_eq!(read_u8(0b01), 5);
+        assert_eq!(read_u8(0b11), 2);
+        // Removing a key which is not in the map does nothing.
+        assert_eq!(kernel.call(&[6]).unwrap(), [5, 9].to_vec());
+        assert_eq!(kernel.call(&[5]).unwrap(), [9].to_vec());
+        assert_eq!(read_u8(5 << 2), 0);
+        assert_eq!(read_u8(9 << 2 | 0b10), 1);
+        assert_eq!(read_u8(0b01), 9);
+        assert_eq!(read_u8(0b11), 1);
+
+        // The cap must cover all of the keys of the map.
+        kernel.register(key("small"), write_cap(0.into(), 255.into()), |_input| {
+            match StorageEnumerableMap::<u8, u8>::from(0) {
+                Err(data::DataStructureError::TooSmall) => Ok(Vec::new()),
+                _ => Err(Error),
+            }
+        });
+        kernel.set_entry(key("small"));
+        assert!(kernel.call(&[]).is_ok());
+    }
+}
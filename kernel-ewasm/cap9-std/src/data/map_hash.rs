@@ -0,0 +1,306 @@
+extern crate pwasm_abi;
+extern crate tiny_keccak;
+use pwasm_abi::types::*;
+use cap9_core::Serialize;
+use cap9_core::StorageValue;
+
+use crate::proc_table;
+use crate::syscalls::*;
+use crate::*;
+use crate::data::*;
+
+use core::marker::PhantomData;
+
+/// A map of values in storage, which hashes its keys.
+///
+/// [`StorageMap`] and [`StorageEnumerableMap`] place the key directly in the
+/// storage key, which means the cap must cover the whole key space. That is
+/// not practical for wide or sparse keys such as [`H256`] hashes. A
+/// [`StorageHashMap`] instead places each key in one of a fixed number of
+/// buckets, chosen by the keccak hash of the key, within the region of a
+/// single cap. This is similar to a Solidity mapping, except that it is
+/// bounded by the cap. Keys wider than 32 bytes, such as strings, can be
+/// hashed to an [`H256`] first.
+///
+/// ## Layout
+///
+/// The first storage key of the cap holds the number of elements in the map.
+/// It is followed by the buckets, each of which is `2 + V::n_keys()` storage
+/// keys:
+///
+/// * The tag, which is the index of the bucket the key hashed to plus one, or
+///   zero if the bucket is empty.
+/// * The key.
+/// * The value.
+///
+/// The number of buckets is the size of the cap divided by the size of a
+/// bucket, rounded down. There are no alignment requirements.
+///
+/// ## Collisions
+///
+/// Collisions are handled with linear probing: if the bucket a key hashes to
+/// is taken by another key, the following buckets are tried in turn, wrapping
+/// around at the end of the cap. Removing a key moves any following keys of
+/// the same run back, so that no run has a gap in it. Once every bucket is
+/// taken, inserting a new key returns `DataStructureError::Full`. As with any
+/// hash map, lookups get slower as the map fills up, so the cap should be
+/// comfortably larger than the number of keys expected.
+pub struct StorageHashMap<K,V> {
+    cap_index: u8,
+    /// The start location of the map.
+    location: H256,
+    /// The number of buckets in the map.
+    n_buckets: U256,
+    /// The key type of the map.
+    key_type: PhantomData<K>,
+    /// The data type of the map.
+    data_type: PhantomData<V>,
+}
+
+impl<K: Keyable, V: Storable> StorageHashMap<K,V> {
+
+    /// Derive a [`StorageHashMap`] from the cap at the given index.
+    pub fn from(cap_index: u8) -> Result<Self, DataStructureError> {
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size)
+    }
+
+    /// Derive a [`StorageHashMap`] from a region of the cap at the given
+    /// index, which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        // The region covers size+1 keys, the first of which is the length.
+        let n_buckets = match size.checked_div(Self::bucket_size()) {
+            // Return an error on divide-by-zero
+            None => return Err(DataStructureError::Other),
+            Some(x) => x,
+        };
+        if n_buckets.is_zero() {
+            Err(DataStructureError::TooSmall)
+        } else {
+            Ok(StorageHashMap {
+                cap_index,
+                location: location.into(),
+                n_buckets,
+                key_type: PhantomData,
+                data_type: PhantomData,
+            })
+        }
+    }
+
+    /// Return the start/base location of the map.
+    pub fn location(&self) -> H256 {
+        self.location
+    }
+
+    /// Return the number of keys the map can hold.
+    pub fn capacity(&self) -> U256 {
+        self.n_buckets
+    }
+
+    /// Return the number of elements in the map.
+    pub fn length(&self) -> U256 {
+        U256::from(read(&self.location))
+    }
+
+    fn set_length(&self, length: U256) {
+        write(self.cap_index, &self.location.to_fixed_bytes(), &length.into()).unwrap();
+    }
+
+    /// The number of storage keys used by a bucket.
+    pub(crate) fn bucket_size() -> U256 {
+        V::n_keys().saturating_add(2.into())
+    }
+
+    /// The index of the bucket a key hashes to.
+    fn home(&self, key: &K) -> U256 {
+        let hash = tiny_keccak::keccak256(key.key_slice().as_slice());
+        U256::from_big_endian(&hash) % self.n_buckets
+    }
+
+    /// The index of the bucket following a given bucket.
+    fn next(&self, index: U256) -> U256 {
+        let next = index + U256::from(1);
+        if next == self.n_buckets {
+            U256::zero()
+        } else {
+            next
+        }
+    }
+
+    /// Return the storage key of the tag of a bucket. The key of the bucket
+    /// follows at +1, and the value at +2.
+    fn bucket_key(&self, index: U256) -> U256 {
+        // This can't overflow, as the buckets are within the cap.
+        U256::from(self.location) + U256::from(1) + index * Self::bucket_size()
+    }
+
+    /// Return the tag of a bucket.
+    fn tag(&self, index: U256) -> U256 {
+        U256::from(read(&H256::from(self.bucket_key(index))))
+    }
+
+    /// Find the bucket of a key. If the key is not in the map, return the
+    /// empty bucket where it would go, if there is one.
+    fn find(&self, key: &K) -> Result<U256, Option<U256>> {
+        let key_slice = key.key_slice();
+        let mut index = self.home(key);
+        let mut probed = U256::zero();
+        while probed < self.n_buckets {
+            if self.tag(index).is_zero() {
+                return Err(Some(index));
+            }
+            let storage_key = H256::from(self.bucket_key(index) + U256::from(1));
+            let storage_value: StorageValue = read(&storage_key).into();
+            let bucket_key: K = storage_value.into();
+            if bucket_key.key_slice() == key_slice {
+                return Ok(index);
+            }
+            index = self.next(index);
+            probed += U256::from(1);
+        }
+        Err(None)
+    }
+
+    /// Return true if the given key is associated with a value in the map.
+    pub fn present(&self, key: &K) -> bool {
+        self.find(key).is_ok()
+    }
+
+    /// Get the value associated with a given key, if it exists.
+    pub fn get(&self, key: K) -> Option<V> {
+        match self.find(&key) {
+            Ok(index) => V::read(self.bucket_key(index) + U256::from(2)),
+            Err(_) => None,
+        }
+    }
+
+    /// Insert a value at a given key. If the key is already in the map its
+    /// value is replaced. Returns `DataStructureError::Full` if the key is not
+    /// in the map and there is no room for it.
+    pub fn insert(&mut self, key: K, value: V) -> Result<(), DataStructureError> {
+        let index = match self.find(&key) {
+            Ok(index) => index,
+            Err(Some(index)) => {
+                let bucket_key = self.bucket_key(index);
+                let tag = self.home(&key) + U256::from(1);
+                write(self.cap_index, &H256::from(bucket_key).to_fixed_bytes(), &tag.into()).unwrap();
+                let k_val: StorageValue = key.into();
+                write(self.cap_index, &H256::from(bucket_key + U256::from(1)).to_fixed_bytes(), &k_val.into()).unwrap();
+                self.set_length(self.length() + U256::from(1));
+                index
+            },
+            Err(None) => return Err(DataStructureError::Full),
+        };
+        value.store(self.cap_index, self.bucket_key(index) + U256::from(2));
+        Ok(())
+    }
+
+    /// Remove a value at a given key.
+    pub fn remove(&mut self, key: K) {
+        let mut hole = match self.find(&key) {
+            Ok(index) => index,
+            Err(_) => return (),
+        };
+        // Move back any of the following keys which would no longer be found
+        // with a gap in front of them. A key can stay where it is if the
+        // bucket it hashed to is after the hole.
+        let mut index = self.next(hole);
+        while index != hole {
+            let tag = self.tag(index);
+            if tag.is_zero() {
+                break;
+            }
+            let home = tag - U256::from(1);
+            let stays = if hole <= index {
+                hole < home && home <= index
+            } else {
+                hole < home || home <= index
+            };
+            if !stays {
+                self.move_bucket(index, hole);
+                hole = index;
+            }
+            index = self.next(index);
+        }
+        self.clear_bucket(hole);
+        self.set_length(self.length() - U256::from(1));
+    }
+
+    /// Copy the contents of one bucket over another.
+    fn move_bucket(&self, from: U256, to: U256) {
+        let from_key = self.bucket_key(from);
+        let to_key = self.bucket_key(to);
+        let mut offset = U256::zero();
+        while offset < Self::bucket_size() {
+            let value = read(&H256::from(from_key + offset));
+            write(self.cap_index, &H256::from(to_key + offset).to_fixed_bytes(), &value).unwrap();
+            offset += U256::from(1);
+        }
+    }
+
+    fn clear_bucket(&self, index: U256) {
+        let bucket_key = self.bucket_key(index);
+        write(self.cap_index, &H256::from(bucket_key).to_fixed_bytes(), &[0; 32]).unwrap();
+        write(self.cap_index, &H256::from(bucket_key + U256::from(1)).to_fixed_bytes(), &[0; 32]).unwrap();
+        V::clear(self.cap_index, bucket_key + U256::from(2));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn storage_hash_map() {
+        let mut kernel = TestKernel::new();
+        // Room for the length and 4 buckets of a tag, a key and a value.
+        kernel.register(key("map"), write_cap(0.into(), 12.into()), |input| {
+            let mut map: StorageHashMap<U256, U256> = StorageHashMap::from(0).map_err(|_| Error)?;
+            let key = U256::from(input[1]);
+            match input[0] {
+                0 => match map.insert(key, input[2].into()) {
+                    Ok(()) => Ok([1].to_vec()),
+                    Err(_) => Ok([0].to_vec()),
+                },
+                1 => match map.get(key) {
+                    Some(value) => Ok([1, value.as_u32() as u8].to_vec()),
+                    None => Ok([0].to_vec()),
+                },
+                _ => {
+                    map.remove(key);
+                    Ok([map.length().as_u32() as u8].to_vec())
+                }
+            }
+        });
+        kernel.set_entry(key("map"));
+
+        // With only 4 buckets, some of these keys hash to the same bucket.
+        for k in 1..5 {
+            assert_eq!(kernel.call(&[0, k * 50, k]).unwrap(), [1].to_vec());
+        }
+        assert_eq!(read_u8(0), 4);
+        assert_eq!(kernel.call(&[0, 250, 5]).unwrap(), [0].to_vec());
+        // Replacing a value does not need another bucket.
+        assert_eq!(kernel.call(&[0, 100, 6]).unwrap(), [1].to_vec());
+        assert_eq!(kernel.call(&[1, 100]).unwrap(), [1, 6].to_vec());
+
+        assert_eq!(kernel.call(&[2, 50]).unwrap(), [3].to_vec());
+        assert_eq!(kernel.call(&[1, 50]).unwrap(), [0].to_vec());
+        for k in 3..5 {
+            assert_eq!(kernel.call(&[1, k * 50]).unwrap(), [1, k].to_vec());
+        }
+        assert_eq!(kernel.call(&[0, 250, 5]).unwrap(), [1].to_vec());
+        assert_eq!(kernel.call(&[1, 250]).unwrap(), [1, 5].to_vec());
+        // Removing a key which is not in the map does nothing.
+        assert_eq!(kernel.call(&[2, 7]).unwrap(), [4].to_vec());
+        for k in 2..6 {
+            assert_eq!(kernel.call(&[2, k * 50]).unwrap(), [5 - k].to_vec());
+        }
+        for location in 0..13 {
+            assert_eq!(read_u8(location), 0);
+        }
+    }
+}
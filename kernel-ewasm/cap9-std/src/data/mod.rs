@@ -11,10 +11,25 @@ use crate::*;
 
 use core::marker::PhantomData;
 
+pub mod bytes;
+pub mod deque;
+pub mod layout;
 pub mod map;
 pub mod map_enumerable;
+pub mod map_hash;
+pub mod nested;
+pub mod set;
 pub mod vec;
 
+/// Items used by the code generated by the derives of cap9-derive, so that it
+/// does not depend on the imports of the crate using them.
+#[doc(hidden)]
+pub mod derive {
+    pub use cap9_core::StorageValue;
+    pub use pwasm_abi::types::U256;
+    pub use pwasm_std::Vec;
+}
+
 
 #[derive(Debug)]
 pub enum DataStructureError {
@@ -30,13 +45,41 @@ pub enum DataStructureError {
     /// The data structure was given a capability that is not of the correct
     /// type, or does not exist.
     BadCap,
+    /// There is no room left in the data structure for another value. This
+    /// only occurs for data structures which cannot hold every key in their
+    /// key space, such as a hash map.
+    Full,
+    /// There is no value at the given index of a data structure, such as a
+    /// [`vec::StorageVec`].
+    OutOfBounds,
+    /// A [`layout::StorageRegions`] has no data structure of the requested
+    /// kind with the given name.
+    UnknownName,
+    /// The same name is used for more than one data structure in a
+    /// [`layout::StorageLayout`].
+    DuplicateName,
     /// Miscellaneous other errors, such as divide-by-zero.
     Other,
 }
 
+/// Return the location and size of the StoreWrite cap at the given index of
+/// the current procedure.
+pub(crate) fn store_write_region(cap_index: u8) -> Result<(U256, U256), DataStructureError> {
+    let this_proc_key = proc_table::get_current_proc_id();
+    if let Some(proc_table::cap::Capability::StoreWrite(proc_table::cap::StoreWriteCap {location, size})) =
+            proc_table::get_proc_cap(this_proc_key, proc_table::cap::CAP_STORE_WRITE, cap_index) {
+        Ok((U256::from(location), U256::from(size)))
+    } else {
+        Err(DataStructureError::BadCap)
+    }
+}
+
 // A type which implements Keyable must follow these rules:
 //    1. key width must be 32 or less.
 //    2. key_slice() must return a vec with a length of exactly key width.
+//
+// Keys with a width of 32 can only be used with a StorageHashMap, as the other
+// maps require the key to fit in the storage key alongside the location.
 pub trait Keyable: From<StorageValue> + Into<StorageValue> + Clone {
     /// The width of the key in bytes.
     fn key_width() -> u8;
@@ -65,6 +108,28 @@ impl Keyable for Address {
     }
 }
 
+impl Keyable for H256 {
+    fn key_width() -> u8 {
+        32
+    }
+
+    fn key_slice(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl Keyable for U256 {
+    fn key_width() -> u8 {
+        32
+    }
+
+    fn key_slice(&self) -> Vec<u8> {
+        let mut buf: [u8; 32] = [0; 32];
+        self.to_big_endian(&mut buf);
+        buf.to_vec()
+    }
+}
+
 
 /// A value which can be stored in Ethereum storage as a sequence of 32-byte
 /// values.
@@ -112,7 +177,7 @@ impl Storable for u8 {
     }
 
     fn read(location: U256) -> Option<Self> {
-        let u = pwasm_ethereum::read(&location.into());
+        let u = read(&location.into());
         let u: U256 = u.into();
         Some(u.as_u32() as u8)
     }
@@ -145,7 +210,7 @@ impl Storable for SysCallProcedureKey {
     }
 
     fn read(location: U256) -> Option<Self> {
-        let h: H256 = pwasm_ethereum::read(&location.into()).into();
+        let h: H256 = read(&location.into()).into();
         Some(h.into())
     }
 
@@ -182,7 +247,7 @@ impl Storable for U256 {
     }
 
     fn read(location: U256) -> Option<Self> {
-        let h: H256 = pwasm_ethereum::read(&location.into()).into();
+        let h: H256 = read(&location.into()).into();
         Some(h.into())
     }
 
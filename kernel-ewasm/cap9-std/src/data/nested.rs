@@ -0,0 +1,303 @@
+extern crate pwasm_abi;
+use pwasm_abi::types::*;
+
+use crate::data::*;
+use crate::data::bytes::*;
+use crate::data::deque::*;
+use crate::data::map::*;
+use crate::data::map_enumerable::*;
+use crate::data::map_hash::*;
+use crate::data::set::*;
+use crate::data::vec::*;
+
+use core::marker::PhantomData;
+
+/// A data structure which can be derived from a region of a cap, and so can
+/// be nested in a [`StorageNestedMap`].
+pub trait Nestable: Sized {
+    /// The number of key bits of the smallest region the data structure can
+    /// be derived from, where the region covers all of the keys with those
+    /// bits and its location has that many trailing zero bits.
+    fn min_bits() -> u32;
+    /// Derive the data structure from a region of the cap at the given index,
+    /// which covers size+1 keys from location.
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError>;
+}
+
+/// Return the smallest number of bits which can index `n_keys` keys.
+fn bits_for(n_keys: U256) -> u32 {
+    if n_keys <= U256::from(1) {
+        0
+    } else {
+        256 - (n_keys - U256::from(1)).leading_zeros()
+    }
+}
+
+impl<K: Keyable, V: Storable> Nestable for StorageMap<K,V> {
+    fn min_bits() -> u32 {
+        Self::align_bits()
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageMap::from_region(cap_index, location, size)
+    }
+}
+
+impl<K: Keyable, V: Storable> Nestable for StorageEnumerableMap<K,V> {
+    fn min_bits() -> u32 {
+        Self::map_bits()
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageEnumerableMap::from_region(cap_index, location, size)
+    }
+}
+
+impl<K: Keyable, V: Storable> Nestable for StorageHashMap<K,V> {
+    /// The length and a single bucket.
+    fn min_bits() -> u32 {
+        bits_for(Self::bucket_size().saturating_add(1.into()))
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageHashMap::from_region(cap_index, location, size)
+    }
+}
+
+impl<K: Keyable> Nestable for StorageSet<K> {
+    fn min_bits() -> u32 {
+        Self::set_bits()
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageSet::from_region(cap_index, location, size)
+    }
+}
+
+impl<V: Storable> Nestable for StorageVec<V> {
+    /// The length and a single value.
+    fn min_bits() -> u32 {
+        bits_for(V::n_keys().saturating_add(1.into()))
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageVec::from_region(cap_index, location, size)
+    }
+}
+
+impl<V: Storable> Nestable for StorageDeque<V> {
+    /// The head and tail indices, and the two slots needed to hold a single
+    /// value.
+    fn min_bits() -> u32 {
+        bits_for(V::n_keys().saturating_mul(2.into()).saturating_add(2.into()))
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageDeque::from_region(cap_index, location, size)
+    }
+}
+
+impl Nestable for StorageBytes {
+    /// The length and a single chunk.
+    fn min_bits() -> u32 {
+        1
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageBytes::from_region(cap_index, location, size)
+    }
+}
+
+/// A map from keys to data structures in storage, such as a map of maps or a
+/// map of vectors.
+///
+/// Each key of the outer map has its own region of storage, which holds the
+/// inner data structure (`C`) for that key. As with a [`StorageMap`], there is
+/// no hashing: the region of a key is found by placing the key bits above the
+/// `inner_bits` bits which index the keys of the region.
+///
+/// ```compile_fail
+///     *-------------*--------------------*------------*
+///     | location    | key                | inner      |
+///     *-------------*--------------------*------------*
+///     | 256-160-4   | 160                | 4          |
+///     *-------------*--------------------*------------*
+/// ```
+///
+/// The last `key_width_in_bits+inner_bits` bits of the location must be
+/// zeroes, and the capability must cover all of the keys after the location
+/// up to that boundary. The inner bits default to [`Nestable::min_bits`] of
+/// the inner data structure, which is as small as it can be. Data structures
+/// whose size is not fixed by their type, such as a [`StorageVec`] or a
+/// [`StorageHashMap`], can be given more room with
+/// [`StorageNestedMap::with_inner_bits`].
+///
+/// The key widths of both levels have to fit in a storage key, so a map of
+/// maps with [`Address`] keys at both levels is not possible. ERC20-style
+/// allowances can instead use a [`StorageHashMap`] for the inner map:
+///
+/// ```ignore
+/// // Room for 5 spenders for each owner.
+/// let allowances: StorageNestedMap<Address, StorageHashMap<Address, U256>> =
+///     StorageNestedMap::with_inner_bits(0, 4)?;
+/// let mut spenders = allowances.get(owner)?;
+/// spenders.insert(spender, amount)?;
+/// ```
+pub struct StorageNestedMap<K, C> {
+    cap_index: u8,
+    /// The start location of the map.
+    location: H256,
+    /// The number of bits of storage key used by each inner data structure.
+    inner_bits: u32,
+    /// The key type of the map.
+    key_type: PhantomData<K>,
+    /// The type of the inner data structures.
+    inner_type: PhantomData<C>,
+}
+
+impl<K: Keyable, C: Nestable> StorageNestedMap<K, C> {
+
+    /// Derive a [`StorageNestedMap`] from the cap at the given index.
+    pub fn from(cap_index: u8) -> Result<Self, DataStructureError> {
+        Self::with_inner_bits(cap_index, C::min_bits())
+    }
+
+    /// Derive a [`StorageNestedMap`] from the cap at the given index, where
+    /// each inner data structure uses `inner_bits` bits of storage key.
+    pub fn with_inner_bits(cap_index: u8, inner_bits: u32) -> Result<Self, DataStructureError> {
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region_with_inner_bits(cap_index, location, size, inner_bits)
+    }
+
+    /// Derive a [`StorageNestedMap`] from a region of the cap at the given
+    /// index, which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        Self::from_region_with_inner_bits(cap_index, location, size, C::min_bits())
+    }
+
+    /// Derive a [`StorageNestedMap`] from a region of the cap at the given
+    /// index, where each inner data structure uses `inner_bits` bits of
+    /// storage key.
+    pub fn from_region_with_inner_bits(cap_index: u8, location: U256, size: U256, inner_bits: u32) -> Result<Self, DataStructureError> {
+        if inner_bits < C::min_bits() {
+            return Err(DataStructureError::TooSmall);
+        }
+        let map_bits = (K::key_width() as u32 * 8).saturating_add(inner_bits);
+        if map_bits >= 256 {
+            return Err(DataStructureError::TooSmall);
+        }
+        let map_size = U256::from(1) << map_bits as usize;
+        // The region covers size+1 keys.
+        if size < map_size - U256::from(1) {
+            Err(DataStructureError::TooSmall)
+        } else if location.trailing_zeros() < map_bits {
+            Err(DataStructureError::MisAligned)
+        } else {
+            Ok(StorageNestedMap {
+                cap_index,
+                location: location.into(),
+                inner_bits,
+                key_type: PhantomData,
+                inner_type: PhantomData,
+            })
+        }
+    }
+
+    /// Return the start/base location of the map.
+    pub fn location(&self) -> H256 {
+        self.location
+    }
+
+    /// The number of bits of storage key used by each inner data structure.
+    pub fn inner_bits(&self) -> u32 {
+        self.inner_bits
+    }
+
+    /// The number of bits of storage key used by the map, which is also the
+    /// alignment of its location.
+    pub fn map_bits(&self) -> u32 {
+        K::key_width() as u32 * 8 + self.inner_bits
+    }
+
+    /// Get the inner data structure of a given key. Every key has one, which
+    /// starts out empty.
+    pub fn get(&self, key: K) -> Result<C, DataStructureError> {
+        let key_bits = U256::from_big_endian(key.key_slice().as_slice());
+        let location = U256::from(self.location) | (key_bits << self.inner_bits as usize);
+        let size = (U256::from(1) << self.inner_bits as usize) - U256::from(1);
+        C::from_region(self.cap_index, location, size)
+    }
+}
+
+impl<K: Keyable, C: Nestable> Nestable for StorageNestedMap<K, C> {
+    fn min_bits() -> u32 {
+        (K::key_width() as u32 * 8).saturating_add(C::min_bits())
+    }
+
+    fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        StorageNestedMap::from_region(cap_index, location, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn storage_nested_map() {
+        // The inner maps cover 10 bits each, and the outer keys 8 bits.
+        assert_eq!(StorageNestedMap::<u8, StorageEnumerableMap<u8, u8>>::min_bits(), 18);
+        // Two address keys do not fit in a storage key.
+        assert!(StorageNestedMap::<Address, StorageMap<Address, U256>>::min_bits() >= 256);
+
+        let mut kernel = TestKernel::new();
+        kernel.register(key("groups"), write_cap(0.into(), ((1 << 18) - 1).into()), |input| {
+            let groups: StorageNestedMap<u8, StorageEnumerableMap<u8, u8>> = StorageNestedMap::from(0).map_err(|_| Error)?;
+            let mut members = groups.get(input[0]).map_err(|_| Error)?;
+            members.insert(input[1], input[2]);
+            Ok([members.length().as_u32() as u8].to_vec())
+        });
+        // Room for the length and 3 values for each key.
+        kernel.register(key("history"), write_cap(0.into(), ((1 << 10) - 1).into()), |input| {
+            let history: StorageNestedMap<u8, StorageVec<u8>> = StorageNestedMap::with_inner_bits(0, 2).map_err(|_| Error)?;
+            let mut values = history.get(input[0]).map_err(|_| Error)?;
+            values.push(input[1]).map_err(|_| Error)?;
+            Ok(values.iter().collect())
+        });
+        // Room for 5 spenders for each owner.
+        kernel.register(key("allowances"), write_cap(0.into(), (U256::from(1) << 164) - U256::from(1)), |input| {
+            let allowances: StorageNestedMap<Address, StorageHashMap<Address, U256>> = StorageNestedMap::with_inner_bits(0, 4).map_err(|_| Error)?;
+            let mut spenders = allowances.get(Address::repeat_byte(input[0])).map_err(|_| Error)?;
+            spenders.insert(Address::repeat_byte(input[1]), input[2].into()).map_err(|_| Error)?;
+            Ok([spenders.length().as_u32() as u8, spenders.capacity().as_u32() as u8].to_vec())
+        });
+
+        kernel.set_entry(key("groups"));
+        assert_eq!(kernel.call(&[1, 5, 7]).unwrap(), [1].to_vec());
+        assert_eq!(kernel.call(&[2, 5, 8]).unwrap(), [1].to_vec());
+        assert_eq!(kernel.call(&[1, 6, 9]).unwrap(), [2].to_vec());
+        // The value of key 5 in the map of key 2.
+        assert_eq!(read_key(2 << 10 | 5 << 2), 8.into());
+        assert_eq!(read_key(1 << 10 | 0b11), 2.into());
+
+        kernel.set_entry(key("history"));
+        assert_eq!(kernel.call(&[3, 4]).unwrap(), [4].to_vec());
+        assert_eq!(kernel.call(&[3, 5]).unwrap(), [4, 5].to_vec());
+        assert_eq!(kernel.call(&[1, 6]).unwrap(), [6].to_vec());
+        assert_eq!(read_key(3 << 2), 2.into());
+        assert_eq!(read_key(3 << 2 | 2), 5.into());
+        assert_eq!(kernel.call(&[3, 6]).unwrap(), [4, 5, 6].to_vec());
+        assert!(kernel.call(&[3, 7]).is_err());
+
+        kernel.set_entry(key("allowances"));
+        assert_eq!(kernel.call(&[1, 2, 100]).unwrap(), [1, 5].to_vec());
+        assert_eq!(kernel.call(&[1, 3, 50]).unwrap(), [2, 5].to_vec());
+        assert_eq!(kernel.call(&[2, 3, 10]).unwrap(), [1, 5].to_vec());
+        let owner = U256::from_big_endian(Address::repeat_byte(1).as_bytes()) << 4;
+        let length: U256 = pwasm_ethereum::read(&H256::from(owner)).into();
+        assert_eq!(length, 2.into());
+    }
+}
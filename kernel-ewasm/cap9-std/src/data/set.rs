@@ -0,0 +1,273 @@
+extern crate pwasm_abi;
+use pwasm_abi::types::*;
+use cap9_core::StorageValue;
+
+use crate::*;
+use crate::data::*;
+
+use core::marker::PhantomData;
+
+/// A set of keys in storage, which can be enumerated/iterated.
+///
+/// A [`StorageSet`] is the same as a [`StorageEnumerableMap`] without values.
+/// Checking, inserting and removing a key all take a constant number of
+/// storage operations. No guarantee is made on the ordering of enumeration.
+///
+/// ## Alignment
+///
+/// As with the maps, a [`StorageSet`] is aligned to a boundary determined by
+/// its key type (`K`). The last `key_width_in_bits+2` bits of the location of
+/// the storage capability must be zeroes, and the capability must cover all of
+/// the keys after the location up to that boundary. Storage keys that form
+/// part of this set have the following format, from the most significant bit:
+///
+/// * `location`: Arbitrary bits dictating the location of the set in storage.
+/// * `key`: The bits of the key.
+/// * `p`: Set if it is a "presence" value.
+/// * `e`: Set if it is part of the enumeration vector.
+///
+/// ```compile_fail
+///     *-------------*--------------------*---*---*
+///     | location    | key                | p | e |
+///     *-------------*--------------------*---*---*
+///     | 256-160-2   | 160                | 1 | 1 |
+///     *-------------*--------------------*---*---*
+/// ```
+///
+/// These are used as in a [`StorageEnumerableMap`]. With `p` set, the storage
+/// key holds the 1-based index of the key in the enumeration vector, or zero
+/// if the key is not in the set. With `e` set, the `key` bits instead hold an
+/// index into the enumeration vector, minus one, and the storage key holds the
+/// set key at that index. The length of the set is stored with both `p` and
+/// `e` set and the other bits clear. Storage keys with both `p` and `e` clear
+/// are not used.
+pub struct StorageSet<K> {
+    cap_index: u8,
+    /// The start location of the set.
+    location: H256,
+    /// The key type of the set.
+    key_type: PhantomData<K>,
+    /// Possibly the cached number of keys in the set.
+    length: Option<U256>,
+}
+
+impl<K: Keyable> StorageSet<K> {
+
+    /// Derive a [`StorageSet`] from the cap at the given index.
+    pub fn from(cap_index: u8) -> Result<Self, DataStructureError> {
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size)
+    }
+
+    /// Derive a [`StorageSet`] from a region of the cap at the given index,
+    /// which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self, DataStructureError> {
+        let set_bits = Self::set_bits();
+        if set_bits >= 256 {
+            return Err(DataStructureError::TooSmall);
+        }
+        let set_size = U256::from(1) << set_bits as usize;
+        // The region covers size+1 keys.
+        if size < set_size - U256::from(1) {
+            Err(DataStructureError::TooSmall)
+        } else if location.trailing_zeros() < set_bits {
+            Err(DataStructureError::MisAligned)
+        } else {
+            Ok(StorageSet {
+                cap_index,
+                location: location.into(),
+                key_type: PhantomData,
+                length: None,
+            })
+        }
+    }
+
+    /// Return the start/base location of the set.
+    pub fn location(&self) -> H256 {
+        self.location
+    }
+
+    /// The number of bits of storage key used by the set, which is also the
+    /// alignment of its location.
+    pub fn set_bits() -> u32 {
+        K::key_width() as u32 * 8 + 2
+    }
+
+    /// Return the storage key at the given key bits and flags. The flags are
+    /// the presence and enumeration bits.
+    fn flagged_key(&self, key_bits: U256, flags: u8) -> H256 {
+        H256::from(U256::from(self.location) | (key_bits << 2) | U256::from(flags))
+    }
+
+    fn presence_key(&self, key: &K) -> H256 {
+        let key_bits = U256::from_big_endian(key.key_slice().as_slice());
+        self.flagged_key(key_bits, 0b10)
+    }
+
+    /// Return the storage key of the set key at a given 1-based index of the
+    /// enumeration vector.
+    fn element_key(&self, index: U256) -> H256 {
+        self.flagged_key(index - U256::from(1), 0b01)
+    }
+
+    fn length_key(&self) -> H256 {
+        self.flagged_key(U256::zero(), 0b11)
+    }
+
+    /// Return the number of keys in the set.
+    pub fn length(&self) -> U256 {
+        match self.length {
+            Some(l) => l,
+            None => U256::from(read(&self.length_key())),
+        }
+    }
+
+    fn set_length(&mut self, length: U256) {
+        self.length = Some(length);
+        write(self.cap_index, &self.length_key().to_fixed_bytes(), &length.into()).unwrap();
+    }
+
+    /// Return the 1-based index of the key in the enumeration vector, if it is
+    /// in the set.
+    fn index(&self, key: &K) -> Option<U256> {
+        let index = U256::from(read(&self.presence_key(key)));
+        if index.is_zero() {
+            None
+        } else {
+            Some(index)
+        }
+    }
+
+    /// Return true if the key is in the set.
+    pub fn contains(&self, key: &K) -> bool {
+        self.index(key).is_some()
+    }
+
+    /// Return the key at a given index in the set. The ordering of keys is not
+    /// well defined, and this should only be used for enumeration.
+    pub fn get_key_at_index(&self, index: U256) -> Option<K> {
+        if index >= self.length() {
+            return None;
+        }
+        let storage_value: StorageValue = read(&self.element_key(index + U256::from(1))).into();
+        Some(storage_value.into())
+    }
+
+    /// Add a key to the set. Returns false if the key was already in the set.
+    pub fn insert(&mut self, key: K) -> bool {
+        if self.contains(&key) {
+            return false;
+        }
+        let index = self.length() + U256::from(1);
+        let storable_index: StorageValue = index.into();
+        write(self.cap_index, &self.presence_key(&key).to_fixed_bytes(), &storable_index.into()).unwrap();
+        let k_val: StorageValue = key.into();
+        write(self.cap_index, &self.element_key(index).to_fixed_bytes(), &k_val.into()).unwrap();
+        self.set_length(index);
+        true
+    }
+
+    /// Remove a key from the set. Returns false if the key was not in the
+    /// set.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let index = match self.index(key) {
+            None => return false,
+            Some(index) => index,
+        };
+        let length = self.length();
+        // Move the last key of the enumeration vector into the position of the
+        // key we are removing.
+        let last_element_key = self.element_key(length);
+        if index != length {
+            let last_value: StorageValue = read(&last_element_key).into();
+            write(self.cap_index, &self.element_key(index).to_fixed_bytes(), &last_value.clone().into()).unwrap();
+            let storable_index: StorageValue = index.into();
+            write(self.cap_index, &self.presence_key(&last_value.into()).to_fixed_bytes(), &storable_index.into()).unwrap();
+        }
+        write(self.cap_index, &last_element_key.to_fixed_bytes(), &[0; 32]).unwrap();
+        write(self.cap_index, &self.presence_key(key).to_fixed_bytes(), &[0; 32]).unwrap();
+        self.set_length(length - U256::from(1));
+        true
+    }
+
+    /// Produce an iterator over the keys.
+    pub fn iter(&self) -> StorageSetIter<K> {
+        StorageSetIter {
+            storage_set: self,
+            offset: U256::zero(),
+        }
+    }
+}
+
+/// An iterator over the keys of a [`StorageSet`].
+pub struct StorageSetIter<'a, K> {
+    /// The [`StorageSet`] we are iterating over.
+    storage_set: &'a StorageSet<K>,
+    /// The current offset into the enumeration vector.
+    offset: U256,
+}
+
+impl<'a, K: Keyable> Iterator for StorageSetIter<'a, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.storage_set.get_key_at_index(self.offset)?;
+        self.offset += U256::from(1);
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::proc_table::cap::*;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn storage_set() {
+        assert_eq!(StorageSet::<u8>::set_bits(), 10);
+        let plan = StorageLayout::new().set::<u8>("members").plan(0.into()).unwrap();
+        assert_eq!(U256::from(plan.cap.size), U256::from(1023));
+        let mut kernel = TestKernel::new();
+        let cap_list = NewCapList(
+            [NewCapability {
+                cap: Capability::StoreWrite(plan.cap),
+                parent_index: 0,
+            }]
+            .to_vec(),
+        );
+        kernel.register(key("set"), cap_list, |input| {
+            let mut set: StorageSet<u8> = StorageSet::from(0).map_err(|_| Error)?;
+            let changed = if input[0] == 0 {
+                set.insert(input[1])
+            } else {
+                set.remove(&input[1])
+            };
+            let mut keys: Vec<u8> = set.iter().collect();
+            keys.sort();
+            keys.push(changed as u8);
+            keys.push(set.contains(&5) as u8);
+            Ok(keys)
+        });
+        kernel.set_entry(key("set"));
+
+        assert_eq!(kernel.call(&[0, 5]).unwrap(), [5, 1, 1].to_vec());
+        assert_eq!(kernel.call(&[0, 9]).unwrap(), [5, 9, 1, 1].to_vec());
+        // Inserting a key which is already in the set does nothing.
+        assert_eq!(kernel.call(&[0, 5]).unwrap(), [5, 9, 0, 1].to_vec());
+        // The presence value, the first element of the enumeration vector,
+        // and the length.
+        assert_eq!(read_u8(5 << 2 | 0b10), 1);
+        assert_eq!(read_u8(0b01), 5);
+        assert_eq!(read_u8(0b11), 2);
+        assert_eq!(kernel.call(&[1, 6]).unwrap(), [5, 9, 0, 1].to_vec());
+        assert_eq!(kernel.call(&[1, 5]).unwrap(), [9, 1, 0].to_vec());
+        assert_eq!(read_u8(5 << 2 | 0b10), 0);
+        assert_eq!(read_u8(9 << 2 | 0b10), 1);
+        assert_eq!(read_u8(0b01), 9);
+        assert_eq!(read_key(1 << 2 | 0b01), 0.into());
+        assert_eq!(read_u8(0b11), 1);
+    }
+}
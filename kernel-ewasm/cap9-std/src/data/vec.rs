@@ -41,27 +41,29 @@ impl<V: Storable> StorageVec<V> {
     // needs to access capability data. The capacity is also defined by the
     // capability. The capability does not need to be aligned to the data size.
 
-    /// Derive a [`StorageMap`] from the cap at the given index.
+    /// Derive a [`StorageVec`] from the cap at the given index.
     pub fn from(cap_index: u8) -> Result<Self,DataStructureError> {
-        let this_proc_key = proc_table::get_current_proc_id();
-        if let Some(proc_table::cap::Capability::StoreWrite(proc_table::cap::StoreWriteCap {location, size})) =
-                proc_table::get_proc_cap(this_proc_key, proc_table::cap::CAP_STORE_WRITE, cap_index) {
-                    let initial_length = pwasm_ethereum::read(&H256::from(location));
-                    let capacity = match (U256::from(size).saturating_add(1.into())).checked_div(V::n_keys()) {
-                            // Return an error on divide-by-zero
-                            None => return Err(DataStructureError::Other),
-                            Some(x) => x,
-                    };
-                    Ok(StorageVec {
-                        cap_index,
-                        location: location.into(),
-                        data_type: PhantomData,
-                        capacity,
-                        length: U256::from(initial_length),
-                    })
-        } else {
-            Err(DataStructureError::BadCap)
-        }
+        let (location, size) = store_write_region(cap_index)?;
+        Self::from_region(cap_index, location, size)
+    }
+
+    /// Derive a [`StorageVec`] from a region of the cap at the given index,
+    /// which covers size+1 keys from location.
+    pub fn from_region(cap_index: u8, location: U256, size: U256) -> Result<Self,DataStructureError> {
+        let initial_length = read(&H256::from(location));
+        // The region covers size+1 keys, the first of which is the length.
+        let capacity = match size.checked_div(V::n_keys()) {
+            // Return an error on divide-by-zero
+            None => return Err(DataStructureError::Other),
+            Some(x) => x,
+        };
+        Ok(StorageVec {
+            cap_index,
+            location: location.into(),
+            data_type: PhantomData,
+            capacity,
+            length: U256::from(initial_length),
+        })
     }
 
     /// Capacity is a function of both the capability and the size of the data.
@@ -78,61 +80,166 @@ impl<V: Storable> StorageVec<V> {
         self.location
     }
 
+    /// The key of the value at the given index. The first key of the vector
+    /// is used for its length, so the values start at the key after it.
+    fn value_key(&self, index: U256) -> U256 {
+        U256::from(self.location) + U256::from(1) + index * V::n_keys()
+    }
+
+    fn set_length(&mut self, length: U256) {
+        self.length = length;
+        write(self.cap_index, &U256::from(self.location).into(), &self.length.into()).unwrap();
+    }
+
+    /// Move the value at index `from` to index `to`, overwriting the value
+    /// there.
+    fn move_value(&self, from: U256, to: U256) {
+        if let Some(value) = V::read(self.value_key(from)) {
+            value.store(self.cap_index, self.value_key(to));
+        }
+    }
+
     /// Get the value at the given index.
     pub fn get(&self, index: U256) -> Option<V> {
         if index >= self.length {
             return None;
         }
-        let start_key_opt: Option<U256> = match V::n_keys().checked_mul(index) {
-            None => None,
-            Some(x) => U256::from(self.location).checked_add(x),
-        };
-        let start_key: U256 = start_key_opt.unwrap();
-        // We add 1 to the offset, as the first storage key us for the length of
-        // the vector.
-        let offset = start_key.checked_add(1.into()).unwrap();
-        V::read(offset)
-    }
-
-    /// Push a value to the end of the vector.
-    pub fn push(&mut self, value: V) {
-        // The location of the first value in the vector (i.e. after the length
-        // value).
-        let start_key: U256 = U256::from(self.location).checked_add(1.into()).unwrap();
-        // The offset into the vector for a new value.
-        let offset: U256 = self.length.checked_mul(V::n_keys()).unwrap();
-        let new_val_location: U256 = start_key.checked_add(offset).unwrap();
-        value.store(self.cap_index, new_val_location);
-        // Update length value.
-        self.length = self.length.checked_add(1.into()).unwrap();
-        // Store length value.
-        write(self.cap_index, &U256::from(self.location).into(), &self.length.into()).unwrap();
+        V::read(self.value_key(index))
+    }
+
+    /// Get the first value of the vector, or `None` if it is empty.
+    pub fn first(&self) -> Option<V> {
+        self.get(U256::zero())
+    }
+
+    /// Get the last value of the vector, or `None` if it is empty.
+    pub fn last(&self) -> Option<V> {
+        if self.length.is_zero() {
+            return None;
+        }
+        self.get(self.length - U256::from(1))
+    }
+
+    /// Replace the value at the given index. Returns
+    /// `DataStructureError::OutOfBounds` if there is no value at that index.
+    pub fn set(&mut self, index: U256, value: V) -> Result<(), DataStructureError> {
+        if index >= self.length {
+            return Err(DataStructureError::OutOfBounds);
+        }
+        value.store(self.cap_index, self.value_key(index));
+        Ok(())
+    }
+
+    /// Push a value to the end of the vector. Returns
+    /// `DataStructureError::Full` if the vector is already at capacity.
+    pub fn push(&mut self, value: V) -> Result<(), DataStructureError> {
+        if self.length >= self.capacity {
+            return Err(DataStructureError::Full);
+        }
+        value.store(self.cap_index, self.value_key(self.length));
+        self.set_length(self.length + U256::from(1));
+        Ok(())
+    }
+
+    /// Add the values of an iterator to the end of the vector. Returns
+    /// `DataStructureError::Full` if there is not enough room for all of
+    /// them, in which case nothing is changed.
+    pub fn extend<I: IntoIterator<Item = V>>(&mut self, values: I) -> Result<(), DataStructureError> {
+        let values: Vec<V> = values.into_iter().collect();
+        match self.length.checked_add(U256::from(values.len())) {
+            Some(length) if length <= self.capacity => (),
+            _ => return Err(DataStructureError::Full),
+        }
+        for value in values {
+            self.push(value)?;
+        }
+        Ok(())
+    }
+
+    /// Insert a value at the given index, shifting the values after it up by
+    /// one. Returns `DataStructureError::OutOfBounds` if the index is greater
+    /// than the length, and `DataStructureError::Full` if the vector is
+    /// already at capacity.
+    pub fn insert(&mut self, index: U256, value: V) -> Result<(), DataStructureError> {
+        if index > self.length {
+            return Err(DataStructureError::OutOfBounds);
+        }
+        if self.length >= self.capacity {
+            return Err(DataStructureError::Full);
+        }
+        let mut i = self.length;
+        while i > index {
+            self.move_value(i - U256::from(1), i);
+            i -= U256::from(1);
+        }
+        value.store(self.cap_index, self.value_key(index));
+        self.set_length(self.length + U256::from(1));
+        Ok(())
     }
 
     /// Pop a value off the end of the vector.
     pub fn pop(&mut self) -> Option<V> {
-        if self.length() < U256::from(1) {
+        if self.length.is_zero() {
             return None;
         }
-        // The location of the first value in the vector (i.e. after the length
-        // value).
-        let base_key: U256 = U256::from(self.location);
-        // The offset into the vector for the last value.
-        let offset: U256 = self.length.checked_mul(V::n_keys()).unwrap();
-        let val_location: U256 = base_key.checked_add(offset).unwrap();
-        let value = V::read(val_location);
+        let last = self.length - U256::from(1);
+        let value = self.get(last);
         // Clear the value from storage. Clearing away values is usually good
         // but not done on most systems as it is cheaper to overwrite it later.
         // On Ethereum we get a refund for clearing unused storage, so it is
         // actually cheaper to do so than not.
-        V::clear(self.cap_index, val_location);
-        // Update length value.
-        self.length = self.length.checked_sub(1.into()).unwrap();
-        // Store length value.
-        write(self.cap_index, &U256::from(self.location).into(), &self.length.into()).unwrap();
+        V::clear(self.cap_index, self.value_key(last));
+        self.set_length(last);
         value
     }
 
+    /// Remove and return the value at the given index, shifting the values
+    /// after it down by one. Returns `None` if there is no value at that
+    /// index.
+    pub fn remove(&mut self, index: U256) -> Option<V> {
+        let value = self.get(index)?;
+        let mut i = index + U256::from(1);
+        while i < self.length {
+            self.move_value(i, i - U256::from(1));
+            i += U256::from(1);
+        }
+        self.pop();
+        Some(value)
+    }
+
+    /// Remove and return the value at the given index, replacing it with the
+    /// last value. This does not preserve the order of the values, but does
+    /// not need to shift them. Returns `None` if there is no value at that
+    /// index.
+    pub fn swap_remove(&mut self, index: U256) -> Option<V> {
+        let value = self.get(index)?;
+        let last = self.length - U256::from(1);
+        if index != last {
+            self.move_value(last, index);
+        }
+        self.pop();
+        Some(value)
+    }
+
+    /// Shorten the vector to `length` values, clearing the rest. This has no
+    /// effect if the vector is already no longer than `length`.
+    pub fn truncate(&mut self, length: U256) {
+        if length >= self.length {
+            return;
+        }
+        let mut i = length;
+        while i < self.length {
+            V::clear(self.cap_index, self.value_key(i));
+            i += U256::from(1);
+        }
+        self.set_length(length);
+    }
+
+    /// Remove all of the values.
+    pub fn clear(&mut self) {
+        self.truncate(U256::zero());
+    }
+
     /// Produce an iterator over values in the vector.
     pub fn iter(&self) -> StorageVecIter<V> {
         StorageVecIter::new(self)
@@ -140,6 +247,15 @@ impl<V: Storable> StorageVec<V> {
 
 }
 
+impl<V: Storable + PartialEq> StorageVec<V> {
+
+    /// Return true if the vector contains the given value.
+    pub fn contains(&self, value: &V) -> bool {
+        self.iter().any(|x| &x == value)
+    }
+
+}
+
 /// An iterator over the values of a [`StorageVec`].
 pub struct StorageVecIter<'a, V> {
     /// The [`StorageVec`] we are iterating over.
@@ -170,3 +286,100 @@ impl<'a, V: Storable> Iterator for StorageVecIter<'a, V> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_support::*;
+    use crate::emulator::TestKernel;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn storage_vec() {
+        let mut kernel = TestKernel::new();
+        kernel.register(key("pusher"), write_cap(0.into(), 10.into()), |input| {
+            let mut vec: StorageVec<U256> = StorageVec::from(0).map_err(|_| Error)?;
+            vec.push(input[0].into()).map_err(|_| Error)?;
+            Ok([vec.length().as_u32() as u8].to_vec())
+        });
+        kernel.set_entry(key("pusher"));
+
+        assert_eq!(kernel.call(&[4]).unwrap(), [1].to_vec());
+        assert_eq!(kernel.call(&[9]).unwrap(), [2].to_vec());
+        // The length is stored at the start of the capability, followed by
+        // the values.
+        assert_eq!(read_u8(0), 2);
+        assert_eq!(read_u8(1), 4);
+        assert_eq!(read_u8(2), 9);
+    }
+
+    #[test]
+    fn storage_vec_operations() {
+        let mut kernel = TestKernel::new();
+        kernel.register(key("vec"), write_cap(0.into(), 5.into()), |input| {
+            let mut vec: StorageVec<u8> = StorageVec::from(0).map_err(|_| Error)?;
+            vec.extend(input.iter().cloned()).map_err(|_| Error)?;
+            // [1, 2, 3] -> [1, 7, 2, 3]
+            vec.insert(1.into(), 7).map_err(|_| Error)?;
+            // [1, 7, 2, 3] -> [7, 2, 3]
+            let removed = vec.remove(0.into()).ok_or(Error)?;
+            // [7, 2, 3] -> [3, 2]
+            let swapped = vec.swap_remove(0.into()).ok_or(Error)?;
+            // [3, 2] -> [3, 9]
+            vec.set(1.into(), 9).map_err(|_| Error)?;
+            if vec.set(2.into(), 9).is_ok() || vec.insert(3.into(), 9).is_ok() {
+                return Err(Error);
+            }
+            Ok([
+                removed,
+                swapped,
+                vec.first().ok_or(Error)?,
+                vec.last().ok_or(Error)?,
+                vec.contains(&9) as u8,
+                vec.contains(&7) as u8,
+            ].to_vec())
+        });
+        kernel.register(key("full"), write_cap(0.into(), 5.into()), |input| {
+            let mut vec: StorageVec<u8> = StorageVec::from(0).map_err(|_| Error)?;
+            // There is room for 5 values, so this fails without changing
+            // anything.
+            if vec.extend([1, 2, 3, 4].iter().cloned()).is_ok() {
+                return Err(Error);
+            }
+            for value in input {
+                vec.push(*value).map_err(|_| Error)?;
+            }
+            Ok([vec.length().as_u32() as u8].to_vec())
+        });
+        kernel.register(key("truncate"), write_cap(0.into(), 5.into()), |input| {
+            let mut vec: StorageVec<u8> = StorageVec::from(0).map_err(|_| Error)?;
+            if input[0] == 0 {
+                vec.clear();
+            } else {
+                vec.truncate(input[0].into());
+            }
+            Ok([vec.length().as_u32() as u8].to_vec())
+        });
+
+        kernel.set_entry(key("vec"));
+        assert_eq!(kernel.call(&[1, 2, 3]).unwrap(), [1, 7, 3, 9, 1, 0].to_vec());
+        assert_eq!(read_u8(0), 2);
+        assert_eq!(read_u8(1), 3);
+        assert_eq!(read_u8(2), 9);
+        // The values after the end are cleared.
+        assert_eq!(read_u8(3), 0);
+        assert_eq!(read_u8(4), 0);
+
+        kernel.set_entry(key("full"));
+        assert_eq!(kernel.call(&[4, 5, 6]).unwrap(), [5].to_vec());
+        assert!(kernel.call(&[7]).is_err());
+        assert_eq!(read_u8(0), 5);
+
+        kernel.set_entry(key("truncate"));
+        assert_eq!(kernel.call(&[3]).unwrap(), [3].to_vec());
+        assert_eq!(read_u8(3), 4);
+        assert_eq!(read_u8(4), 0);
+        assert_eq!(kernel.call(&[0]).unwrap(), [0].to_vec());
+        assert_eq!(read_u8(1), 0);
+    }
+}
@@ -0,0 +1,276 @@
+//! A kernel for native unit tests, running procedures as Rust closures.
+//!
+//! [TestKernel] keeps the procedure table in the mock storage provided by
+//! pwasm_test and handles syscalls just as the kernel does: the syscall is
+//! deserialized, checked against the capabilities of the current procedure,
+//! and executed. This means that procedures using [crate::StorageVec],
+//! [crate::StorageEnumerableMap], or any other syscall can be tested without a
+//! node.
+//!
+//! ```ignore
+//! let mut kernel = TestKernel::new();
+//! kernel.register(key, cap_list, |_input| {
+//!     cap9_std::write(0, &[1; 32], &[2; 32])?;
+//!     Ok(Vec::new())
+//! });
+//! kernel.set_entry(key);
+//! kernel.call(&[]).unwrap();
+//! ```
+//!
+//! A syscall which fails its capability check returns an error to the
+//! procedure. As in the kernel, a call syscall to a procedure which then fails
+//! panics, since the storage here is not transactional and could not be
+//! reverted.
+extern crate pwasm_abi;
+extern crate pwasm_test;
+use pwasm_abi::types::*;
+
+use cap9_core::{Cursor, Deserialize};
+
+use crate::proc_table;
+use crate::proc_table::cap::NewCapList;
+use crate::proc_table::ProcedureKey;
+use crate::syscalls::{Error, SysCall};
+
+/// An in-process kernel. Creating one resets the storage and the deployed
+/// procedures of the current thread.
+pub struct TestKernel {
+    _private: (),
+}
+
+impl TestKernel {
+    /// Create a kernel with an empty procedure table.
+    pub fn new() -> Self {
+        pwasm_test::ext_reset(|e| e);
+        cap9_test::emulator::reset();
+        cap9_test::emulator::set_syscall_handler(syscall);
+        TestKernel { _private: () }
+    }
+
+    /// Deploy `procedure` and register it in the procedure table under `key`
+    /// with the capabilities in `cap_list`. Returns the address of the
+    /// procedure.
+    pub fn register<F>(&mut self, key: ProcedureKey, cap_list: NewCapList, procedure: F) -> Address
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>, Error> + 'static,
+    {
+        let address: Address =
+            cap9_test::emulator::deploy(move |input| procedure(input).map_err(|_| ())).into();
+        proc_table::insert_proc(key, address, cap_list).expect("could not register procedure");
+        address
+    }
+
+    /// Set the entry procedure.
+    pub fn set_entry(&mut self, key: ProcedureKey) {
+        proc_table::set_entry_proc_id(key).expect("invalid procedure key");
+    }
+
+    /// Call the kernel from outside, which executes the entry procedure with
+    /// `input`. Returns the result of the entry procedure.
+    pub fn call(&mut self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let proc_id = proc_table::get_entry_proc_id();
+        let entry_address = proc_table::get_proc_addr(proc_id).ok_or(Error)?;
+        proc_table::set_current_proc_id(proc_id).unwrap();
+        let result = crate::actual_call_code(
+            pwasm_ethereum::gas_left() - 10000,
+            &entry_address,
+            U256::zero(),
+            input,
+            &mut Vec::new(),
+        );
+        proc_table::set_current_proc_id([0; 24]).unwrap();
+        result.map(|()| crate::result())
+    }
+}
+
+/// Handle a syscall from the current procedure, as the kernel does.
+fn syscall(input: &[u8]) -> Result<Vec<u8>, ()> {
+    let mut input = Cursor::new(input);
+    let syscall = SysCall::deserialize(&mut input).map_err(|_| ())?;
+    if !syscall.check_cap() {
+        return Err(());
+    }
+    syscall.execute();
+    Ok(crate::result())
+}
+
+/// Fixtures for the tests of the emulator and of the data structures.
+#[cfg(test)]
+pub(crate) mod test_support {
+    use pwasm_abi::types::*;
+    use crate::proc_table::cap::*;
+    use crate::proc_table::ProcedureKey;
+
+    pub(crate) fn write_cap(location: U256, size: U256) -> NewCapList {
+        NewCapList(
+            [NewCapability {
+                cap: Capability::StoreWrite(StoreWriteCap {
+                    location: location.into(),
+                    size: size.into(),
+                }),
+                parent_index: 0,
+            }]
+            .to_vec(),
+        )
+    }
+
+    pub(crate) fn key(name: &str) -> ProcedureKey {
+        let mut key = [0; 24];
+        key[..name.len()].copy_from_slice(name.as_bytes());
+        key
+    }
+
+    pub(crate) fn read_u8(location: u8) -> u8 {
+        let mut key = [0; 32];
+        key[31] = location;
+        pwasm_ethereum::read(&key.into())[31]
+    }
+
+    pub(crate) fn read_key(location: u32) -> U256 {
+        pwasm_ethereum::read(&H256::from(U256::from(location))).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_support::*;
+    use crate::proc_table::cap::*;
+    use crate::*;
+    use crate::syscalls::Error;
+
+    #[test]
+    fn write_with_cap() {
+        let mut kernel = TestKernel::new();
+        kernel.register(key("writer"), write_cap(0.into(), 10.into()), |input| {
+            let mut location = [0; 32];
+            location[31] = input[0];
+            let mut value = [0; 32];
+            value[31] = input[1];
+            write(0, &location, &value)?;
+            Ok([1].to_vec())
+        });
+        kernel.set_entry(key("writer"));
+
+        assert_eq!(kernel.call(&[3, 7]).unwrap(), [1].to_vec());
+        assert_eq!(read_u8(3), 7);
+        // Outside of the capability
+        assert!(kernel.call(&[11, 7]).is_err());
+        assert_eq!(read_u8(11), 0);
+    }
+
+    #[test]
+    fn call_procedure() {
+        let mut kernel = TestKernel::new();
+        let call_cap = NewCapList(
+            [NewCapability {
+                cap: Capability::ProcedureCall(ProcedureCallCap {
+                    prefix: 0,
+                    key: [0; 24],
+                }),
+                parent_index: 0,
+            }]
+            .to_vec(),
+        );
+        kernel.register(key("entry"), call_cap, |input| {
+            call(0, key("writer").into(), input.to_vec())?;
+            // The result of the called procedure is available to the caller.
+            Ok(result())
+        });
+        kernel.register(key("writer"), write_cap(0.into(), 10.into()), |input| {
+            let mut value = [0; 32];
+            value[31] = input[0];
+            write(0, &[0; 32], &value)?;
+            Ok([2].to_vec())
+        });
+        kernel.set_entry(key("entry"));
+
+        assert_eq!(kernel.call(&[5]).unwrap(), [2].to_vec());
+        assert_eq!(read_u8(0), 5);
+    }
+
+    #[test]
+    fn buffered_writes() {
+        let mut kernel = TestKernel::new();
+        kernel.register(key("buffered"), write_cap(0.into(), 10.into()), |input| {
+            let mut vec: StorageVec<u8> = StorageVec::from(0).map_err(|_| Error)?;
+            buffer::begin();
+            for value in input.iter().skip(1) {
+                vec.push(*value).map_err(|_| Error)?;
+            }
+            // The writes are read back from the buffer, but not yet stored.
+            let before = [
+                read(&H256::from(U256::from(1)))[31],
+                pwasm_ethereum::read(&H256::from(U256::from(1)))[31],
+                vec.get(0.into()).unwrap_or(0),
+            ];
+            if input[0] == 0 {
+                buffer::flush()?;
+            } else {
+                // Write outside of the cap, so the whole batch fails.
+                write(0, &H256::from(U256::from(11)).to_fixed_bytes(), &[1; 32])?;
+                buffer::flush()?;
+            }
+            Ok(before.to_vec())
+        });
+        kernel.set_entry(key("buffered"));
+
+        assert_eq!(kernel.call(&[0, 4, 5, 6]).unwrap(), [4, 0, 4].to_vec());
+        assert_eq!(read_u8(0), 3);
+        assert_eq!(read_u8(1), 4);
+        assert_eq!(read_u8(3), 6);
+        assert!(kernel.call(&[1, 7]).is_err());
+        assert_eq!(read_u8(0), 3);
+        assert_eq!(read_u8(4), 0);
+        assert!(!buffer::is_buffering());
+
+        // An error discards the buffered writes.
+        let result: Result<(), Error> = buffer::buffered(|| {
+            write(0, &[0; 32], &[1; 32])?;
+            Err(Error)
+        });
+        assert!(result.is_err());
+        assert!(!buffer::is_buffering());
+        assert_eq!(read(&H256::zero())[31], 3);
+    }
+
+    #[test]
+    fn buffered_writes_to_two_caps() {
+        let mut kernel = TestKernel::new();
+        let mut caps = write_cap(0.into(), 10.into());
+        caps.0.extend(write_cap(20.into(), 10.into()).0);
+        kernel.register(key("buffered"), caps, |input| {
+            buffer::buffered(|| {
+                write(0, &H256::from(U256::from(1)).to_fixed_bytes(), &[1; 32])?;
+                // The second cap only covers up to key 30.
+                write(1, &H256::from(U256::from(input[0])).to_fixed_bytes(), &[2; 32])?;
+                Ok(Vec::new())
+            })
+        });
+        kernel.set_entry(key("buffered"));
+
+        // The write to the first cap is not made if the second one is
+        // outside of its cap.
+        assert!(kernel.call(&[31]).is_err());
+        assert_eq!(read_u8(1), 0);
+        assert_eq!(read_u8(31), 0);
+        assert!(!buffer::is_buffering());
+
+        assert!(kernel.call(&[30]).is_ok());
+        assert_eq!(read_u8(1), 1);
+        assert_eq!(read_u8(30), 2);
+    }
+
+    #[test]
+    fn no_cap() {
+        let mut kernel = TestKernel::new();
+        kernel.register(key("entry"), NewCapList([].to_vec()), |_input| {
+            write(0, &[0; 32], &[1; 32])?;
+            Ok(Vec::new())
+        });
+        kernel.set_entry(key("entry"));
+
+        assert!(kernel.call(&[]).is_err());
+        assert_eq!(read_u8(0), 0);
+    }
+}
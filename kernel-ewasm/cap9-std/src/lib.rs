@@ -14,15 +14,31 @@ pub mod proc_table;
 pub mod syscalls;
 pub use syscalls::*;
 
+/// An opt-in buffer for storage writes.
+pub mod buffer;
+
 /// Capability compatible data structures for use with Ethereum storage.
 pub mod data;
+pub use data::bytes::{BytesRef, StorageBytes, StorageBytesHeap, StorageString};
+pub use data::deque::StorageDeque;
+pub use data::layout::{StorageLayout, StorageRegions};
 pub use data::map::StorageMap;
 use data::map::*;
 pub use data::map_enumerable::StorageEnumerableMap;
 use data::map_enumerable::*;
+pub use data::map_hash::StorageHashMap;
+use data::map_hash::*;
+pub use data::nested::StorageNestedMap;
+pub use data::set::StorageSet;
 pub use data::vec::StorageVec;
 use data::vec::*;
 
+/// An in-process kernel for testing procedures natively.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod emulator;
+#[cfg(not(target_arch = "wasm32"))]
+pub use emulator::TestKernel;
+
 // Re-export pwasm::Vec as the Vec type for cap9_std
 pub use pwasm_std::Vec;
 
@@ -197,8 +213,12 @@ pub fn cap9_syscall(input: &[u8], result: &mut [u8]) -> Result<(), Error> {
     }
 }
 
-/// Perform a write system call.
+/// Perform a write system call. While writes are being buffered by
+/// [`buffer`], this buffers the write instead.
 pub fn write(cap_index: u8, key: &[u8; 32], value: &[u8; 32]) -> Result<(), Error> {
+    if buffer::buffer_write(cap_index, key, value) {
+        return Ok(());
+    }
     let mut input = Vec::with_capacity(1 + 1 + 32 + 32);
     let syscall = SysCall {
         cap_index,
@@ -208,6 +228,25 @@ pub fn write(cap_index: u8, key: &[u8; 32], value: &[u8; 32]) -> Result<(), Erro
     cap9_syscall(&input, &mut Vec::new())
 }
 
+/// Perform a write batch system call, which is checked against the cap once.
+pub fn write_batch(cap_index: u8, writes: Vec<WriteCall>) -> Result<(), Error> {
+    let mut input = Vec::with_capacity(1 + 1 + writes.len() * (32 + 32));
+    let syscall = SysCall {
+        cap_index,
+        action: SysCallAction::WriteBatch(WriteBatchCall{writes}),
+    };
+    syscall.serialize(&mut input).unwrap();
+    cap9_syscall(&input, &mut Vec::new())
+}
+
+/// Read a value from storage, including any value buffered by [`buffer`].
+pub fn read(key: &H256) -> [u8; 32] {
+    match buffer::buffered_read(key) {
+        Some(value) => value,
+        None => pwasm_ethereum::read(key),
+    }
+}
+
 /// Perform a procedure call system call.
 pub fn call(cap_index: u8, proc_id: SysCallProcedureKey, payload: Vec<u8>) -> Result<(), Error> {
     let mut input = Vec::new();
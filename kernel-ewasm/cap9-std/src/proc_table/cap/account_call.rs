@@ -1,11 +1,12 @@
 use super::{AsCap,ProcedureKey,matching_keys};
-use cap9_core::{Serialize, Deserialize};
+use cap9_derive::{Serialize, Deserialize};
 use pwasm_abi::types::*;
 
 pub const CAP_ACC_CALL: u8 = 9;
 pub const CAP_ACC_CALL_SIZE: u8 = 1;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serialize(via = "U256")]
 pub struct AccountCallCap {
     pub can_call_any: bool,
     pub can_send: bool,
@@ -51,31 +52,6 @@ impl AsCap for AccountCallCap {
     }
 }
 
-impl Deserialize<U256> for AccountCallCap {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<U256>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let mut buf = [U256::zero(); 1];
-        reader.read(&mut buf).unwrap();
-        let val: U256 = buf[0];
-
-        let can_call_any = val.bit(255);
-        let can_send = val.bit(254);
-
-        let mut address = [0u8; 20];
-        address.copy_from_slice(&<[u8; 32]>::from(val)[12..]);
-        let address = H160::from(address);
-
-        let account_call_cap = AccountCallCap {
-            can_call_any: can_call_any,
-            can_send: can_send,
-            address: address,
-        };
-
-        Ok(account_call_cap)
-    }
-}
-
 impl From<U256> for AccountCallCap {
     fn from(val: U256) -> Self {
         let can_call_any = val.bit(255);
@@ -93,20 +69,17 @@ impl From<U256> for AccountCallCap {
     }
 }
 
-impl Serialize<U256> for AccountCallCap {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<U256>>(&self, writer: &mut W) -> Result<(), Self::Error> {
+impl From<AccountCallCap> for U256 {
+    fn from(cap: AccountCallCap) -> Self {
         let mut res = [0u8; 32];
-        res[0] |= if self.can_call_any {
+        res[0] |= if cap.can_call_any {
             0x80
         } else {
             0
         };
-        res[0] |= if self.can_send { 0x40 } else { 0 };
+        res[0] |= if cap.can_send { 0x40 } else { 0 };
 
-        res[12..].copy_from_slice(self.address.as_fixed_bytes());
-        writer.write(&[res.into()])?;
-        Ok(())
+        res[12..].copy_from_slice(cap.address.as_fixed_bytes());
+        res.into()
     }
 }
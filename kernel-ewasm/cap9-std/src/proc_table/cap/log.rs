@@ -1,5 +1,5 @@
 use super::{AsCap,ProcedureKey,matching_keys};
-use cap9_core::{Serialize, Deserialize};
+use cap9_derive::{Serialize, Deserialize};
 use pwasm_abi::types::*;
 
 #[cfg(feature="std")]
@@ -8,8 +8,10 @@ use rustc_hex::ToHex;
 pub const CAP_LOG: u8 = 8;
 pub const CAP_LOG_SIZE: u8 = 5;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LogCap {
+    /// The number of topics required, of which there are at most four.
+    #[serialize(max = 4)]
     pub topics: u8,
     pub t1: [u8; 32],
     pub t2: [u8; 32],
@@ -62,49 +64,3 @@ impl AsCap for LogCap {
         true
     }
 }
-
-impl Deserialize<U256> for LogCap {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<U256>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let mut buf = [U256::zero(); 5];
-        reader.read(&mut buf).unwrap();
-
-        let topics_len: usize = buf[0].byte(0) as usize;
-        let mut topics = [[0; 32]; 4];
-        if topics_len != 0 {
-            match topics_len {
-                1..=4 => {
-                    for i in 0..topics_len {
-                        topics[i] = buf[i+1].into()
-                    }
-                }
-                _ => return Err(cap9_core::Error::InvalidData)
-                // _ => return Err(CapDecodeErr::InvalidCapLen(topics_len as u8)),
-            }
-        }
-
-        Ok(LogCap {
-            topics: topics_len as u8,
-            t1: topics[0],
-            t2: topics[1],
-            t3: topics[2],
-            t4: topics[3],
-        })
-    }
-}
-
-impl Serialize<U256> for LogCap {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<U256>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        let topics_len = U256::from(self.topics);
-        let t1 = U256::from(self.t1);
-        let t2 = U256::from(self.t2);
-        let t3 = U256::from(self.t3);
-        let t4 = U256::from(self.t4);
-
-        writer.write(&[topics_len, t1, t2, t3, t4])?;
-        Ok(())
-    }
-}
@@ -123,6 +123,22 @@ impl Capability {
     }
 }
 
+/// Split the word of a cap which covers the procedure keys with a prefix, in
+/// which the prefix is the first byte and the key the last 24 bytes.
+fn prefixed_key_from_word(val: U256) -> (u8, ProcedureKey) {
+    let mut key = [0u8; 24];
+    key.copy_from_slice(&<[u8; 32]>::from(val)[8..]);
+    (val.byte(31), key)
+}
+
+/// The word of a cap which covers the procedure keys with a prefix.
+fn prefixed_key_to_word(prefix: u8, key: &ProcedureKey) -> U256 {
+    let mut res = [0u8; 32];
+    res[0] = prefix;
+    res[8..].copy_from_slice(key);
+    res.into()
+}
+
 pub trait AsCap {
     fn is_subset_of(&self, parent_cap: &Self) -> bool;
 }
@@ -737,7 +753,7 @@ mod tests {
             key,
         };
 
-        let list = [arr.into()].to_vec();
+        let list: Vec<U256> = [arr.into()].to_vec();
 
         assert_eq!(ProcedureCallCap::deserialize(&mut Cursor::new(&list)).unwrap(), sample_cap);
     }
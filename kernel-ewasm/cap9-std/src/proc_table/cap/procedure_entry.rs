@@ -1,5 +1,5 @@
 use super::{AsCap,ProcedureKey,matching_keys};
-use cap9_core::{Serialize, Deserialize};
+use cap9_derive::{Serialize, Deserialize};
 use pwasm_abi::types::*;
 
 #[cfg(feature="std")]
@@ -8,7 +8,7 @@ use rustc_hex::ToHex;
 pub const CAP_PROC_ENTRY: u8 = 6;
 pub const CAP_PROC_ENTRY_SIZE: u8 = 0;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ProcedureEntryCap;
 
 #[cfg(feature="std")]
@@ -25,20 +25,3 @@ impl AsCap for ProcedureEntryCap {
         true
     }
 }
-
-impl Deserialize<U256> for ProcedureEntryCap {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<U256>>(_reader: &mut R) -> Result<Self, Self::Error> {
-        Ok(ProcedureEntryCap {})
-    }
-}
-
-
-impl Serialize<U256> for ProcedureEntryCap {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<U256>>(&self, _writer: &mut W) -> Result<(), Self::Error> {
-        Ok(())
-    }
-}
@@ -1,5 +1,5 @@
-use super::{AsCap,ProcedureKey,matching_keys};
-use cap9_core::{Serialize, Deserialize};
+use super::{AsCap,ProcedureKey,matching_keys,prefixed_key_from_word,prefixed_key_to_word};
+use cap9_derive::{Serialize, Deserialize};
 use pwasm_abi::types::*;
 
 #[cfg(feature="std")]
@@ -8,7 +8,8 @@ use rustc_hex::ToHex;
 pub const CAP_PROC_REGISTER: u8 = 4;
 pub const CAP_PROC_REGISTER_SIZE: u8 = 1;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serialize(via = "U256")]
 pub struct ProcedureRegisterCap {
     pub prefix: u8,
     pub key: ProcedureKey,
@@ -33,31 +34,15 @@ impl AsCap for ProcedureRegisterCap {
     }
 }
 
-impl Deserialize<U256> for ProcedureRegisterCap {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<U256>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let mut buf = [U256::zero(); 1];
-        reader.read(&mut buf).unwrap();
-        let val: U256 = buf[0];
-        let mut key = [0u8; 24];
-        key.copy_from_slice(&<[u8; 32]>::from(val)[8..]);
-
-        Ok(ProcedureRegisterCap {
-            prefix: val.byte(31),
-            key: key,
-        })
+impl From<U256> for ProcedureRegisterCap {
+    fn from(val: U256) -> Self {
+        let (prefix, key) = prefixed_key_from_word(val);
+        ProcedureRegisterCap { prefix, key }
     }
 }
 
-impl Serialize<U256> for ProcedureRegisterCap {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<U256>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        let mut res = [0u8; 32];
-        res[0] = self.prefix;
-        res[8..].copy_from_slice(&self.key);
-        writer.write(&[res.into()])?;
-        Ok(())
+impl From<ProcedureRegisterCap> for U256 {
+    fn from(cap: ProcedureRegisterCap) -> Self {
+        prefixed_key_to_word(cap.prefix, &cap.key)
     }
 }
@@ -1,5 +1,5 @@
 use super::AsCap;
-use cap9_core::{Serialize, Deserialize};
+use cap9_derive::{Serialize, Deserialize};
 use pwasm_abi::types::*;
 
 #[cfg(feature="std")]
@@ -8,7 +8,7 @@ use rustc_hex::ToHex;
 pub const CAP_STORE_WRITE: u8 = 7;
 pub const CAP_STORE_WRITE_SIZE: u8 = 2;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct StoreWriteCap {
     pub location: [u8; 32],
     pub size: [u8; 32],
@@ -38,28 +38,3 @@ impl AsCap for StoreWriteCap {
         true
     }
 }
-
-impl Deserialize<U256> for StoreWriteCap {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<U256>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let mut buf = [U256::zero(); 2];
-        reader.read(&mut buf).unwrap();
-        let location: [u8; 32] = buf[0].into();
-        let size: [u8; 32] = buf[1].into();
-
-        Ok(StoreWriteCap {
-            location: location,
-            size: size,
-        })
-    }
-}
-
-impl Serialize<U256> for StoreWriteCap {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<U256>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        writer.write(&[U256::from(self.location), U256::from(self.size)])?;
-        Ok(())
-    }
-}
@@ -2,6 +2,7 @@ extern crate pwasm_abi;
 use pwasm_abi::types::*;
 use cap9_core;
 use cap9_core::{Deserialize, Serialize};
+use cap9_derive;
 
 /// Generic wasm error
 #[derive(Debug)]
@@ -12,6 +13,11 @@ use proc_table::cap::Capability;
 use proc_table::cap::*;
 use proc_table::ProcedureKey;
 
+/// The syscall type of a [`WriteBatchCall`]. Other syscalls are identified by
+/// the type of the cap they require, but a write batch requires the same
+/// StoreWrite cap as a single write.
+pub const SYSCALL_WRITE_BATCH: u8 = cap9_core::SYSCALL_VARIANT | CAP_STORE_WRITE;
+
 /// A full system call request, including the cap_index. This is permitted to
 /// access the procedure table as part of the environment.
 #[derive(Clone, Debug, PartialEq)]
@@ -25,6 +31,7 @@ impl SysCall {
         match &self.action {
             SysCallAction::Call(_)  => CAP_PROC_CALL,
             SysCallAction::Write(_) => CAP_STORE_WRITE,
+            SysCallAction::WriteBatch(_) => CAP_STORE_WRITE,
             SysCallAction::Log(_) => CAP_LOG,
             SysCallAction::Register(_) => CAP_PROC_REGISTER,
             SysCallAction::Delete(_) => CAP_PROC_DELETE,
@@ -33,6 +40,14 @@ impl SysCall {
         }
     }
 
+    /// The type of the syscall, which is written before the cap index.
+    pub fn syscall_type(&self) -> u8 {
+        match &self.action {
+            SysCallAction::WriteBatch(_) => SYSCALL_WRITE_BATCH,
+            _ => self.cap_type(),
+        }
+    }
+
     pub fn execute(&self) {
         self.action.execute()
     }
@@ -68,6 +83,12 @@ impl Deserialize<u8> for SysCall {
                     action: SysCallAction::Write(WriteCall::deserialize(reader)?)
                 })
             },
+            SYSCALL_WRITE_BATCH => {
+                Ok(SysCall {
+                    cap_index,
+                    action: SysCallAction::WriteBatch(WriteBatchCall::deserialize(reader)?)
+                })
+            },
             CAP_LOG => {
                 Ok(SysCall {
                     cap_index,
@@ -98,7 +119,7 @@ impl Deserialize<u8> for SysCall {
                     action: SysCallAction::AccountCall(AccountCall::deserialize(reader)?)
                 })
             },
-            _ => panic!("unknown syscall"),
+            _ => Err(cap9_core::Error::InvalidData),
         }
     }
 }
@@ -109,7 +130,7 @@ impl Serialize<u8> for SysCall {
 
     fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
         // Write syscall type
-        writer.write(&[self.cap_type()])?;
+        writer.write(&[self.syscall_type()])?;
         // Write cap index
         writer.write(&[self.cap_index])?;
         self.action.serialize(writer)?;
@@ -123,6 +144,7 @@ impl Serialize<u8> for SysCall {
 #[derive(Clone, Debug, PartialEq)]
 pub enum SysCallAction {
     Write(WriteCall),
+    WriteBatch(WriteBatchCall),
     Call(Call),
     Log(LogCall),
     Register(RegisterProc),
@@ -207,6 +229,30 @@ impl SysCallAction {
                 }
                 false
             },
+            // WRITE BATCH syscall
+            SysCallAction::WriteBatch(WriteBatchCall{writes}) => {
+                if let Capability::StoreWrite(proc_table::cap::StoreWriteCap {location, size}) = cap {
+                    // Rather than checking each write, we only check that
+                    // the lowest and highest keys are within the cap.
+                    let lowest = writes.iter().map(|write| write.key).min();
+                    let highest = writes.iter().map(|write| write.key).max();
+                    let (lowest, highest) = match (lowest, highest) {
+                        (Some(lowest), Some(highest)) => (lowest, highest),
+                        // An empty batch writes nothing.
+                        _ => return true,
+                    };
+                    let location_u256: U256 = location.into();
+                    let size_u256: U256 = size.into();
+                    if lowest < location_u256 {
+                        return false;
+                    }
+                    if highest > location_u256.saturating_add(size_u256) {
+                        return false;
+                    }
+                    return true;
+                }
+                false
+            },
             // LOG syscall
             SysCallAction::Log(LogCall{topics,value:_}) => {
                 if let Capability::Log(proc_table::cap::LogCap {topics: n_required_topics, t1, t2, t3, t4}) = cap {
@@ -273,6 +319,13 @@ impl SysCallAction {
                 let value_h256: H256 = value.into();
                 pwasm_ethereum::write(&key.into(), &value_h256.as_fixed_bytes());
             },
+            // WRITE BATCH syscall
+            SysCallAction::WriteBatch(WriteBatchCall{writes}) => {
+                for WriteCall{key,value} in writes {
+                    let value_h256: H256 = value.into();
+                    pwasm_ethereum::write(&key.into(), value_h256.as_fixed_bytes());
+                }
+            },
             // LOG syscall
             SysCallAction::Log(LogCall{topics,value}) => {
                 pwasm_ethereum::log(&topics.as_slice(), &value.0.as_slice());
@@ -327,6 +380,10 @@ impl Serialize<u8> for SysCallAction {
                 write_call.serialize(writer)?;
                 Ok(())
             },
+            SysCallAction::WriteBatch(write_batch_call) => {
+                write_batch_call.serialize(writer)?;
+                Ok(())
+            },
             SysCallAction::Log(log_call) => {
                 log_call.serialize(writer)?;
                 Ok(())
@@ -352,159 +409,51 @@ impl Serialize<u8> for SysCallAction {
 }
 
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
 pub struct AccountCall {
     pub address: Address,
     pub value: U256,
     pub payload: Payload,
 }
 
-impl Deserialize<u8> for AccountCall {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let address: Address = Address::deserialize(reader)?;
-        let value: U256 = U256::deserialize(reader)?;
-        let payload = Payload::deserialize(reader)?;
-        Ok(AccountCall{address, value, payload})
-    }
-}
-
-impl Serialize<u8> for AccountCall {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        self.address.serialize(writer)?;
-        self.value.serialize(writer)?;
-        self.payload.serialize(writer)?;
-        Ok(())
-    }
-}
-
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
 pub struct WriteCall {
     pub key: U256,
     pub value: U256,
 }
 
-impl Deserialize<u8> for WriteCall {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let key: U256 = U256::deserialize(reader)?;
-        let value: U256 = U256::deserialize(reader)?;
-        Ok(WriteCall{key, value})
-    }
+/// A number of writes which are checked against a single StoreWrite cap.
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
+pub struct WriteBatchCall {
+    /// The writes take up the rest of the buffer.
+    #[serialize(rest)]
+    pub writes: Vec<WriteCall>,
 }
 
-impl Serialize<u8> for WriteCall {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        // Write key
-        self.key.serialize(writer)?;
-        // Write value
-        self.value.serialize(writer)?;
-        Ok(())
-    }
-}
-
-
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
 pub struct SetEntry {
+    #[serialize(via = "SysCallProcedureKey")]
     pub proc_id: proc_table::ProcedureKey,
 }
 
-impl Deserialize<u8> for SetEntry {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let SysCallProcedureKey(proc_id) = SysCallProcedureKey::deserialize(reader)?;
-        Ok(SetEntry{proc_id})
-    }
-}
-
-impl Serialize<u8> for SetEntry {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        // Write procedure id
-        SysCallProcedureKey(self.proc_id).serialize(writer)?;
-        Ok(())
-    }
-}
-
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
 pub struct LogCall {
+    #[serialize(len = "u8")]
     pub topics: Vec<H256>,
     pub value: Payload,
 }
 
-impl Deserialize<u8> for LogCall {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let n_topics = u8::deserialize(reader)?;
-        let mut topics : Vec<H256> = Vec::new();
-        for _i in 0..(n_topics as usize) {
-            topics.push(H256::deserialize(reader)?);
-        }
-        let value: Payload = Payload::deserialize(reader)?;
-        Ok(LogCall{topics, value})
-    }
-}
-
-impl Serialize<u8> for LogCall {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        let n_topics = self.topics.len() as u8;
-        n_topics.serialize(writer)?;
-        for topic in &self.topics {
-            topic.serialize(writer)?;
-        }
-        self.value.serialize(writer)?;
-        Ok(())
-    }
-}
-
-
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
 pub struct RegisterProc {
+    #[serialize(via = "SysCallProcedureKey")]
     pub proc_id: proc_table::ProcedureKey,
     pub address: Address,
     pub cap_list: NewCapList,
 }
 
-impl Deserialize<u8> for RegisterProc {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let SysCallProcedureKey(proc_id) = SysCallProcedureKey::deserialize(reader)?;
-        let address = Address::deserialize(reader)?;
-        let cap_list = NewCapList::deserialize(reader)?;
-        Ok(RegisterProc{proc_id, address, cap_list})
-    }
-}
-
-
-impl Serialize<u8> for RegisterProc {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        // Write procedure id
-        SysCallProcedureKey(self.proc_id).serialize(writer)?;
-        // Write the address of the contract
-        self.address.serialize(writer)?;
-        // Write the caps out as 32-byte values, as per the spec
-        self.cap_list.serialize(writer)?;
-        Ok(())
-    }
-}
-
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Payload(pub Vec<u8>);
+/// The bytes which take up the rest of the buffer.
+#[derive(Debug, Clone, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
+pub struct Payload(#[serialize(rest)] pub Vec<u8>);
 
 impl Payload {
     pub fn new() -> Self {
@@ -513,84 +462,22 @@ impl Payload {
 }
 
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
 pub struct DeleteProc {
+    #[serialize(via = "SysCallProcedureKey")]
     pub proc_id: proc_table::ProcedureKey,
 }
 
-impl Deserialize<u8> for DeleteProc {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let SysCallProcedureKey(proc_id) = SysCallProcedureKey::deserialize(reader)?;
-        Ok(DeleteProc{proc_id})
-    }
-}
-
-
-impl Serialize<u8> for DeleteProc {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        // Write procedure id
-        SysCallProcedureKey(self.proc_id).serialize(writer)?;
-        Ok(())
-    }
-}
-
-
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
 pub struct Call {
+    #[serialize(via = "SysCallProcedureKey")]
     pub proc_id: proc_table::ProcedureKey,
     pub payload: Payload,
 }
 
-impl Deserialize<u8> for Call {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let SysCallProcedureKey(proc_id) = SysCallProcedureKey::deserialize(reader)?;
-        let payload = Payload::deserialize(reader)?;
-        Ok(Call{proc_id, payload})
-    }
-}
-
-
-impl Serialize<u8> for Call {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        // Write procedure id
-        SysCallProcedureKey(self.proc_id).serialize(writer)?;
-        // Write payload
-        self.payload.serialize(writer)?;
-        Ok(())
-    }
-}
-
-impl Deserialize<u8> for Payload {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        // Read all the remaining bytes in the buffer.
-        let mut payload: Vec<u8> = Vec::new();
-        payload.resize(reader.remaining(), 0_u8);
-        reader.read(&mut payload)?;
-        Ok(Payload(payload))
-    }
-}
-
-impl Serialize<u8> for Payload {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        writer.write(self.0.as_slice())?;
-        Ok(())
-    }
-}
-
 /// Newtype wrapper over procedure keys for interaction with syscalls.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, cap9_derive::Serialize, cap9_derive::Deserialize)]
+#[serialize(via = "H256")]
 pub struct SysCallProcedureKey(pub proc_table::ProcedureKey);
 
 impl From<H256> for SysCallProcedureKey {
@@ -629,45 +516,18 @@ impl Into<ProcedureKey> for SysCallProcedureKey {
     }
 }
 
-impl Deserialize<u8> for SysCallProcedureKey {
-    type Error = cap9_core::Error;
-
-    fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
-        let proc_id_u256: U256 = U256::deserialize(reader)?;
-        let mut proc_id_buffer: [u8; 32] = [0; 32];
-        proc_id_u256.to_big_endian(&mut proc_id_buffer);
-        let mut proc_id: proc_table::ProcedureKey = [0; 24];
-        proc_id.copy_from_slice(&proc_id_buffer[8..32]);
-        Ok(SysCallProcedureKey(proc_id))
-    }
-}
-
-impl Serialize<u8> for SysCallProcedureKey {
-    type Error = cap9_core::Error;
-
-    fn serialize<W: cap9_core::Write<u8>>(&self, writer: &mut W) -> Result<(), Self::Error> {
-        let mut proc_id_u256: [u8; 32] = [0; 32];
-        proc_id_u256[8..32].copy_from_slice(&self.0);
-        writer.write(&proc_id_u256)?;
-        Ok(())
-    }
-}
-
-
 impl Deserialize<u8> for NewCapList {
     type Error = cap9_core::Error;
 
     fn deserialize<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Self, Self::Error> {
+        // The caps take up the rest of the buffer. How many words each cap
+        // takes depends on its type, so the list is read as words first.
         let mut cap_list_raw: Vec<U256> = Vec::new();
-        loop {
-            if let Ok(cap_val) = H256::deserialize(reader) {
-                cap_list_raw.push(cap_val.into());
-            } else {
-                break;
-            }
+        while reader.remaining() > 0 {
+            cap_list_raw.push(U256::deserialize(reader)?);
         }
-        let cap_list = NewCapList::from_u256_list(cap_list_raw.as_slice()).unwrap();
-        Ok(cap_list)
+        NewCapList::from_u256_list(cap_list_raw.as_slice())
+            .map_err(|_| cap9_core::Error::InvalidData)
     }
 }
 
@@ -707,6 +567,27 @@ mod tests {
         assert_eq!(buffer, expected);
     }
 
+    #[test]
+    fn serialize_write_batch() {
+        let syscall = SysCall {
+            cap_index: 2,
+            action: SysCallAction::WriteBatch(WriteBatchCall{writes: [
+                WriteCall{key: 1.into(), value: 2.into()},
+                WriteCall{key: 3.into(), value: 4.into()},
+            ].to_vec()}),
+        };
+        let mut buffer = Vec::new();
+        syscall.serialize(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), 1 + 1 + 2 * (32 + 32));
+        assert_eq!(buffer[0..2], [SYSCALL_WRITE_BATCH, 2]);
+        assert_eq!(buffer[33], 1);
+        assert_eq!(buffer[65], 2);
+        assert_eq!(buffer[97], 3);
+        assert_eq!(buffer[129], 4);
+        let mut cursor = cap9_core::Cursor::new(buffer.as_slice());
+        assert_eq!(SysCall::deserialize(&mut cursor).unwrap(), syscall);
+    }
+
     #[test]
     fn matching_keys_test_1() {
         let prefix = 0;
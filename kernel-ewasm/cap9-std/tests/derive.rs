@@ -0,0 +1,353 @@
+//! Tests of the derives of cap9-derive, with procedures run by
+//! [cap9_std::TestKernel].
+use cap9_core::{Cursor, Deserialize, Serialize};
+use cap9_derive::{Keyable, Storable};
+use cap9_std::data::{Keyable, Storable};
+use cap9_std::proc_table::cap::*;
+use cap9_std::proc_table::ProcedureKey;
+use cap9_std::syscalls::Error;
+use cap9_std::*;
+use pwasm_abi::types::*;
+
+#[derive(Clone, Debug, PartialEq, Storable)]
+struct Account {
+    balance: U256,
+    group: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Storable)]
+enum Role {
+    Guest,
+    Member(u8),
+    Admin { level: U256, group: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq, Keyable)]
+struct GroupId(u8);
+
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
+struct Transfer {
+    amount: U256,
+    group: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
+enum Action {
+    Stop,
+    Transfer(Transfer),
+    Delete {
+        #[serialize(via = "SysCallProcedureKey")]
+        proc_id: ProcedureKey,
+    },
+}
+
+/// Two bytes packed into the last two bytes of a word.
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
+#[serialize(via = "U256")]
+struct Packed {
+    high: u8,
+    low: u8,
+}
+
+impl From<U256> for Packed {
+    fn from(val: U256) -> Self {
+        Packed {
+            high: val.byte(1),
+            low: val.byte(0),
+        }
+    }
+}
+
+impl From<Packed> for U256 {
+    fn from(packed: Packed) -> Self {
+        U256::from(packed.high) << 8 | U256::from(packed.low)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, cap9_derive::Serialize, cap9_derive::Deserialize)]
+struct Batch {
+    #[serialize(max = 2)]
+    kind: u8,
+    #[serialize(len = "u8")]
+    groups: Vec<u8>,
+    #[serialize(rest)]
+    amounts: Vec<U256>,
+}
+
+fn write_cap(size: u8) -> NewCapList {
+    let mut size_bytes = [0; 32];
+    size_bytes[31] = size;
+    NewCapList(
+        [NewCapability {
+            cap: Capability::StoreWrite(StoreWriteCap {
+                location: [0; 32],
+                size: size_bytes,
+            }),
+            parent_index: 0,
+        }]
+        .to_vec(),
+    )
+}
+
+fn key(name: &str) -> ProcedureKey {
+    let mut key = [0; 24];
+    key[..name.len()].copy_from_slice(name.as_bytes());
+    key
+}
+
+fn read_u8(location: u8) -> u8 {
+    let mut key = [0; 32];
+    key[31] = location;
+    pwasm_ethereum::read(&key.into())[31]
+}
+
+#[test]
+fn n_keys() {
+    assert_eq!(Account::n_keys(), 2.into());
+    // The variant and the fields of Admin.
+    assert_eq!(Role::n_keys(), 3.into());
+    assert_eq!(GroupId::key_width(), 1);
+    assert_eq!(GroupId(7).key_slice(), [7].to_vec());
+}
+
+#[test]
+fn read_vec_u256() {
+    assert_eq!(
+        Account::read_vec_u256([5.into(), 2.into()].to_vec()),
+        Some(Account {
+            balance: 5.into(),
+            group: 2
+        })
+    );
+    assert_eq!(Account::read_vec_u256([5.into()].to_vec()), None);
+    assert_eq!(
+        Role::read_vec_u256([2.into(), 9.into()].to_vec()),
+        Some(Role::Member(9))
+    );
+    assert_eq!(Role::read_vec_u256([1.into()].to_vec()), Some(Role::Guest));
+    assert_eq!(Role::read_vec_u256([0.into()].to_vec()), None);
+}
+
+#[test]
+fn store_struct() {
+    let mut kernel = TestKernel::new();
+    kernel.register(key("accounts"), write_cap(10), |input| {
+        let mut accounts: StorageVec<Account> = StorageVec::from(0).map_err(|_| Error)?;
+        accounts.push(Account {
+            balance: input[0].into(),
+            group: input[1],
+        }).map_err(|_| Error)?;
+        let account = accounts
+            .get(accounts.length() - U256::from(1))
+            .ok_or(Error)?;
+        Ok([account.balance.as_u32() as u8, account.group].to_vec())
+    });
+    kernel.set_entry(key("accounts"));
+
+    assert_eq!(kernel.call(&[100, 1]).unwrap(), [100, 1].to_vec());
+    assert_eq!(kernel.call(&[50, 2]).unwrap(), [50, 2].to_vec());
+    // The fields are stored in order after the length of the vector.
+    assert_eq!(read_u8(0), 2);
+    assert_eq!(read_u8(1), 100);
+    assert_eq!(read_u8(2), 1);
+    assert_eq!(read_u8(3), 50);
+    assert_eq!(read_u8(4), 2);
+}
+
+#[test]
+fn store_enum() {
+    let mut kernel = TestKernel::new();
+    kernel.register(key("roles"), write_cap(3), |input| {
+        let role = match input[0] {
+            0 => Role::Guest,
+            1 => Role::Member(input[1]),
+            _ => Role::Admin {
+                level: input[1].into(),
+                group: input[2],
+            },
+        };
+        let location = U256::from(1);
+        role.store(0, location);
+        if Role::read(location) == Some(role) {
+            Ok(Vec::new())
+        } else {
+            Err(Error)
+        }
+    });
+    kernel.set_entry(key("roles"));
+
+    assert!(kernel.call(&[2, 3, 4]).is_ok());
+    assert_eq!(read_u8(1), 3);
+    assert_eq!(read_u8(2), 3);
+    assert_eq!(read_u8(3), 4);
+    // A smaller variant clears the rest of the larger one.
+    assert!(kernel.call(&[1, 7]).is_ok());
+    assert_eq!(read_u8(1), 2);
+    assert_eq!(read_u8(2), 7);
+    assert_eq!(read_u8(3), 0);
+    assert!(kernel.call(&[0]).is_ok());
+    assert_eq!(read_u8(1), 1);
+    assert_eq!(read_u8(2), 0);
+}
+
+#[test]
+fn newtype_key() {
+    let mut kernel = TestKernel::new();
+    let mut size = [0; 32];
+    size[30] = 0x03;
+    size[31] = 0xff;
+    let cap_list = NewCapList(
+        [NewCapability {
+            cap: Capability::StoreWrite(StoreWriteCap {
+                location: [0; 32],
+                size,
+            }),
+            parent_index: 0,
+        }]
+        .to_vec(),
+    );
+    kernel.register(key("groups"), cap_list, |input| {
+        let mut groups: StorageEnumerableMap<GroupId, U256> =
+            StorageEnumerableMap::from(0).map_err(|_| Error)?;
+        groups.insert(GroupId(input[0]), input[1].into());
+        Ok(groups.keys().map(|group| group.0).collect())
+    });
+    kernel.set_entry(key("groups"));
+
+    assert_eq!(kernel.call(&[4, 8]).unwrap(), [4].to_vec());
+    assert_eq!(kernel.call(&[6, 9]).unwrap(), [4, 6].to_vec());
+    assert_eq!(read_u8(6 << 2), 9);
+}
+
+#[test]
+fn serialize_struct() {
+    let transfer = Transfer {
+        amount: 0x0102.into(),
+        group: 3,
+    };
+
+    let mut bytes: Vec<u8> = Vec::new();
+    transfer.serialize(&mut bytes).unwrap();
+    let mut expected = [0; 33].to_vec();
+    expected[30] = 0x01;
+    expected[31] = 0x02;
+    expected[32] = 3;
+    assert_eq!(bytes, expected);
+    assert_eq!(
+        Transfer::deserialize(&mut Cursor::new(&bytes)).unwrap(),
+        transfer
+    );
+
+    let mut words: Vec<U256> = Vec::new();
+    transfer.serialize(&mut words).unwrap();
+    assert_eq!(words, [0x0102.into(), 3.into()].to_vec());
+    assert_eq!(
+        Transfer::deserialize(&mut Cursor::new(&words)).unwrap(),
+        transfer
+    );
+
+    // Too few words to read.
+    assert!(Transfer::deserialize(&mut Cursor::new(&words[..1])).is_err());
+}
+
+#[test]
+fn serialize_enum() {
+    let mut bytes: Vec<u8> = Vec::new();
+    Action::Stop.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes, [0].to_vec());
+
+    let action = Action::Transfer(Transfer {
+        amount: 7.into(),
+        group: 1,
+    });
+    let mut bytes: Vec<u8> = Vec::new();
+    action.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 1 + 32 + 1);
+    assert_eq!(bytes[0], 1);
+    assert_eq!(
+        Action::deserialize(&mut Cursor::new(&bytes)).unwrap(),
+        action
+    );
+
+    // The procedure key is written as a 32-byte value.
+    let action = Action::Delete {
+        proc_id: key("delete"),
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    action.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 1 + 32);
+    assert_eq!(&bytes[1..9], &[0; 8]);
+    assert_eq!(&bytes[9..15], b"delete");
+    assert_eq!(
+        Action::deserialize(&mut Cursor::new(&bytes)).unwrap(),
+        action
+    );
+
+    // An unknown variant.
+    assert!(Action::deserialize(&mut Cursor::new(&[3_u8])).is_err());
+}
+
+#[test]
+fn serialize_via_type() {
+    let packed = Packed { high: 1, low: 2 };
+    let mut words: Vec<U256> = Vec::new();
+    packed.serialize(&mut words).unwrap();
+    assert_eq!(words, [0x0102.into()].to_vec());
+    assert_eq!(
+        Packed::deserialize(&mut Cursor::new(&words)).unwrap(),
+        packed
+    );
+
+    let mut bytes: Vec<u8> = Vec::new();
+    packed.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 32);
+    assert_eq!(&bytes[30..], &[1, 2]);
+}
+
+#[test]
+fn serialize_collections() {
+    let batch = Batch {
+        kind: 2,
+        groups: [4, 5].to_vec(),
+        amounts: [6.into(), 7.into()].to_vec(),
+    };
+    let mut words: Vec<U256> = Vec::new();
+    batch.serialize(&mut words).unwrap();
+    let expected: Vec<U256> = [2, 2, 4, 5, 6, 7].iter().map(|&x| x.into()).collect();
+    assert_eq!(words, expected);
+    assert_eq!(
+        Batch::deserialize(&mut Cursor::new(&words)).unwrap(),
+        batch
+    );
+
+    // The last field takes up the rest of the input, which may be empty.
+    let empty = Batch {
+        kind: 0,
+        groups: Vec::new(),
+        amounts: Vec::new(),
+    };
+    let words: Vec<U256> = [0.into(), 0.into()].to_vec();
+    assert_eq!(
+        Batch::deserialize(&mut Cursor::new(&words)).unwrap(),
+        empty
+    );
+
+    // More groups than there are words.
+    let words: Vec<U256> = [0.into(), 3.into(), 4.into()].to_vec();
+    assert!(Batch::deserialize(&mut Cursor::new(&words)).is_err());
+    // A kind greater than the maximum.
+    let words: Vec<U256> = [3.into(), 0.into()].to_vec();
+    assert!(Batch::deserialize(&mut Cursor::new(&words)).is_err());
+    // A u8 which does not fit in a byte.
+    let words: Vec<U256> = [0x100.into(), 0.into()].to_vec();
+    assert!(Batch::deserialize(&mut Cursor::new(&words)).is_err());
+
+    // Too many groups to write their number as a u8.
+    let batch = Batch {
+        kind: 0,
+        groups: [0; 256].to_vec(),
+        amounts: Vec::new(),
+    };
+    let mut words: Vec<U256> = Vec::new();
+    assert!(batch.serialize(&mut words).is_err());
+}
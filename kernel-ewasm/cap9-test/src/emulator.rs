@@ -0,0 +1,111 @@
+//! An in-process stand-in for the kernel, so that procedures which make
+//! syscalls can be tested natively.
+//!
+//! Procedures are Rust closures deployed at an address. `call_code` executes
+//! the procedure at the given address, and `cap9_syscall_low` passes the
+//! syscall on to a handler. This crate sits below cap9-std and knows nothing of
+//! the syscall format, so the handler is installed by cap9-std's `TestKernel`,
+//! which deserializes, checks, and executes the syscall as the kernel does.
+//!
+//! All state is thread local, so each test has its own emulator.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// A procedure, taking its input and returning its result. An `Err` reverts
+/// the call.
+pub type Procedure = Rc<dyn Fn(&[u8]) -> Result<Vec<u8>, ()>>;
+
+/// A handler for syscalls, taking the serialized syscall and returning its
+/// result. An `Err` means the syscall was rejected.
+pub type SyscallHandler = Rc<dyn Fn(&[u8]) -> Result<Vec<u8>, ()>>;
+
+/// The gas reported by `gasleft`. Callers subtract a margin from this before
+/// calling on, so it just needs to be large.
+pub const GAS: i64 = 1_000_000_000;
+
+#[derive(Default)]
+struct Emulator {
+    /// The procedures which have been deployed, by address.
+    procedures: BTreeMap<[u8; 20], Procedure>,
+    /// The number of procedures deployed, used to give each a unique address.
+    deployed: u64,
+    syscall_handler: Option<SyscallHandler>,
+    /// The return data of the last call, as read by `result_length` and
+    /// `fetch_result`.
+    result: Vec<u8>,
+}
+
+thread_local! {
+    static EMULATOR: RefCell<Emulator> = RefCell::new(Emulator::default());
+}
+
+/// Remove all deployed procedures and the syscall handler.
+pub fn reset() {
+    EMULATOR.with(|emulator| *emulator.borrow_mut() = Emulator::default());
+}
+
+/// Deploy a procedure, returning the address it is deployed at.
+pub fn deploy<F>(procedure: F) -> [u8; 20]
+where
+    F: Fn(&[u8]) -> Result<Vec<u8>, ()> + 'static,
+{
+    EMULATOR.with(|emulator| {
+        let mut emulator = emulator.borrow_mut();
+        emulator.deployed += 1;
+        // Addresses are counted up from the top of the address space so that
+        // they are unlikely to collide with addresses chosen by tests.
+        let mut address = [0xff; 20];
+        address[12..].copy_from_slice(&(u64::max_value() - emulator.deployed).to_be_bytes());
+        emulator.procedures.insert(address, Rc::new(procedure));
+        address
+    })
+}
+
+/// Set the handler to which `cap9_syscall_low` passes syscalls.
+pub fn set_syscall_handler<F>(handler: F)
+where
+    F: Fn(&[u8]) -> Result<Vec<u8>, ()> + 'static,
+{
+    EMULATOR.with(|emulator| emulator.borrow_mut().syscall_handler = Some(Rc::new(handler)));
+}
+
+/// Execute the procedure deployed at `address` with `input`, as `call_code`
+/// does. Calling an address with no procedure fails.
+pub fn call(address: &[u8; 20], input: &[u8]) -> Result<Vec<u8>, ()> {
+    // The procedure is taken out of the emulator before it is run, as it may
+    // itself make syscalls and calls.
+    let procedure = EMULATOR.with(|emulator| emulator.borrow().procedures.get(address).cloned());
+    let result = match procedure {
+        Some(procedure) => procedure(input),
+        None => Err(()),
+    };
+    set_result(&result);
+    result
+}
+
+/// Pass a syscall to the syscall handler. If no handler is set, every syscall
+/// fails.
+pub fn syscall(input: &[u8]) -> Result<Vec<u8>, ()> {
+    let handler = EMULATOR.with(|emulator| emulator.borrow().syscall_handler.clone());
+    let result = match handler {
+        Some(handler) => handler(input),
+        None => Err(()),
+    };
+    set_result(&result);
+    result
+}
+
+/// The return data of the last call.
+pub fn result() -> Vec<u8> {
+    EMULATOR.with(|emulator| emulator.borrow().result.clone())
+}
+
+fn set_result(result: &Result<Vec<u8>, ()>) {
+    let data = match result {
+        Ok(data) => data.clone(),
+        Err(()) => Vec::new(),
+    };
+    EMULATOR.with(|emulator| emulator.borrow_mut().result = data);
+}
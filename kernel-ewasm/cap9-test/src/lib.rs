@@ -1,4 +1,10 @@
-#![no_std]
+//! Native implementations of the functions that the cap9 kernel, and those
+//! parts of parity not covered by pwasm_test, provide to procedures. Syscalls
+//! and calls are executed by the in-process kernel in [emulator].
+
+pub mod emulator;
+
+use std::slice;
 
 #[no_mangle]
 pub extern fn extcodesize( _address: *const u8) -> i32 {
@@ -23,28 +29,54 @@ pub extern fn extcodecopy( _dest: *mut u8, _address: *const u8) {
 //     }
 
 #[no_mangle]
-pub extern fn cap9_syscall_low(_input_ptr: *const u8, _input_len: u32, _result_ptr: *mut u8, _result_len: u32) -> i32 {
-    panic!("cap9_syscall_low not available")
+pub extern fn cap9_syscall_low(input_ptr: *const u8, input_len: u32, result_ptr: *mut u8, result_len: u32) -> i32 {
+    let input = unsafe { slice::from_raw_parts(input_ptr, input_len as usize) };
+    let result = emulator::syscall(input);
+    unsafe { write_result(result, result_ptr, result_len) }
 }
 
 #[no_mangle]
 pub extern fn gasleft() -> i64 {
-    panic!("gasleft not available");
+    emulator::GAS
 }
 
 #[no_mangle]
-pub extern fn call_code() -> i64 {
-    panic!("call_code not available");
+pub extern fn call_code(
+    _gas: i64,
+    address: *const u8,
+    _val_ptr: *const u8,
+    input_ptr: *const u8,
+    input_len: u32,
+    result_ptr: *mut u8,
+    result_len: u32,
+) -> i32 {
+    let mut address_buf = [0; 20];
+    address_buf.copy_from_slice(unsafe { slice::from_raw_parts(address, 20) });
+    let input = unsafe { slice::from_raw_parts(input_ptr, input_len as usize) };
+    let result = emulator::call(&address_buf, input);
+    unsafe { write_result(result, result_ptr, result_len) }
 }
 
-
 #[no_mangle]
-pub extern fn result_length() -> i64 {
-    panic!("result_length not available");
+pub extern fn result_length() -> i32 {
+    emulator::result().len() as i32
 }
 
-
 #[no_mangle]
-pub extern fn fetch_result() -> i64 {
-    panic!("fetch_result not available");
+pub extern fn fetch_result(dest: *mut u8) {
+    let result = emulator::result();
+    unsafe { slice::from_raw_parts_mut(dest, result.len()) }.copy_from_slice(&result);
+}
+
+/// Copy as much of a successful result as fits into the result buffer, and
+/// return the status code: 0 for success and 1 for failure.
+unsafe fn write_result(result: Result<Vec<u8>, ()>, result_ptr: *mut u8, result_len: u32) -> i32 {
+    match result {
+        Ok(data) => {
+            let len = data.len().min(result_len as usize);
+            slice::from_raw_parts_mut(result_ptr, len).copy_from_slice(&data[..len]);
+            0
+        }
+        Err(()) => 1,
+    }
 }
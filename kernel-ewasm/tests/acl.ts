@@ -226,47 +226,38 @@ async function checkKernelState(kernel, keys, data) {
 
 // Given a (hex) map from storage keys to storage values, remove each of the
 // key/value pairs associated with StorageEnumerable map, roughly checking that
-// the StorageEnumerableMap is well formed.
+// the StorageEnumerableMap is well formed. The values of the maps checked here
+// each take a single storage key, so there are no data bits. A storage key of
+// the map is the base key, with the map key shifted past the presence and
+// enumeration bits.
 function removeMap(storage, baseHex) {
-    const baseKey = hexToBuffer(baseHex);
-    // The length value is stored at the baseKey with the length bit set. Does
-    // this entry exist?
-    baseKey[31] = baseKey[31] | 0b01000000;
-    const baseKeyBN = web3.utils.toBN(bufferToHex(baseKey));
-    const mapLength = web3.utils.hexToNumber(storage.get(bufferToHex(baseKey)));
-    // console.log(`baseKey: ${baseKey}`)
-    // console.log(`baseKey: ${bufferToHex(baseKey)}`)
-    // console.log(`baseKey: ${hexToBuffer(bufferToHex(baseKey))}`)
-    // console.log(`mapLength: ${mapLength}`)
-    storage.delete(bufferToHex(baseKey));
+    const baseKeyBN = web3.utils.toBN(baseHex);
+    const PRESENCE = web3.utils.toBN(0b10);
+    const ENUMERATION = web3.utils.toBN(0b01);
+    const toKey = (bn) => "0x" + bn.toString(16).padStart(64, "0");
+    // The storage key at the given key bits, with the given flags set.
+    const flaggedKey = (keyBits, flags) => toKey(baseKeyBN.add(keyBits.shln(2)).add(flags));
+    // The length value is stored at the baseKey with both the presence and
+    // enumeration bits set. Does this entry exist?
+    const lengthKey = flaggedKey(web3.utils.toBN(0), PRESENCE.add(ENUMERATION));
+    const mapLength = web3.utils.hexToNumber(storage.get(lengthKey));
+    storage.delete(lengthKey);
     const mapKeys = [];
     // Enumerate all the keys in the map and remove them from our storage map.
+    // Element i of the enumeration vector is stored at key bits i with the
+    // enumeration bit set.
     for (let i = 0; i < mapLength; i++) {
-        const k = "0x" + web3.utils.toHex(baseKeyBN.add(web3.utils.toBN(1 + i))).slice(2).padStart(64, "0");
-        // console.log(`k: ${k}`);
+        const k = flaggedKey(web3.utils.toBN(i), ENUMERATION);
         const val = storage.get(k);
-        // console.log(`${i}: ${val}`);
         storage.delete(k);
         mapKeys.push(val);
     }
     // For each of the map keys, remove the presence indicator, and the value.
-    // key width in bytes
     for (const mapKeyRaw of mapKeys) {
-        const mapKey = hexToBuffer(baseHex);
-        // console.log(`mapKeyRaw: ${mapKeyRaw}`)
-        const startIndex = hexToBuffer(mapKeyRaw).findIndex(x => x !== 0x00);
-        // console.log(`startIndex: ${startIndex}`);
-        for (let i = startIndex; i < 32; i++) {
-            // shift left 1 byte
-            mapKey[i - 1] = hexToBuffer(mapKeyRaw)[i];
-        }
-        const presenceKey = mapKey.slice();
-        presenceKey[31] = presenceKey[31] | 0b10000000;
-        // console.log(`mapKey: ${bufferToHex(mapKey)}`)
-        // console.log(`presenceKey: ${bufferToHex(presenceKey)}`)
+        const mapKeyBN = web3.utils.toBN(mapKeyRaw);
         // Remove map key
-        storage.delete(bufferToHex(mapKey));
+        storage.delete(flaggedKey(mapKeyBN, web3.utils.toBN(0)));
         // Remove presence key
-        storage.delete(bufferToHex(presenceKey));
+        storage.delete(flaggedKey(mapKeyBN, PRESENCE));
     }
 }
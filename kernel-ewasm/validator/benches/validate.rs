@@ -0,0 +1,128 @@
+//! Benchmark the validator over the example procedures in `cap9-std/examples`.
+//!
+//! The procedures must first be built with `scripts/build.sh`, which places
+//! them in `target/<name>.wasm`. Alternatively, paths to WASM files can be
+//! given as arguments:
+//!
+//!     cargo bench -p validator --bench validate -- path/to/procedure.wasm
+//!
+//! For each procedure this reports the number of instructions the validator
+//! decodes, the number of heap allocations made while validating, and the
+//! average time taken. The instruction count is what dominates the gas cost
+//! of validating in the kernel.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use validator::{Module, PwasmPolicy, Validity};
+
+/// The number of times each procedure is validated when timing.
+const ITERATIONS: u32 = 100;
+
+/// An allocator which counts the allocations made through it.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+    // Cargo passes "--bench" to benchmarks, we ignore any flags.
+    let args: Vec<PathBuf> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+        .collect();
+    let paths = if args.is_empty() {
+        example_procedures()
+    } else {
+        args
+    };
+
+    println!(
+        "{:<32} {:>8} {:>10} {:>13} {:>12} {:>10}  {}",
+        "procedure", "bytes", "functions", "instructions", "allocations", "time (us)", "result"
+    );
+    for path in paths {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let code = match fs::read(&path) {
+            Ok(code) => code,
+            Err(_) => {
+                println!("{:<32} not built ({})", name, path.display());
+                continue;
+            }
+        };
+        let module = match Module::new(code.as_slice()) {
+            Ok(module) => module,
+            Err(err) => {
+                println!("{:<32} not valid WASM ({:?})", name, err);
+                continue;
+            }
+        };
+
+        let (functions, instructions) = match module.functions() {
+            Some(funcs) => funcs.fold((0, 0), |(functions, instructions), func| {
+                (functions + 1, instructions + func.code().count())
+            }),
+            None => (0, 0),
+        };
+
+        let before = ALLOCATIONS.load(Ordering::SeqCst);
+        let result = module.validate_with(&PwasmPolicy);
+        let allocations = ALLOCATIONS.load(Ordering::SeqCst) - before;
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            let _ = Module::new(code.as_slice()).map(|module| module.validate_with(&PwasmPolicy));
+        }
+        let elapsed = start.elapsed() / ITERATIONS;
+
+        println!(
+            "{:<32} {:>8} {:>10} {:>13} {:>12} {:>10}  {}",
+            name,
+            code.len(),
+            functions,
+            instructions,
+            allocations,
+            elapsed.as_micros(),
+            match result {
+                Ok(()) => String::from("valid"),
+                Err(err) => format!("{:?}", err),
+            }
+        );
+    }
+}
+
+/// The built WASM for each of the examples in `cap9-std/examples`.
+fn example_procedures() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let mut paths: Vec<PathBuf> = fs::read_dir(root.join("cap9-std").join("examples"))
+        .expect("could not read cap9-std examples")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "rs"))
+        .filter_map(|path| {
+            path.file_stem()
+                .map(|stem| root.join("target").join(stem).with_extension("wasm"))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
@@ -0,0 +1,13 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use validator::modules::Code;
+
+fuzz_target!(|data: &[u8]| {
+    // Any bytes may be given as a function body. Decoding must report an
+    // error rather than panic.
+    let code = Code::new(data);
+    let _ = code.locals().count();
+    for _instruction in code {}
+});
@@ -0,0 +1,40 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use parity_wasm::elements;
+use validator::{Module, PwasmPolicy, SubstratePolicy, Validity};
+use validator_fuzz::{cross_check, reference_validity};
+
+fuzz_target!(|data: &[u8]| {
+    // Whatever the input, the validator must not panic, so the raw bytes are
+    // parsed and validated before anything else.
+    let module = Module::new(data);
+    let validity = module.as_ref().ok().map(|module| {
+        (
+            module.is_valid(),
+            module.is_valid_with(&SubstratePolicy),
+        )
+    });
+
+    // The runtime rejects malformed WASM before a procedure can be deployed,
+    // so the validator only has to agree with parity-wasm about modules which
+    // parity-wasm accepts.
+    let reference = match elements::deserialize_buffer::<elements::Module>(data) {
+        Ok(reference) => reference,
+        Err(_) => return,
+    };
+    let module = module.expect("parity-wasm accepted the module");
+    let (valid, valid_substrate) = validity.unwrap();
+    cross_check(&reference, &module);
+    assert_eq!(
+        valid,
+        reference_validity(&reference, &PwasmPolicy),
+        "validity (pwasm)"
+    );
+    assert_eq!(
+        valid_substrate,
+        reference_validity(&reference, &SubstratePolicy),
+        "validity (substrate)"
+    );
+});
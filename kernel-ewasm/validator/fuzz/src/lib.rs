@@ -0,0 +1,150 @@
+//! Checks of the validator against parity-wasm, shared by the fuzz targets.
+//!
+//! The validator parses WASM by hand, straight from the buffer. parity-wasm
+//! is used as the reference parser: any module it accepts must be parsed by
+//! the validator to the same imports and function bodies, and must be judged
+//! valid by the same rules.
+use parity_wasm::elements;
+use validator::{Instruction, Listing, ListingPolicy, Module};
+
+/// Panic if the validator disagrees with parity-wasm about the contents of
+/// `module`.
+pub fn cross_check(reference: &elements::Module, module: &Module) {
+    // Imports
+    let expected_imports = reference
+        .import_section()
+        .map(|section| section.entries())
+        .unwrap_or(&[]);
+    let imports: Vec<_> = module
+        .imports()
+        .map(|imports| imports.collect())
+        .unwrap_or_default();
+    assert_eq!(imports.len(), expected_imports.len(), "import count");
+    for (import, expected) in imports.iter().zip(expected_imports) {
+        assert_eq!(import.mod_name, expected.module().as_bytes(), "import module");
+        assert_eq!(import.field_name, expected.field().as_bytes(), "import field");
+    }
+
+    // Functions
+    let expected_bodies = reference
+        .code_section()
+        .map(|section| section.bodies())
+        .unwrap_or(&[]);
+    let functions: Vec<_> = module
+        .functions()
+        .map(|functions| functions.collect())
+        .unwrap_or_default();
+    assert_eq!(functions.len(), expected_bodies.len(), "function count");
+    for (function, expected) in functions.iter().zip(expected_bodies) {
+        let code = function.code();
+        let locals: Vec<u32> = code.locals().map(|local| local.count()).collect();
+        let expected_locals: Vec<u32> = expected.locals().iter().map(|local| local.count()).collect();
+        assert_eq!(locals, expected_locals, "locals");
+
+        let instructions: Vec<Instruction> = code
+            .collect::<Result<_, _>>()
+            .expect("parity-wasm decoded this body");
+        let expected_instructions = expected.code().elements();
+        assert_eq!(instructions.len(), expected_instructions.len(), "instruction count");
+        // Calls are what decide validity, so these must match exactly.
+        for (instruction, expected) in instructions.iter().zip(expected_instructions) {
+            match (instruction, expected) {
+                (Instruction::Call(index), elements::Instruction::Call(expected)) => {
+                    assert_eq!(index, expected, "call")
+                }
+                (
+                    Instruction::CallIndirect(type_index, table),
+                    elements::Instruction::CallIndirect(expected_type_index, expected_table),
+                ) => assert_eq!(
+                    (type_index, table),
+                    (expected_type_index, expected_table),
+                    "call_indirect"
+                ),
+                (Instruction::Call(_), _)
+                | (Instruction::CallIndirect(_, _), _)
+                | (_, elements::Instruction::Call(_))
+                | (_, elements::Instruction::CallIndirect(_, _)) => {
+                    panic!("instruction mismatch: {:?} {:?}", instruction, expected)
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// An independent implementation of the validation rules, over a module
+/// parsed by parity-wasm. Only MVP instructions are expected, as those are
+/// all parity-wasm will decode.
+pub fn reference_validity<P: ListingPolicy>(module: &elements::Module, policy: &P) -> bool {
+    let mut dcall = None;
+    let mut gasleft = None;
+    let mut sender = None;
+
+    let imports = module
+        .import_section()
+        .map(|section| section.entries())
+        .unwrap_or(&[]);
+    let function_imports = imports.iter().filter(|import| match import.external() {
+        elements::External::Function(_) => true,
+        _ => false,
+    });
+    for import in imports {
+        let mod_name = import.module().as_bytes();
+        let field_name = import.field().as_bytes();
+        match (policy.listing(mod_name, field_name), import.external()) {
+            (Listing::Black, _) => return false,
+            (Listing::Grey, elements::External::Function(_)) => (),
+            (Listing::Grey, _) => return false,
+            (Listing::White, _) => (),
+        }
+    }
+    for (index, import) in function_imports.enumerate() {
+        let mod_name = import.module().as_bytes();
+        let field_name = import.field().as_bytes();
+        let index = index as u32;
+        if policy.listing(mod_name, field_name) == Listing::Grey && dcall.replace(index).is_some() {
+            return false;
+        }
+        if policy.is_gasleft(mod_name, field_name) && gasleft.replace(index).is_some() {
+            return false;
+        }
+        if policy.is_sender(mod_name, field_name) && sender.replace(index).is_some() {
+            return false;
+        }
+    }
+
+    let dcall = match dcall {
+        Some(dcall) => dcall,
+        None => return true,
+    };
+    let bodies = module
+        .code_section()
+        .map(|section| section.bodies())
+        .unwrap_or(&[]);
+    for body in bodies {
+        let instructions = body.code().elements();
+        if let (Some(gasleft), Some(sender)) = (gasleft, sender) {
+            let syscall = [
+                elements::Instruction::Call(gasleft),
+                elements::Instruction::Call(sender),
+                elements::Instruction::GetLocal(0),
+                elements::Instruction::GetLocal(1),
+                elements::Instruction::GetLocal(2),
+                elements::Instruction::GetLocal(3),
+                elements::Instruction::Call(dcall),
+                elements::Instruction::End,
+            ];
+            if body.locals().is_empty() && instructions == &syscall[..] {
+                continue;
+            }
+        }
+        for instruction in instructions {
+            match instruction {
+                elements::Instruction::Call(index) if *index == dcall => return false,
+                elements::Instruction::CallIndirect(_, _) => return false,
+                _ => (),
+            }
+        }
+    }
+    true
+}
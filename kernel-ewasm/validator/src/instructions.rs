@@ -532,6 +532,16 @@ pub enum Instruction {
     I64x2TruncSF64x2Sat,
     I64x2TruncUF64x2Sat,
 
+    // https://github.com/WebAssembly/nontrapping-float-to-int-conversions
+    I32TruncSatSF32,
+    I32TruncSatUF32,
+    I32TruncSatSF64,
+    I32TruncSatUF64,
+    I64TruncSatSF32,
+    I64TruncSatUF32,
+    I64TruncSatSF64,
+    I64TruncSatUF64,
+
     // https://github.com/WebAssembly/bulk-memory-operations
     MemoryInit(u32),
     MemoryDrop(u32),
@@ -574,6 +584,137 @@ impl Instruction {
             _ => false,
         }
     }
+
+    /// The post-MVP proposal which introduced this instruction, or `None` if
+    /// it is part of the MVP.
+    pub fn proposal(&self) -> Option<Proposal> {
+        use self::Instruction::*;
+        match self {
+            I32Extend8S | I32Extend16S | I64Extend8S | I64Extend16S | I64Extend32S => {
+                Some(Proposal::SignExtension)
+            }
+            I32TruncSatSF32 | I32TruncSatUF32 | I32TruncSatSF64 | I32TruncSatUF64
+            | I64TruncSatSF32 | I64TruncSatUF32 | I64TruncSatSF64 | I64TruncSatUF64 => {
+                Some(Proposal::NonTrappingFloatToInt)
+            }
+            MemoryInit(_) | MemoryDrop(_) | MemoryCopy | MemoryFill | TableInit(_)
+            | TableDrop(_) | TableCopy => Some(Proposal::BulkMemory),
+            AtomicWake(..) | I32AtomicWait(..) | I64AtomicWait(..) | I32AtomicLoad(..)
+            | I64AtomicLoad(..) | I32AtomicLoad8u(..) | I32AtomicLoad16u(..)
+            | I64AtomicLoad8u(..) | I64AtomicLoad16u(..) | I64AtomicLoad32u(..)
+            | I32AtomicStore(..) | I64AtomicStore(..) | I32AtomicStore8u(..)
+            | I32AtomicStore16u(..) | I64AtomicStore8u(..) | I64AtomicStore16u(..)
+            | I64AtomicStore32u(..) | I32AtomicRmwAdd(..) | I64AtomicRmwAdd(..)
+            | I32AtomicRmwAdd8u(..) | I32AtomicRmwAdd16u(..) | I64AtomicRmwAdd8u(..)
+            | I64AtomicRmwAdd16u(..) | I64AtomicRmwAdd32u(..) | I32AtomicRmwSub(..)
+            | I64AtomicRmwSub(..) | I32AtomicRmwSub8u(..) | I32AtomicRmwSub16u(..)
+            | I64AtomicRmwSub8u(..) | I64AtomicRmwSub16u(..) | I64AtomicRmwSub32u(..)
+            | I32AtomicRmwAnd(..) | I64AtomicRmwAnd(..) | I32AtomicRmwAnd8u(..)
+            | I32AtomicRmwAnd16u(..) | I64AtomicRmwAnd8u(..) | I64AtomicRmwAnd16u(..)
+            | I64AtomicRmwAnd32u(..) | I32AtomicRmwOr(..) | I64AtomicRmwOr(..)
+            | I32AtomicRmwOr8u(..) | I32AtomicRmwOr16u(..) | I64AtomicRmwOr8u(..)
+            | I64AtomicRmwOr16u(..) | I64AtomicRmwOr32u(..) | I32AtomicRmwXor(..)
+            | I64AtomicRmwXor(..) | I32AtomicRmwXor8u(..) | I32AtomicRmwXor16u(..)
+            | I64AtomicRmwXor8u(..) | I64AtomicRmwXor16u(..) | I64AtomicRmwXor32u(..)
+            | I32AtomicRmwXchg(..) | I64AtomicRmwXchg(..) | I32AtomicRmwXchg8u(..)
+            | I32AtomicRmwXchg16u(..) | I64AtomicRmwXchg8u(..) | I64AtomicRmwXchg16u(..)
+            | I64AtomicRmwXchg32u(..) | I32AtomicRmwCmpxchg(..) | I64AtomicRmwCmpxchg(..)
+            | I32AtomicRmwCmpxchg8u(..) | I32AtomicRmwCmpxchg16u(..)
+            | I64AtomicRmwCmpxchg8u(..) | I64AtomicRmwCmpxchg16u(..)
+            | I64AtomicRmwCmpxchg32u(..) => Some(Proposal::Threads),
+            V128Const(..) | V128Load(..) | V128Store(..) | I8x16Splat | I16x8Splat | I32x4Splat
+            | I64x2Splat | F32x4Splat | F64x2Splat | I8x16ExtractLaneS(..)
+            | I8x16ExtractLaneU(..) | I16x8ExtractLaneS(..) | I16x8ExtractLaneU(..)
+            | I32x4ExtractLane(..) | I64x2ExtractLane(..) | F32x4ExtractLane(..)
+            | F64x2ExtractLane(..) | I8x16ReplaceLane(..) | I16x8ReplaceLane(..)
+            | I32x4ReplaceLane(..) | I64x2ReplaceLane(..) | F32x4ReplaceLane(..)
+            | F64x2ReplaceLane(..) | V8x16Shuffle(..) | I8x16Add | I16x8Add | I32x4Add
+            | I64x2Add | I8x16Sub | I16x8Sub | I32x4Sub | I64x2Sub | I8x16Mul | I16x8Mul
+            | I32x4Mul | I8x16Neg | I16x8Neg | I32x4Neg | I64x2Neg | I8x16AddSaturateS
+            | I8x16AddSaturateU | I16x8AddSaturateS | I16x8AddSaturateU | I8x16SubSaturateS
+            | I8x16SubSaturateU | I16x8SubSaturateS | I16x8SubSaturateU | I8x16Shl | I16x8Shl
+            | I32x4Shl | I64x2Shl | I8x16ShrS | I8x16ShrU | I16x8ShrS | I16x8ShrU | I32x4ShrS
+            | I32x4ShrU | I64x2ShrS | I64x2ShrU | V128And | V128Or | V128Xor | V128Not
+            | V128Bitselect | I8x16AnyTrue | I16x8AnyTrue | I32x4AnyTrue | I64x2AnyTrue
+            | I8x16AllTrue | I16x8AllTrue | I32x4AllTrue | I64x2AllTrue | I8x16Eq | I16x8Eq
+            | I32x4Eq | F32x4Eq | F64x2Eq | I8x16Ne | I16x8Ne | I32x4Ne | F32x4Ne | F64x2Ne
+            | I8x16LtS | I8x16LtU | I16x8LtS | I16x8LtU | I32x4LtS | I32x4LtU | F32x4Lt
+            | F64x2Lt | I8x16LeS | I8x16LeU | I16x8LeS | I16x8LeU | I32x4LeS | I32x4LeU
+            | F32x4Le | F64x2Le | I8x16GtS | I8x16GtU | I16x8GtS | I16x8GtU | I32x4GtS
+            | I32x4GtU | F32x4Gt | F64x2Gt | I8x16GeS | I8x16GeU | I16x8GeS | I16x8GeU
+            | I32x4GeS | I32x4GeU | F32x4Ge | F64x2Ge | F32x4Neg | F64x2Neg | F32x4Abs
+            | F64x2Abs | F32x4Min | F64x2Min | F32x4Max | F64x2Max | F32x4Add | F64x2Add
+            | F32x4Sub | F64x2Sub | F32x4Div | F64x2Div | F32x4Mul | F64x2Mul | F32x4Sqrt
+            | F64x2Sqrt | F32x4ConvertSI32x4 | F32x4ConvertUI32x4 | F64x2ConvertSI64x2
+            | F64x2ConvertUI64x2 | I32x4TruncSF32x4Sat | I32x4TruncUF32x4Sat
+            | I64x2TruncSF64x2Sat | I64x2TruncUF64x2Sat => Some(Proposal::Simd),
+            _ => None,
+        }
+    }
+}
+
+/// A post-MVP WebAssembly proposal which adds instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Proposal {
+    /// https://github.com/WebAssembly/sign-extension-ops
+    SignExtension,
+    /// https://github.com/WebAssembly/nontrapping-float-to-int-conversions
+    NonTrappingFloatToInt,
+    /// https://github.com/WebAssembly/bulk-memory-operations
+    BulkMemory,
+    /// https://github.com/WebAssembly/threads
+    Threads,
+    /// https://github.com/WebAssembly/simd
+    Simd,
+}
+
+/// The set of post-MVP proposals whose instructions are permitted in a
+/// contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Proposals {
+    pub sign_extension: bool,
+    pub non_trapping_float_to_int: bool,
+    pub bulk_memory: bool,
+    pub threads: bool,
+    pub simd: bool,
+}
+
+impl Proposals {
+    /// Permit only the MVP instruction set.
+    pub fn mvp() -> Self {
+        Proposals {
+            sign_extension: false,
+            non_trapping_float_to_int: false,
+            bulk_memory: false,
+            threads: false,
+            simd: false,
+        }
+    }
+
+    /// Whether instructions from `proposal` are permitted.
+    pub fn allows(&self, proposal: Proposal) -> bool {
+        match proposal {
+            Proposal::SignExtension => self.sign_extension,
+            Proposal::NonTrappingFloatToInt => self.non_trapping_float_to_int,
+            Proposal::BulkMemory => self.bulk_memory,
+            Proposal::Threads => self.threads,
+            Proposal::Simd => self.simd,
+        }
+    }
+}
+
+/// By default we permit the proposals that rustc may emit for single-threaded
+/// code. Threads and SIMD are not permitted.
+impl Default for Proposals {
+    fn default() -> Self {
+        Proposals {
+            sign_extension: true,
+            non_trapping_float_to_int: true,
+            bulk_memory: true,
+            threads: false,
+            simd: false,
+        }
+    }
 }
 
 #[allow(missing_docs)]
@@ -1004,6 +1145,14 @@ pub mod opcodes {
     pub const F64X2_CONVERT_U_I64X2: u32 = 0xb2;
 
     pub const BULK_PREFIX: u8 = 0xfc;
+    pub const I32_TRUNC_SAT_S_F32: u8 = 0x00;
+    pub const I32_TRUNC_SAT_U_F32: u8 = 0x01;
+    pub const I32_TRUNC_SAT_S_F64: u8 = 0x02;
+    pub const I32_TRUNC_SAT_U_F64: u8 = 0x03;
+    pub const I64_TRUNC_SAT_S_F32: u8 = 0x04;
+    pub const I64_TRUNC_SAT_U_F32: u8 = 0x05;
+    pub const I64_TRUNC_SAT_S_F64: u8 = 0x06;
+    pub const I64_TRUNC_SAT_U_F64: u8 = 0x07;
     pub const MEMORY_INIT: u8 = 0x08;
     pub const MEMORY_DROP: u8 = 0x09;
     pub const MEMORY_COPY: u8 = 0x0a;
@@ -1575,38 +1724,52 @@ fn deserialize_bulk<R: cap9_core::Read<u8>>(reader: &mut R) -> Result<Instructio
     use self::Instruction::*;
     use self::opcodes::*;
 
+    // The saturating conversions share the 0xfc prefix with the bulk memory
+    // instructions. The bulk memory instructions are decoded as per the final
+    // version of the proposal, where the memory and table references follow
+    // any segment index.
     let val: u8 = Uint8::deserialize(reader)?.into();
     Ok(match val {
+        I32_TRUNC_SAT_S_F32 => I32TruncSatSF32,
+        I32_TRUNC_SAT_U_F32 => I32TruncSatUF32,
+        I32_TRUNC_SAT_S_F64 => I32TruncSatSF64,
+        I32_TRUNC_SAT_U_F64 => I32TruncSatUF64,
+        I64_TRUNC_SAT_S_F32 => I64TruncSatSF32,
+        I64_TRUNC_SAT_U_F32 => I64TruncSatUF32,
+        I64_TRUNC_SAT_S_F64 => I64TruncSatSF64,
+        I64_TRUNC_SAT_U_F64 => I64TruncSatUF64,
+
         MEMORY_INIT => {
-            if u8::from(Uint8::deserialize(reader)?) != 0 {
-                return Err(Error::UnknownOpcode(val))
-            }
-            MemoryInit(VarUint32::deserialize(reader)?.into())
+            let segment = VarUint32::deserialize(reader)?.into();
+            let memory_ref: u8 = Uint8::deserialize(reader)?.into();
+            if memory_ref != 0 { return Err(Error::InvalidMemoryReference(memory_ref)); }
+            MemoryInit(segment)
         }
         MEMORY_DROP => MemoryDrop(VarUint32::deserialize(reader)?.into()),
         MEMORY_FILL => {
-            if u8::from(Uint8::deserialize(reader)?) != 0 {
-                return Err(Error::UnknownOpcode(val))
-            }
+            let memory_ref: u8 = Uint8::deserialize(reader)?.into();
+            if memory_ref != 0 { return Err(Error::InvalidMemoryReference(memory_ref)); }
             MemoryFill
         }
         MEMORY_COPY => {
-            if u8::from(Uint8::deserialize(reader)?) != 0 {
-                return Err(Error::UnknownOpcode(val))
+            for _ in 0..2 {
+                let memory_ref: u8 = Uint8::deserialize(reader)?.into();
+                if memory_ref != 0 { return Err(Error::InvalidMemoryReference(memory_ref)); }
             }
             MemoryCopy
         }
 
         TABLE_INIT => {
-            if u8::from(Uint8::deserialize(reader)?) != 0 {
-                return Err(Error::UnknownOpcode(val))
-            }
-            TableInit(VarUint32::deserialize(reader)?.into())
+            let segment = VarUint32::deserialize(reader)?.into();
+            let table_ref: u8 = Uint8::deserialize(reader)?.into();
+            if table_ref != 0 { return Err(Error::InvalidTableReference(table_ref)); }
+            TableInit(segment)
         }
         TABLE_DROP => TableDrop(VarUint32::deserialize(reader)?.into()),
         TABLE_COPY => {
-            if u8::from(Uint8::deserialize(reader)?) != 0 {
-                return Err(Error::UnknownOpcode(val))
+            for _ in 0..2 {
+                let table_ref: u8 = Uint8::deserialize(reader)?.into();
+                if table_ref != 0 { return Err(Error::InvalidTableReference(table_ref)); }
             }
             TableCopy
         }
@@ -13,8 +13,11 @@ extern crate alloc;
 mod func;
 mod import_entry;
 mod instructions;
+pub use instructions::{Instruction, Proposal, Proposals};
 pub use cap9_core::*;
 mod primitives;
+#[cfg(feature = "std")]
+pub mod requirements;
 pub mod serialization;
 mod types;
 
@@ -24,18 +27,49 @@ use self::primitives::{
 
 mod listing;
 pub mod modules;
-use listing::*;
+use listing::ImportEntry;
+pub use listing::{Listing, ListingPolicy, PwasmPolicy, SubstratePolicy};
 pub use modules::Function;
 pub use modules::Module;
 
+/// The reason a contract failed validation. Functions are identified by their
+/// position in the code section.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidityError {
+    /// The import at this index is blacklisted.
+    BlacklistedImport(usize),
+    /// The import at this index is part of the syscall, but has already been
+    /// imported.
+    DuplicateImport(usize),
+    /// The function calls dcall, or makes an indirect call, but is not the
+    /// syscall.
+    GreylistedCall(usize),
+    /// The function uses an instruction from a proposal the policy does not
+    /// permit.
+    DisallowedInstruction(usize, Proposal),
+    /// The function body could not be decoded.
+    InvalidCode(usize, serialization::Error),
+}
+
 /// A trait for types which can be validated against the cap9 spec.
 pub trait Validity {
-    /// Tests the object for validity.
-    fn is_valid(&self) -> bool;
+    /// Tests the object for validity using the default [PwasmPolicy].
+    fn is_valid(&self) -> bool {
+        self.is_valid_with(&PwasmPolicy)
+    }
+
+    /// Tests the object for validity, using `policy` to list the imports and
+    /// the permitted instructions.
+    fn is_valid_with<P: ListingPolicy>(&self, policy: &P) -> bool {
+        self.validate_with(policy).is_ok()
+    }
+
+    /// As [Validity::is_valid_with], but reports why the object is invalid.
+    fn validate_with<P: ListingPolicy>(&self, policy: &P) -> Result<(), ValidityError>;
 }
 
 impl<'a> Validity for modules::Module<'a> {
-    fn is_valid(&self) -> bool {
+    fn validate_with<P: ListingPolicy>(&self, policy: &P) -> Result<(), ValidityError> {
         // Now that we have our hooks into the module, let's iterate over the
         // imports to determine white/grey/black listings. We need to remember
         // where the function and code data starts.
@@ -49,99 +83,132 @@ impl<'a> Validity for modules::Module<'a> {
         let mut sender_index: Option<usize> = None;
 
         // Iterate through each of the imports. If we find one of the imports of
-        // entry (as above) we note its index. If the import is a blacklisted
-        // import we know immediately that the contract is invalid so we return
-        // false early. If the import is neither of those (i.e. it's
-        // whitelisted) we simply skip over it. Import indices come before
-        // function indices, so we can just consider them the same while we are
-        // iterating through imports.
+        // entry (as above) we note its function index. If the import is a
+        // blacklisted import we know immediately that the contract is invalid
+        // so we return early. If the import is neither of those (i.e. it's
+        // whitelisted) we simply skip over it. Imported functions come first
+        // in the function index space, but only function imports are counted;
+        // memories, tables, and globals have their own index spaces.
         if let Some(imports) = self.imports() {
+            let mut function_index = 0;
             for (index, import) in imports.enumerate() {
-                if import.mod_name == "env" && import.field_name == "sender" {
+                let listing = policy.listing(import.mod_name, import.field_name);
+                if let Listing::Black = listing {
+                    // If we encounter a blacklisted import we can return
+                    // early.
+                    return Err(ValidityError::BlacklistedImport(index));
+                }
+                let is_function = match import.external {
+                    import_entry::External::Function(_) => true,
+                    _ => false,
+                };
+                if !is_function {
+                    // The greylisted import must be a function, otherwise it
+                    // can't be part of the syscall.
+                    if let Listing::Grey = listing {
+                        return Err(ValidityError::BlacklistedImport(index));
+                    }
+                    continue;
+                }
+
+                if policy.is_sender(import.mod_name, import.field_name) {
                     if sender_index.is_some() {
-                        panic!("sender imported multiple times");
+                        return Err(ValidityError::DuplicateImport(index));
                     }
-                    sender_index = Some(index as usize);
+                    sender_index = Some(function_index);
                 }
 
-                if import.mod_name == "env" && import.field_name == "gasleft" {
+                if policy.is_gasleft(import.mod_name, import.field_name) {
                     if gasleft_index.is_some() {
-                        panic!("gasleft imported multiple times");
+                        return Err(ValidityError::DuplicateImport(index));
                     }
-                    gasleft_index = Some(index as usize);
+                    gasleft_index = Some(function_index);
                 }
 
-                match import.listing() {
-                    Listing::White => (),
-                    Listing::Grey => {
-                        if dcall_index.is_some() {
-                            panic!("dcall imported multiple times");
-                        }
-                        dcall_index = Some(index as usize);
-                    }
-                    Listing::Black => {
-                        // If we encounter a blacklisted import we can return
-                        // early.
-                        return false;
+                if let Listing::Grey = listing {
+                    if dcall_index.is_some() {
+                        return Err(ValidityError::DuplicateImport(index));
                     }
+                    dcall_index = Some(function_index);
                 }
+                function_index += 1;
             }
         }
-        // If there is no dcall imported (therefore dcall_index is
-        // None) then we know all functions must be valid so we can skip
-        // iterating through the functions.
-        if let Some(dcall_i) = dcall_index {
-            if let Some(funcs) = self.functions() {
-                // Iterate through each of the functions and determine if it is
-                // valid.
-                for (_i, func) in funcs.enumerate() {
-                    // Check if the function is a system call, this is only
-                    // worth doing if we have indices for gasleft and sender, as
-                    // they are necessary for the syscall.
-                    if let (Some(gasleft_i), Some(sender_i)) = (gasleft_index, sender_index) {
-                        if func.is_syscall(dcall_i as u32, gasleft_i as u32, sender_i as u32) {
-                            // If the function is a system call we can continue
-                            // past it as it is valid.
-                            continue;
+        // Every function must decode and only use permitted instructions. If
+        // dcall is imported we must also check that only the syscall calls it.
+        let proposals = policy.proposals();
+        if let Some(funcs) = self.functions() {
+            // Iterate through each of the functions and determine if it is
+            // valid.
+            for (i, func) in funcs.enumerate() {
+                // Check if the function is a system call, this is only worth
+                // doing if we have indices for dcall, gasleft and sender, as
+                // they are necessary for the syscall.
+                if let (Some(dcall_i), Some(gasleft_i), Some(sender_i)) =
+                    (dcall_index, gasleft_index, sender_index)
+                {
+                    if func.is_syscall(dcall_i as u32, gasleft_i as u32, sender_i as u32) {
+                        // If the function is a system call we can continue
+                        // past it as it is valid.
+                        continue;
+                    }
+                }
+                for instruction in func.code() {
+                    let instruction =
+                        instruction.map_err(|err| ValidityError::InvalidCode(i, err))?;
+                    if let Some(proposal) = instruction.proposal() {
+                        if !proposals.allows(proposal) {
+                            return Err(ValidityError::DisallowedInstruction(i, proposal));
                         }
                     }
-                    // At this point we know that the function is not a syscall.
-                    // We must now check that it has no grey listed calls (i.e.
-                    // dcall). We only care about calls here.
-                    if func.contains_grey_call(dcall_i as u32) {
-                        // This function contains a greylisted call (i.e.
-                        // dcall), so we must return with false as the
-                        // contract is invalid.
-                        return false;
+                    // At this point we know that the function is not a
+                    // syscall. We must now check that it has no grey listed
+                    // calls (i.e. dcall). We only care about calls here.
+                    if let Some(dcall_i) = dcall_index {
+                        match instruction {
+                            instructions::Instruction::Call(f_ind) if f_ind == dcall_i as u32 => {
+                                return Err(ValidityError::GreylistedCall(i));
+                            }
+                            instructions::Instruction::CallIndirect(_, _) => {
+                                // We currently don't have the functionality to
+                                // check that tables are safe. For now we will
+                                // just forbid indirect calls by assuming any
+                                // indirect call could be a dcall.
+                                return Err(ValidityError::GreylistedCall(i));
+                            }
+                            _ => (),
+                        }
                     }
                 }
             }
         }
-        // All the tests have passed so we can return true.
-        true
+        // All the tests have passed so the contract is valid.
+        Ok(())
     }
 }
 
 /// Parse a variable size VarUint32 (i.e. LEB) as per the WASM spec. TODO: let's
 /// see if we can import this from parity-wasm.
-fn parse_varuint_32(cursor: &mut Cursor<u8>) -> u32 {
+fn parse_varuint_32(cursor: &mut Cursor<u8>) -> Result<u32, serialization::Error> {
     let mut res = 0;
     let mut shift = 0;
     loop {
         if shift > 31 {
-            panic!("invalid varuint32 {}", shift);
+            return Err(serialization::Error::InvalidVarUint32);
         }
-        let b = cursor.read_ref().unwrap().clone() as u32;
-        res |= (b & 0x7f).checked_shl(shift).expect("invalid varuint32");
+        let b = *cursor.read_ref().ok_or(serialization::Error::UnexpectedEof)? as u32;
+        res |= (b & 0x7f)
+            .checked_shl(shift)
+            .ok_or(serialization::Error::InvalidVarUint32)?;
         shift += 7;
         if (b >> 7) == 0 {
             if shift >= 32 && (b as u8).leading_zeros() < 4 {
-                panic!("invalid varuint32, b: {}", b);
+                return Err(serialization::Error::InvalidVarUint32);
             }
             break;
         }
     }
-    res
+    Ok(res)
 }
 
 #[cfg(test)]
@@ -156,7 +223,7 @@ mod tests {
     fn module_only_pass() {
         let wat = "(module)";
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, true);
     }
 
@@ -171,7 +238,7 @@ mod tests {
   (export "call" (func $call)))
 "#;
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, true);
     }
 
@@ -184,7 +251,7 @@ mod tests {
         .expect("could not open file");
         let mut wasm = Vec::new();
         f.read_to_end(&mut wasm).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, false);
     }
 
@@ -195,7 +262,7 @@ mod tests {
             .expect("could not open file");
         let mut wasm = Vec::new();
         f.read_to_end(&mut wasm).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         // NB: the kernel currently passes because it doesn't do any syscalls.
         // This will change.
         assert_eq!(validation_result, true);
@@ -208,10 +275,42 @@ mod tests {
         let mut wat = Vec::new();
         f.read_to_end(&mut wat).unwrap();
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, true);
     }
 
+    #[test]
+    fn with_syscall_memory_import_pass() {
+        // Only function imports take up a function index, so importing memory
+        // first must not shift the indices of the syscall imports.
+        let mut f =
+            File::open("test_files/with_syscall_compliant.wat").expect("could not open file");
+        let mut wat = String::new();
+        f.read_to_string(&mut wat).unwrap();
+        let wat = wat.replacen("  (memory $M0 2)\n", "", 1).replacen(
+            "  (import \"env\" \"dcall\"",
+            "  (import \"env\" \"memory\" (memory $M0 2))\n  (import \"env\" \"dcall\"",
+            1,
+        );
+        let wasm = wat2wasm(wat).unwrap();
+        assert_eq!(Module::new(wasm.as_slice()).unwrap().validate_with(&PwasmPolicy), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_dcall_fail() {
+        let wat = r#"
+(module
+  (type $t0 (func (param i64 i32 i32 i32 i32 i32) (result i32)))
+  (import "env" "dcall" (func $env.dcall (type $t0)))
+  (import "env" "dcall" (func $env.dcall2 (type $t0))))
+"#;
+        let wasm = wat2wasm(wat).unwrap();
+        assert_eq!(
+            Module::new(wasm.as_slice()).unwrap().validate_with(&PwasmPolicy),
+            Err(ValidityError::DuplicateImport(1))
+        );
+    }
+
     #[test]
     fn with_syscall_noncompliant_notpass() {
         let mut f =
@@ -219,7 +318,7 @@ mod tests {
         let mut wat = Vec::new();
         f.read_to_end(&mut wat).unwrap();
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, false);
     }
 
@@ -230,7 +329,7 @@ mod tests {
         let mut wat = Vec::new();
         f.read_to_end(&mut wat).unwrap();
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, false);
     }
 
@@ -241,7 +340,7 @@ mod tests {
         let mut wat = Vec::new();
         f.read_to_end(&mut wat).unwrap();
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, false);
     }
 
@@ -261,7 +360,7 @@ mod tests {
   (export "call" (func $call)))
 "#;
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, false);
     }
 
@@ -285,7 +384,190 @@ mod tests {
 )
 "#;
         let wasm = wat2wasm(wat).unwrap();
-        let validation_result = Module::new(wasm.as_slice()).is_valid();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid();
         assert_eq!(validation_result, false);
     }
+
+    #[test]
+    fn substrate_storage_policy() {
+        let wat = r#"
+;; Substrate contract which writes to its own storage
+(module
+  (import "env" "ext_scratch_read" (func $ext_scratch_read (param i32 i32 i32)))
+  (import "env" "ext_set_storage" (func $ext_set_storage (param i32 i32 i32 i32)))
+  (import "env" "ext_get_storage" (func $ext_get_storage (param i32) (result i32)))
+  (import "env" "memory" (memory 1 1))
+  (func (export "call")
+    (call $ext_set_storage (i32.const 0) (i32.const 1) (i32.const 32) (i32.const 32))
+    (drop (call $ext_get_storage (i32.const 0)))
+    (call $ext_scratch_read (i32.const 64) (i32.const 0) (i32.const 32)))
+  (func (export "deploy")))
+"#;
+        let wasm = wat2wasm(wat).unwrap();
+        let module = Module::new(wasm.as_slice()).unwrap();
+        assert_eq!(module.is_valid_with(&SubstratePolicy), true);
+        // The pwasm policy does not recognise any of the ext_* imports.
+        assert_eq!(module.is_valid(), false);
+    }
+
+    #[test]
+    fn substrate_uncapped_call_fail() {
+        let wat = r#"
+;; Substrate contract which calls another contract without using its caps
+(module
+  (import "env" "ext_call" (func $ext_call (param i32 i32 i64 i32 i32 i32 i32) (result i32)))
+  (import "env" "memory" (memory 1 1))
+  (func (export "call")
+    (drop (call $ext_call (i32.const 0) (i32.const 32) (i64.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))))
+"#;
+        let wasm = wat2wasm(wat).unwrap();
+        let validation_result = Module::new(wasm.as_slice()).unwrap().is_valid_with(&SubstratePolicy);
+        assert_eq!(validation_result, false);
+    }
+
+    #[test]
+    fn custom_policy() {
+        // A policy that allows nothing but memory.
+        struct MemoryOnly;
+
+        impl ListingPolicy for MemoryOnly {
+            fn listing(&self, mod_name: &[u8], field_name: &[u8]) -> Listing {
+                match (mod_name, field_name) {
+                    (b"env", b"memory") => Listing::White,
+                    _ => Listing::Black,
+                }
+            }
+        }
+
+        let wat = r#"
+(module
+  (type $t0 (func))
+  (type $t1 (func (param i32 i32)))
+  (import "env" "ret" (func $env.ret (type $t1)))
+  (func $call (type $t0)
+    i32.const 0
+    i32.const 0
+    call $env.ret)
+  (export "call" (func $call)))
+"#;
+        let wasm = wat2wasm(wat).unwrap();
+        let module = Module::new(wasm.as_slice()).unwrap();
+        assert_eq!(module.is_valid(), true);
+        assert_eq!(module.is_valid_with(&MemoryOnly), false);
+    }
+
+    /// Build a module with a single `() -> ()` function whose body (after the
+    /// locals) is `code`. This lets us test instructions our version of wabt
+    /// does not accept.
+    fn module_with_code(code: &[u8]) -> Vec<u8> {
+        let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        // Type section: one function type with no params or results.
+        wasm.extend_from_slice(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]);
+        // Function section: one function of type 0.
+        wasm.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+        // Code section: one body with no locals.
+        let body_size = code.len() as u8 + 1;
+        wasm.extend_from_slice(&[0x0a, body_size + 2, 0x01, body_size, 0x00]);
+        wasm.extend_from_slice(code);
+        wasm
+    }
+
+    #[test]
+    fn post_mvp_instructions_pass() {
+        // i32.const 1; i32.extend8_s; drop
+        let sign_ext = module_with_code(&[0x41, 0x01, 0xc0, 0x1a, 0x0b]);
+        // f32.const 0; i32.trunc_sat_f32_s; drop
+        let sat = module_with_code(&[0x43, 0x00, 0x00, 0x00, 0x00, 0xfc, 0x00, 0x1a, 0x0b]);
+        // i32.const 0; i32.const 0; i32.const 0; memory.copy
+        let bulk = module_with_code(&[0x41, 0x00, 0x41, 0x00, 0x41, 0x00, 0xfc, 0x0a, 0x00, 0x00, 0x0b]);
+        for wasm in &[sign_ext, sat, bulk] {
+            assert_eq!(Module::new(wasm.as_slice()).unwrap().validate_with(&PwasmPolicy), Ok(()));
+        }
+    }
+
+    #[test]
+    fn data_count_section_pass() {
+        // i32.const 0; i32.const 0; i32.const 0; memory.copy
+        let mut wasm = module_with_code(&[0x41, 0x00, 0x41, 0x00, 0x41, 0x00, 0xfc, 0x0a, 0x00, 0x00, 0x0b]);
+        // The DataCount section of the bulk memory proposal, declaring no
+        // data segments, goes between the function and code sections, which
+        // starts after 18 bytes.
+        wasm.splice(18..18, [0x0c, 0x01, 0x00].iter().cloned());
+        assert_eq!(Module::new(wasm.as_slice()).unwrap().validate_with(&PwasmPolicy), Ok(()));
+    }
+
+    #[test]
+    fn malformed_module_fail() {
+        // The header alone is a valid module.
+        let header = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        assert!(Module::new(&header).is_ok());
+        assert_eq!(Module::new(&header[..6]).unwrap_err(), serialization::Error::UnexpectedEof);
+        assert_eq!(
+            Module::new(&[0x00, 0x61, 0x73, 0x6e, 0x01, 0x00, 0x00, 0x00]).unwrap_err(),
+            serialization::Error::InvalidMagic
+        );
+        assert_eq!(
+            Module::new(&[0x00, 0x61, 0x73, 0x6d, 0x02, 0x00, 0x00, 0x00]).unwrap_err(),
+            serialization::Error::UnsupportedVersion(2)
+        );
+        let with_section = |section: &[u8]| {
+            let mut wasm = header.to_vec();
+            wasm.extend_from_slice(section);
+            wasm
+        };
+        // A section which is longer than the buffer.
+        assert_eq!(
+            Module::new(&with_section(&[0x01, 0x04, 0x01, 0x60])).unwrap_err(),
+            serialization::Error::UnexpectedEof
+        );
+        // An import whose module name is longer than the section.
+        assert_eq!(
+            Module::new(&with_section(&[0x02, 0x03, 0x01, 0x10, 0x61])).unwrap_err(),
+            serialization::Error::UnexpectedEof
+        );
+        // A function without a body.
+        assert_eq!(
+            Module::new(&with_section(&[0x03, 0x02, 0x01, 0x00])).unwrap_err(),
+            serialization::Error::InconsistentLength { expected: 1, actual: 0 }
+        );
+        // A truncated code section, with a body longer than the buffer.
+        let mut wasm = module_with_code(&[0x0b]);
+        wasm.pop();
+        assert_eq!(Module::new(&wasm).unwrap_err(), serialization::Error::UnexpectedEof);
+    }
+
+    #[test]
+    fn disallowed_proposal_fail() {
+        struct MvpOnly;
+
+        impl ListingPolicy for MvpOnly {
+            fn listing(&self, mod_name: &[u8], field_name: &[u8]) -> Listing {
+                PwasmPolicy.listing(mod_name, field_name)
+            }
+
+            fn proposals(&self) -> Proposals {
+                Proposals::mvp()
+            }
+        }
+
+        // i32.const 1; i32.extend8_s; drop
+        let wasm = module_with_code(&[0x41, 0x01, 0xc0, 0x1a, 0x0b]);
+        let module = Module::new(wasm.as_slice()).unwrap();
+        assert_eq!(module.is_valid(), true);
+        assert_eq!(
+            module.validate_with(&MvpOnly),
+            Err(ValidityError::DisallowedInstruction(0, Proposal::SignExtension))
+        );
+    }
+
+    #[test]
+    fn unknown_opcode_fail() {
+        let wasm = module_with_code(&[0xff, 0x0b]);
+        let module = Module::new(wasm.as_slice()).unwrap();
+        assert_eq!(module.is_valid(), false);
+        assert_eq!(
+            module.validate_with(&PwasmPolicy),
+            Err(ValidityError::InvalidCode(0, serialization::Error::UnknownOpcode(0xff)))
+        );
+    }
 }
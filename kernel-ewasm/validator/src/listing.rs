@@ -1,5 +1,5 @@
-#[cfg(not(feature = "std"))]
-use pwasm_std::String;
+use crate::import_entry::External;
+use crate::instructions::Proposals;
 /// A listing is a category of import. There are 3 types of imports whitelisted,
 /// greylisted, and blacklisted. There is no blacklist, everything that is not
 /// whitlisted or greylisted is blacklisted, even if we don't recognise it.
@@ -13,7 +13,7 @@ use pwasm_std::String;
 ///      imported, but must be checked for safety.
 ///  * Blacklisted: Everything else. These cannot even be imported. If they are
 ///      imported the contract is not valid.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Listing {
     White,
     Grey,
@@ -24,51 +24,154 @@ pub trait Listed {
     fn listing(&self) -> Listing;
 }
 
+/// An import, borrowed from the module buffer. The names are compared as raw
+/// bytes, so that listing an import requires neither allocation nor UTF-8
+/// validation.
 #[derive(Debug, Clone)]
-pub struct ImportEntry {
-    pub mod_name: String,
-    pub field_name: String,
+pub struct ImportEntry<'a> {
+    pub mod_name: &'a [u8],
+    pub field_name: &'a [u8],
+    pub(crate) external: External,
 }
 
-impl Listed for ImportEntry {
+impl<'a> Listed for ImportEntry<'a> {
     fn listing(&self) -> Listing {
+        PwasmPolicy.listing(self.mod_name, self.field_name)
+    }
+}
+
+/// A policy which decides the [Listing] of each import. The validator is
+/// generic over the policy so that contracts for different runtimes can be
+/// checked against the host functions that runtime provides.
+///
+/// Anything not explicitly listed by a policy should be treated as
+/// blacklisted.
+pub trait ListingPolicy {
+    /// The listing of the import `mod_name.field_name`. Names are the raw
+    /// bytes from the import section.
+    fn listing(&self, mod_name: &[u8], field_name: &[u8]) -> Listing;
+
+    /// Whether this import is the `gasleft` function used as part of the
+    /// syscall. Policies with no greylisted imports have no syscall and can
+    /// leave this as the default.
+    fn is_gasleft(&self, _mod_name: &[u8], _field_name: &[u8]) -> bool {
+        false
+    }
+
+    /// Whether this import is the `sender` function used as part of the
+    /// syscall.
+    fn is_sender(&self, _mod_name: &[u8], _field_name: &[u8]) -> bool {
+        false
+    }
+
+    /// The post-MVP proposals whose instructions may be used.
+    fn proposals(&self) -> Proposals {
+        Proposals::default()
+    }
+}
+
+/// The default policy, for pwasm contracts running on the ewasm kernel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PwasmPolicy;
+
+impl ListingPolicy for PwasmPolicy {
+    fn listing(&self, mod_name: &[u8], field_name: &[u8]) -> Listing {
         // Nothing should need to be imported from outside "env", but let's
         // blacklist it just in case.
-        if self.mod_name != "env" {
+        if mod_name != b"env" {
             Listing::Black
         } else {
             // Tehcnically we don't have to list blacklisted items here, but we
             // do just for clarity.
-            match self.field_name.as_ref() {
-                "memory" => Listing::White,
-                "storage_read" => Listing::White,
-                "storage_write" => Listing::Black,
-                "ret" => Listing::White,
-                "gas" => Listing::White,
-                "input_length" => Listing::White,
-                "fetch_input" => Listing::White,
-                "panic" => Listing::White,
-                "debug" => Listing::White,
-                "ccall" => Listing::Black,
-                "dcall" => Listing::Grey,
-                "scall" => Listing::White,
-                "value" => Listing::White,
-                "create" => Listing::Black,
-                "suicide" => Listing::White,
-                "blockhash" => Listing::White,
-                "blocknumber" => Listing::White,
-                "coinbase" => Listing::White,
-                "difficulty" => Listing::White,
-                "gaslimit" => Listing::White,
-                "timestamp" => Listing::White,
-                "address" => Listing::White,
-                "sender" => Listing::White,
-                "origin" => Listing::White,
-                "elog" => Listing::Black,
-                "extcodesize" => Listing::White,
-                "extcodecopy" => Listing::White,
-                "create2" => Listing::Black,
-                "gasleft" => Listing::White,
+            match field_name {
+                b"memory" => Listing::White,
+                b"storage_read" => Listing::White,
+                b"storage_write" => Listing::Black,
+                b"ret" => Listing::White,
+                b"gas" => Listing::White,
+                b"input_length" => Listing::White,
+                b"fetch_input" => Listing::White,
+                b"panic" => Listing::White,
+                b"debug" => Listing::White,
+                b"ccall" => Listing::Black,
+                b"dcall" => Listing::Grey,
+                b"scall" => Listing::White,
+                b"value" => Listing::White,
+                b"create" => Listing::Black,
+                b"suicide" => Listing::White,
+                b"blockhash" => Listing::White,
+                b"blocknumber" => Listing::White,
+                b"coinbase" => Listing::White,
+                b"difficulty" => Listing::White,
+                b"gaslimit" => Listing::White,
+                b"timestamp" => Listing::White,
+                b"address" => Listing::White,
+                b"sender" => Listing::White,
+                b"origin" => Listing::White,
+                b"elog" => Listing::Black,
+                b"extcodesize" => Listing::White,
+                b"extcodecopy" => Listing::White,
+                b"create2" => Listing::Black,
+                b"gasleft" => Listing::White,
+                _ => Listing::Black,
+            }
+        }
+    }
+
+    fn is_gasleft(&self, mod_name: &[u8], field_name: &[u8]) -> bool {
+        mod_name == b"env" && field_name == b"gasleft"
+    }
+
+    fn is_sender(&self, mod_name: &[u8], field_name: &[u8]) -> bool {
+        mod_name == b"env" && field_name == b"sender"
+    }
+}
+
+/// A policy for Substrate/ink contracts, which import the `ext_*` functions of
+/// the contracts module. A contract on Substrate can only modify its own
+/// storage, so storage writes are permitted, but anything which acts on other
+/// accounts must go through the capability checked `cap9_*` functions
+/// provided by the runtime. There is no syscall, and therefore nothing is
+/// greylisted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubstratePolicy;
+
+impl ListingPolicy for SubstratePolicy {
+    fn listing(&self, mod_name: &[u8], field_name: &[u8]) -> Listing {
+        if mod_name != b"env" {
+            Listing::Black
+        } else {
+            match field_name {
+                b"memory" => Listing::White,
+                b"ext_get_storage" => Listing::White,
+                b"ext_set_storage" => Listing::White,
+                b"ext_scratch_size" => Listing::White,
+                b"ext_scratch_read" => Listing::White,
+                b"ext_scratch_write" => Listing::White,
+                b"ext_input_size" => Listing::White,
+                b"ext_input_copy" => Listing::White,
+                b"ext_return" => Listing::White,
+                b"ext_println" => Listing::White,
+                b"ext_address" => Listing::White,
+                b"ext_caller" => Listing::White,
+                b"ext_balance" => Listing::White,
+                b"ext_value_transferred" => Listing::White,
+                b"ext_gas_price" => Listing::White,
+                b"ext_gas_left" => Listing::White,
+                b"ext_block_number" => Listing::White,
+                b"ext_now" => Listing::White,
+                b"ext_random_seed" => Listing::White,
+                b"ext_minimum_balance" => Listing::White,
+                b"ext_call" => Listing::Black,
+                b"ext_create" => Listing::Black,
+                b"ext_instantiate" => Listing::Black,
+                b"ext_dispatch_call" => Listing::Black,
+                b"ext_deposit_event" => Listing::Black,
+                b"ext_set_rent_allowance" => Listing::Black,
+                b"ext_terminate" => Listing::Black,
+                b"cap9_clist" => Listing::White,
+                b"cap9_clist_downgrade" => Listing::White,
+                b"cap9_call_with_caps" => Listing::White,
                 _ => Listing::Black,
             }
         }
@@ -4,10 +4,9 @@ use super::parse_varuint_32;
 use super::Cursor;
 use super::ImportEntry;
 use crate::instructions;
-use crate::primitives::CountedList;
-use crate::serialization::{WASMDeserialize};
-#[cfg(not(feature = "std"))]
-use pwasm_std::String;
+use crate::primitives::{CountedList, VarUint32};
+use crate::serialization::{Error, WASMDeserialize};
+use crate::types::{FunctionType, Type};
 #[cfg(not(feature = "std"))]
 use pwasm_std::Vec;
 /// A read-only representation of a WASM module. The data is held in WASM binary
@@ -41,8 +40,11 @@ pub struct Module<'a> {
 }
 
 impl<'a> Module<'a> {
-    /// Create a new `Module` struct using the given buffer.
-    pub fn new(buffer: &'a [u8]) -> Self {
+    /// Create a new `Module` struct using the given buffer. The sections are
+    /// located and the imports and function bodies are checked to be well
+    /// formed, so that iterating over them cannot fail. The function bodies
+    /// themselves are only decoded when validating.
+    pub fn new(buffer: &'a [u8]) -> Result<Self, Error> {
         // Create a cursor, with which we will seek over the WASM code in
         // the buffer (self is the buffer, and is read-only).
         let mut cursor = Cursor {
@@ -57,14 +59,21 @@ impl<'a> Module<'a> {
         // future versions of wasm code being deployed (for which our
         // assumptions may not hold).
 
+        if buffer.len() < 8 {
+            return Err(Error::UnexpectedEof);
+        }
+
         // Take the magic number, check that it matches
         if cursor.read_ref_n(4) != &[0, 97, 115, 109] {
-            panic!("magic number not found");
+            return Err(Error::InvalidMagic);
         }
 
         // Take the version, check that it matches
-        if cursor.read_ref_n(4) != &[1, 0, 0, 0] {
-            panic!("proper version number not found");
+        let version = cursor.read_ref_n(4);
+        if version != &[1, 0, 0, 0] {
+            let mut version_bytes = [0; 4];
+            version_bytes.copy_from_slice(version);
+            return Err(Error::UnsupportedVersion(u32::from_le_bytes(version_bytes)));
         }
 
         // First we find all of the relevant section offsets.
@@ -74,7 +83,7 @@ impl<'a> Module<'a> {
         let mut code_section_offset: Option<usize> = None;
         let mut table_section_offset: Option<usize> = None;
         while cursor.current_offset < buffer.len() {
-            let section: Section = parse_section(&mut cursor);
+            let section: Section = parse_section(&mut cursor)?;
             // There are many section types we don't care about, for
             // example, Custom sections generally contain debugging symbols
             // and meaningful function names which are irrelevant to the
@@ -83,31 +92,31 @@ impl<'a> Module<'a> {
             match section.type_ {
                 SectionType::Type => {
                     if type_section_offset.is_some() {
-                        panic!("multiple type sections");
+                        return Err(Error::DuplicatedSections(1));
                     }
                     type_section_offset = Some(section.offset);
                 }
                 SectionType::Import => {
                     if import_section_offset.is_some() {
-                        panic!("multiple import sections");
+                        return Err(Error::DuplicatedSections(2));
                     }
                     import_section_offset = Some(section.offset);
                 }
                 SectionType::Function => {
                     if function_section_offset.is_some() {
-                        panic!("multiple function sections");
+                        return Err(Error::DuplicatedSections(3));
                     }
                     function_section_offset = Some(section.offset);
                 }
                 SectionType::Code => {
                     if code_section_offset.is_some() {
-                        panic!("multiple code sections");
+                        return Err(Error::DuplicatedSections(10));
                     }
                     code_section_offset = Some(section.offset);
                 }
                 SectionType::Table => {
                     if table_section_offset.is_some() {
-                        panic!("multiple code sections");
+                        return Err(Error::DuplicatedSections(4));
                     }
                     table_section_offset = Some(section.offset);
                 }
@@ -115,22 +124,79 @@ impl<'a> Module<'a> {
                 _ => (),
             }
         }
-        if cursor.current_offset != buffer.len() {
-            panic!("mismatched length");
-        }
-        Module {
+        let module = Module {
             buffer,
             type_section_offset,
             import_section_offset,
             function_section_offset,
             code_section_offset,
             table_section_offset,
+        };
+        module.check_imports()?;
+        module.check_functions()?;
+        Ok(module)
+    }
+
+    /// Check that each of the imports can be parsed.
+    fn check_imports(&self) -> Result<(), Error> {
+        if let Some(imports_offset) = self.import_section_offset {
+            let mut cursor = Cursor {
+                current_offset: imports_offset,
+                body: self.buffer,
+            };
+            let _section_size = parse_varuint_32(&mut cursor)?;
+            let n = parse_varuint_32(&mut cursor)?;
+            for _ in 0..n {
+                parse_import(&mut cursor)?;
+            }
         }
+        Ok(())
+    }
+
+    /// Check that there is a body in the code section for each function in
+    /// the function section, and that each body is within the buffer.
+    fn check_functions(&self) -> Result<(), Error> {
+        let mut n_functions = 0;
+        if let Some(functions_offset) = self.function_section_offset {
+            let mut cursor = Cursor {
+                current_offset: functions_offset,
+                body: self.buffer,
+            };
+            let _section_size = parse_varuint_32(&mut cursor)?;
+            n_functions = parse_varuint_32(&mut cursor)?;
+            for _ in 0..n_functions {
+                let _type_index = parse_varuint_32(&mut cursor)?;
+            }
+        }
+        let mut n_bodies = 0;
+        if let Some(code_offset) = self.code_section_offset {
+            let mut cursor = Cursor {
+                current_offset: code_offset,
+                body: self.buffer,
+            };
+            let _section_size = parse_varuint_32(&mut cursor)?;
+            n_bodies = parse_varuint_32(&mut cursor)?;
+            for _ in 0..n_bodies {
+                let body_size = parse_varuint_32(&mut cursor)? as usize;
+                if body_size > cursor.remaining() {
+                    return Err(Error::UnexpectedEof);
+                }
+                cursor.skip(body_size);
+            }
+        }
+        // These should be the same, if not, the WASM is invalid.
+        if n_functions != n_bodies {
+            return Err(Error::InconsistentLength {
+                expected: n_functions as usize,
+                actual: n_bodies as usize,
+            });
+        }
+        Ok(())
     }
 
     /// Return an iterator over the imports in the import section. The
     /// imports are in order.
-    pub fn imports(&self) -> Option<ImportIterator> {
+    pub fn imports(&self) -> Option<ImportIterator<'a>> {
         // TODO: generalise to SectionIter
         if let Some(imports_offset) = self.import_section_offset {
             Some(ImportIterator::new(self.buffer, imports_offset))
@@ -155,9 +221,67 @@ impl<'a> Module<'a> {
             None
         }
     }
+
+    /// Return the function signatures declared in the type section, or none
+    /// if the type section cannot be decoded.
+    pub(crate) fn function_types(&self) -> Vec<FunctionType> {
+        if let Some(types_offset) = self.type_section_offset {
+            let mut cursor = Cursor {
+                current_offset: types_offset,
+                body: self.buffer,
+            };
+            parse_varuint_32(&mut cursor)
+                .and_then(|_section_size| CountedList::<Type>::deserialize(&mut cursor))
+                .map(|types| {
+                    types
+                        .into_inner()
+                        .into_iter()
+                        .map(|Type::Function(function_type)| function_type)
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Return each imported function along with its type index, in order.
+    /// These are the first entries in the function index space, unlike
+    /// [Module::imports] which also includes tables, memories, and globals.
+    pub(crate) fn imported_functions(&self) -> Vec<(ImportEntry<'a>, u32)> {
+        let mut functions = Vec::new();
+        if let Some(imports) = self.imports() {
+            for import in imports {
+                if let import_entry::External::Function(type_index) = import.external {
+                    functions.push((import, type_index));
+                }
+            }
+        }
+        functions
+    }
+
+    /// Return the type index of each function defined in the module, in
+    /// order. These follow the imported functions in the function index space.
+    pub(crate) fn defined_function_type_indices(&self) -> Vec<u32> {
+        if let Some(functions_offset) = self.function_section_offset {
+            let mut cursor = Cursor {
+                current_offset: functions_offset,
+                body: self.buffer,
+            };
+            // The function section has been checked by Module::new.
+            parse_varuint_32(&mut cursor)
+                .and_then(|_section_size| CountedList::<VarUint32>::deserialize(&mut cursor))
+                .map(|indices| indices.into_inner().into_iter().map(u32::from).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+    }
 }
 
-/// An iterator over the imports in the import section.
+/// An iterator over the imports in the import section. The imports have been
+/// checked by [Module::new], so parsing them again cannot fail, but iteration
+/// ends rather than panics if it does.
 pub struct ImportIterator<'a> {
     section_offset: usize,
     offset_into_section: usize,
@@ -173,9 +297,9 @@ impl<'a> ImportIterator<'a> {
             body: buffer,
         };
         // How big is this section in bytes?
-        let _section_size = parse_varuint_32(&mut imports_cursor);
+        let _section_size = parse_varuint_32(&mut imports_cursor).unwrap_or(0);
         // How many imports do we have?
-        let n = parse_varuint_32(&mut imports_cursor);
+        let n = parse_varuint_32(&mut imports_cursor).unwrap_or(0);
         ImportIterator {
             section_offset,
             offset_into_section: (imports_cursor.current_offset - section_offset),
@@ -187,7 +311,7 @@ impl<'a> ImportIterator<'a> {
 }
 
 impl<'a> Iterator for ImportIterator<'a> {
-    type Item = ImportEntry;
+    type Item = ImportEntry<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.current_entry < self.n {
@@ -195,7 +319,7 @@ impl<'a> Iterator for ImportIterator<'a> {
                 current_offset: self.section_offset + self.offset_into_section,
                 body: self.buffer,
             };
-            let val = parse_import(&mut reader);
+            let val = parse_import(&mut reader).ok()?;
             self.offset_into_section = reader.current_offset - self.section_offset;
             self.current_entry += 1;
             Some(val)
@@ -205,20 +329,25 @@ impl<'a> Iterator for ImportIterator<'a> {
     }
 }
 
-// TODO: ugly function
-fn parse_import(cursor: &mut Cursor<u8>) -> ImportEntry {
-    let mut reader = Cursor {
-        current_offset: cursor.current_offset,
-        body: cursor.body,
-    };
-    let import: import_entry::ImportEntry =
-        import_entry::ImportEntry::deserialize(&mut reader).expect("counted list");
-    let val = ImportEntry {
-        mod_name: String::from(import.module()),
-        field_name: String::from(import.field()),
-    };
-    cursor.current_offset = reader.current_offset;
-    val
+/// Parse an import entry, borrowing the names from the buffer.
+fn parse_import<'a>(cursor: &mut Cursor<'a, u8>) -> Result<ImportEntry<'a>, Error> {
+    let mod_name = parse_name(cursor)?;
+    let field_name = parse_name(cursor)?;
+    let external = import_entry::External::deserialize(cursor)?;
+    Ok(ImportEntry {
+        mod_name,
+        field_name,
+        external,
+    })
+}
+
+/// Parse a length-prefixed name as raw bytes.
+fn parse_name<'a>(cursor: &mut Cursor<'a, u8>) -> Result<&'a [u8], Error> {
+    let length = parse_varuint_32(cursor)? as usize;
+    if length > cursor.remaining() {
+        return Err(Error::UnexpectedEof);
+    }
+    Ok(cursor.read_ref_n(length))
 }
 
 /// TODO: this should be made by combining function and code iterators.
@@ -245,19 +374,16 @@ impl<'a> FunctionIterator<'a> {
         };
         // Get the sizes of the two sections. These aren't important for
         // these, we just need to skip past them.
-        let _function_section_size = parse_varuint_32(&mut functions_cursor);
-        let _code_section_size = parse_varuint_32(&mut code_cursor);
-        let n_functions = parse_varuint_32(&mut functions_cursor);
-        let n_bodies = parse_varuint_32(&mut code_cursor);
-
-        // These should be the same, if not, our assumptions are invalid or
-        // the WASM is invalid. In either case we need to abort.
-        assert_eq!(n_functions, n_bodies);
+        let _function_section_size = parse_varuint_32(&mut functions_cursor).unwrap_or(0);
+        let _code_section_size = parse_varuint_32(&mut code_cursor).unwrap_or(0);
+        let n_functions = parse_varuint_32(&mut functions_cursor).unwrap_or(0);
+        // Module::new has checked that there are as many bodies as functions.
+        let _n_bodies = parse_varuint_32(&mut code_cursor).unwrap_or(0);
         FunctionIterator {
             function_section_offset,
             code_section_offset,
-            offset_into_function_section: (code_cursor.current_offset - code_section_offset),
-            offset_into_code_section: (functions_cursor.current_offset - function_section_offset),
+            offset_into_function_section: (functions_cursor.current_offset - function_section_offset),
+            offset_into_code_section: (code_cursor.current_offset - code_section_offset),
             buffer,
             n: n_functions,
             current_entry: 0,
@@ -283,10 +409,11 @@ impl<'a> Iterator for FunctionIterator<'a> {
                 code_entry_offset: code_cursor.current_offset,
                 buffer: self.buffer,
             };
-            let body_size = parse_varuint_32(&mut code_cursor);
-            let f_size = parse_varuint_32(&mut functions_cursor);
+            let body_size = parse_varuint_32(&mut code_cursor).ok()?;
+            // Each entry in the function section is just the type index.
+            let _type_index = parse_varuint_32(&mut functions_cursor).ok()?;
             self.offset_into_function_section =
-                functions_cursor.current_offset - self.function_section_offset + f_size as usize;
+                functions_cursor.current_offset - self.function_section_offset;
             self.offset_into_code_section =
                 code_cursor.current_offset - self.code_section_offset + body_size as usize;
             self.current_entry += 1;
@@ -305,22 +432,29 @@ pub struct Function<'a> {
 }
 
 impl<'a> Function<'a> {
-    pub fn code(&self) -> Code {
-        Code::new(self.buffer)
+    pub fn code(&self) -> Code<'a> {
+        Code::new(self.body())
     }
 
-    pub fn is_syscall(&self, dcall_i: u32, gasleft_i: u32, sender_i: u32) -> bool {
+    /// The function body from the code section, excluding the size.
+    pub(crate) fn body(&self) -> &'a [u8] {
         let mut code_cursor = Cursor {
             current_offset: self.code_entry_offset,
             body: self.buffer,
         };
-        let body_size = parse_varuint_32(&mut code_cursor);
-        let body = &self.buffer
-            [(code_cursor.current_offset)..(code_cursor.current_offset + body_size as usize)];
-        let mut code_iter = Code::new(body);
+        let body_size = parse_varuint_32(&mut code_cursor).unwrap_or(0) as usize;
+        let start = code_cursor.current_offset;
+        // The body has been checked to be within the buffer by Module::new.
+        self.buffer
+            .get(start..start.saturating_add(body_size))
+            .unwrap_or(&[])
+    }
+
+    pub fn is_syscall(&self, dcall_i: u32, gasleft_i: u32, sender_i: u32) -> bool {
+        let mut code_iter = self.code();
 
         // Check that no locals are used
-        if code_iter.locals.len() > 0 {
+        if code_iter.has_locals() {
             return false;
         }
 
@@ -335,7 +469,7 @@ impl<'a> Function<'a> {
         // $a, $b, and $c will be used later.
 
         //   0. call gasleft
-        if let Some(instructions::Instruction::Call(f_ind)) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::Call(f_ind))) = code_iter.next() {
             if f_ind != gasleft_i {
                 return false;
             }
@@ -343,7 +477,7 @@ impl<'a> Function<'a> {
             return false;
         }
         //   1. call sender
-        if let Some(instructions::Instruction::Call(f_ind)) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::Call(f_ind))) = code_iter.next() {
             if f_ind != sender_i {
                 return false;
             }
@@ -351,28 +485,28 @@ impl<'a> Function<'a> {
             return false;
         }
         //   2. get_local 0
-        if let Some(instructions::Instruction::GetLocal(0)) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::GetLocal(0))) = code_iter.next() {
         } else {
             return false;
         }
         //   3. get_local 1
-        if let Some(instructions::Instruction::GetLocal(1)) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::GetLocal(1))) = code_iter.next() {
         } else {
             return false;
         }
         //   4. get_local 2
-        if let Some(instructions::Instruction::GetLocal(2)) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::GetLocal(2))) = code_iter.next() {
         } else {
             return false;
         }
         //   5. get_local 3
-        if let Some(instructions::Instruction::GetLocal(3)) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::GetLocal(3))) = code_iter.next() {
         } else {
             return false;
         }
 
         //   6. call dcall
-        if let Some(instructions::Instruction::Call(f_ind)) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::Call(f_ind))) = code_iter.next() {
             if f_ind != dcall_i {
                 return false;
             }
@@ -380,7 +514,7 @@ impl<'a> Function<'a> {
             return false;
         }
         //   7. END
-        if let Some(instructions::Instruction::End) = code_iter.next() {
+        if let Some(Ok(instructions::Instruction::End)) = code_iter.next() {
         } else {
             return false;
         }
@@ -390,15 +524,13 @@ impl<'a> Function<'a> {
     }
     // TODO: we need to account for indirect calls too.
     pub fn contains_grey_call(&self, dcall_i: u32) -> bool {
-        let mut code_cursor = Cursor {
-            current_offset: self.code_entry_offset,
-            body: self.buffer,
-        };
-        let body_size = parse_varuint_32(&mut code_cursor);
-        let body = &self.buffer
-            [(code_cursor.current_offset)..(code_cursor.current_offset + body_size as usize)];
-        let code_iter = Code::new(body);
+        let code_iter = self.code();
         for instruction in code_iter {
+            // If we can't decode the function we can't rule out a grey call.
+            let instruction = match instruction {
+                Ok(instruction) => instruction,
+                Err(_) => return true,
+            };
             // We only care about Call or CallIndirect instructions
             match instruction {
                 instructions::Instruction::Call(f_ind) => {
@@ -423,46 +555,111 @@ impl<'a> Function<'a> {
     }
 }
 
-/// An iterator over the instructions of a function body.
+/// An iterator over the instructions of a function body. If an instruction
+/// cannot be decoded the error is yielded and iteration ends. The local
+/// declarations are skipped over rather than collected, they can be read
+/// with [Code::locals].
 pub struct Code<'a> {
-    pub locals: Vec<func::Local>,
+    /// The number of local declarations, each of which declares `count`
+    /// locals of a single type.
+    local_entries: u32,
+    /// The offset of the first local declaration.
+    locals_offset: usize,
+    /// An error encountered while skipping the locals, yielded in place of
+    /// the first instruction.
+    locals_error: Option<Error>,
     pub current_offset: usize,
     pub body: &'a [u8],
 }
 
 impl<'a> Code<'a> {
-    pub fn new(body: &'a [u8]) -> Code {
+    pub fn new(body: &'a [u8]) -> Code<'a> {
         let mut reader = Cursor {
             current_offset: 0,
             body: body,
         };
-        let locals: Vec<func::Local> = CountedList::<func::Local>::deserialize(&mut reader)
-            .expect("counted list")
-            .into_inner();
+        let (local_entries, locals_offset, locals_error) = match skip_locals(&mut reader) {
+            Ok((local_entries, locals_offset)) => (local_entries, locals_offset, None),
+            Err(err) => (0, 0, Some(err)),
+        };
         Code {
-            locals,
-            current_offset: reader.current_offset,
+            local_entries,
+            locals_offset,
+            current_offset: if locals_error.is_some() { body.len() } else { reader.current_offset },
+            locals_error,
             body: body,
         }
     }
+
+    /// Whether the function declares any locals (not including parameters).
+    pub fn has_locals(&self) -> bool {
+        self.local_entries > 0
+    }
+
+    /// Iterate over the local declarations of the function.
+    pub fn locals(&self) -> Locals<'a> {
+        Locals {
+            remaining: self.local_entries,
+            cursor: Cursor {
+                current_offset: self.locals_offset,
+                body: self.body,
+            },
+        }
+    }
+}
+
+/// Skip over the local declarations at the start of a function body,
+/// returning the number of declarations and the offset of the first one.
+fn skip_locals(reader: &mut Cursor<u8>) -> Result<(u32, usize), Error> {
+    let local_entries: u32 = VarUint32::deserialize(reader)?.into();
+    let locals_offset = reader.current_offset;
+    for _ in 0..local_entries {
+        func::Local::deserialize(reader)?;
+    }
+    Ok((local_entries, locals_offset))
+}
+
+/// An iterator over the local declarations of a function body.
+pub struct Locals<'a> {
+    remaining: u32,
+    cursor: Cursor<'a, u8>,
+}
+
+impl<'a> Iterator for Locals<'a> {
+    type Item = func::Local;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            // These have already been parsed once when skipping them.
+            func::Local::deserialize(&mut self.cursor).ok()
+        } else {
+            None
+        }
+    }
 }
 
 impl<'a> Iterator for Code<'a> {
-    type Item = crate::instructions::Instruction;
+    type Item = Result<crate::instructions::Instruction, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.locals_error.take() {
+            return Some(Err(err));
+        }
         if self.current_offset < self.body.len() {
             // We need to parse the code into something meaningful
             let mut reader = Cursor {
                 current_offset: self.current_offset,
                 body: self.body,
             };
-            let val = Some(
-                crate::instructions::Instruction::deserialize(&mut reader)
-                    .expect("expected valid instruction"),
-            );
-            self.current_offset = reader.current_offset;
-            val
+            let val = crate::instructions::Instruction::deserialize(&mut reader);
+            self.current_offset = if val.is_ok() {
+                reader.current_offset
+            } else {
+                // There is no way to resynchronise after a bad instruction.
+                self.body.len()
+            };
+            Some(val)
         } else {
             None
         }
@@ -483,6 +680,11 @@ enum SectionType {
     Element,
     Code,
     Data,
+    DataCount,
+    /// A section with an id we do not know, possibly from a later version of
+    /// the spec. As with the sections we do know but don't care about, it is
+    /// skipped.
+    Unknown,
 }
 
 #[derive(Debug)]
@@ -493,14 +695,17 @@ struct Section {
     offset: usize,
 }
 
-fn parse_section(cursor: &mut Cursor<u8>) -> Section {
-    let type_n = cursor.read_ref().expect("no bytes left to parse section type");
+fn parse_section(cursor: &mut Cursor<u8>) -> Result<Section, Error> {
+    let type_n = cursor.read_ref().ok_or(Error::UnexpectedEof)?;
     let offset = cursor.current_offset;
-    let size_n = parse_varuint_32(cursor);
+    let size_n = parse_varuint_32(cursor)? as usize;
+    if size_n > cursor.remaining() {
+        return Err(Error::UnexpectedEof);
+    }
     let type_ = n_to_section(type_n);
     let section = Section { type_, offset };
-    cursor.current_offset += size_n as usize;
-    section
+    cursor.current_offset += size_n;
+    Ok(section)
 }
 
 fn n_to_section(byte: &u8) -> SectionType {
@@ -517,6 +722,7 @@ fn n_to_section(byte: &u8) -> SectionType {
         9 => SectionType::Element,
         10 => SectionType::Code,
         11 => SectionType::Data,
-        _ => panic!("invalid section type"),
+        12 => SectionType::DataCount,
+        _ => SectionType::Unknown,
     }
 }
@@ -0,0 +1,879 @@
+//! # Capability Requirements
+//!
+//! Static inference of the capabilities a procedure will use, taken from its
+//! bytecode before it is deployed. Every syscall begins with the syscall type
+//! and the cap index, written as the first two bytes of the input buffer. The
+//! syscall type is the type of the cap it requires, possibly marked as a
+//! variant such as a write batch. We walk each function, constant-folding
+//! values and tracking the bytes written to memory, and when we reach a call
+//! to the syscall function we look up the first two bytes of its input
+//! buffer.
+//!
+//! This is an approximation. A byte is only reported if it was written as a
+//! constant within the same function as the call, and on every path to the
+//! call. The analysis is optimistic in that it assumes memory which has been
+//! written is not overwritten by called functions or by loops. Where a value
+//! cannot be determined it is reported as `None` rather than guessed.
+use crate::instructions::Instruction;
+use crate::modules::{Function, Module};
+use crate::types::{BlockType, FunctionType};
+use crate::{Listing, ListingPolicy};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A single call to the syscall function, and the capability it requires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CapRequirement {
+    /// The index (in the function index space) of the function which makes
+    /// the syscall.
+    pub function_index: u32,
+    /// The type of the cap the syscall requires, if it could be determined.
+    /// This is taken from the syscall type.
+    pub cap_type: Option<u8>,
+    /// The cap index of the syscall, if it could be determined.
+    pub cap_index: Option<u8>,
+}
+
+impl CapRequirement {
+    /// Whether both the cap type and cap index were determined.
+    pub fn is_known(&self) -> bool {
+        self.cap_type.is_some() && self.cap_index.is_some()
+    }
+
+    /// Test whether a procedure with the given cap list could satisfy this
+    /// requirement. `cap_types` is the type of each cap in the list, in
+    /// order. As the cap index only counts caps of the same type, index `i`
+    /// of type `t` requires at least `i + 1` caps of type `t`. Any part of the
+    /// requirement which is not known is assumed to be satisfiable.
+    pub fn is_satisfied_by(&self, cap_types: &[u8]) -> bool {
+        let n_of_type = |cap_type: u8| cap_types.iter().filter(|t| **t == cap_type).count();
+        match (self.cap_type, self.cap_index) {
+            (Some(cap_type), Some(cap_index)) => n_of_type(cap_type) > cap_index as usize,
+            (Some(cap_type), None) => n_of_type(cap_type) > 0,
+            (None, Some(cap_index)) => cap_types
+                .iter()
+                .any(|cap_type| n_of_type(*cap_type) > cap_index as usize),
+            (None, None) => true,
+        }
+    }
+}
+
+impl fmt::Display for CapRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "function {}: cap type ", self.function_index)?;
+        match self.cap_type {
+            Some(cap_type) => write!(f, "{}", cap_type)?,
+            None => write!(f, "unknown")?,
+        }
+        write!(f, ", cap index ")?;
+        match self.cap_index {
+            Some(cap_index) => write!(f, "{}", cap_index),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Infer the capabilities required by each call to the syscall function in
+/// `module`. The syscall function is found in the same way as when checking
+/// validity, using `policy` to identify the imports it is built from. If the
+/// module contains no syscall function the result is empty.
+pub fn cap_requirements<P: ListingPolicy>(module: &Module, policy: &P) -> Vec<CapRequirement> {
+    let imported_functions = module.imported_functions();
+    let mut dcall_index: Option<u32> = None;
+    let mut gasleft_index: Option<u32> = None;
+    let mut sender_index: Option<u32> = None;
+    for (index, (import, _)) in imported_functions.iter().enumerate() {
+        if policy.is_gasleft(import.mod_name, import.field_name) {
+            gasleft_index = Some(index as u32);
+        }
+        if policy.is_sender(import.mod_name, import.field_name) {
+            sender_index = Some(index as u32);
+        }
+        if let Listing::Grey = policy.listing(import.mod_name, import.field_name) {
+            dcall_index = Some(index as u32);
+        }
+    }
+    let (dcall_i, gasleft_i, sender_i) = match (dcall_index, gasleft_index, sender_index) {
+        (Some(dcall_i), Some(gasleft_i), Some(sender_i)) => (dcall_i, gasleft_i, sender_i),
+        _ => return Vec::new(),
+    };
+
+    let types = module.function_types();
+    let n_imported = imported_functions.len() as u32;
+    let mut function_types: Vec<u32> = imported_functions.iter().map(|(_, t)| *t).collect();
+    function_types.extend(module.defined_function_type_indices());
+
+    let functions: Vec<Function> = match module.functions() {
+        Some(functions) => functions.collect(),
+        None => return Vec::new(),
+    };
+    let syscalls: HashSet<u32> = functions
+        .iter()
+        .enumerate()
+        .filter(|(_, func)| func.is_syscall(dcall_i, gasleft_i, sender_i))
+        .map(|(i, _)| n_imported + i as u32)
+        .collect();
+    if syscalls.is_empty() {
+        return Vec::new();
+    }
+
+    let mut requirements = Vec::new();
+    for (i, func) in functions.iter().enumerate() {
+        let function_index = n_imported + i as u32;
+        if syscalls.contains(&function_index) {
+            continue;
+        }
+        let mut analysis = Analysis::new(&types, &function_types, &syscalls, function_index);
+        analysis.run(func);
+        requirements.extend(analysis.requirements);
+    }
+    requirements
+}
+
+/// The abstract value of an item on the stack, in a local, or in a global.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Value {
+    /// A value known statically.
+    Const(i64),
+    /// An unknown value plus a known offset. Every unknown value is given its
+    /// own symbol, so that memory accessed through the same pointer can still
+    /// be matched up.
+    Sym(u32, i64),
+}
+
+/// A memory location, relative to either an unknown base (a symbol) or to
+/// zero.
+type Location = (Option<u32>, i64);
+
+/// The part of the abstract state which must be merged where control flow
+/// meets.
+#[derive(Clone, Debug)]
+struct State {
+    locals: Vec<Value>,
+    /// The individual bytes of memory which are known.
+    memory: HashMap<Location, u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FrameKind {
+    Block,
+    Loop,
+    If,
+}
+
+#[derive(Debug)]
+struct Frame {
+    kind: FrameKind,
+    /// The height of the value stack when the frame was entered.
+    height: usize,
+    has_result: bool,
+    /// Set after an unconditional branch, until the end of the frame.
+    unreachable: bool,
+    /// Frames entered from unreachable code are never analysed.
+    dead: bool,
+    /// For an `if`, the state before either branch was taken.
+    entry: Option<State>,
+    /// For an `if` with an `else`, the state at the end of the first branch
+    /// (`None` if the end of that branch was unreachable).
+    then_state: Option<Option<State>>,
+    /// The states carried to the end of the frame by branches to it. A branch
+    /// to a loop goes to its start instead, which `loop_writes` accounts for.
+    branches: Vec<State>,
+}
+
+struct Analysis<'m> {
+    types: &'m [FunctionType],
+    function_types: &'m [u32],
+    syscalls: &'m HashSet<u32>,
+    function_index: u32,
+    next_symbol: u32,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    state: State,
+    globals: HashMap<u32, Value>,
+    /// The locals written to within each loop, keyed by the position of the
+    /// loop instruction.
+    loop_writes: HashMap<usize, HashSet<u32>>,
+    /// Set if we encounter an instruction whose stack effect we don't model.
+    /// From then on any syscall is reported as unknown.
+    lost: bool,
+    requirements: Vec<CapRequirement>,
+}
+
+impl<'m> Analysis<'m> {
+    fn new(
+        types: &'m [FunctionType],
+        function_types: &'m [u32],
+        syscalls: &'m HashSet<u32>,
+        function_index: u32,
+    ) -> Self {
+        Analysis {
+            types,
+            function_types,
+            syscalls,
+            function_index,
+            next_symbol: 0,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            state: State {
+                locals: Vec::new(),
+                memory: HashMap::new(),
+            },
+            globals: HashMap::new(),
+            loop_writes: HashMap::new(),
+            lost: false,
+            requirements: Vec::new(),
+        }
+    }
+
+    fn run(&mut self, func: &Function) {
+        let code = func.code();
+        // Parameters are unknown, declared locals are initialised to zero.
+        let n_params = self
+            .function_type(self.function_index)
+            .map(|t| t.params().len())
+            .unwrap_or(0);
+        for _ in 0..n_params {
+            let value = self.fresh();
+            self.state.locals.push(value);
+        }
+        for local in code.locals() {
+            for _ in 0..local.count() {
+                self.state.locals.push(Value::Const(0));
+            }
+        }
+        let mut instructions: Vec<Instruction> = Vec::new();
+        for instruction in code {
+            match instruction {
+                Ok(instruction) => instructions.push(instruction),
+                // The rest of the body can't be decoded, so any syscalls we
+                // would have found are unknown.
+                Err(_) => {
+                    self.lost = true;
+                    break;
+                }
+            }
+        }
+        self.loop_writes = loop_writes(&instructions);
+        // The function body itself behaves as a block.
+        self.frames.push(Frame {
+            kind: FrameKind::Block,
+            height: 0,
+            has_result: false,
+            unreachable: false,
+            dead: false,
+            entry: None,
+            then_state: None,
+            branches: Vec::new(),
+        });
+        for (position, instruction) in instructions.into_iter().enumerate() {
+            if self.lost {
+                if let Instruction::Call(f_ind) = instruction {
+                    if self.syscalls.contains(&f_ind) {
+                        self.record(None);
+                    }
+                }
+            } else {
+                self.step(position, instruction);
+            }
+        }
+    }
+
+    fn fresh(&mut self) -> Value {
+        let symbol = self.next_symbol;
+        self.next_symbol += 1;
+        Value::Sym(symbol, 0)
+    }
+
+    fn pop(&mut self) -> Value {
+        match self.stack.pop() {
+            Some(value) => value,
+            // Only possible if the code is invalid.
+            None => self.fresh(),
+        }
+    }
+
+    fn push_fresh(&mut self) {
+        let value = self.fresh();
+        self.stack.push(value);
+    }
+
+    fn function_type(&self, function_index: u32) -> Option<&FunctionType> {
+        self.function_types
+            .get(function_index as usize)
+            .and_then(|t| self.types.get(*t as usize))
+    }
+
+    fn record(&mut self, input_ptr: Option<Value>) {
+        let (cap_type, cap_index) = match input_ptr {
+            Some(ptr) => (
+                self.state
+                    .memory
+                    .get(&location(ptr, 0))
+                    .map(|syscall_type| cap9_core::syscall_cap_type(*syscall_type)),
+                self.state.memory.get(&location(ptr, 1)).cloned(),
+            ),
+            None => (None, None),
+        };
+        self.requirements.push(CapRequirement {
+            function_index: self.function_index,
+            cap_type,
+            cap_index,
+        });
+    }
+
+    fn call(&mut self, function_type: Option<FunctionType>, syscall: bool) {
+        let function_type = match function_type {
+            Some(function_type) => function_type,
+            None => {
+                self.lost = true;
+                return;
+            }
+        };
+        let mut args: Vec<Value> = Vec::new();
+        for _ in 0..function_type.params().len() {
+            args.push(self.pop());
+        }
+        args.reverse();
+        if syscall {
+            let input_ptr = args.get(0).cloned();
+            self.record(input_ptr);
+        }
+        if function_type.return_type().is_some() {
+            self.push_fresh();
+        }
+    }
+
+    fn load(&mut self, offset: u32, n: usize) {
+        let address = self.pop();
+        let mut bytes = Vec::new();
+        for i in 0..n {
+            match self.state.memory.get(&location(address, offset as i64 + i as i64)) {
+                Some(byte) => bytes.push(*byte),
+                None => {
+                    self.push_fresh();
+                    return;
+                }
+            }
+        }
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0_u64, |acc, byte| (acc << 8) | *byte as u64);
+        self.stack.push(Value::Const(value as i64));
+    }
+
+    fn store(&mut self, offset: u32, n: usize) {
+        let value = self.pop();
+        let address = self.pop();
+        for i in 0..n {
+            let loc = location(address, offset as i64 + i as i64);
+            match value {
+                Value::Const(c) => {
+                    self.state.memory.insert(loc, (c >> (8 * i)) as u8);
+                }
+                Value::Sym(_, _) => {
+                    self.state.memory.remove(&loc);
+                }
+            }
+        }
+    }
+
+    /// Merge the states of two paths where they meet. Anything that is not
+    /// the same in both is forgotten. A path which is never taken is `None`.
+    fn merge(&mut self, a: Option<State>, b: Option<State>) -> Option<State> {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                let mut locals = Vec::new();
+                for (x, y) in a.locals.iter().zip(b.locals.iter()) {
+                    if x == y {
+                        locals.push(*x);
+                    } else {
+                        locals.push(self.fresh());
+                    }
+                }
+                let memory = a
+                    .memory
+                    .into_iter()
+                    .filter(|(loc, byte)| b.memory.get(loc) == Some(byte))
+                    .collect();
+                Some(State { locals, memory })
+            }
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+
+    /// Carry the current state to the end of the frame `depth` frames out
+    /// from the current one.
+    fn branch(&mut self, depth: u32) {
+        let state = self.state.clone();
+        let target = (depth as usize)
+            .checked_add(1)
+            .and_then(|n| self.frames.len().checked_sub(n));
+        if let Some(frame) = target.and_then(|i| self.frames.get_mut(i)) {
+            if frame.kind != FrameKind::Loop {
+                frame.branches.push(state);
+            }
+        }
+    }
+
+    fn step(&mut self, position: usize, instruction: Instruction) {
+        let unreachable = self.frames.last().map(|f| f.unreachable).unwrap_or(true);
+        match instruction {
+            Instruction::Block(block_type) => self.enter(FrameKind::Block, block_type, unreachable),
+            Instruction::Loop(block_type) => {
+                if !unreachable {
+                    // The loop may be re-entered with any of the locals it
+                    // writes to changed.
+                    if let Some(written) = self.loop_writes.get(&position).cloned() {
+                        for local in written {
+                            let value = self.fresh();
+                            if let Some(slot) = self.state.locals.get_mut(local as usize) {
+                                *slot = value;
+                            }
+                        }
+                    }
+                }
+                self.enter(FrameKind::Loop, block_type, unreachable)
+            }
+            Instruction::If(block_type) => {
+                if !unreachable {
+                    self.pop();
+                }
+                self.enter(FrameKind::If, block_type, unreachable)
+            }
+            Instruction::Else => {
+                let state = self.state.clone();
+                if let Some(frame) = self.frames.last_mut() {
+                    if frame.dead {
+                        return;
+                    }
+                    frame.then_state = Some(if frame.unreachable { None } else { Some(state) });
+                    frame.unreachable = false;
+                    let height = frame.height;
+                    if let Some(entry) = frame.entry.clone() {
+                        self.state = entry;
+                    }
+                    self.stack.truncate(height);
+                }
+            }
+            Instruction::End => {
+                let frame = match self.frames.pop() {
+                    Some(frame) => frame,
+                    None => return,
+                };
+                if frame.dead {
+                    return;
+                }
+                let mut state = if frame.unreachable {
+                    None
+                } else {
+                    Some(self.state.clone())
+                };
+                if frame.kind == FrameKind::If {
+                    state = match frame.then_state {
+                        Some(then_state) => self.merge(then_state, state),
+                        None => self.merge(frame.entry, state),
+                    };
+                }
+                // Every branch to the frame meets the fall through at its end.
+                for branch in frame.branches {
+                    state = self.merge(state, Some(branch));
+                }
+                if let Some(state) = state {
+                    self.state = state;
+                }
+                self.stack.truncate(frame.height);
+                if frame.has_result {
+                    self.push_fresh();
+                }
+            }
+            _ if unreachable => (),
+            Instruction::Unreachable
+            | Instruction::Return
+            | Instruction::Br(_)
+            | Instruction::BrTable(_) => {
+                match &instruction {
+                    Instruction::Br(depth) => self.branch(*depth),
+                    Instruction::BrTable(data) => {
+                        self.pop();
+                        for depth in data.table.iter().chain(Some(&data.default)) {
+                            self.branch(*depth);
+                        }
+                    }
+                    _ => (),
+                }
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.unreachable = true;
+                    let height = frame.height;
+                    self.stack.truncate(height);
+                }
+            }
+            Instruction::BrIf(depth) => {
+                self.pop();
+                self.branch(depth);
+            }
+            Instruction::Nop => (),
+            Instruction::Call(f_ind) => {
+                let function_type = self.function_type(f_ind).cloned();
+                self.call(function_type, self.syscalls.contains(&f_ind));
+            }
+            Instruction::CallIndirect(type_index, _) => {
+                self.pop();
+                let function_type = self.types.get(type_index as usize).cloned();
+                // Indirect calls to the syscall are not permitted by the
+                // validator, so we need not consider them.
+                self.call(function_type, false);
+            }
+            Instruction::Drop => {
+                self.pop();
+            }
+            Instruction::Select => {
+                let condition = self.pop();
+                let b = self.pop();
+                let a = self.pop();
+                let value = match condition {
+                    Value::Const(0) => b,
+                    Value::Const(_) => a,
+                    _ if a == b => a,
+                    _ => self.fresh(),
+                };
+                self.stack.push(value);
+            }
+            Instruction::GetLocal(i) => {
+                let value = match self.state.locals.get(i as usize) {
+                    Some(value) => *value,
+                    None => self.fresh(),
+                };
+                self.stack.push(value);
+            }
+            Instruction::SetLocal(i) => {
+                let value = self.pop();
+                if let Some(slot) = self.state.locals.get_mut(i as usize) {
+                    *slot = value;
+                }
+            }
+            Instruction::TeeLocal(i) => {
+                let value = self.pop();
+                if let Some(slot) = self.state.locals.get_mut(i as usize) {
+                    *slot = value;
+                }
+                self.stack.push(value);
+            }
+            Instruction::GetGlobal(i) => {
+                let value = match self.globals.get(&i) {
+                    Some(value) => *value,
+                    None => {
+                        let value = self.fresh();
+                        self.globals.insert(i, value);
+                        value
+                    }
+                };
+                self.stack.push(value);
+            }
+            Instruction::SetGlobal(i) => {
+                let value = self.pop();
+                self.globals.insert(i, value);
+            }
+            Instruction::I32Load(_, offset) => self.load(offset, 4),
+            Instruction::I64Load(_, offset) => self.load(offset, 8),
+            Instruction::I32Load8U(_, offset) => self.load(offset, 1),
+            Instruction::I32Load16U(_, offset) => self.load(offset, 2),
+            Instruction::I64Load8U(_, offset) => self.load(offset, 1),
+            Instruction::I64Load16U(_, offset) => self.load(offset, 2),
+            Instruction::I64Load32U(_, offset) => self.load(offset, 4),
+            Instruction::F32Load(_, _)
+            | Instruction::F64Load(_, _)
+            | Instruction::I32Load8S(_, _)
+            | Instruction::I32Load16S(_, _)
+            | Instruction::I64Load8S(_, _)
+            | Instruction::I64Load16S(_, _)
+            | Instruction::I64Load32S(_, _) => {
+                self.pop();
+                self.push_fresh();
+            }
+            Instruction::I32Store(_, offset) => self.store(offset, 4),
+            Instruction::I64Store(_, offset) => self.store(offset, 8),
+            Instruction::F32Store(_, offset) => self.store(offset, 4),
+            Instruction::F64Store(_, offset) => self.store(offset, 8),
+            Instruction::I32Store8(_, offset) => self.store(offset, 1),
+            Instruction::I32Store16(_, offset) => self.store(offset, 2),
+            Instruction::I64Store8(_, offset) => self.store(offset, 1),
+            Instruction::I64Store16(_, offset) => self.store(offset, 2),
+            Instruction::I64Store32(_, offset) => self.store(offset, 4),
+            Instruction::CurrentMemory(_) => self.push_fresh(),
+            Instruction::GrowMemory(_) => {
+                self.pop();
+                self.push_fresh();
+            }
+            Instruction::I32Const(c) => self.stack.push(Value::Const(c as i64)),
+            Instruction::I64Const(c) => self.stack.push(Value::Const(c)),
+            Instruction::F32Const(_) | Instruction::F64Const(_) => self.push_fresh(),
+            Instruction::I32Add => self.binary(|a, b| (a as i32).wrapping_add(b as i32) as i64, Offset::Add),
+            Instruction::I32Sub => self.binary(|a, b| (a as i32).wrapping_sub(b as i32) as i64, Offset::Sub),
+            Instruction::I64Add => self.binary(|a, b| a.wrapping_add(b), Offset::Add),
+            Instruction::I64Sub => self.binary(|a, b| a.wrapping_sub(b), Offset::Sub),
+            Instruction::I32Mul => self.binary(|a, b| (a as i32).wrapping_mul(b as i32) as i64, Offset::None),
+            Instruction::I32And => self.binary(|a, b| (a as i32 & b as i32) as i64, Offset::None),
+            Instruction::I32Or => self.binary(|a, b| (a as i32 | b as i32) as i64, Offset::None),
+            Instruction::I32Xor => self.binary(|a, b| (a as i32 ^ b as i32) as i64, Offset::None),
+            Instruction::I32Shl => self.binary(|a, b| (a as i32).wrapping_shl(b as u32) as i64, Offset::None),
+            Instruction::I32ShrU => self.binary(|a, b| (a as u32).wrapping_shr(b as u32) as i32 as i64, Offset::None),
+            Instruction::I64Mul => self.binary(|a, b| a.wrapping_mul(b), Offset::None),
+            Instruction::I64And => self.binary(|a, b| a & b, Offset::None),
+            Instruction::I64Or => self.binary(|a, b| a | b, Offset::None),
+            Instruction::I64Xor => self.binary(|a, b| a ^ b, Offset::None),
+            Instruction::I64Shl => self.binary(|a, b| a.wrapping_shl(b as u32), Offset::None),
+            Instruction::I64ShrU => self.binary(|a, b| (a as u64).wrapping_shr(b as u32) as i64, Offset::None),
+            // Conversions between integer widths keep the identity of
+            // pointers.
+            Instruction::I32WrapI64 => self.unary(|a| a as i32 as i64, true),
+            Instruction::I64ExtendUI32 => self.unary(|a| a as u32 as i64, true),
+            Instruction::I64ExtendSI32 => self.unary(|a| a as i32 as i64, true),
+            other => match arity(&other) {
+                Some((pops, pushes)) => {
+                    for _ in 0..pops {
+                        self.pop();
+                    }
+                    for _ in 0..pushes {
+                        self.push_fresh();
+                    }
+                }
+                None => self.lost = true,
+            },
+        }
+    }
+
+    fn enter(&mut self, kind: FrameKind, block_type: BlockType, unreachable: bool) {
+        let entry = if kind == FrameKind::If && !unreachable {
+            Some(self.state.clone())
+        } else {
+            None
+        };
+        self.frames.push(Frame {
+            kind,
+            height: self.stack.len(),
+            has_result: block_type != BlockType::NoResult,
+            unreachable,
+            dead: unreachable,
+            entry,
+            then_state: None,
+            branches: Vec::new(),
+        });
+    }
+
+    fn unary<F: Fn(i64) -> i64>(&mut self, f: F, keep_symbol: bool) {
+        let value = match self.pop() {
+            Value::Const(a) => Value::Const(f(a)),
+            sym @ Value::Sym(_, _) if keep_symbol => sym,
+            Value::Sym(_, _) => self.fresh(),
+        };
+        self.stack.push(value);
+    }
+
+    fn binary<F: Fn(i64, i64) -> i64>(&mut self, f: F, offset: Offset) {
+        let b = self.pop();
+        let a = self.pop();
+        let value = match (a, b, offset) {
+            (Value::Const(a), Value::Const(b), _) => Value::Const(f(a, b)),
+            (Value::Sym(s, k), Value::Const(c), Offset::Add)
+            | (Value::Const(c), Value::Sym(s, k), Offset::Add) => Value::Sym(s, k + c),
+            (Value::Sym(s, k), Value::Const(c), Offset::Sub) => Value::Sym(s, k - c),
+            _ => self.fresh(),
+        };
+        self.stack.push(value);
+    }
+}
+
+/// How an operation moves a symbolic pointer.
+#[derive(Clone, Copy)]
+enum Offset {
+    Add,
+    Sub,
+    None,
+}
+
+/// The memory location of `offset` bytes past `address`.
+fn location(address: Value, offset: i64) -> Location {
+    match address {
+        Value::Const(c) => (None, c as u32 as i64 + offset),
+        Value::Sym(s, k) => (Some(s), k + offset),
+    }
+}
+
+/// Find the locals written to within each loop, so that they can be
+/// forgotten on entry to the loop.
+fn loop_writes(instructions: &[Instruction]) -> HashMap<usize, HashSet<u32>> {
+    let mut writes = HashMap::new();
+    // Each open block, with its position if it is a loop.
+    let mut open: Vec<(Option<usize>, HashSet<u32>)> = Vec::new();
+    for (position, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Loop(_) => open.push((Some(position), HashSet::new())),
+            Instruction::Block(_) | Instruction::If(_) => open.push((None, HashSet::new())),
+            Instruction::SetLocal(i) | Instruction::TeeLocal(i) => {
+                if let Some((_, locals)) = open.last_mut() {
+                    locals.insert(*i);
+                }
+            }
+            Instruction::End => {
+                if let Some((loop_position, locals)) = open.pop() {
+                    if let Some((_, parent)) = open.last_mut() {
+                        parent.extend(locals.iter());
+                    }
+                    if let Some(loop_position) = loop_position {
+                        writes.insert(loop_position, locals);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    writes
+}
+
+/// The number of values popped and pushed by the simple numeric instructions.
+/// Returns `None` for instructions we don't model, such as bulk memory.
+fn arity(instruction: &Instruction) -> Option<(usize, usize)> {
+    use crate::instructions::Instruction::*;
+    match instruction {
+        I32Eqz | I64Eqz | I32Clz | I32Ctz | I32Popcnt | I64Clz | I64Ctz | I64Popcnt | F32Abs
+        | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt | F64Abs | F64Neg
+        | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt | I32TruncSF32 | I32TruncUF32
+        | I32TruncSF64 | I32TruncUF64 | I64TruncSF32 | I64TruncUF32 | I64TruncSF64
+        | I64TruncUF64 | F32ConvertSI32 | F32ConvertUI32 | F32ConvertSI64 | F32ConvertUI64
+        | F32DemoteF64 | F64ConvertSI32 | F64ConvertUI32 | F64ConvertSI64 | F64ConvertUI64
+        | F64PromoteF32 | I32ReinterpretF32 | I64ReinterpretF64 | F32ReinterpretI32
+        | F64ReinterpretI64 | I32Extend8S | I32Extend16S | I64Extend8S | I64Extend16S
+        | I64Extend32S | I32TruncSatSF32 | I32TruncSatUF32 | I32TruncSatSF64
+        | I32TruncSatUF64 | I64TruncSatSF32 | I64TruncSatUF32 | I64TruncSatSF64
+        | I64TruncSatUF64 => Some((1, 1)),
+        I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS | I32GeU
+        | I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS
+        | I64GeU | F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge | F64Eq | F64Ne | F64Lt
+        | F64Gt | F64Le | F64Ge | I32DivS | I32DivU | I32RemS | I32RemU | I32ShrS | I32Rotl
+        | I32Rotr | I64DivS | I64DivU | I64RemS | I64RemU | I64ShrS | I64Rotl | I64Rotr
+        | F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign | F64Add
+        | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => Some((2, 1)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PwasmPolicy;
+    use std::fs::File;
+    use std::io::Read;
+    use wabt::wat2wasm;
+
+    fn requirements_of(path: &str) -> Vec<CapRequirement> {
+        let mut f = File::open(path).expect("could not open file");
+        let mut wat = Vec::new();
+        f.read_to_end(&mut wat).unwrap();
+        let wasm = wat2wasm(wat).unwrap();
+        cap_requirements(&Module::new(wasm.as_slice()).unwrap(), &PwasmPolicy)
+    }
+
+    #[test]
+    fn no_syscalls() {
+        let requirements = requirements_of("test_files/with_syscall_compliant.wat");
+        assert_eq!(requirements, vec![]);
+    }
+
+    #[test]
+    fn constant_requirements() {
+        let requirements = requirements_of("test_files/with_syscall_requirements.wat");
+        assert_eq!(
+            requirements,
+            vec![
+                // $write: a write using the second store write cap.
+                CapRequirement {
+                    function_index: 6,
+                    cap_type: Some(7),
+                    cap_index: Some(1),
+                },
+                // $log_or_call: a log in one branch, a call in the other.
+                CapRequirement {
+                    function_index: 7,
+                    cap_type: Some(8),
+                    cap_index: Some(0),
+                },
+                CapRequirement {
+                    function_index: 7,
+                    cap_type: Some(3),
+                    cap_index: Some(2),
+                },
+                // $write_with: the cap index is a parameter.
+                CapRequirement {
+                    function_index: 8,
+                    cap_type: Some(7),
+                    cap_index: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn branch_requirements() {
+        let requirements = requirements_of("test_files/with_syscall_branch_requirements.wat");
+        assert_eq!(
+            requirements,
+            vec![
+                // $br_if_differs: the cap type is 7 on one path and 8 on the
+                // other.
+                CapRequirement {
+                    function_index: 6,
+                    cap_type: None,
+                    cap_index: Some(0),
+                },
+                // $br_if_same: the cap type is 7 on both paths, but the cap
+                // index is only written on one.
+                CapRequirement {
+                    function_index: 7,
+                    cap_type: Some(7),
+                    cap_index: None,
+                },
+                // $br_table: the cap index is 1 or 2 depending on the target.
+                CapRequirement {
+                    function_index: 8,
+                    cap_type: Some(3),
+                    cap_index: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn write_batch_requirement() {
+        let requirements = requirements_of("test_files/with_syscall_write_batch.wat");
+        // The write batch requires the second store write cap, like a single
+        // write would.
+        let requirement = CapRequirement {
+            function_index: 6,
+            cap_type: Some(7),
+            cap_index: Some(1),
+        };
+        assert_eq!(requirements, vec![requirement]);
+        assert!(requirement.is_satisfied_by(&[7, 7]));
+        assert!(!requirement.is_satisfied_by(&[7]));
+    }
+
+    #[test]
+    fn satisfied_by_cap_list() {
+        let requirement = CapRequirement {
+            function_index: 0,
+            cap_type: Some(7),
+            cap_index: Some(1),
+        };
+        assert!(!requirement.is_satisfied_by(&[7, 3]));
+        assert!(requirement.is_satisfied_by(&[7, 3, 7]));
+        let unknown_index = CapRequirement {
+            cap_index: None,
+            ..requirement
+        };
+        assert!(unknown_index.is_satisfied_by(&[7]));
+        assert!(!unknown_index.is_satisfied_by(&[3]));
+    }
+}
@@ -24,7 +24,7 @@ pub trait WASMSerialize {
 
 
 /// Deserialization/serialization error
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
     /// Unexpected end of input.
     UnexpectedEof,
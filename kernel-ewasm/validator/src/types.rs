@@ -48,8 +48,7 @@ impl WASMDeserialize for ValueType {
             -0x03 => Ok(ValueType::F32),
             -0x04 => Ok(ValueType::F64),
             -0x05 => Ok(ValueType::V128),
-            // _ => Err(Error::UnknownValueType(val.into())),
-            _ => panic!("unknown value type")
+            _ => Err(Error::UnknownValueType(val.into())),
         }
     }
 }
@@ -76,8 +75,7 @@ impl WASMDeserialize for BlockType {
             -0x04 => Ok(BlockType::Value(ValueType::F64)),
             0x7b => Ok(BlockType::Value(ValueType::V128)),
             -0x40 => Ok(BlockType::NoResult),
-            // _ => Err(Error::UnknownValueType(val.into())),
-            _ => panic!("unknown value type")
+            _ => Err(Error::UnknownValueType(val.into())),
         }
     }
 }
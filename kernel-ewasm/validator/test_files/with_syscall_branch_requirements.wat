@@ -0,0 +1,101 @@
+;;; A compliant contract with syscalls whose buffers are written differently
+;;; on different paths, used to test the inference of capability
+;;; requirements.
+(module
+  (type $t0 (func (param i32 i32)))
+  (type $t3 (func (param i32 i32 i32 i32) (result i32)))
+  (type $t4 (func (param i64 i32 i32 i32 i32 i32) (result i32)))
+  (type $t6 (func (result i64)))
+  (type $t7 (func (result i32)))
+  (type $t9 (func))
+  (type $t10 (func (param i32)))
+  (import "env" "memory" (memory $M0 2))
+  (import "env" "dcall" (func $env.dcall (type $t4)))
+  (import "env" "gasleft" (func $env.gasleft (type $t6)))
+  (import "env" "sender" (func $env.sender (type $t7)))
+  (import "env" "ret" (func $env.ret (type $t0)))
+  ;; This is our system call which we have statically linked in
+  (func $syscall (type $t3) (param $p0 i32) (param $p1 i32) (param $p2 i32) (param $p3 i32) (result i32)
+    call $env.gasleft
+    call $env.sender
+    get_local $p0
+    get_local $p1
+    get_local $p2
+    get_local $p3
+    call $env.dcall)
+  ;; This is the entry point of the contract
+  (func $call (type $t9)
+    i32.const 0
+    i32.const 0
+    call $env.ret)
+  ;; The cap type is only overwritten if the branch out of the block is not
+  ;; taken, so it is not known.
+  (func $br_if_differs (type $t10) (param $which i32) (local $buf i32)
+    i32.const 1024
+    set_local $buf
+    block
+      get_local $buf
+      i32.const 7
+      i32.store8
+      get_local $which
+      br_if 0
+      get_local $buf
+      i32.const 8
+      i32.store8
+    end
+    get_local $buf
+    i32.const 0
+    i32.store8 offset=1
+    get_local $buf
+    i32.const 2
+    i32.const 0
+    i32.const 0
+    call $syscall
+    drop)
+  ;; The cap type is written before the branch, so it is the same on both
+  ;; paths.
+  (func $br_if_same (type $t10) (param $which i32) (local $buf i32)
+    i32.const 2048
+    set_local $buf
+    block
+      get_local $buf
+      i32.const 7
+      i32.store8
+      get_local $which
+      br_if 0
+      get_local $buf
+      i32.const 1
+      i32.store8 offset=1
+    end
+    get_local $buf
+    i32.const 2
+    i32.const 0
+    i32.const 0
+    call $syscall
+    drop)
+  ;; One of the targets of the table leaves the cap index unwritten.
+  (func $br_table (type $t10) (param $which i32) (local $buf i32)
+    i32.const 3072
+    set_local $buf
+    get_local $buf
+    i32.const 3
+    i32.store8
+    block
+      block
+        get_local $buf
+        i32.const 1
+        i32.store8 offset=1
+        get_local $which
+        br_table 0 1
+      end
+      get_local $buf
+      i32.const 2
+      i32.store8 offset=1
+    end
+    get_local $buf
+    i32.const 2
+    i32.const 0
+    i32.const 0
+    call $syscall
+    drop)
+  (export "call" (func $call)))
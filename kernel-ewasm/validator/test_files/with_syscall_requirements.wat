@@ -0,0 +1,92 @@
+;;; A compliant contract with a number of syscalls, used to test the inference
+;;; of capability requirements.
+(module
+  (type $t0 (func (param i32 i32)))
+  (type $t3 (func (param i32 i32 i32 i32) (result i32)))
+  (type $t4 (func (param i64 i32 i32 i32 i32 i32) (result i32)))
+  (type $t6 (func (result i64)))
+  (type $t7 (func (result i32)))
+  (type $t9 (func))
+  (type $t10 (func (param i32)))
+  (import "env" "memory" (memory $M0 2))
+  (import "env" "dcall" (func $env.dcall (type $t4)))
+  (import "env" "gasleft" (func $env.gasleft (type $t6)))
+  (import "env" "sender" (func $env.sender (type $t7)))
+  (import "env" "ret" (func $env.ret (type $t0)))
+  ;; This is our system call which we have statically linked in
+  (func $syscall (type $t3) (param $p0 i32) (param $p1 i32) (param $p2 i32) (param $p3 i32) (result i32)
+    call $env.gasleft
+    call $env.sender
+    get_local $p0
+    get_local $p1
+    get_local $p2
+    get_local $p3
+    call $env.dcall)
+  ;; This is the entry point of the contract
+  (func $call (type $t9)
+    i32.const 0
+    i32.const 0
+    call $env.ret)
+  ;; Write using store write cap 1, with the buffer on the stack
+  (func $write (type $t9) (local $sp i32)
+    get_global $g0
+    i32.const 80
+    i32.sub
+    tee_local $sp
+    set_global $g0
+    get_local $sp
+    i32.const 7
+    i32.store8
+    get_local $sp
+    i32.const 1
+    i32.store8 offset=1
+    get_local $sp
+    i32.const 66
+    get_local $sp
+    i32.const 0
+    call $syscall
+    drop)
+  ;; Either log using log cap 0, or call using procedure call cap 2
+  (func $log_or_call (type $t10) (param $which i32) (local $buf i32)
+    i32.const 1024
+    set_local $buf
+    get_local $which
+    if
+      get_local $buf
+      i32.const 0x0008
+      i32.store16
+      get_local $buf
+      i32.const 2
+      i32.const 0
+      i32.const 0
+      call $syscall
+      drop
+    else
+      get_local $buf
+      i32.const 0x0203
+      i32.store16
+      get_local $buf
+      i32.const 2
+      i32.const 0
+      i32.const 0
+      call $syscall
+      drop
+    end)
+  ;; Write using a cap index passed as a parameter
+  (func $write_with (type $t10) (param $cap_index i32) (local $buf i32)
+    i32.const 2048
+    set_local $buf
+    get_local $buf
+    i32.const 7
+    i32.store8
+    get_local $buf
+    get_local $cap_index
+    i32.store8 offset=1
+    get_local $buf
+    i32.const 66
+    i32.const 0
+    i32.const 0
+    call $syscall
+    drop)
+  (global $g0 (mut i32) (i32.const 65536))
+  (export "call" (func $call)))
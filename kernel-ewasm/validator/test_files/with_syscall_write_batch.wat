@@ -0,0 +1,44 @@
+;;; A compliant contract with a write batch syscall, used to test the inference
+;;; of capability requirements.
+(module
+  (type $t0 (func (param i32 i32)))
+  (type $t3 (func (param i32 i32 i32 i32) (result i32)))
+  (type $t4 (func (param i64 i32 i32 i32 i32 i32) (result i32)))
+  (type $t6 (func (result i64)))
+  (type $t7 (func (result i32)))
+  (type $t9 (func))
+  (type $t10 (func (param i32)))
+  (import "env" "memory" (memory $M0 2))
+  (import "env" "dcall" (func $env.dcall (type $t4)))
+  (import "env" "gasleft" (func $env.gasleft (type $t6)))
+  (import "env" "sender" (func $env.sender (type $t7)))
+  (import "env" "ret" (func $env.ret (type $t0)))
+  ;; This is our system call which we have statically linked in
+  (func $syscall (type $t3) (param $p0 i32) (param $p1 i32) (param $p2 i32) (param $p3 i32) (result i32)
+    call $env.gasleft
+    call $env.sender
+    get_local $p0
+    get_local $p1
+    get_local $p2
+    get_local $p3
+    call $env.dcall)
+  ;; This is the entry point of the contract
+  (func $call (type $t9)
+    i32.const 0
+    i32.const 0
+    call $env.ret)
+  ;; Write a batch using store write cap 1. The syscall type is 0x87, the
+  ;; store write cap type with the variant bit set.
+  (func $write_batch (type $t9) (local $buf i32)
+    i32.const 1024
+    set_local $buf
+    get_local $buf
+    i32.const 0x0187
+    i32.store16
+    get_local $buf
+    i32.const 130
+    i32.const 0
+    i32.const 0
+    call $syscall
+    drop)
+  (export "call" (func $call)))